@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use mcmc::ess::{
+    compute_effective_sample_size, compute_effective_sample_size_with_options,
+    compute_split_effective_sample_size, EssOptions,
+};
+use mcmc::rhat::split_potential_scale_reduction_factor;
+use mcmc::Array2;
+
+/// Deterministic AR(1)-like chains so benchmark inputs are reproducible.
+fn chains(num_chains: usize, num_draws: usize) -> Array2 {
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    (0..num_chains)
+        .map(|_| {
+            let mut prev = 0.0;
+            (0..num_draws)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let noise = (state >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+                    prev = 0.5 * prev + noise;
+                    prev
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn bench_rhat(c: &mut Criterion) {
+    let mut group = c.benchmark_group("split_rhat");
+    for num_draws in [1_000, 100_000, 1_000_000] {
+        let input = chains(4, num_draws);
+        group.bench_with_input(BenchmarkId::from_parameter(num_draws), &input, |b, x| {
+            b.iter(|| split_potential_scale_reduction_factor(black_box(x)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_ess(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ess");
+    // the autocovariance is still quadratic in the number of draws
+    for num_draws in [1_000, 4_000] {
+        let input = chains(4, num_draws);
+        group.bench_with_input(BenchmarkId::from_parameter(num_draws), &input, |b, x| {
+            b.iter(|| compute_effective_sample_size(black_box(x)))
+        });
+    }
+    // capping the lags makes it linear for well mixing chains
+    let options = EssOptions { max_lag: Some(100) };
+    for num_draws in [4_000, 100_000] {
+        let input = chains(4, num_draws);
+        let id = BenchmarkId::new("max_lag_100", num_draws);
+        group.bench_with_input(id, &input, |b, x| {
+            b.iter(|| compute_effective_sample_size_with_options(black_box(x), &options))
+        });
+    }
+    group.finish();
+}
+
+fn bench_split_ess(c: &mut Criterion) {
+    let mut group = c.benchmark_group("split_ess");
+    // uneven chains exercise the trimming to the shortest chain
+    for num_draws in [1_000, 4_000] {
+        let mut input = chains(4, num_draws);
+        input[0].push(0.0);
+        group.bench_with_input(BenchmarkId::from_parameter(num_draws), &input, |b, x| {
+            b.iter(|| compute_split_effective_sample_size(black_box(x)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_rhat, bench_ess, bench_split_ess);
+criterion_main!(benches);
@@ -0,0 +1,69 @@
+//! R bindings for the `mcmc` crate.  Draws for a single parameter are passed
+//! from R as a numeric matrix with one column per chain, the same layout as
+//! the `draws_matrix` objects of the posterior package for one variable.
+use extendr_api::prelude::*;
+use mcmc::{ess, rhat, summary, Array2};
+
+/// Column-major matrix data to one vector per chain.
+fn to_chains(x: &RMatrix<f64>) -> Array2 {
+    let nrows = x.nrows().max(1);
+    x.data().chunks(nrows).map(|c| c.to_vec()).collect()
+}
+
+fn r_error(err: anyhow::Error) -> Error {
+    Error::Other(format!("{:#}", err))
+}
+
+/// Split potential scale reduction factor (R hat).
+/// @export
+#[extendr]
+fn split_rhat(x: RMatrix<f64>) -> Result<f64> {
+    rhat::split_potential_scale_reduction_factor(&to_chains(&x)).map_err(r_error)
+}
+
+/// Effective sample size (Geyer's initial monotone sequence estimator).
+/// @export
+#[extendr]
+fn ess(x: RMatrix<f64>) -> Result<f64> {
+    ess::compute_effective_sample_size(&to_chains(&x)).map_err(r_error)
+}
+
+/// Split effective sample size.
+/// @export
+#[extendr]
+fn split_ess(x: RMatrix<f64>) -> Result<f64> {
+    ess::compute_split_effective_sample_size(&to_chains(&x)).map_err(r_error)
+}
+
+/// Monte Carlo standard error of the posterior mean.
+/// @export
+#[extendr]
+fn mcse(x: RMatrix<f64>) -> Result<f64> {
+    ess::compute_estimated_mcse(&to_chains(&x)).map_err(r_error)
+}
+
+/// Posterior summary with the same columns as CmdStan's stansummary.
+/// @export
+#[extendr]
+fn summarize(x: RMatrix<f64>) -> Result<List> {
+    let s = summary::summarize(&to_chains(&x)).map_err(r_error)?;
+    Ok(list!(
+        mean = s.mean,
+        mcse = s.mcse,
+        sd = s.sd,
+        q5 = s.q5,
+        q50 = s.q50,
+        q95 = s.q95,
+        ess = s.ess,
+        rhat = s.rhat
+    ))
+}
+
+extendr_module! {
+    mod mcmcrs;
+    fn split_rhat;
+    fn ess;
+    fn split_ess;
+    fn mcse;
+    fn summarize;
+}
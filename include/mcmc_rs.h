@@ -0,0 +1,50 @@
+/*
+ * C interface to the mcmc Rust crate, available when it is built with the
+ * `ffi` feature as a shared library named libmcmc:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Draws for a single parameter are passed as one contiguous buffer of
+ * num_chains * num_draws doubles in chain-major order: draw i of chain c is
+ * at index c * num_draws + i.  Every function returns MCMC_RS_OK on success
+ * and leaves the output untouched otherwise.
+ */
+#ifndef MCMC_RS_H
+#define MCMC_RS_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MCMC_RS_OK 0
+#define MCMC_RS_INVALID_ARGUMENT 1
+#define MCMC_RS_COMPUTATION_FAILED 2
+
+typedef struct mcmc_rs_summary {
+    double mean;
+    double mcse;
+    double sd;
+    double q5;
+    double q50;
+    double q95;
+    double ess;
+    double rhat;
+} mcmc_rs_summary;
+
+/* Effective sample size (Geyer's initial monotone sequence estimator). */
+int mcmc_rs_ess(const double *draws, size_t num_chains, size_t num_draws, double *out);
+
+/* Split potential scale reduction factor (R hat). */
+int mcmc_rs_rhat(const double *draws, size_t num_chains, size_t num_draws, double *out);
+
+/* Posterior summary with the same columns as CmdStan's stansummary. */
+int mcmc_rs_summarize(const double *draws, size_t num_chains, size_t num_draws,
+                      mcmc_rs_summary *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MCMC_RS_H */
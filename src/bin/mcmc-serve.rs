@@ -0,0 +1,50 @@
+//! HTTP service computing diagnostics of posted draws, see the
+//! `mcmc::server` module for the routes.
+//!
+//! Usage: `mcmc-serve [--addr ADDRESS] [--max-body BYTES]
+//! [--max-connections N] [--request-timeout SECONDS]`, listening on
+//! `127.0.0.1:8080` by default.
+use anyhow::{anyhow, Context, Error, Result};
+use mcmc::server::{serve, Service, ServiceOptions};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn parse_args() -> Result<(String, ServiceOptions), Error> {
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut options = ServiceOptions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--addr" => addr = value()?,
+            "--max-body" => {
+                options.max_body = value()?.parse().context("Invalid --max-body")?;
+            }
+            "--max-connections" => {
+                options.max_connections = value()?
+                    .parse()
+                    .context("Invalid --max-connections")?;
+            }
+            "--request-timeout" => {
+                let seconds: u64 = value()?.parse().context("Invalid --request-timeout")?;
+                options.request_timeout = Duration::from_secs(seconds);
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unknown argument {:?}\nUsage: mcmc-serve [--addr ADDRESS] [--max-body BYTES] [--max-connections N] [--request-timeout SECONDS]",
+                    arg
+                ))
+            }
+        }
+    }
+    Ok((addr, options))
+}
+
+fn main() -> Result<(), Error> {
+    let (addr, options) = parse_args()?;
+    let listener =
+        TcpListener::bind(&addr).with_context(|| format!("Failed to listen on {}", addr))?;
+    eprintln!("Serving diagnostics on http://{}", listener.local_addr()?);
+    serve(listener, Arc::new(Service::new(options)))
+}
@@ -0,0 +1,359 @@
+//! Terminal dashboard for watching CmdStan output files while sampling.
+//!
+//! Usage: `mcmc-watch [--interval SECONDS] FILE...`, with one output CSV file
+//! per chain.  Files that do not exist yet are picked up once the sampler
+//! creates them.  Use the arrow keys to select a parameter and `q` to quit.
+use anyhow::{anyhow, Context, Error, Result};
+use mcmc::io::stream::{StreamOptions, StreamParser};
+use mcmc::online::{OnlineMonitor, Snapshot};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table, TableState};
+use ratatui::Frame;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Number of most recent draws kept per chain for the trace sparklines.
+const TRACE_LENGTH: usize = 200;
+/// Maximum number of chains shown in the trace panel.
+const MAX_TRACES: usize = 4;
+/// Longest accepted refresh interval in seconds.
+const MAX_INTERVAL: f64 = 3600.0;
+
+/// One chain's output file, read incrementally as the sampler appends to it.
+struct TailedFile {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    partial: String,
+    parser: StreamParser,
+}
+
+impl TailedFile {
+    fn new(path: PathBuf) -> TailedFile {
+        TailedFile {
+            path,
+            reader: None,
+            partial: String::new(),
+            parser: StreamParser::new(StreamOptions::default()),
+        }
+    }
+
+    /// Name of the chain, the file name without its extension, e.g.
+    /// `output_2` for CmdStan's `output_2.csv`.
+    fn name(&self) -> String {
+        self.path.file_stem().map_or_else(
+            || self.path.display().to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        )
+    }
+
+    /// Returns the draws in all complete lines appended since the last call.
+    fn poll(&mut self) -> Result<Vec<Vec<f64>>, Error> {
+        if self.reader.is_none() {
+            match File::open(&self.path) {
+                Ok(f) => self.reader = Some(BufReader::new(f)),
+                Err(_) => return Ok(Vec::new()),
+            }
+        }
+        let TailedFile {
+            path,
+            reader,
+            partial,
+            parser,
+        } = self;
+        let reader = reader.as_mut().unwrap();
+        let mut draws = Vec::new();
+        loop {
+            let read = reader
+                .read_line(partial)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            // a line without a newline is still being written, so keep it
+            // around and finish it on the next poll
+            if read == 0 || !partial.ends_with('\n') {
+                break;
+            }
+            let parsed = parser
+                .parse_line(partial)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            partial.clear();
+            if let Some((_, draw)) = parsed {
+                draws.push(draw);
+            }
+        }
+        Ok(draws)
+    }
+}
+
+/// Everything shown on screen.
+struct App {
+    files: Vec<TailedFile>,
+    monitor: Option<OnlineMonitor>,
+    snapshot: Option<Snapshot>,
+    divergent_column: Option<usize>,
+    draw_counts: Vec<usize>,
+    divergences: Vec<usize>,
+    // traces[chain][parameter] holds the most recent draws
+    traces: Vec<Vec<VecDeque<f64>>>,
+    table: TableState,
+    error: Option<String>,
+}
+
+impl App {
+    fn new(paths: Vec<PathBuf>) -> App {
+        let num_chains = paths.len();
+        App {
+            files: paths.into_iter().map(TailedFile::new).collect(),
+            monitor: None,
+            snapshot: None,
+            divergent_column: None,
+            draw_counts: vec![0; num_chains],
+            divergences: vec![0; num_chains],
+            traces: vec![Vec::new(); num_chains],
+            table: TableState::default().with_selected(Some(0)),
+            error: None,
+        }
+    }
+
+    fn refresh(&mut self) -> Result<(), Error> {
+        for chain in 0..self.files.len() {
+            let draws = self.files[chain].poll()?;
+            if draws.is_empty() {
+                continue;
+            }
+            let names = self.files[chain].parser.names().to_vec();
+            let monitor = match self.monitor {
+                Some(ref mut monitor) => monitor,
+                None => {
+                    self.divergent_column = names.iter().position(|n| n == "divergent__");
+                    self.monitor
+                        .get_or_insert(OnlineMonitor::new(names.clone()))
+                }
+            };
+            if monitor.names() != names.as_slice() {
+                return Err(anyhow!(
+                    "Header of {} does not match the other files",
+                    self.files[chain].path.display()
+                ));
+            }
+            let traces = &mut self.traces[chain];
+            traces.resize(names.len(), VecDeque::new());
+            for draw in draws.iter() {
+                monitor.push(chain, draw)?;
+                self.draw_counts[chain] += 1;
+                if let Some(idx) = self.divergent_column {
+                    if draw[idx] > 0.0 {
+                        self.divergences[chain] += 1;
+                    }
+                }
+                for (trace, x) in traces.iter_mut().zip(draw) {
+                    if trace.len() == TRACE_LENGTH {
+                        trace.pop_front();
+                    }
+                    trace.push_back(*x);
+                }
+            }
+        }
+        self.snapshot = self.monitor.as_ref().map(|m| m.snapshot());
+        Ok(())
+    }
+
+    fn select(&mut self, offset: isize) {
+        let len = self.snapshot.as_ref().map_or(0, |s| s.parameters.len());
+        if len == 0 {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let next = (current + offset).clamp(0, len as isize - 1);
+        self.table.select(Some(next as usize));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let num_traces = self.files.len().min(MAX_TRACES) as u16;
+        let [header, table, traces] = Layout::vertical([
+            Constraint::Length(5),
+            Constraint::Min(5),
+            Constraint::Length(3 * num_traces),
+        ])
+        .areas(frame.area());
+
+        let draws_per_chain: Vec<String> = self.draw_counts.iter().map(|n| n.to_string()).collect();
+        let mut lines = vec![
+            format!("Draws per chain: {}", draws_per_chain.join(", ")),
+            format!(
+                "Divergences per chain: {}",
+                match self.divergent_column {
+                    Some(_) => self
+                        .divergences
+                        .iter()
+                        .map(|d| d.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    None => "n/a".to_string(),
+                }
+            ),
+        ];
+        if let Some(ref err) = self.error {
+            lines.push(format!("Error: {}", err));
+        }
+        frame.render_widget(
+            Paragraph::new(lines.join("\n")).block(Block::bordered().title("mcmc-watch")),
+            header,
+        );
+
+        let rows: Vec<Row> = self
+            .snapshot
+            .iter()
+            .flat_map(|s| s.parameters.iter())
+            .map(|p| {
+                let rhat = p.rhat.map_or("-".to_string(), |r| format!("{:.3}", r));
+                let style = match p.rhat {
+                    Some(r) if r > 1.01 => Style::default().fg(Color::Red),
+                    _ => Style::default(),
+                };
+                let interval = match (p.q5, p.q95) {
+                    (Some(lo), Some(hi)) => format!("[{:.3}, {:.3}]", lo, hi),
+                    _ => "-".to_string(),
+                };
+                Row::new(vec![
+                    p.name.clone(),
+                    format!("{:.4}", p.mean),
+                    format!("{:.4}", p.sd),
+                    interval,
+                    rhat,
+                    p.ess.map_or("-".to_string(), |e| format!("{:.0}", e)),
+                ])
+                .style(style)
+            })
+            .collect();
+        let widths = [
+            Constraint::Percentage(20),
+            Constraint::Percentage(15),
+            Constraint::Percentage(15),
+            Constraint::Percentage(25),
+            Constraint::Percentage(10),
+            Constraint::Percentage(15),
+        ];
+        let table_widget = Table::new(rows, widths)
+            .header(
+                Row::new(vec![
+                    "parameter",
+                    "mean",
+                    "sd",
+                    "90% interval",
+                    "R hat",
+                    "ESS (approx)",
+                ])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::bordered().title("Convergence (q to quit)"))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table_widget, table, &mut self.table);
+
+        let selected = self.table.selected().unwrap_or(0);
+        let name = self
+            .snapshot
+            .as_ref()
+            .and_then(|s| s.parameters.get(selected))
+            .map_or(String::new(), |p| p.name.clone());
+        let areas =
+            Layout::vertical(vec![Constraint::Length(3); num_traces as usize]).split(traces);
+        for (chain, area) in areas.iter().enumerate() {
+            let data = self.traces[chain]
+                .get(selected)
+                .map(scale)
+                .unwrap_or_default();
+            let title = format!("{} trace, chain {}", name, self.files[chain].name());
+            frame.render_widget(
+                Sparkline::default()
+                    .block(Block::bordered().title(title))
+                    .data(&data),
+                *area,
+            );
+        }
+    }
+}
+
+/// Scales a trace to the integer range expected by the sparkline widget.
+fn scale(trace: &VecDeque<f64>) -> Vec<u64> {
+    let lo = trace.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = trace.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = if hi > lo { hi - lo } else { 1.0 };
+    trace
+        .iter()
+        .map(|x| (1.0 + 99.0 * (x - lo) / range) as u64)
+        .collect()
+}
+
+fn parse_args() -> Result<(Duration, Vec<PathBuf>), Error> {
+    let mut interval = Duration::from_secs(1);
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--interval" {
+            let secs: f64 = args
+                .next()
+                .ok_or_else(|| anyhow!("--interval needs a value"))?
+                .parse()
+                .context("--interval must be a number of seconds")?;
+            // Duration::from_secs_f64 panics on negative, NaN or huge values
+            if !(secs > 0.0 && secs <= MAX_INTERVAL) {
+                return Err(anyhow!(
+                    "--interval must be positive and at most {} seconds, got {}",
+                    MAX_INTERVAL,
+                    secs
+                ));
+            }
+            interval = Duration::from_secs_f64(secs);
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    if paths.is_empty() {
+        return Err(anyhow!("Usage: mcmc-watch [--interval SECONDS] FILE..."));
+    }
+    Ok((interval, paths))
+}
+
+fn main() -> Result<(), Error> {
+    let (interval, paths) = parse_args()?;
+    let mut app = App::new(paths);
+    let mut terminal = ratatui::init();
+    // refresh right away, or after the first interval if the clock can't
+    // go back a whole interval
+    let mut last_refresh = Instant::now()
+        .checked_sub(interval)
+        .unwrap_or_else(Instant::now);
+    let result = loop {
+        if last_refresh.elapsed() >= interval {
+            if let Err(err) = app.refresh() {
+                app.error = Some(format!("{:#}", err));
+            }
+            last_refresh = Instant::now();
+        }
+        if let Err(err) = terminal.draw(|frame| app.draw(frame)) {
+            break Err(err.into());
+        }
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+                    KeyCode::Down => app.select(1),
+                    KeyCode::Up => app.select(-1),
+                    KeyCode::PageDown => app.select(20),
+                    KeyCode::PageUp => app.select(-20),
+                    _ => {}
+                },
+                Ok(_) => {}
+                Err(err) => break Err(err.into()),
+            },
+            Ok(false) => {}
+            Err(err) => break Err(err.into()),
+        }
+    };
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,390 @@
+use crate::draws::Draws;
+use crate::stats::hdi;
+use crate::utils::{beta_quantile, csv_field, flatten, quantile_sorted};
+use anyhow::{anyhow, Context, Error, Result};
+use std::fmt::Write;
+
+/// Kind of posterior interval whose coverage is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntervalKind {
+    /// Equal tailed interval between the `(1 - level) / 2` and
+    /// `(1 + level) / 2` quantiles
+    Central,
+    /// Highest density interval, see [`Draws::hdi`](../draws/struct.Draws.html#method.hdi)
+    Hdi,
+}
+
+impl IntervalKind {
+    fn name(self) -> &'static str {
+        match self {
+            IntervalKind::Central => "central",
+            IntervalKind::Hdi => "hdi",
+        }
+    }
+}
+
+/// Options of a [`CoverageStudy`](struct.CoverageStudy.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageOptions {
+    /// Nominal levels of the intervals, each in (0, 1)
+    pub levels: Vec<f64>,
+    /// Kinds of intervals to check at every level
+    pub kinds: Vec<IntervalKind>,
+    /// Confidence level of the Clopper-Pearson intervals around the
+    /// empirical coverages, in (0, 1)
+    pub confidence: f64,
+}
+
+impl Default for CoverageOptions {
+    fn default() -> CoverageOptions {
+        CoverageOptions {
+            levels: vec![0.5, 0.8, 0.9, 0.95],
+            kinds: vec![IntervalKind::Central, IntervalKind::Hdi],
+            confidence: 0.95,
+        }
+    }
+}
+
+/// Empirical coverage of one kind of interval of one parameter at one
+/// level, a row of the recovery table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// Name of the parameter
+    pub parameter: String,
+    /// Kind of the intervals
+    pub kind: IntervalKind,
+    /// Nominal level of the intervals
+    pub level: f64,
+    /// Number of runs whose interval contains the true value
+    pub covered: usize,
+    /// Number of runs with a true value for the parameter
+    pub num_runs: usize,
+    /// Fraction of the runs whose interval contains the true value
+    pub coverage: f64,
+    /// Lower end of the exact binomial confidence interval of the coverage
+    pub lower: f64,
+    /// Upper end of the exact binomial confidence interval of the coverage
+    pub upper: f64,
+}
+
+impl Coverage {
+    /// Whether the nominal level lies in the confidence interval of the
+    /// coverage, i.e. the intervals are not detectably too narrow or too
+    /// wide.
+    pub fn is_calibrated(&self) -> bool {
+        self.lower <= self.level && self.level <= self.upper
+    }
+}
+
+/// Counts of covered true values of one parameter.
+#[derive(Debug, Clone, PartialEq)]
+struct ParameterCounts {
+    name: String,
+    num_runs: usize,
+    // indexed by kind, then level
+    covered: Vec<Vec<usize>>,
+}
+
+/// Checks the coverage of posterior intervals in a simulation study: every
+/// run fits data simulated from known parameter values, and a well
+/// calibrated fit puts the true values inside its `level` intervals in
+/// about a `level` fraction of the runs.  Runs are added one at a time so
+/// that their draws need not be kept in memory.
+///
+/// ```
+/// use mcmc::calibration::{CoverageOptions, CoverageStudy};
+/// use mcmc::draws::Draws;
+///
+/// let mut study = CoverageStudy::new(CoverageOptions::default()).unwrap();
+/// let fit = vec![(0..100).map(|i| i as f64 / 100.0).collect()];
+/// let draws = Draws::from_chains(vec!["mu".to_string()], vec![fit]).unwrap();
+/// study.push_run(&draws, &[("mu", 0.52)]).unwrap();
+/// for row in study.table() {
+///     println!("{} {:?} {}: {}", row.parameter, row.kind, row.level, row.coverage);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageStudy {
+    options: CoverageOptions,
+    parameters: Vec<ParameterCounts>,
+    num_runs: usize,
+}
+
+impl CoverageStudy {
+    /// Creates a study without any runs.
+    pub fn new(options: CoverageOptions) -> Result<CoverageStudy, Error> {
+        if options.levels.is_empty() || options.kinds.is_empty() {
+            return Err(anyhow!("Need at least one interval level and kind"));
+        }
+        if let Some(level) = options.levels.iter().find(|l| !(**l > 0.0 && **l < 1.0)) {
+            return Err(anyhow!("Interval levels must be in (0, 1), got {}", level));
+        }
+        if !(options.confidence > 0.0 && options.confidence < 1.0) {
+            return Err(anyhow!(
+                "Confidence level must be in (0, 1), got {}",
+                options.confidence
+            ));
+        }
+        Ok(CoverageStudy {
+            options,
+            parameters: Vec::new(),
+            num_runs: 0,
+        })
+    }
+
+    /// Adds a fitted run, checking its intervals of the parameters with a
+    /// true value.  Parameters are added to the table as they first appear,
+    /// so runs may check different subsets of them, but a parameter may be
+    /// given only once per run.
+    ///
+    /// # Arguments
+    /// * `draws` - Draws of the run; tempered chains are left out
+    /// * `truth` - Names of the parameters and the values the data of the
+    ///   run were simulated from
+    pub fn push_run(&mut self, draws: &Draws, truth: &[(&str, f64)]) -> Result<(), Error> {
+        let run = self.num_runs + 1;
+        let mut hits = Vec::with_capacity(truth.len());
+        for (i, &(name, value)) in truth.iter().enumerate() {
+            if truth[..i].iter().any(|(other, _)| *other == name) {
+                return Err(anyhow!(
+                    "True value of {} is given twice in run {}",
+                    name,
+                    run
+                ));
+            }
+            if !value.is_finite() {
+                return Err(anyhow!(
+                    "True value of {} in run {} is not finite",
+                    name,
+                    run
+                ));
+            }
+            let idx = draws
+                .index_of(name)
+                .ok_or_else(|| anyhow!("No parameter named {:?} in run {}", name, run))?;
+            let covered = self
+                .covered(draws, idx, value)
+                .with_context(|| format!("Failed to check intervals of {} in run {}", name, run))?;
+            hits.push((name, covered));
+        }
+        // only count the run once all of its intervals were found
+        for (name, covered) in hits.into_iter() {
+            let counts = match self.parameters.iter().position(|p| p.name == name) {
+                Some(pos) => &mut self.parameters[pos],
+                None => {
+                    let levels = vec![0; self.options.levels.len()];
+                    self.parameters.push(ParameterCounts {
+                        name: name.to_string(),
+                        num_runs: 0,
+                        covered: vec![levels; self.options.kinds.len()],
+                    });
+                    self.parameters.last_mut().unwrap()
+                }
+            };
+            counts.num_runs += 1;
+            for (counts, covered) in counts.covered.iter_mut().zip(covered.iter()) {
+                for (count, &hit) in counts.iter_mut().zip(covered.iter()) {
+                    *count += hit as usize;
+                }
+            }
+        }
+        self.num_runs += 1;
+        Ok(())
+    }
+
+    /// Whether each interval of the parameter contains the value, by kind
+    /// and level.  Sorts a copy of the pooled draws rather than caching the
+    /// sort in the run, which is dropped after being checked anyway.
+    fn covered(&self, draws: &Draws, idx: usize, value: f64) -> Result<Vec<Vec<bool>>, Error> {
+        let mut sorted = flatten(&draws.target_parameter(idx));
+        if sorted.iter().any(|x| !x.is_finite()) {
+            return Err(anyhow!("Draws contain NaN or infinite values"));
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        self.options
+            .kinds
+            .iter()
+            .map(|kind| {
+                self.options
+                    .levels
+                    .iter()
+                    .map(|&level| {
+                        let (lower, upper) = match kind {
+                            IntervalKind::Central => {
+                                let probs = [(1.0 - level) / 2.0, (1.0 + level) / 2.0];
+                                (
+                                    quantile_sorted(&sorted, probs[0])?,
+                                    quantile_sorted(&sorted, probs[1])?,
+                                )
+                            }
+                            IntervalKind::Hdi => hdi(&sorted, level)?,
+                        };
+                        Ok(lower <= value && value <= upper)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Number of runs added.
+    pub fn num_runs(&self) -> usize {
+        self.num_runs
+    }
+
+    /// Coverage of every parameter, kind and level, with parameters in order
+    /// of first appearance, then kinds and levels in the order of the
+    /// options.
+    pub fn table(&self) -> Vec<Coverage> {
+        let alpha = 1.0 - self.options.confidence;
+        let mut rows = Vec::new();
+        for p in self.parameters.iter() {
+            let n = p.num_runs;
+            for (kind, covered) in self.options.kinds.iter().zip(p.covered.iter()) {
+                for (&level, &k) in self.options.levels.iter().zip(covered.iter()) {
+                    let (lower, upper) = clopper_pearson(k, n, alpha);
+                    rows.push(Coverage {
+                        parameter: p.name.clone(),
+                        kind: *kind,
+                        level,
+                        covered: k,
+                        num_runs: n,
+                        coverage: k as f64 / n as f64,
+                        lower,
+                        upper,
+                    });
+                }
+            }
+        }
+        rows
+    }
+
+    /// Formats the table as CSV with the columns `parameter`, `interval`
+    /// (`central` or `hdi`), `level`, `covered`, `runs`, `coverage`,
+    /// `lower` and `upper`.  Parameter names with commas or quotes are
+    /// quoted.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("parameter,interval,level,covered,runs,coverage,lower,upper\n");
+        for row in self.table() {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                csv_field(&row.parameter),
+                row.kind.name(),
+                row.level,
+                row.covered,
+                row.num_runs,
+                row.coverage,
+                row.lower,
+                row.upper
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+/// Exact two sided binomial confidence interval of a proportion with `k`
+/// successes in `n` trials, at level `1 - alpha`.
+fn clopper_pearson(k: usize, n: usize, alpha: f64) -> (f64, f64) {
+    let (kf, nf) = (k as f64, n as f64);
+    let lower = if k == 0 {
+        0.0
+    } else {
+        beta_quantile(alpha / 2.0, kf, nf - kf + 1.0)
+    };
+    let upper = if k == n {
+        1.0
+    } else {
+        beta_quantile(1.0 - alpha / 2.0, kf + 1.0, nf - kf)
+    };
+    (lower, upper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    #[test]
+    fn test_clopper_pearson() {
+        // binom.test(7, 20)$conf.int in R
+        let (lower, upper) = clopper_pearson(7, 20, 0.05);
+        assert_abs_diff_eq!(lower, 0.1539092, epsilon = 1e-6);
+        assert_abs_diff_eq!(upper, 0.5921885, epsilon = 1e-6);
+        assert_eq!(clopper_pearson(0, 10, 0.05).0, 0.0);
+        assert_eq!(clopper_pearson(10, 10, 0.05).1, 1.0);
+    }
+
+    #[test]
+    fn test_coverage_study() {
+        // posterior N(truth + noise, 1) with noise ~ N(0, 1) is calibrated,
+        // and N(truth + noise, 0.5) is overconfident
+        let mut study = CoverageStudy::new(CoverageOptions::default()).unwrap();
+        let noise = normal_draws(200, 1);
+        for (run, shift) in noise.iter().enumerate() {
+            let names = vec!["mu".to_string(), "sigma".to_string()];
+            let z = normal_draws(400, run as u64 + 100);
+            let mu = z.iter().map(|x| x + shift).collect();
+            let sigma = z.iter().map(|x| 0.5 * x + shift).collect();
+            let draws = Draws::from_chains(names, vec![vec![mu, sigma]]).unwrap();
+            let truth: &[(&str, f64)] = if run % 2 == 0 {
+                &[("mu", 0.0), ("sigma", 0.0)]
+            } else {
+                &[("mu", 0.0)]
+            };
+            study.push_run(&draws, truth).unwrap();
+        }
+        assert_eq!(study.num_runs(), 200);
+        let table = study.table();
+        assert_eq!(table.len(), 16);
+        let mu: Vec<&Coverage> = table.iter().filter(|r| r.parameter == "mu").collect();
+        assert!(mu.iter().all(|r| r.num_runs == 200 && r.is_calibrated()));
+        assert_eq!(mu[4].kind, IntervalKind::Hdi);
+        let sigma_90 = table
+            .iter()
+            .find(|r| r.parameter == "sigma" && r.level == 0.9)
+            .unwrap();
+        assert_eq!(sigma_90.num_runs, 100);
+        assert!(sigma_90.upper < 0.9);
+        assert!(!sigma_90.is_calibrated());
+
+        let csv = study.to_csv();
+        assert!(csv.starts_with("parameter,interval,level,covered,runs,coverage,lower,upper\n"));
+        assert_eq!(csv.lines().count(), 17);
+        assert!(csv.lines().nth(5).unwrap().starts_with("mu,hdi,0.5,"));
+    }
+
+    #[test]
+    fn test_coverage_study_errors() {
+        let levels = |levels: Vec<f64>| CoverageOptions {
+            levels,
+            ..CoverageOptions::default()
+        };
+        assert!(CoverageStudy::new(levels(vec![])).is_err());
+        assert!(CoverageStudy::new(levels(vec![0.9, 1.0])).is_err());
+        let confidence = CoverageOptions {
+            confidence: 0.0,
+            ..CoverageOptions::default()
+        };
+        assert!(CoverageStudy::new(confidence).is_err());
+
+        let mut study = CoverageStudy::new(CoverageOptions::default()).unwrap();
+        let chains = vec![vec![normal_draws(100, 1)]];
+        let draws = Draws::from_chains(vec!["mu".to_string()], chains).unwrap();
+        assert!(study.push_run(&draws, &[("tau", 0.0)]).is_err());
+        assert!(study.push_run(&draws, &[("mu", f64::NAN)]).is_err());
+        assert!(study.push_run(&draws, &[("mu", 0.0), ("mu", 1.0)]).is_err());
+        assert_eq!(study.num_runs(), 0);
+        assert!(study.table().is_empty());
+
+        let chains = vec![vec![normal_draws(100, 2)]];
+        let draws = Draws::from_chains(vec!["b,\"1\"".to_string()], chains).unwrap();
+        study.push_run(&draws, &[("b,\"1\"", 0.0)]).unwrap();
+        let csv = study.to_csv();
+        assert!(csv
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("\"b,\"\"1\"\"\",central,"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Little endian binary encoding of accumulator states for checkpoints.
+//! Floats are stored bit for bit, so a restored accumulator continues
+//! exactly where the saved one stopped.
+use anyhow::{anyhow, Error, Result};
+use std::convert::TryFrom;
+
+/// Buffer the encoded values are appended to.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn u64(&mut self, x: u64) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    pub(crate) fn usize(&mut self, x: usize) {
+        self.u64(x as u64);
+    }
+
+    pub(crate) fn f64(&mut self, x: f64) {
+        self.u64(x.to_bits());
+    }
+
+    pub(crate) fn bool(&mut self, x: bool) {
+        self.bytes(&[x as u8]);
+    }
+
+    pub(crate) fn f64s(&mut self, xs: &[f64]) {
+        self.usize(xs.len());
+        for &x in xs.iter() {
+            self.f64(x);
+        }
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads values back in the order they were encoded, failing on truncated
+/// input rather than panicking.
+#[derive(Debug)]
+pub(crate) struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data }
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.data.len() {
+            return Err(anyhow!("Checkpoint is truncated"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, Error> {
+        let x = self.u64()?;
+        usize::try_from(x).map_err(|_| anyhow!("Checkpoint value {} is too large", x))
+    }
+
+    /// Length of a sequence of items of `item_size` bytes each, checked
+    /// against the remaining input so corrupt lengths can't allocate huge
+    /// buffers.
+    pub(crate) fn len(&mut self, item_size: usize) -> Result<usize, Error> {
+        let n = self.usize()?;
+        if n.saturating_mul(item_size) > self.data.len() {
+            return Err(anyhow!("Checkpoint is truncated"));
+        }
+        Ok(n)
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64, Error> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, Error> {
+        match self.bytes(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(anyhow!("Invalid flag {} in checkpoint", b)),
+        }
+    }
+
+    pub(crate) fn f64s(&mut self) -> Result<Vec<f64>, Error> {
+        let n = self.len(8)?;
+        (0..n).map(|_| self.f64()).collect()
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, Error> {
+        let n = self.len(1)?;
+        String::from_utf8(self.bytes(n)?.to_vec())
+            .map_err(|_| anyhow!("Invalid UTF-8 string in checkpoint"))
+    }
+
+    /// Fails unless all of the input was read.
+    pub(crate) fn finish(self) -> Result<(), Error> {
+        if !self.data.is_empty() {
+            return Err(anyhow!(
+                "Checkpoint has {} unexpected trailing bytes",
+                self.data.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut encoder = Encoder::new();
+        encoder.usize(7);
+        encoder.f64(-0.1);
+        encoder.bool(true);
+        encoder.f64s(&[1.0, f64::NAN]);
+        encoder.str("theta[1]");
+        let bytes = encoder.into_bytes();
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.usize().unwrap(), 7);
+        assert_eq!(decoder.f64().unwrap(), -0.1);
+        assert!(decoder.bool().unwrap());
+        let xs = decoder.f64s().unwrap();
+        assert_eq!(xs[0], 1.0);
+        assert!(xs[1].is_nan());
+        assert_eq!(decoder.string().unwrap(), "theta[1]");
+        decoder.finish().unwrap();
+
+        assert!(Decoder::new(&bytes[..3]).usize().is_err());
+        assert!(Decoder::new(&u64::MAX.to_le_bytes()).f64s().is_err());
+        assert!(Decoder::new(&[2]).bool().is_err());
+        assert!(Decoder::new(&bytes).finish().is_err());
+    }
+}
@@ -0,0 +1,1650 @@
+use crate::draws::{Draws, Sampler};
+use crate::ess::{
+    compute_bulk_effective_sample_size, compute_bulk_tail_ess, compute_ess_quantile,
+    compute_estimated_mcse, integrated_autocorrelation_time_with_options, AutocorrTimeOptions,
+};
+use crate::rhat::rank_normalized_split_potential_scale_reduction_factor;
+use crate::stats::{kde_grid, named_target_chains};
+use crate::utils::{chi_square_sf, flatten, mean, ranks};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+use hmc::is_sampler_column;
+use std::fmt;
+
+/// Diagnostics specific to Hamiltonian Monte Carlo samplers such as NUTS
+pub mod hmc;
+
+/// Which tail of the distribution is used to estimate the Pareto shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tail {
+    /// Both tails, reporting the larger of the two shape estimates
+    Both,
+    /// Lower tail only
+    Left,
+    /// Upper tail only
+    Right,
+}
+
+/// Options for the Pareto diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParetoOptions {
+    /// Tail (or tails) used for the shape estimate
+    pub tail: Tail,
+    /// Relative efficiency of the draws, i.e. ESS over the number of draws.
+    /// When `None` it is estimated from the tail effective sample size.
+    pub r_eff: Option<f64>,
+    /// Number of draws in each tail.  When `None` it is
+    /// `ceil(3 * sqrt(S / r_eff))` for more than 225 draws and `S / 5`
+    /// otherwise, capped at half the draws for two tailed estimates.
+    pub ndraws_tail: Option<usize>,
+}
+
+impl Default for ParetoOptions {
+    fn default() -> ParetoOptions {
+        ParetoOptions {
+            tail: Tail::Both,
+            r_eff: None,
+            ndraws_tail: None,
+        }
+    }
+}
+
+/// Pareto diagnostics of the tails of a distribution estimated from draws.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParetoDiagnostics {
+    /// Estimated shape of the generalized Pareto distribution fitted to the
+    /// tail.  Means are estimated reliably when it is below `khat_threshold`.
+    pub khat: f64,
+    /// Minimum number of draws needed for a reliable Pareto smoothed
+    /// estimate of the mean given `khat`, infinite when `khat >= 1`
+    pub min_ss: f64,
+    /// Largest `khat` for which the number of draws is sufficient,
+    /// `1 - 1 / log10(S)`
+    pub khat_threshold: f64,
+    /// Relative convergence rate of the error of the Pareto smoothed
+    /// mean compared to the usual `1 / sqrt(S)`
+    pub convergence_rate: f64,
+}
+
+/// Computes the Pareto diagnostics of the specified parameter across all
+/// chains with the default options, defined as in `pareto_diags` from the R
+/// package [posterior](https://mc-stan.org/posterior/) so the numbers can be
+/// compared directly.
+///
+/// See Vehtari et al. (2024)
+/// ["Pareto smoothed importance sampling"](https://jmlr.org/papers/v25/19-556.html).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn pareto_diags(chains: &Array2) -> Result<ParetoDiagnostics, Error> {
+    pareto_diags_with_options(chains, &ParetoOptions::default())
+}
+
+/// Computes the Pareto diagnostics of the specified parameter across all
+/// chains, see [`pareto_diags`](fn.pareto_diags.html).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `options` - Tail, relative efficiency and tail length to use
+pub fn pareto_diags_with_options(
+    chains: &Array2,
+    options: &ParetoOptions,
+) -> Result<ParetoDiagnostics, Error> {
+    let khat = pareto_khat(chains, options)?;
+    let num_draws = flatten(chains).len() as f64;
+    Ok(ParetoDiagnostics {
+        khat,
+        min_ss: min_sample_size(khat),
+        khat_threshold: 1.0 - 1.0 / num_draws.log10(),
+        convergence_rate: convergence_rate(khat, num_draws),
+    })
+}
+
+/// Estimates the Pareto shape of one or both tails of the pooled draws.
+fn pareto_khat(chains: &Array2, options: &ParetoOptions) -> Result<f64, Error> {
+    let mut sorted = flatten(chains);
+    let n = sorted.len();
+    if n < 5 {
+        return Err(anyhow!(
+            "Must have at least 5 samples to compute Pareto khat"
+        ));
+    }
+    if sorted.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to compute Pareto khat"));
+    }
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    if sorted[0] == sorted[n - 1] {
+        return Err(anyhow!("No Pareto khat when elements are all constant"));
+    }
+
+    let mut ndraws_tail = match options.ndraws_tail {
+        Some(m) => m,
+        None => {
+            let r_eff = match options.r_eff {
+                Some(r_eff) => r_eff,
+                None => tail_effective_sample_size(chains)? / n as f64,
+            };
+            if n > 225 {
+                (3.0 * (n as f64 / r_eff).sqrt()).ceil() as usize
+            } else {
+                n / 5
+            }
+        }
+    };
+    if options.tail == Tail::Both {
+        ndraws_tail = ndraws_tail.min(n / 2);
+    }
+    ndraws_tail = ndraws_tail.min(n - 1);
+
+    let right = || {
+        let cutoff = sorted[n - ndraws_tail - 1];
+        let tail: Array1 = sorted[n - ndraws_tail..]
+            .iter()
+            .map(|x| x - cutoff)
+            .collect();
+        tail_khat(&tail)
+    };
+    let left = || {
+        let cutoff = sorted[ndraws_tail];
+        let tail: Array1 = sorted[..ndraws_tail]
+            .iter()
+            .rev()
+            .map(|x| cutoff - x)
+            .collect();
+        tail_khat(&tail)
+    };
+    let khat = match options.tail {
+        Tail::Right => right(),
+        Tail::Left => left(),
+        Tail::Both => match (left(), right()) {
+            (Some(l), Some(r)) => Some(l.max(r)),
+            (l, r) => l.or(r),
+        },
+    };
+    khat.ok_or_else(|| anyhow!("Not enough distinct tail draws to compute Pareto khat"))
+}
+
+/// Shape estimate for sorted exceedances over the cutoff, or `None` when
+/// there are fewer than five of them or they are all equal.
+fn tail_khat(tail: &[f64]) -> Option<f64> {
+    if tail.len() < 5 || tail[tail.len() - 1] - tail[0] < f64::EPSILON / 100.0 {
+        return None;
+    }
+    Some(gpdfit(tail).0)
+}
+
+/// Tail effective sample size, the smaller of the quantile ESS at 5% and
+/// 95%.
+fn tail_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
+    Ok(compute_ess_quantile(chains, 0.05)?.min(compute_ess_quantile(chains, 0.95)?))
+}
+
+/// Number of draws per chain needed to reach a target effective sample size,
+/// see [`draws_needed`](fn.draws_needed.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawsNeeded {
+    /// Current bulk effective sample size, the rank normalized split ESS
+    pub bulk_ess: f64,
+    /// Current tail effective sample size, the smaller quantile ESS at 5%
+    /// and 95%
+    pub tail_ess: f64,
+    /// Additional draws per chain for the bulk ESS to reach the target
+    pub bulk: usize,
+    /// Additional draws per chain for the tail ESS to reach the target
+    pub tail: usize,
+    /// Additional draws per chain for both to reach the target, the larger
+    /// of `bulk` and `tail`
+    pub additional: usize,
+}
+
+/// Estimates how many more iterations each chain needs for the bulk and
+/// tail effective sample sizes to reach `target_ess`, e.g. to decide how far
+/// to extend a run that has not converged yet.  The ESS per draw is assumed
+/// to stay the same, so the estimate is `target_ess * N / ESS - N` for `N`
+/// draws per chain.  This is only as reliable as the current autocorrelation
+/// estimates, so check again after extending.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `target_ess` - Effective sample size to reach
+pub fn draws_needed(chains: &Array2, target_ess: f64) -> Result<DrawsNeeded, Error> {
+    if !(target_ess > 0.0 && target_ess.is_finite()) {
+        return Err(anyhow!(
+            "Target ESS must be positive and finite, got {}",
+            target_ess
+        ));
+    }
+    let num_draws = chains.iter().map(|c| c.len()).min().unwrap_or(0) as f64;
+    let bulk_ess = compute_bulk_effective_sample_size(chains)?;
+    let tail_ess = tail_effective_sample_size(chains)?;
+    let needed = |ess: f64| -> usize {
+        let total = target_ess * num_draws / ess;
+        if total.is_finite() {
+            (total - num_draws).ceil().max(0.0) as usize
+        } else {
+            usize::MAX
+        }
+    };
+    let (bulk, tail) = (needed(bulk_ess), needed(tail_ess));
+    Ok(DrawsNeeded {
+        bulk_ess,
+        tail_ess,
+        bulk,
+        tail,
+        additional: bulk.max(tail),
+    })
+}
+
+/// Run length recommendation for one parameter, see
+/// [`recommend_run_length`](fn.recommend_run_length.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunLength {
+    /// Name of the parameter
+    pub parameter: String,
+    /// Integrated autocorrelation time of the parameter
+    pub tau: f64,
+    /// Current Monte Carlo standard error of the mean over the posterior
+    /// standard deviation
+    pub mcse_ratio: f64,
+    /// Draws per chain needed for the ratio to reach the target
+    pub draws_per_chain: usize,
+    /// Additional draws per chain beyond the current ones, zero if the
+    /// target is already met
+    pub additional: usize,
+}
+
+/// Recommends how long to run the chains so that the Monte Carlo standard
+/// error of every parameter's mean falls below `target_mcse_ratio` times its
+/// posterior standard deviation, e.g. 0.05 for an MCSE of at most 5% of the
+/// sd.  With `M` chains and an integrated autocorrelation time `tau` the
+/// ratio is `sqrt(tau / (M N))` for `N` draws per chain, so `N` must be at
+/// least `tau / (M r^2)`.  The autocorrelation time is Sokal's estimate,
+/// also when the chains are too short for it to be reliable, so check again
+/// after extending a short run.
+///
+/// Sampler diagnostics, e.g. `treedepth__` from Stan or `tree_depth` from
+/// Turing.jl and PyMC, and constant parameters are left out, and so are
+/// tempered chains.
+///
+/// # Arguments
+/// * `draws` - Draws to check
+/// * `target_mcse_ratio` - Largest acceptable MCSE as a fraction of the sd
+pub fn recommend_run_length(
+    draws: &Draws,
+    target_mcse_ratio: f64,
+) -> Result<Vec<RunLength>, Error> {
+    if !(target_mcse_ratio > 0.0 && target_mcse_ratio.is_finite()) {
+        return Err(anyhow!(
+            "Target MCSE ratio must be positive and finite, got {}",
+            target_mcse_ratio
+        ));
+    }
+    let options = AutocorrTimeOptions {
+        quiet: true,
+        ..AutocorrTimeOptions::default()
+    };
+    let mut lengths = Vec::new();
+    for (idx, name) in draws.names().iter().enumerate() {
+        if is_sampler_column(name, draws.sampler()) {
+            continue;
+        }
+        let chains = draws.target_parameter(idx);
+        let mut values = chains.iter().flatten();
+        if let Some(first) = values.next() {
+            if values.all(|x| x == first) {
+                continue;
+            }
+        }
+        let tau = integrated_autocorrelation_time_with_options(&chains, &options)
+            .with_context(|| format!("Failed to compute autocorrelation time of {}", name))?;
+        let num_chains = chains.len() as f64;
+        let num_draws = chains.iter().map(|c| c.len()).min().unwrap_or(0);
+        let needed = (tau / (num_chains * target_mcse_ratio.powi(2))).ceil() as usize;
+        lengths.push(RunLength {
+            parameter: name.clone(),
+            tau,
+            mcse_ratio: (tau / (num_chains * num_draws as f64)).sqrt(),
+            draws_per_chain: needed,
+            additional: needed.saturating_sub(num_draws),
+        });
+    }
+    Ok(lengths)
+}
+
+/// Result of a chi-square test for uniformity of one chain's ranks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankUniformityTest {
+    /// Pearson chi-square statistic over the rank histogram bins
+    pub statistic: f64,
+    /// Degrees of freedom, one less than the number of bins
+    pub df: usize,
+    /// Upper tail probability of the statistic; small values mean the chain
+    /// explores a different part of the distribution than the others
+    pub p_value: f64,
+}
+
+/// Tests whether each chain's share of the pooled ranks is uniform, which is
+/// what a rank plot shows visually.  The draws of all chains are ranked
+/// together, the ranks are binned into `num_bins` bins of equal width and
+/// each chain's histogram is compared with a flat one using Pearson's
+/// chi-square test.  Returns one result per chain in the original order.
+///
+/// Draws are not independent, so p-values of well mixing chains with high
+/// autocorrelation are smaller than nominal; the test is best used to flag
+/// chains for a closer look rather than as an exact test.
+///
+/// See Vehtari et al. (2021)
+/// ["Rank-normalization, folding, and localization"](https://doi.org/10.1214/20-BA1221).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `num_bins` - Number of rank histogram bins, e.g. 20 as in rank plots
+pub fn rank_uniformity_test(
+    chains: &Array2,
+    num_bins: usize,
+) -> Result<Vec<RankUniformityTest>, Error> {
+    if num_bins < 2 {
+        return Err(anyhow!("Need at least 2 bins to test rank uniformity"));
+    }
+    let pooled = flatten(chains);
+    if pooled.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to test rank uniformity"));
+    }
+    let total = pooled.len() as f64;
+    let pooled_ranks = ranks(&pooled);
+    let mut results = Vec::with_capacity(chains.len());
+    let mut offset = 0;
+    for chain in chains.iter() {
+        if chain.len() < num_bins {
+            return Err(anyhow!(
+                "Each chain needs at least as many draws as bins to test rank uniformity"
+            ));
+        }
+        let mut counts = vec![0.0; num_bins];
+        for r in pooled_ranks[offset..offset + chain.len()].iter() {
+            let bin = (((r - 1.0) / total) * num_bins as f64) as usize;
+            counts[bin.min(num_bins - 1)] += 1.0;
+        }
+        offset += chain.len();
+        let expected = chain.len() as f64 / num_bins as f64;
+        let statistic: f64 = counts
+            .iter()
+            .map(|c| (c - expected) * (c - expected) / expected)
+            .sum();
+        results.push(RankUniformityTest {
+            statistic,
+            df: num_bins - 1,
+            p_value: chi_square_sf(statistic, (num_bins - 1) as f64),
+        });
+    }
+    Ok(results)
+}
+
+/// Mean of a single chain with its Monte Carlo standard error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainMean {
+    /// Name of the chain, as in
+    /// [`ChainSummary`](../stats/struct.ChainSummary.html#structfield.chain_name)
+    pub chain_name: String,
+    /// Mean of the chain's draws
+    pub mean: f64,
+    /// Monte Carlo standard error of the mean, from the chain's own
+    /// effective sample size
+    pub mcse: f64,
+}
+
+/// Comparison of the means of two chains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainPairTest {
+    /// Indices of the two chains
+    pub chains: (usize, usize),
+    /// Difference of the means in units of its standard error,
+    /// `(mean_a - mean_b) / sqrt(mcse_a^2 + mcse_b^2)`
+    pub z: f64,
+    /// Two sided normal tail probability of `z`
+    pub p_value: f64,
+}
+
+/// Per chain means and the pairwise tests of
+/// [`chain_mean_consistency`](fn.chain_mean_consistency.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainMeanConsistency {
+    /// Mean and MCSE of every chain, in the original order
+    pub chains: Vec<ChainMean>,
+    /// Test of every pair of chains `(a, b)` with `a < b`
+    pub pairs: Vec<ChainPairTest>,
+}
+
+impl ChainMeanConsistency {
+    /// Largest absolute z-score over the pairs, zero for a single chain.
+    pub fn max_abs_z(&self) -> f64 {
+        self.pairs.iter().map(|p| p.z.abs()).fold(0.0, f64::max)
+    }
+
+    /// Whether no pair of chains differs at level `alpha`, with a Bonferroni
+    /// correction for the number of pairs.
+    pub fn is_consistent(&self, alpha: f64) -> bool {
+        let num_pairs = self.pairs.len().max(1) as f64;
+        self.pairs.iter().all(|p| p.p_value >= alpha / num_pairs)
+    }
+}
+
+/// Computes the mean and Monte Carlo standard error of each chain separately
+/// and tests whether the chain means agree within their standard errors,
+/// with a z-score for every pair of chains.  With only a few chains this is
+/// easier to act on than R hat: a large z-score says which chains disagree
+/// and by how many standard errors, rather than a single number for all of
+/// them.
+///
+/// Each chain's MCSE comes from its own effective sample size, so chains
+/// that are each stuck in a different mode get small standard errors and
+/// large z-scores even though every one of them looks stationary.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn chain_mean_consistency(chains: &Array2) -> Result<ChainMeanConsistency, Error> {
+    let chain_names = (1..=chains.len()).map(|c| c.to_string()).collect();
+    chain_mean_consistency_named(chains, chain_names)
+}
+
+/// Tests the [consistency of the chain means](fn.chain_mean_consistency.html)
+/// of a parameter of the draws, over the chains at inverse temperature
+/// one, with the names of the chains.
+///
+/// # Arguments
+/// * `draws` - Draws containing the parameter
+/// * `name` - Name of the parameter
+pub fn chain_mean_consistency_from_draws(
+    draws: &Draws,
+    name: &str,
+) -> Result<ChainMeanConsistency, Error> {
+    let (chains, chain_names) = named_target_chains(draws, name)?;
+    chain_mean_consistency_named(&chains, chain_names)
+}
+
+fn chain_mean_consistency_named(
+    chains: &Array2,
+    chain_names: Vec<String>,
+) -> Result<ChainMeanConsistency, Error> {
+    if chains.is_empty() {
+        return Err(anyhow!("Need at least one chain"));
+    }
+    let means = chains
+        .iter()
+        .zip(chain_names)
+        .map(|(chain, chain_name)| {
+            let mcse = compute_estimated_mcse(&vec![chain.clone()])
+                .with_context(|| format!("Failed to compute the MCSE of chain {}", chain_name))?;
+            Ok(ChainMean {
+                chain_name,
+                mean: mean(chain)?,
+                mcse,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut pairs = Vec::new();
+    for a in 0..means.len() {
+        for b in a + 1..means.len() {
+            let se = (means[a].mcse.powi(2) + means[b].mcse.powi(2)).sqrt();
+            let z = (means[a].mean - means[b].mean) / se;
+            pairs.push(ChainPairTest {
+                chains: (a, b),
+                z,
+                p_value: chi_square_sf(z * z, 1.0),
+            });
+        }
+    }
+    Ok(ChainMeanConsistency {
+        chains: means,
+        pairs,
+    })
+}
+
+/// Modes of a parameter's density over the pooled draws and in each chain,
+/// see [`multimodality`](fn.multimodality.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Multimodality {
+    /// Modes of the density of the pooled draws, in increasing order
+    pub modes: Array1,
+    /// Number of modes of the density of each chain
+    pub chain_modes: Vec<usize>,
+    /// Index into `modes` of the pooled mode around the highest peak of
+    /// each chain's density, i.e. the mode the chain mostly samples
+    pub chain_basins: Vec<usize>,
+}
+
+impl Multimodality {
+    /// Number of different pooled modes the chains mostly sample.
+    pub fn num_occupied_modes(&self) -> usize {
+        let mut basins = self.chain_basins.clone();
+        basins.sort_unstable();
+        basins.dedup();
+        basins.len()
+    }
+
+    /// Whether the pooled draws have several modes while every chain has a
+    /// single one, and the chains sit in different modes: the signature of
+    /// chains stuck in different modes rather than a multimodal posterior
+    /// that each chain explores.
+    pub fn chains_stuck(&self) -> bool {
+        self.modes.len() > 1
+            && self.chain_modes.iter().all(|&n| n == 1)
+            && self.num_occupied_modes() > 1
+    }
+}
+
+/// Smallest fraction of the density a mode must hold to count, see
+/// [`multimodality`](fn.multimodality.html).
+const MIN_MODE_MASS: f64 = 0.05;
+
+/// Peaks of the kernel density estimate of sorted draws as `(location,
+/// height)` pairs, and the locations of the valleys between consecutive
+/// peaks.  Peaks separated by a valley whose depth is less than `min_dip`
+/// of the height of the lower peak are merged into the higher one, which
+/// leaves out the small bumps of the estimate, and so are peaks holding
+/// less than `MIN_MODE_MASS` of the density, e.g. isolated outliers.
+fn density_modes(sorted: &[f64], min_dip: f64) -> Result<(Vec<(f64, f64)>, Array1), Error> {
+    if sorted[0] == sorted[sorted.len() - 1] {
+        return Ok((vec![(sorted[0], 1.0)], Vec::new()));
+    }
+    let (lo, step, density) = kde_grid(sorted)?;
+    let last = density.len() - 1;
+    let mut peaks: Vec<usize> = (0..=last)
+        .filter(|&i| {
+            (i == 0 || density[i] > density[i - 1]) && (i == last || density[i] >= density[i + 1])
+        })
+        .collect();
+    let lowest = |from: usize, to: usize| {
+        (from..=to)
+            .min_by(|&a, &b| density[a].total_cmp(&density[b]))
+            .unwrap()
+    };
+    let mut valleys: Vec<usize> = peaks.windows(2).map(|w| lowest(w[0], w[1])).collect();
+    let total: f64 = density.iter().sum();
+    loop {
+        // the valley that is shallowest relative to the lower of its peaks
+        let shallowest = (0..valleys.len())
+            .map(|v| {
+                let lower = density[peaks[v]].min(density[peaks[v + 1]]);
+                (v, density[valleys[v]] / lower)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        // the peak with the least mass between the valleys around it, e.g.
+        // one made by a few outlying draws
+        let lightest = (0..peaks.len())
+            .map(|p| {
+                let from = if p == 0 { 0 } else { valleys[p - 1] };
+                let to = if p == valleys.len() { last } else { valleys[p] };
+                (p, density[from..=to].iter().sum::<f64>() / total)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let removed = match (shallowest, lightest) {
+            (Some((v, ratio)), _) if ratio > 1.0 - min_dip => {
+                if density[peaks[v]] < density[peaks[v + 1]] {
+                    v
+                } else {
+                    v + 1
+                }
+            }
+            (Some(_), Some((p, mass))) if mass < MIN_MODE_MASS => p,
+            _ => break,
+        };
+        peaks.remove(removed);
+        // the valleys on either side of the removed peak become one
+        if removed > 0 && removed < valleys.len() {
+            let (left, right) = (valleys[removed - 1], valleys[removed]);
+            valleys[removed - 1] = if density[left] <= density[right] {
+                left
+            } else {
+                right
+            };
+            valleys.remove(removed);
+        } else {
+            valleys.remove(removed.min(valleys.len() - 1));
+        }
+    }
+    let at = |i: usize| lo + i as f64 * step;
+    Ok((
+        peaks.iter().map(|&i| (at(i), density[i])).collect(),
+        valleys.iter().map(|&i| at(i)).collect(),
+    ))
+}
+
+/// Looks for chains stuck in different modes by comparing the modes of a
+/// parameter's density over the pooled draws with those of each chain.
+/// When every chain is unimodal but the chains sit in different modes of
+/// the pooled density, as in the classic picture of chains that never
+/// cross a low density region, R hat is large but only shows that the
+/// chains disagree; this shows how.  A posterior that is truly multimodal
+/// and explored by every chain has multimodal chains instead.
+///
+/// The densities are Gaussian kernel density estimates with Silverman's
+/// rule of thumb bandwidth, as in [`hdr`](../stats/fn.hdr.html), and two
+/// peaks only count as separate modes if the density dips between them by
+/// at least `min_dip` of the lower peak, a dip test in the spirit of
+/// Hartigan's, and each holds at least 5% of the draws.  This is a
+/// heuristic: bandwidths from the rule of thumb
+/// smooth over modes that are close together, so a unimodal result proves
+/// nothing.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `min_dip` - Smallest relative depth of the valley between two modes,
+///   in (0, 1), e.g. 0.5
+pub fn multimodality(chains: &Array2, min_dip: f64) -> Result<Multimodality, Error> {
+    if !(min_dip > 0.0 && min_dip < 1.0) {
+        return Err(anyhow!("Dip must be in (0, 1), got {}", min_dip));
+    }
+    if chains.is_empty() || chains.iter().any(|c| c.is_empty()) {
+        return Err(anyhow!("Need at least one draw in every chain"));
+    }
+    if chains.iter().flatten().any(|x| !x.is_finite()) {
+        return Err(anyhow!("Can't compute density of non-finite draws"));
+    }
+    let sort = |mut draws: Array1| {
+        draws.sort_by(|a, b| a.total_cmp(b));
+        draws
+    };
+    let (pooled, valleys) = density_modes(&sort(flatten(chains)), min_dip)?;
+    let mut chain_modes = Vec::with_capacity(chains.len());
+    let mut chain_basins = Vec::with_capacity(chains.len());
+    for (idx, chain) in chains.iter().enumerate() {
+        let (peaks, _) = density_modes(&sort(chain.clone()), min_dip)
+            .with_context(|| format!("Failed to find the modes of chain {}", idx + 1))?;
+        let highest = peaks.iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap().0;
+        chain_modes.push(peaks.len());
+        chain_basins.push(valleys.partition_point(|&v| v < highest));
+    }
+    Ok(Multimodality {
+        modes: pooled.iter().map(|p| p.0).collect(),
+        chain_modes,
+        chain_basins,
+    })
+}
+
+/// Names of the columns with the Hamiltonian energy of each draw, as written
+/// by Stan and Turing.jl.
+const ENERGY_COLUMNS: [&str; 2] = ["energy__", "hamiltonian_energy"];
+/// Names of the columns flagging divergent transitions, as written by Stan
+/// and Turing.jl.
+pub(crate) const DIVERGENT_COLUMNS: [&str; 2] = ["divergent__", "numerical_error"];
+/// Name of PyMC's sample stat with the Hamiltonian energy of each draw.
+const PYMC_ENERGY_COLUMN: &str = "energy";
+/// Name of PyMC's sample stat flagging divergent transitions.
+const PYMC_DIVERGENT_COLUMN: &str = "diverging";
+
+/// Position of the first of the given sampler columns in the draws, or of
+/// the PyMC sample stat in draws known to come from PyMC.
+fn find_sampler_column(draws: &Draws, columns: &[&str], pymc: &str) -> Option<usize> {
+    columns.iter().find_map(|c| draws.index_of(c)).or_else(|| {
+        if draws.sampler() == Sampler::PyMc {
+            draws.index_of(pymc)
+        } else {
+            None
+        }
+    })
+}
+
+/// Position of the column flagging divergent transitions, if any.
+pub(crate) fn divergent_column(draws: &Draws) -> Option<usize> {
+    find_sampler_column(draws, &DIVERGENT_COLUMNS, PYMC_DIVERGENT_COLUMN)
+}
+
+/// Limits used by [`verdict_with_thresholds`](fn.verdict_with_thresholds.html)
+/// and the HTML report to decide what to warn about.  The defaults follow
+/// the recommendations of Stan and Vehtari et al. (2021); teams with
+/// stricter standards can tighten them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// Largest acceptable rank normalized split R hat
+    pub rhat_max: f64,
+    /// Rank normalized split R hat above which the chains have clearly not converged,
+    /// rather than just needing to run longer
+    pub rhat_not_converged: f64,
+    /// Smallest acceptable bulk and tail effective sample size
+    pub ess_min: f64,
+    /// Smallest acceptable bulk ESS per draw, which flags samplers that mix
+    /// so slowly that the autocorrelation estimates themselves are doubtful
+    pub ess_relative_min: f64,
+    /// Smallest acceptable E-BFMI of a chain, see [`ebfmi`](fn.ebfmi.html)
+    pub ebfmi_min: f64,
+    /// Largest acceptable fraction of divergent transitions
+    pub divergence_rate_max: f64,
+    /// Smallest relative depth of the density valley between two modes for
+    /// the check for chains stuck in different modes, see
+    /// [`multimodality`](fn.multimodality.html); 0 or less, or 1 or more,
+    /// turns it off
+    pub mode_dip_min: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds {
+            rhat_max: 1.01,
+            rhat_not_converged: 1.1,
+            ess_min: 400.0,
+            ess_relative_min: 0.1,
+            ebfmi_min: 0.3,
+            divergence_rate_max: 0.0,
+            mode_dip_min: 0.5,
+        }
+    }
+}
+
+/// One reason for a verdict other than converged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Evidence {
+    /// Rank normalized split R hat above the threshold
+    HighRhat {
+        /// Name of the parameter
+        parameter: String,
+        /// Rank normalized split R hat of the parameter
+        rhat: f64,
+    },
+    /// Bulk effective sample size below the threshold
+    LowBulkEss {
+        /// Name of the parameter
+        parameter: String,
+        /// Bulk ESS of the parameter
+        ess: f64,
+    },
+    /// Tail effective sample size below the threshold
+    LowTailEss {
+        /// Name of the parameter
+        parameter: String,
+        /// Tail ESS of the parameter
+        ess: f64,
+    },
+    /// Bulk effective sample size per draw below the threshold
+    LowRelativeEss {
+        /// Name of the parameter
+        parameter: String,
+        /// Bulk ESS of the parameter over the number of draws
+        ratio: f64,
+    },
+    /// Some draws are `NaN` or infinite
+    NonFinite {
+        /// Name of the parameter
+        parameter: String,
+    },
+    /// Energy Bayesian fraction of missing information below the threshold
+    LowEbfmi {
+        /// Index of the chain among the chains that sample the target
+        chain: usize,
+        /// Name of the chain, see
+        /// [`Draws::chain_name`](../draws/struct.Draws.html#method.chain_name)
+        name: String,
+        /// E-BFMI of the chain
+        ebfmi: f64,
+    },
+    /// Every chain is unimodal but the chains sit in different modes of the
+    /// pooled density, see [`multimodality`](fn.multimodality.html)
+    Multimodal {
+        /// Name of the parameter
+        parameter: String,
+        /// Number of different modes the chains sit in
+        modes: usize,
+    },
+    /// More divergent transitions than the threshold allows
+    Divergences {
+        /// Number of divergent transitions
+        count: usize,
+        /// Fraction of transitions that diverged
+        rate: f64,
+    },
+}
+
+impl Evidence {
+    /// Kind, parameter, chain and value of the evidence for machine readable
+    /// output, e.g. the warnings table of an SQLite export.
+    pub(crate) fn columns(&self) -> (&'static str, Option<&str>, Option<usize>, Option<f64>) {
+        match self {
+            Evidence::HighRhat { parameter, rhat } => {
+                ("high_rhat", Some(parameter), None, Some(*rhat))
+            }
+            Evidence::LowBulkEss { parameter, ess } => {
+                ("low_bulk_ess", Some(parameter), None, Some(*ess))
+            }
+            Evidence::LowTailEss { parameter, ess } => {
+                ("low_tail_ess", Some(parameter), None, Some(*ess))
+            }
+            Evidence::LowRelativeEss { parameter, ratio } => {
+                ("low_relative_ess", Some(parameter), None, Some(*ratio))
+            }
+            Evidence::NonFinite { parameter } => ("non_finite", Some(parameter), None, None),
+            Evidence::LowEbfmi { chain, ebfmi, .. } => {
+                ("low_ebfmi", None, Some(*chain), Some(*ebfmi))
+            }
+            Evidence::Multimodal { parameter, modes } => {
+                ("multimodal", Some(parameter), None, Some(*modes as f64))
+            }
+            Evidence::Divergences { count, .. } => ("divergences", None, None, Some(*count as f64)),
+        }
+    }
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Evidence::HighRhat { parameter, rhat } => {
+                write!(
+                    f,
+                    "{}: rank normalized split R hat is {:.3}",
+                    parameter, rhat
+                )
+            }
+            Evidence::LowBulkEss { parameter, ess } => {
+                write!(f, "{}: bulk ESS is {:.0}", parameter, ess)
+            }
+            Evidence::LowTailEss { parameter, ess } => {
+                write!(f, "{}: tail ESS is {:.0}", parameter, ess)
+            }
+            Evidence::LowRelativeEss { parameter, ratio } => {
+                write!(f, "{}: bulk ESS per draw is {:.3}", parameter, ratio)
+            }
+            Evidence::NonFinite { parameter } => {
+                write!(f, "{}: some draws are not finite", parameter)
+            }
+            Evidence::LowEbfmi { name, ebfmi, .. } => {
+                write!(f, "chain {}: E-BFMI is {:.3}", name, ebfmi)
+            }
+            Evidence::Multimodal { parameter, modes } => {
+                write!(
+                    f,
+                    "{}: chains are stuck in {} different modes",
+                    parameter, modes
+                )
+            }
+            Evidence::Divergences { count, rate } => {
+                write!(f, "{} divergent transitions ({:.1}%)", count, 100.0 * rate)
+            }
+        }
+    }
+}
+
+/// Gate decision on whether a set of draws can be used, see
+/// [`verdict`](fn.verdict.html).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// Every check passed
+    Converged,
+    /// Some checks failed by a small margin, e.g. R hat slightly above 1.01
+    /// or too few effective draws; running longer usually helps
+    Suspect(Vec<Evidence>),
+    /// The chains disagree or the draws are broken, so the results can't be
+    /// used.  The evidence includes the milder problems as well.
+    NotConverged(Vec<Evidence>),
+}
+
+impl Verdict {
+    /// Whether every check passed.
+    pub fn is_converged(&self) -> bool {
+        *self == Verdict::Converged
+    }
+
+    /// The evidence behind the verdict, empty when converged.
+    pub fn evidence(&self) -> &[Evidence] {
+        match self {
+            Verdict::Converged => &[],
+            Verdict::Suspect(evidence) | Verdict::NotConverged(evidence) => evidence,
+        }
+    }
+
+    /// Lower case name of the verdict for machine readable output.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Verdict::Converged => "converged",
+            Verdict::Suspect(_) => "suspect",
+            Verdict::NotConverged(_) => "not converged",
+        }
+    }
+}
+
+/// Decides whether the draws have converged with the default
+/// [`Thresholds`](struct.Thresholds.html), for automated pipelines that only
+/// need a go or no-go decision, see
+/// [`verdict_with_thresholds`](fn.verdict_with_thresholds.html).
+///
+/// # Arguments
+/// * `draws` - Draws to check
+pub fn verdict(draws: &Draws) -> Result<Verdict, Error> {
+    verdict_with_thresholds(draws, &Thresholds::default())
+}
+
+/// Decides whether the draws have converged:
+///
+/// * not converged if any rank normalized split R hat, see
+///   [`rank_normalized_split_potential_scale_reduction_factor`](../rhat/fn.rank_normalized_split_potential_scale_reduction_factor.html),
+///   is above `rhat_not_converged` or any draw is not finite,
+/// * suspect if any rank normalized split R hat is above `rhat_max`, any
+///   bulk or tail ESS is below `ess_min`, any bulk ESS per draw is below
+///   `ess_relative_min`, any chain's E-BFMI is below `ebfmi_min`, there are
+///   more divergent transitions than `divergence_rate_max` allows or
+///   unimodal chains sit in different modes of a parameter, see
+///   [`multimodality`](fn.multimodality.html) and `mode_dip_min`,
+/// * converged otherwise.
+///
+/// The energy and divergences are read from the `energy__` and
+/// `divergent__` columns written by Stan, `hamiltonian_energy` and
+/// `numerical_error` from Turing.jl, or the `energy` and `diverging`
+/// sample stats of PyMC in draws tagged with
+/// [`Sampler::PyMc`](../draws/enum.Sampler.html), when present, and an
+/// energy that is not finite is an error.  Other sampler diagnostics such
+/// as `treedepth__` or `tree_depth` are left out, but the log density
+/// `lp__` or `lp` is checked.  Constant parameters are left out too, and so
+/// are tempered chains.
+///
+/// # Arguments
+/// * `draws` - Draws to check
+/// * `thresholds` - Limits for each check
+pub fn verdict_with_thresholds(draws: &Draws, thresholds: &Thresholds) -> Result<Verdict, Error> {
+    let mut evidence = Vec::new();
+    let mut not_converged = false;
+    for (idx, name) in draws.names().iter().enumerate() {
+        if is_sampler_column(name, draws.sampler()) && name != "lp__" && name != "lp" {
+            continue;
+        }
+        let chains = draws.target_parameter(idx);
+        let mut values = chains.iter().flatten();
+        if values.clone().any(|x| !x.is_finite()) {
+            evidence.push(Evidence::NonFinite {
+                parameter: name.clone(),
+            });
+            not_converged = true;
+            continue;
+        }
+        if let Some(first) = values.next() {
+            if values.all(|x| x == first) {
+                continue;
+            }
+        }
+        let rhat = rank_normalized_split_potential_scale_reduction_factor(&chains)
+            .with_context(|| format!("Failed to compute R hat of {}", name))?;
+        if rhat > thresholds.rhat_max {
+            evidence.push(Evidence::HighRhat {
+                parameter: name.clone(),
+                rhat,
+            });
+        }
+        not_converged |= rhat > thresholds.rhat_not_converged;
+        // the tail ESS is undefined when a tail quantile is the smallest or
+        // largest value, as for many discrete parameters
+        let (bulk, tail) = match compute_bulk_tail_ess(&chains) {
+            Ok(ess) => (ess.bulk, Some(ess.tail)),
+            Err(_) => (
+                compute_bulk_effective_sample_size(&chains)
+                    .with_context(|| format!("Failed to compute ESS of {}", name))?,
+                None,
+            ),
+        };
+        if bulk < thresholds.ess_min {
+            evidence.push(Evidence::LowBulkEss {
+                parameter: name.clone(),
+                ess: bulk,
+            });
+        }
+        if let Some(tail) = tail.filter(|&ess| ess < thresholds.ess_min) {
+            evidence.push(Evidence::LowTailEss {
+                parameter: name.clone(),
+                ess: tail,
+            });
+        }
+        let ratio = bulk / flatten(&chains).len() as f64;
+        if ratio < thresholds.ess_relative_min {
+            evidence.push(Evidence::LowRelativeEss {
+                parameter: name.clone(),
+                ratio,
+            });
+        }
+        if chains.len() > 1 && thresholds.mode_dip_min > 0.0 && thresholds.mode_dip_min < 1.0 {
+            let modes = multimodality(&chains, thresholds.mode_dip_min)
+                .with_context(|| format!("Failed to find the modes of {}", name))?;
+            if modes.chains_stuck() {
+                evidence.push(Evidence::Multimodal {
+                    parameter: name.clone(),
+                    modes: modes.num_occupied_modes(),
+                });
+            }
+        }
+    }
+    if let Some(idx) = find_sampler_column(draws, &ENERGY_COLUMNS, PYMC_ENERGY_COLUMN) {
+        for (chain, energy) in draws.target_parameter(idx).iter().enumerate() {
+            let name = draws.target_chain_name(chain);
+            let value = ebfmi(energy)
+                .with_context(|| format!("Failed to compute E-BFMI of chain {}", name))?;
+            if value < thresholds.ebfmi_min {
+                evidence.push(Evidence::LowEbfmi {
+                    chain,
+                    name,
+                    ebfmi: value,
+                });
+            }
+        }
+    }
+    if let Some(idx) = divergent_column(draws) {
+        let divergent = flatten(&draws.target_parameter(idx));
+        let count = divergent.iter().filter(|&&x| x > 0.0).count();
+        let rate = count as f64 / divergent.len().max(1) as f64;
+        if rate > thresholds.divergence_rate_max {
+            evidence.push(Evidence::Divergences { count, rate });
+        }
+    }
+    Ok(if not_converged {
+        Verdict::NotConverged(evidence)
+    } else if evidence.is_empty() {
+        Verdict::Converged
+    } else {
+        Verdict::Suspect(evidence)
+    })
+}
+
+/// Computes the energy Bayesian fraction of missing information (E-BFMI) of
+/// one chain of a Hamiltonian Monte Carlo sampler, the ratio of the mean
+/// squared change in energy between draws to the variance of the energy.
+/// Values below 0.3 mean the momentum resampling explores the energy
+/// distribution poorly, usually because of heavy tails, and the sampler may
+/// miss parts of the posterior.
+///
+/// See Betancourt (2016)
+/// ["Diagnosing Suboptimal Cotangent Disintegrations in Hamiltonian Monte Carlo"](https://arxiv.org/abs/1604.00695).
+///
+/// # Arguments
+/// * `energy` - Hamiltonian energy of each draw of a chain, e.g. Stan's
+///   `energy__`, which must all be finite
+pub fn ebfmi(energy: &[f64]) -> Result<f64, Error> {
+    if energy.len() < 2 {
+        return Err(anyhow!("Need at least 2 draws to compute E-BFMI"));
+    }
+    if energy.iter().any(|e| !e.is_finite()) {
+        return Err(anyhow!("All energies must be finite to compute E-BFMI"));
+    }
+    let center = energy.iter().sum::<f64>() / energy.len() as f64;
+    let variance: f64 = energy.iter().map(|e| (e - center).powi(2)).sum();
+    if variance <= 0.0 {
+        return Err(anyhow!("No E-BFMI when the energy is constant"));
+    }
+    let squared_changes: f64 = energy.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+    Ok(squared_changes / variance)
+}
+
+/// Fits a generalized Pareto distribution to sorted positive exceedances
+/// with the empirical Bayes method of Zhang and Stephens (2009), returning
+/// the shape and scale.  The shape is shrunk slightly towards 0.5 with a
+/// weakly informative prior, as in the R packages loo and posterior.
+pub(crate) fn gpdfit(x: &[f64]) -> (f64, f64) {
+    let n = x.len();
+    let prior = 3.0;
+    let m = 30 + (n as f64).sqrt().floor() as usize;
+    let x_star = x[(n as f64 / 4.0 + 0.5).floor() as usize - 1];
+    let theta: Array1 = (1..=m)
+        .map(|j| 1.0 / x[n - 1] + (1.0 - (m as f64 / (j as f64 - 0.5)).sqrt()) / prior / x_star)
+        .collect();
+    let profile: Array1 = theta
+        .iter()
+        .map(|&t| {
+            let k = x.iter().map(|&xi| (-t * xi).ln_1p()).sum::<f64>() / n as f64;
+            n as f64 * ((-t / k).ln() - k - 1.0)
+        })
+        .collect();
+    let max = profile.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let weights: Array1 = profile.iter().map(|l| (l - max).exp()).collect();
+    let total: f64 = weights.iter().sum();
+    let theta_hat: f64 = theta
+        .iter()
+        .zip(weights.iter())
+        .map(|(t, w)| t * w / total)
+        .sum();
+    let k = x.iter().map(|&xi| (-theta_hat * xi).ln_1p()).sum::<f64>() / n as f64;
+    let sigma = -k / theta_hat;
+    // weakly informative prior shrinking towards 0.5
+    let a = 10.0;
+    let k = k * n as f64 / (n as f64 + a) + a * 0.5 / (n as f64 + a);
+    (if k.is_nan() { f64::INFINITY } else { k }, sigma)
+}
+
+/// Minimum sample size for a reliable Pareto smoothed estimate.
+fn min_sample_size(khat: f64) -> f64 {
+    if khat < 1.0 {
+        10f64.powf(1.0 / (1.0 - khat.max(0.0)))
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// Relative convergence rate of the Pareto smoothed estimate of the mean,
+/// using the smooth approximation from the appendix of the PSIS paper.
+fn convergence_rate(khat: f64, num_draws: f64) -> f64 {
+    let s = num_draws;
+    if khat < 0.0 {
+        1.0
+    } else if khat > 1.0 {
+        0.0
+    } else if khat == 0.5 {
+        1.0 - 1.0 / s.ln()
+    } else {
+        let rate = (2.0 * (khat - 1.0) * s.powf(2.0 * khat + 1.0)
+            + (1.0 - 2.0 * khat) * s.powf(2.0 * khat)
+            + s * s)
+            / ((s - 1.0) * (s - s.powf(2.0 * khat)));
+        rate.max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draws::ChainLabel;
+    use crate::utils::normal_draws;
+    #[cfg(feature = "fs")]
+    use crate::utils::read_csv;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    fn draws(shift: f64, num_draws: usize) -> Draws {
+        let names = vec![
+            "mu".to_string(),
+            "sigma".to_string(),
+            "fixed".to_string(),
+            "treedepth__".to_string(),
+        ];
+        let chains = (0..4)
+            .map(|c| {
+                vec![
+                    normal_draws(num_draws, 2 * c)
+                        .iter()
+                        .map(|x| x + shift * c as f64)
+                        .collect(),
+                    normal_draws(num_draws, 2 * c + 1),
+                    vec![1.0; num_draws],
+                    vec![c as f64; num_draws],
+                ]
+            })
+            .collect();
+        Draws::from_chains(names, chains).unwrap()
+    }
+
+    #[test]
+    fn test_verdict() {
+        // the constant and the sampler column don't count
+        let converged = verdict(&draws(0.0, 1000)).unwrap();
+        assert!(converged.is_converged());
+        assert!(converged.evidence().is_empty());
+
+        match verdict(&draws(0.0, 60)).unwrap() {
+            Verdict::Suspect(evidence) => {
+                assert!(evidence.contains(&Evidence::LowBulkEss {
+                    parameter: "mu".to_string(),
+                    ess: compute_bulk_effective_sample_size(&draws(0.0, 60).parameter(0)).unwrap(),
+                }));
+                assert!(evidence
+                    .iter()
+                    .all(|e| !matches!(e, Evidence::HighRhat { rhat, .. } if *rhat > 1.1)));
+            }
+            other => panic!("expected suspect, got {:?}", other),
+        }
+
+        let shifted = verdict(&draws(1.0, 1000)).unwrap();
+        match shifted {
+            Verdict::NotConverged(ref evidence) => assert!(matches!(
+                &evidence[0],
+                Evidence::HighRhat { parameter, rhat } if parameter == "mu" && *rhat > 1.1
+            )),
+            ref other => panic!("expected not converged, got {:?}", other),
+        }
+        assert!(!shifted.is_converged());
+
+        let mut broken = self::draws(0.0, 1000);
+        broken.derive("inv", "1 / (mu - mu)").unwrap();
+        assert_eq!(
+            verdict(&broken).unwrap(),
+            Verdict::NotConverged(vec![Evidence::NonFinite {
+                parameter: "inv".to_string()
+            }])
+        );
+    }
+
+    #[test]
+    fn test_verdict_with_thresholds() {
+        let mut draws = draws(0.0, 1000);
+        // a random walk energy in the first chain has a tiny E-BFMI
+        let energy = (0..4)
+            .map(|c| {
+                let noise = normal_draws(1000, 100 + c);
+                if c > 0 {
+                    return noise;
+                }
+                noise
+                    .iter()
+                    .scan(0.0, |sum, x| {
+                        *sum += x;
+                        Some(*sum)
+                    })
+                    .collect()
+            })
+            .collect();
+        draws.add_parameter("energy__", energy).unwrap();
+        let mut divergent = vec![vec![0.0; 1000]; 4];
+        divergent[2][10] = 1.0;
+        divergent[3][500] = 1.0;
+        draws.add_parameter("divergent__", divergent).unwrap();
+
+        let evidence = match verdict(&draws).unwrap() {
+            Verdict::Suspect(evidence) => evidence,
+            other => panic!("expected suspect, got {:?}", other),
+        };
+        assert_eq!(evidence.len(), 2);
+        assert!(matches!(evidence[0], Evidence::LowEbfmi { chain: 0, ebfmi, .. } if ebfmi < 0.1));
+        assert_eq!(
+            evidence[1],
+            Evidence::Divergences {
+                count: 2,
+                rate: 0.0005
+            }
+        );
+        assert_eq!(evidence[1].to_string(), "2 divergent transitions (0.1%)");
+
+        // messages name the chain by its label
+        assert_eq!(evidence[0].to_string().split(':').next(), Some("chain 1"));
+        draws
+            .set_chain_label(0, ChainLabel::new("worker-7"))
+            .unwrap();
+        let evidence = match verdict(&draws).unwrap() {
+            Verdict::Suspect(evidence) => evidence,
+            other => panic!("expected suspect, got {:?}", other),
+        };
+        assert!(evidence[0]
+            .to_string()
+            .starts_with("chain worker-7: E-BFMI"));
+
+        let lenient = Thresholds {
+            ebfmi_min: 0.0,
+            divergence_rate_max: 0.001,
+            ..Thresholds::default()
+        };
+        assert!(verdict_with_thresholds(&draws, &lenient)
+            .unwrap()
+            .is_converged());
+
+        let strict = Thresholds {
+            ess_min: 10_000.0,
+            ess_relative_min: 2.0,
+            ..lenient
+        };
+        let evidence = verdict_with_thresholds(&draws, &strict).unwrap();
+        assert_eq!(evidence.evidence().len(), 6);
+        assert!(evidence.evidence().iter().any(|e| matches!(
+            e,
+            Evidence::LowRelativeEss { parameter, ratio } if parameter == "sigma" && *ratio < 2.0
+        )));
+
+        // a NaN energy fails the verdict rather than passing the check
+        let idx = draws.index_of("energy__").unwrap();
+        let mut energy = draws.parameter(idx).into_owned();
+        energy[1][3] = f64::NAN;
+        let mut broken = self::draws(0.0, 1000);
+        broken.add_parameter("energy__", energy).unwrap();
+        assert!(verdict_with_thresholds(&broken, &lenient).is_err());
+    }
+
+    #[test]
+    fn test_verdict_pymc_sample_stats() {
+        // sample stats of a PyMC trace, with a tree depth that differs
+        // between chains and would fail R hat if it were checked
+        let mut draws = draws(0.0, 1000);
+        let tree_depth = (0..4).map(|c| vec![c as f64; 1000]).collect();
+        draws.add_parameter("tree_depth", tree_depth).unwrap();
+        let energy = (0..4)
+            .map(|c| {
+                let noise = normal_draws(1000, 100 + c);
+                if c > 0 {
+                    return noise;
+                }
+                noise
+                    .iter()
+                    .scan(0.0, |sum, x| {
+                        *sum += x;
+                        Some(*sum)
+                    })
+                    .collect()
+            })
+            .collect();
+        draws.add_parameter("energy", energy).unwrap();
+        let mut diverging = vec![vec![0.0; 1000]; 4];
+        diverging[1][3] = 1.0;
+        diverging[2][10] = 1.0;
+        draws.add_parameter("diverging", diverging).unwrap();
+
+        // without knowing the draws come from PyMC, energy is a parameter
+        // whose chains disagree
+        let evidence = verdict(&draws).unwrap().evidence().to_vec();
+        assert!(evidence.iter().any(|e| matches!(
+            e,
+            Evidence::HighRhat { parameter, .. } if parameter == "energy"
+        )));
+        assert!(!evidence
+            .iter()
+            .any(|e| matches!(e, Evidence::LowEbfmi { .. } | Evidence::Divergences { .. })));
+
+        draws.set_sampler(Sampler::PyMc);
+        let evidence = verdict(&draws).unwrap().evidence().to_vec();
+        assert_eq!(evidence.len(), 2);
+        assert!(matches!(evidence[0], Evidence::LowEbfmi { chain: 0, .. }));
+        assert!(matches!(
+            evidence[1],
+            Evidence::Divergences { count: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_ebfmi() {
+        // independent draws have a mean squared change of twice the variance
+        assert_abs_diff_eq!(
+            ebfmi(&normal_draws(100_000, 3)).unwrap(),
+            2.0,
+            epsilon = 0.02
+        );
+        assert_abs_diff_eq!(ebfmi(&[0.0, 1.0, 0.0, 1.0]).unwrap(), 3.0);
+        assert!(ebfmi(&[1.0]).is_err());
+        assert!(ebfmi(&[1.0, 1.0]).is_err());
+        assert!(ebfmi(&[0.0, f64::NAN, 1.0]).is_err());
+        assert!(ebfmi(&[0.0, f64::INFINITY, 1.0]).is_err());
+    }
+
+    #[test]
+    fn test_draws_needed() {
+        let chains = vec![normal_draws(1000, 1), normal_draws(1000, 2)];
+        let needed = draws_needed(&chains, 10_000.0).unwrap();
+        assert_abs_diff_eq!(
+            needed.bulk_ess,
+            compute_bulk_effective_sample_size(&chains).unwrap()
+        );
+        let expected = (10_000.0 * 1000.0 / needed.bulk_ess - 1000.0f64).ceil() as usize;
+        assert_eq!(needed.bulk, expected);
+        assert_eq!(needed.additional, needed.bulk.max(needed.tail));
+        // independent draws need roughly target / chains per chain in total
+        assert!(needed.bulk > 3000 && needed.bulk < 5000);
+
+        let reached = draws_needed(&chains, 10.0).unwrap();
+        assert_eq!(reached.additional, 0);
+        assert!(draws_needed(&chains, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_recommend_run_length() {
+        let draws = draws(0.0, 1000);
+        let lengths = recommend_run_length(&draws, 0.01).unwrap();
+        // the constant and the sampler column are left out
+        assert_eq!(lengths.len(), 2);
+        let mu = &lengths[0];
+        assert_eq!(mu.parameter, "mu");
+        // independent draws have tau near one, so about 1 / (4 * 0.01^2)
+        assert_abs_diff_eq!(mu.tau, 1.0, epsilon = 0.2);
+        assert_abs_diff_eq!(mu.mcse_ratio, (mu.tau / 4000.0).sqrt());
+        assert_eq!(mu.draws_per_chain, (mu.tau / 4.0 / 1e-4).ceil() as usize);
+        assert_eq!(mu.additional, mu.draws_per_chain - 1000);
+        assert!(recommend_run_length(&draws, 0.5)
+            .unwrap()
+            .iter()
+            .all(|l| l.additional == 0));
+        assert!(recommend_run_length(&draws, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_gpdfit_recovers_shape() {
+        // exact quantiles of a generalized Pareto with shape 0.7 and scale 2
+        let n = 2000;
+        let (k, sigma) = (0.7, 2.0);
+        let x: Array1 = (1..=n)
+            .map(|i| {
+                let p = (i as f64 - 0.5) / n as f64;
+                sigma / k * ((1.0 - p).powf(-k) - 1.0)
+            })
+            .collect();
+        let (k_hat, sigma_hat) = gpdfit(&x);
+        assert_abs_diff_eq!(k_hat, k, epsilon = 0.05);
+        assert_abs_diff_eq!(sigma_hat, sigma, epsilon = 0.2);
+    }
+
+    #[test]
+    fn test_pareto_diags_normal_and_cauchy() {
+        let chains = vec![normal_draws(1000, 1), normal_draws(1000, 2)];
+        let diags = pareto_diags(&chains).unwrap();
+        assert!(diags.khat < 0.3);
+        assert_abs_diff_eq!(diags.khat_threshold, 1.0 - 1.0 / 2000f64.log10());
+        assert!(diags.convergence_rate > 0.9);
+
+        // ratios of normals are Cauchy with shape 1 in both tails
+        let z = normal_draws(8000, 3);
+        let cauchy: Array2 = z
+            .chunks(4000)
+            .map(|c| {
+                c[..2000]
+                    .iter()
+                    .zip(&c[2000..])
+                    .map(|(a, b)| a / b)
+                    .collect()
+            })
+            .collect();
+        let diags = pareto_diags(&cauchy).unwrap();
+        assert!(diags.khat > 0.7);
+        assert!(diags.khat > diags.khat_threshold);
+        let right = ParetoOptions {
+            tail: Tail::Right,
+            r_eff: Some(1.0),
+            ..ParetoOptions::default()
+        };
+        assert!(pareto_diags_with_options(&cauchy, &right).unwrap().khat <= diags.khat);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_pareto_diags_posterior() {
+        // Reference values of pareto_khat and pareto_diags in the R package
+        // posterior 1.5 for lp__, d, sigmasq_delta and mu.1 of the blocker
+        // chains, computed with a line by line transcription of its R code
+        // to double precision
+        // https://github.com/stan-dev/posterior/blob/v1.5.0/R/pareto_smooth.R
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+
+        let expected = [
+            (0, -0.20331925428766, -0.46547148825561, 10.0),
+            (4, -0.05471834956146, -0.09077738979799, 10.0),
+            (5, 0.10611524438573, 0.10611524438573, 13.14354282252834),
+            (6, 0.00906681597829, -0.17060873663307, 10.21291636225331),
+        ];
+        for &(param, khat, right_khat, min_ss) in expected.iter() {
+            let chains = vec![samples1[param].clone(), samples2[param].clone()];
+            let diags = pareto_diags(&chains).unwrap();
+            assert_abs_diff_eq!(diags.khat, khat, epsilon = 1e-10);
+            assert_abs_diff_eq!(diags.min_ss, min_ss, epsilon = 1e-8);
+            let right = ParetoOptions {
+                tail: Tail::Right,
+                ..ParetoOptions::default()
+            };
+            assert_abs_diff_eq!(
+                pareto_diags_with_options(&chains, &right).unwrap().khat,
+                right_khat,
+                epsilon = 1e-10
+            );
+        }
+    }
+
+    #[test]
+    fn test_pareto_diagnostics_formulas() {
+        assert_abs_diff_eq!(min_sample_size(-0.2), 10.0);
+        assert_abs_diff_eq!(min_sample_size(0.5), 100.0, epsilon = 1e-9);
+        assert!(min_sample_size(1.0).is_infinite());
+        assert_abs_diff_eq!(convergence_rate(-0.1, 1000.0), 1.0);
+        assert_abs_diff_eq!(convergence_rate(1.2, 1000.0), 0.0);
+        assert_abs_diff_eq!(convergence_rate(0.5, 1000.0), 1.0 - 1.0 / 1000f64.ln());
+        // the approximation tends to the value at 0.5 up to a 1 / (S - 1)
+        // term, and to one at zero
+        assert_abs_diff_eq!(
+            convergence_rate(0.5 + 1e-5, 1000.0),
+            convergence_rate(0.5, 1000.0) + 1.0 / 999.0,
+            epsilon = 1e-4
+        );
+        assert_abs_diff_eq!(convergence_rate(0.0, 1000.0), 1.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_rank_uniformity_test() {
+        let chains: Array2 = (0..4).map(|seed| normal_draws(1000, seed)).collect();
+        let results = rank_uniformity_test(&chains, 20).unwrap();
+        assert_eq!(results.len(), 4);
+        for result in results.iter() {
+            assert_eq!(result.df, 19);
+            assert!(result.p_value > 0.001);
+        }
+
+        // a shifted chain piles up in the top bins
+        let mut shifted = chains.clone();
+        shifted[3] = shifted[3].iter().map(|x| x + 1.0).collect();
+        let results = rank_uniformity_test(&shifted, 20).unwrap();
+        assert!(results[3].p_value < 1e-10);
+
+        // tied draws share their average rank
+        let discrete = vec![vec![0.0, 1.0, 0.0, 1.0], vec![1.0, 0.0, 1.0, 0.0]];
+        let results = rank_uniformity_test(&discrete, 2).unwrap();
+        assert_abs_diff_eq!(results[0].statistic, 0.0);
+
+        assert!(rank_uniformity_test(&chains, 1).is_err());
+        assert!(rank_uniformity_test(&vec![vec![1.0, 2.0]], 5).is_err());
+    }
+
+    #[test]
+    fn test_chain_mean_consistency() {
+        let chains: Array2 = (0..3).map(|seed| normal_draws(1000, seed)).collect();
+        let result = chain_mean_consistency(&chains).unwrap();
+        assert_eq!(result.chains.len(), 3);
+        assert_eq!(result.pairs.len(), 3);
+        assert_eq!(result.pairs[2].chains, (1, 2));
+        // iid draws: the MCSE is close to 1 / sqrt(n)
+        assert_abs_diff_eq!(result.chains[0].mcse, 1.0 / 1000f64.sqrt(), epsilon = 0.01);
+        assert!(result.is_consistent(0.01));
+
+        let mut shifted = chains.clone();
+        shifted[1] = shifted[1].iter().map(|x| x + 0.5).collect();
+        let result = chain_mean_consistency(&shifted).unwrap();
+        assert!(result.pairs[0].z < -5.0);
+        assert!(result.pairs[2].z > 5.0);
+        assert!(result.pairs[1].z.abs() < 4.0);
+        assert_abs_diff_eq!(
+            result.max_abs_z(),
+            result.pairs[0].z.abs().max(result.pairs[2].z.abs())
+        );
+        assert!(!result.is_consistent(0.01));
+
+        assert!(chain_mean_consistency(&vec![]).is_err());
+        assert!(chain_mean_consistency(&vec![vec![1.0, 2.0]]).is_err());
+
+        let names = vec!["mu".to_string()];
+        let mut draws =
+            Draws::from_chains(names, shifted.into_iter().map(|c| vec![c]).collect()).unwrap();
+        draws.set_chain_label(1, ChainLabel::new("b")).unwrap();
+        let named = chain_mean_consistency_from_draws(&draws, "mu").unwrap();
+        assert_eq!(named.chains[0].chain_name, "1");
+        assert_eq!(named.chains[1].chain_name, "b");
+        assert_eq!(named.pairs, result.pairs);
+        let mut short =
+            Draws::from_chains(vec!["mu".to_string()], vec![vec![vec![1.0, 2.0]]]).unwrap();
+        short.set_chain_label(0, ChainLabel::new("a")).unwrap();
+        let err = chain_mean_consistency_from_draws(&short, "mu").unwrap_err();
+        assert!(format!("{:#}", err).contains("chain a"));
+    }
+
+    #[test]
+    fn test_multimodality() {
+        let shifted = |seed: u64, shift: f64| -> Array1 {
+            normal_draws(1000, seed).iter().map(|x| x + shift).collect()
+        };
+        let mixing: Array2 = (0..4).map(|seed| shifted(seed, 0.0)).collect();
+        let result = multimodality(&mixing, 0.5).unwrap();
+        assert_eq!(result.modes.len(), 1);
+        assert_eq!(result.chain_modes, vec![1; 4]);
+        assert!(!result.chains_stuck());
+
+        // two chains around -4 and two around 4
+        let stuck: Array2 = (0..4)
+            .map(|seed| shifted(seed, if seed < 2 { -4.0 } else { 4.0 }))
+            .collect();
+        let result = multimodality(&stuck, 0.5).unwrap();
+        assert_eq!(result.modes.len(), 2);
+        assert_abs_diff_eq!(result.modes[0], -4.0, epsilon = 0.5);
+        assert_abs_diff_eq!(result.modes[1], 4.0, epsilon = 0.5);
+        assert_eq!(result.chain_basins, vec![0, 0, 1, 1]);
+        assert!(result.chains_stuck());
+
+        // every chain visits both modes
+        let exploring: Array2 = stuck
+            .iter()
+            .map(|c| {
+                c.iter()
+                    .enumerate()
+                    .map(|(i, x)| if i % 2 == 0 { -x } else { *x })
+                    .collect()
+            })
+            .collect();
+        let result = multimodality(&exploring, 0.5).unwrap();
+        assert_eq!(result.modes.len(), 2);
+        assert_eq!(result.chain_modes, vec![2; 4]);
+        assert!(!result.chains_stuck());
+
+        // a constant chain has a single mode
+        let result = multimodality(&vec![vec![-4.0; 300], shifted(1, 4.0)], 0.5).unwrap();
+        assert_eq!(result.chain_modes, vec![1, 1]);
+        assert!(result.chains_stuck());
+
+        assert!(multimodality(&stuck, 0.0).is_err());
+        assert!(multimodality(&vec![vec![]], 0.5).is_err());
+        assert!(multimodality(&vec![vec![1.0, f64::NAN]], 0.5).is_err());
+    }
+
+    #[test]
+    fn test_verdict_chains_in_different_modes() {
+        let chains = (0..4)
+            .map(|c| {
+                let shift = if c < 2 { -4.0 } else { 4.0 };
+                vec![normal_draws(500, c).iter().map(|x| x + shift).collect()]
+            })
+            .collect();
+        let draws = Draws::from_chains(vec!["theta".to_string()], chains).unwrap();
+        let verdict = verdict(&draws).unwrap();
+        let multimodal = Evidence::Multimodal {
+            parameter: "theta".to_string(),
+            modes: 2,
+        };
+        assert!(verdict.evidence().contains(&multimodal));
+        assert_eq!(
+            multimodal.to_string(),
+            "theta: chains are stuck in 2 different modes"
+        );
+
+        for dip in [1.0, 0.0, -0.5].iter() {
+            let off = Thresholds {
+                mode_dip_min: *dip,
+                ..Thresholds::default()
+            };
+            let verdict = verdict_with_thresholds(&draws, &off).unwrap();
+            assert!(!verdict
+                .evidence()
+                .iter()
+                .any(|e| matches!(e, Evidence::Multimodal { .. })));
+        }
+    }
+
+    #[test]
+    fn test_pareto_diags_errors() {
+        assert!(pareto_diags(&vec![vec![1.0, 2.0, 3.0]]).is_err());
+        assert!(pareto_diags(&vec![vec![1.0; 100]]).is_err());
+        assert!(pareto_diags(&vec![vec![1.0, 2.0, 3.0, 4.0, 5.0, f64::NAN]]).is_err());
+    }
+}
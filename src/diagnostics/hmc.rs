@@ -0,0 +1,835 @@
+use super::divergent_column;
+use crate::draws::{Draws, Sampler};
+use crate::utils::{flatten, mean, sample_variance, Rng};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Error, Result};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Names of the columns with the step size of each draw, as written by Stan,
+/// and by Turing.jl and PyMC.
+const STEP_SIZE_COLUMNS: [&str; 2] = ["stepsize__", "step_size"];
+/// Names of the columns with the NUTS tree depth of each draw, as written by
+/// Stan, and by Turing.jl and PyMC.
+const TREE_DEPTH_COLUMNS: [&str; 2] = ["treedepth__", "tree_depth"];
+/// Largest NUTS tree depth accepted, far beyond the maximum of any sampler
+/// as a tree of this depth would take `2^64 - 1` leapfrog steps.
+const MAX_TREE_DEPTH: usize = 64;
+/// Sampler columns written by Turing.jl, which unlike Stan's don't end in
+/// two underscores.
+const TURING_COLUMNS: [&str; 12] = [
+    "lp",
+    "n_steps",
+    "is_accept",
+    "acceptance_rate",
+    "log_density",
+    "hamiltonian_energy",
+    "hamiltonian_energy_error",
+    "max_hamiltonian_energy_error",
+    "tree_depth",
+    "numerical_error",
+    "step_size",
+    "nom_step_size",
+];
+/// Variables of the `sample_stats` group of PyMC's NUTS traces in ArviZ
+/// format, besides those shared with Turing.jl.  A trace flattened into a
+/// table, e.g. with `idata.sample_stats.to_dataframe()`, has one column
+/// each.  Names such as `energy` are common for parameters too, so they
+/// only count as sampler columns in draws known to come from PyMC.
+const PYMC_COLUMNS: [&str; 13] = [
+    "diverging",
+    "energy",
+    "energy_error",
+    "max_energy_error",
+    "step_size_bar",
+    "reached_max_treedepth",
+    "index_in_trajectory",
+    "largest_eigval",
+    "smallest_eigval",
+    "perf_counter_diff",
+    "perf_counter_start",
+    "process_time_diff",
+    "warning",
+];
+
+/// Whether a column of draws written by the given sampler holds a sampler
+/// diagnostic rather than a parameter.
+pub(crate) fn is_sampler_column(name: &str, sampler: Sampler) -> bool {
+    name.ends_with("__")
+        || TURING_COLUMNS.contains(&name)
+        || (sampler == Sampler::PyMc && PYMC_COLUMNS.contains(&name))
+}
+
+/// Warmup schedule of the adaptation, following Stan's windowed adaptation:
+/// a fast initial buffer in which only the step size is adapted, slow
+/// windows that double in size at the end of each of which the inverse
+/// metric is updated, and a fast terminal buffer in which the step size is
+/// adapted to the final metric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptationOptions {
+    /// Draws in the initial fast buffer
+    pub init_buffer: usize,
+    /// Draws in the terminal fast buffer
+    pub term_buffer: usize,
+    /// Draws in the first slow window
+    pub base_window: usize,
+    /// Largest acceptable relative change of the variance of a parameter
+    /// between the last two slow windows, in either direction, e.g. 0.5 for
+    /// ratios between 1 / 1.5 and 1.5
+    pub max_metric_change: f64,
+    /// Largest acceptable relative difference between the final step size
+    /// and the mean step size over the second half of the terminal buffer
+    pub max_step_size_change: f64,
+}
+
+impl Default for AdaptationOptions {
+    fn default() -> AdaptationOptions {
+        AdaptationOptions {
+            init_buffer: 75,
+            term_buffer: 50,
+            base_window: 25,
+            max_metric_change: 0.5,
+            max_step_size_change: 0.2,
+        }
+    }
+}
+
+/// Adaptation trajectory of one chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainAdaptation {
+    /// Step size of every warmup draw
+    pub step_size: Array1,
+    /// Step size used after warmup
+    pub final_step_size: f64,
+    /// Relative difference between the final step size and the mean step
+    /// size over the second half of the terminal buffer
+    pub step_size_change: f64,
+    /// Variance of every parameter in each slow window,
+    /// `window_variances[window][parameter]`, which is what the diagonal
+    /// inverse metric is estimated from
+    pub window_variances: Array2,
+    /// Ratio of the variance of every parameter in the last slow window to
+    /// the one before, empty with fewer than two slow windows
+    pub metric_ratio: Array1,
+    /// Whether the step size and the inverse metric had settled by the end
+    /// of warmup
+    pub settled: bool,
+}
+
+/// Adaptation diagnostics of all chains, see
+/// [`adaptation_diagnostics`](fn.adaptation_diagnostics.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptationReport {
+    /// Names of the parameters the variances refer to
+    pub parameters: Vec<String>,
+    /// Draws of each slow window
+    pub windows: Vec<Range<usize>>,
+    /// Trajectory of each chain
+    pub chains: Vec<ChainAdaptation>,
+}
+
+impl AdaptationReport {
+    /// Whether the adaptation had settled in every chain.
+    pub fn settled(&self) -> bool {
+        self.chains.iter().all(|c| c.settled)
+    }
+}
+
+/// Computes the slow adaptation windows of a warmup of `num_warmup` draws
+/// the way Stan's `windowed_adaptation` does.  When the buffers and the
+/// first window don't fit, they are shrunk to 15%, 10% and 75% of the
+/// warmup, and warmups shorter than 20 draws have no slow windows at all.
+/// Every window after the first is twice as long as the one before, and is
+/// stretched to the terminal buffer when the next one would not fit.
+///
+/// # Arguments
+/// * `num_warmup` - Number of warmup draws
+/// * `options` - Sizes of the buffers and of the first window
+pub fn adaptation_windows(num_warmup: usize, options: &AdaptationOptions) -> Vec<Range<usize>> {
+    if num_warmup < 20 {
+        return Vec::new();
+    }
+    let (init, term, base) =
+        if options.init_buffer + options.term_buffer + options.base_window > num_warmup {
+            let init = (0.15 * num_warmup as f64) as usize;
+            let term = (0.1 * num_warmup as f64) as usize;
+            (init, term, num_warmup - init - term)
+        } else {
+            (
+                options.init_buffer,
+                options.term_buffer,
+                options.base_window,
+            )
+        };
+    let end = num_warmup - term;
+    let mut windows = Vec::new();
+    // the first window is never stretched, see compute_next_window in
+    // stan/mcmc/windowed_adaptation.hpp for the others
+    let mut size = base.max(1);
+    let (mut start, mut stop) = (init, init + size);
+    loop {
+        windows.push(start..stop);
+        if stop >= end {
+            return windows;
+        }
+        size *= 2;
+        start = stop;
+        stop = start + size;
+        if stop != end && stop + 2 * size > end {
+            stop = end;
+        }
+    }
+}
+
+/// Diagnoses the adaptation phase of a Hamiltonian Monte Carlo sampler from
+/// the warmup draws, e.g. those saved by Stan with `save_warmup`:
+///
+/// * the step size of every warmup draw and how far the end of its
+///   trajectory is from the step size used for sampling,
+/// * the variance of every parameter in each slow window, from which Stan
+///   estimates the diagonal inverse metric, and how much it still changed
+///   between the last two windows,
+/// * whether both had settled before sampling began.
+///
+/// A step size that was still drifting or an inverse metric that changed a
+/// lot in the last window mean the warmup was too short, and the sampler
+/// then often mixes poorly or diverges.  The variances are computed on the
+/// scale of the saved draws, which for constrained parameters differs from
+/// the unconstrained scale the sampler adapts on, so large changes should be
+/// read as a hint rather than as the exact change in the metric.  The warmup
+/// draws are assumed not to be thinned.
+///
+/// # Arguments
+/// * `warmup` - Warmup draws with a `stepsize__` or `step_size` column
+/// * `sampling` - Draws after warmup of the same chains, for the final step size
+/// * `options` - Warmup schedule and tolerances
+pub fn adaptation_diagnostics(
+    warmup: &Draws,
+    sampling: &Draws,
+    options: &AdaptationOptions,
+) -> Result<AdaptationReport, Error> {
+    if warmup.num_chains() != sampling.num_chains() {
+        return Err(anyhow!(
+            "Warmup has {} chains but sampling has {}",
+            warmup.num_chains(),
+            sampling.num_chains()
+        ));
+    }
+    let step_column = |draws: &Draws| {
+        STEP_SIZE_COLUMNS
+            .iter()
+            .find_map(|c| draws.index_of(c))
+            .ok_or_else(|| anyhow!("No step size column found"))
+    };
+    let warmup_steps = warmup.parameter(step_column(warmup)?);
+    let sampling_steps = sampling.parameter(step_column(sampling)?);
+    let parameters: Vec<usize> = (0..warmup.num_parameters())
+        .filter(|&idx| !is_sampler_column(&warmup.names()[idx], warmup.sampler()))
+        .collect();
+    let values: Vec<_> = parameters
+        .iter()
+        .map(|&idx| warmup.parameter(idx))
+        .collect();
+    let windows = adaptation_windows(warmup.num_draws(), options);
+    let max_ratio = 1.0 + options.max_metric_change;
+    // the second half of the terminal buffer, when the step size should be
+    // close to its final value
+    let term = warmup.num_draws() - windows.last().map_or(0, |w| w.end);
+    let tail = (term / 2).max(1);
+
+    let mut chains = Vec::with_capacity(warmup.num_chains());
+    for chain in 0..warmup.num_chains() {
+        let step_size = warmup_steps[chain].clone();
+        let final_step_size = *sampling_steps[chain]
+            .first()
+            .ok_or_else(|| anyhow!("No sampling draws in chain {}", sampling.chain_name(chain)))?;
+        if !(final_step_size > 0.0 && final_step_size.is_finite()) {
+            return Err(anyhow!(
+                "Invalid step size {} in chain {}",
+                final_step_size,
+                sampling.chain_name(chain)
+            ));
+        }
+        let last = &step_size[step_size.len().saturating_sub(tail)..];
+        let step_size_change = (mean(last)? - final_step_size).abs() / final_step_size;
+        let window_variances = windows
+            .iter()
+            .map(|window| {
+                values
+                    .iter()
+                    .map(|chains| sample_variance(&chains[chain][window.clone()]))
+                    .collect::<Result<Array1, Error>>()
+            })
+            .collect::<Result<Array2, Error>>()?;
+        let metric_ratio: Array1 = match window_variances.len() {
+            0 | 1 => Vec::new(),
+            n => window_variances[n - 1]
+                .iter()
+                .zip(window_variances[n - 2].iter())
+                .map(|(last, previous)| last / previous)
+                .collect(),
+        };
+        // constant parameters have a NaN ratio and say nothing
+        let metric_settled = metric_ratio
+            .iter()
+            .filter(|r| !r.is_nan())
+            .all(|&r| r <= max_ratio && r >= 1.0 / max_ratio);
+        chains.push(ChainAdaptation {
+            settled: metric_settled && step_size_change <= options.max_step_size_change,
+            step_size,
+            final_step_size,
+            step_size_change,
+            window_variances,
+            metric_ratio,
+        });
+    }
+    Ok(AdaptationReport {
+        parameters: parameters
+            .iter()
+            .map(|&idx| warmup.names()[idx].clone())
+            .collect(),
+        windows,
+        chains,
+    })
+}
+
+/// Distribution of the NUTS tree depth of every chain, see
+/// [`tree_depth_histogram`](fn.tree_depth_histogram.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeDepthHistogram {
+    /// Every depth from zero to the largest one reached, the categories of a
+    /// bar chart
+    pub depths: Vec<usize>,
+    /// Number of draws of each chain at each depth, `counts[chain][depth]`
+    pub counts: Vec<Vec<usize>>,
+    /// Average number of leapfrog steps per draw of each chain implied by
+    /// the depths, as a tree of depth `d` takes `2^d - 1` steps
+    pub mean_leapfrog_steps: Array1,
+}
+
+impl TreeDepthHistogram {
+    /// Number of draws at each depth over all chains.
+    pub fn total(&self) -> Vec<usize> {
+        self.depths
+            .iter()
+            .map(|&depth| self.counts.iter().map(|c| c[depth]).sum())
+            .collect()
+    }
+}
+
+/// Counts how often each NUTS tree depth was reached in every chain.  Draws
+/// piling up at the maximum depth mean the sampler gave up on trajectories
+/// before they turned around, which makes it explore slowly, and the
+/// implied number of leapfrog steps shows how expensive each draw was.
+/// Depths that are negative, fractional or beyond 64 are an error.
+///
+/// # Arguments
+/// * `draws` - Draws with a `treedepth__` or `tree_depth` column; tempered
+///   chains are left out
+pub fn tree_depth_histogram(draws: &Draws) -> Result<TreeDepthHistogram, Error> {
+    let idx = TREE_DEPTH_COLUMNS
+        .iter()
+        .find_map(|c| draws.index_of(c))
+        .ok_or_else(|| anyhow!("No tree depth column found"))?;
+    let chains = draws.target_parameter(idx);
+    let mut counts: Vec<Vec<usize>> = vec![Vec::new(); chains.len()];
+    for (chain, depths) in chains.iter().enumerate() {
+        for &depth in depths.iter() {
+            if depth < 0.0 || depth.fract() != 0.0 || depth > MAX_TREE_DEPTH as f64 {
+                return Err(anyhow!(
+                    "Invalid tree depth {} in chain {}",
+                    depth,
+                    draws.target_chain_name(chain)
+                ));
+            }
+            let depth = depth as usize;
+            if counts[chain].len() <= depth {
+                counts[chain].resize(depth + 1, 0);
+            }
+            counts[chain][depth] += 1;
+        }
+    }
+    let num_depths = counts.iter().map(|c| c.len()).max().unwrap_or(0);
+    for chain_counts in counts.iter_mut() {
+        chain_counts.resize(num_depths, 0);
+    }
+    let mean_leapfrog_steps = counts
+        .iter()
+        .map(|chain_counts| {
+            let num_draws: usize = chain_counts.iter().sum();
+            let steps: f64 = chain_counts
+                .iter()
+                .enumerate()
+                .map(|(depth, &n)| n as f64 * (2f64.powi(depth as i32) - 1.0))
+                .sum();
+            steps / num_draws as f64
+        })
+        .collect();
+    Ok(TreeDepthHistogram {
+        depths: (0..num_depths).collect(),
+        counts,
+        mean_leapfrog_steps,
+    })
+}
+
+/// One divergent transition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DivergentDraw {
+    /// Index of the chain among the chains that sample the target
+    pub chain: usize,
+    /// Index of the draw within the chain
+    pub iteration: usize,
+    /// Values of the selected parameters at the draw
+    pub values: Array1,
+}
+
+/// How the draws of one parameter at divergent transitions differ from the
+/// other draws.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DivergenceComparison {
+    /// Mean of the divergent draws, `NaN` without any
+    pub divergent_mean: f64,
+    /// Standard deviation of the divergent draws, `NaN` without any
+    pub divergent_sd: f64,
+    /// Mean of the other draws
+    pub other_mean: f64,
+    /// Standard deviation of the other draws
+    pub other_sd: f64,
+    /// Difference of the means in units of the standard deviation of the
+    /// other draws.  Divergences concentrated in a narrow region such as
+    /// the neck of a funnel show up as a large difference.
+    pub standardized_difference: f64,
+}
+
+/// Where the divergent transitions happened, see
+/// [`divergence_locations`](fn.divergence_locations.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DivergenceLocations {
+    /// Names of the selected parameters
+    pub parameters: Vec<String>,
+    /// Every divergent transition, by chain and then iteration
+    pub divergent: Vec<DivergentDraw>,
+    /// Comparison of divergent and other draws for each selected parameter
+    pub comparison: Vec<DivergenceComparison>,
+}
+
+/// Mean and standard deviation, both `NaN` without any values.
+fn mean_sd(values: &[f64]) -> (f64, f64) {
+    match (mean(values), sample_variance(values)) {
+        (Ok(m), Ok(v)) => (m, v.sqrt()),
+        _ => (f64::NAN, f64::NAN),
+    }
+}
+
+/// Finds the divergent transitions and the values of the selected
+/// parameters at each of them, together with summaries comparing the
+/// divergent draws to the others.  Divergences that cluster in one part of
+/// parameter space, e.g. at small values of a hierarchical scale, point to
+/// geometry the sampler can't handle and usually call for a
+/// reparameterization, while divergences spread like the other draws are
+/// more likely false positives.
+///
+/// # Arguments
+/// * `draws` - Draws with a `divergent__` or `numerical_error` column, or
+///   PyMC's `diverging`; tempered chains are left out
+/// * `parameters` - Names of the parameters to report
+pub fn divergence_locations(
+    draws: &Draws,
+    parameters: &[&str],
+) -> Result<DivergenceLocations, Error> {
+    let flag = divergent_column(draws).ok_or_else(|| anyhow!("No divergence column found"))?;
+    let flags = draws.target_parameter(flag);
+    let values = parameters
+        .iter()
+        .map(|name| {
+            draws
+                .index_of(name)
+                .map(|idx| draws.target_parameter(idx))
+                .ok_or_else(|| anyhow!("No parameter named {:?}", name))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut divergent = Vec::new();
+    let mut split = vec![(Vec::new(), Vec::new()); parameters.len()];
+    for (chain, chain_flags) in flags.iter().enumerate() {
+        for (iteration, &f) in chain_flags.iter().enumerate() {
+            let at: Array1 = values.iter().map(|v| v[chain][iteration]).collect();
+            for (p, &x) in at.iter().enumerate() {
+                if f > 0.0 {
+                    split[p].0.push(x);
+                } else {
+                    split[p].1.push(x);
+                }
+            }
+            if f > 0.0 {
+                divergent.push(DivergentDraw {
+                    chain,
+                    iteration,
+                    values: at,
+                });
+            }
+        }
+    }
+    let comparison = split
+        .iter()
+        .map(|(divergent, other)| {
+            let (divergent_mean, divergent_sd) = mean_sd(divergent);
+            let (other_mean, other_sd) = mean_sd(other);
+            DivergenceComparison {
+                divergent_mean,
+                divergent_sd,
+                other_mean,
+                other_sd,
+                standardized_difference: (divergent_mean - other_mean) / other_sd,
+            }
+        })
+        .collect();
+    Ok(DivergenceLocations {
+        parameters: parameters.iter().map(|p| p.to_string()).collect(),
+        divergent,
+        comparison,
+    })
+}
+
+/// Options of [`pair_points`](fn.pair_points.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairOptions {
+    /// Largest number of points per pair, or `None` to keep every draw
+    pub max_points: Option<usize>,
+    /// Whether divergent draws are always kept when subsampling, so that
+    /// they stand out even among many draws
+    pub keep_divergent: bool,
+    /// Seed of the random subsample
+    pub seed: u64,
+}
+
+impl Default for PairOptions {
+    fn default() -> PairOptions {
+        PairOptions {
+            max_points: None,
+            keep_divergent: true,
+            seed: 0,
+        }
+    }
+}
+
+/// One draw of a pair of parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairPoint {
+    /// Value of the first parameter
+    pub x: f64,
+    /// Value of the second parameter
+    pub y: f64,
+    /// Whether the draw was a divergent transition
+    pub divergent: bool,
+}
+
+/// Scatter plot data of one pair of parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairPoints {
+    /// Name of the parameter on the x axis
+    pub x: String,
+    /// Name of the parameter on the y axis
+    pub y: String,
+    /// Draws in chain order
+    pub points: Vec<PairPoint>,
+}
+
+impl PairPoints {
+    /// Formats the points as CSV with a header of the two parameter names
+    /// and `divergent`, which is 0 or 1.
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("{},{},divergent\n", self.x, self.y);
+        for p in self.points.iter() {
+            writeln!(csv, "{},{},{}", p.x, p.y, p.divergent as u8).unwrap();
+        }
+        csv
+    }
+}
+
+/// Collects the draws of pairs of parameters together with whether each
+/// draw diverged, like the pair plots of ArviZ that highlight divergences.
+/// Divergent draws that form a tight cluster or lie along an edge of the
+/// other draws show where the sampler struggles.  All pairs use the same
+/// subsample, so the points of different pairs line up.
+///
+/// # Arguments
+/// * `draws` - Draws with a `divergent__` or `numerical_error` column, or
+///   PyMC's `diverging`; tempered chains are left out
+/// * `pairs` - Names of the parameters of each pair
+/// * `options` - Subsampling options
+pub fn pair_points(
+    draws: &Draws,
+    pairs: &[(&str, &str)],
+    options: &PairOptions,
+) -> Result<Vec<PairPoints>, Error> {
+    let flag = divergent_column(draws).ok_or_else(|| anyhow!("No divergence column found"))?;
+    let divergent: Vec<bool> = draws
+        .target_parameter(flag)
+        .iter()
+        .flatten()
+        .map(|&f| f > 0.0)
+        .collect();
+
+    let mut keep: Vec<usize> = (0..divergent.len()).collect();
+    if let Some(max) = options.max_points.filter(|&m| m < divergent.len()) {
+        let (mut chosen, mut rest): (Vec<usize>, Vec<usize>) = if options.keep_divergent {
+            keep.into_iter().partition(|&i| divergent[i])
+        } else {
+            (Vec::new(), keep)
+        };
+        chosen.truncate(max);
+        // partial Fisher-Yates shuffle for the remaining places
+        let mut rng = Rng::new(options.seed);
+        let needed = (max - chosen.len()).min(rest.len());
+        for i in 0..needed {
+            let j = i + rng.below(rest.len() - i);
+            rest.swap(i, j);
+        }
+        chosen.extend_from_slice(&rest[..needed]);
+        chosen.sort_unstable();
+        keep = chosen;
+    }
+
+    let column = |name: &str| {
+        draws
+            .index_of(name)
+            .map(|idx| flatten(&draws.target_parameter(idx)))
+            .ok_or_else(|| anyhow!("No parameter named {:?}", name))
+    };
+    pairs
+        .iter()
+        .map(|&(x_name, y_name)| {
+            let (x, y) = (column(x_name)?, column(y_name)?);
+            Ok(PairPoints {
+                x: x_name.to_string(),
+                y: y_name.to_string(),
+                points: keep
+                    .iter()
+                    .map(|&i| PairPoint {
+                        x: x[i],
+                        y: y[i],
+                        divergent: divergent[i],
+                    })
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_tree_depth_histogram() {
+        let names = vec!["theta".to_string(), "treedepth__".to_string()];
+        let chains = vec![
+            vec![vec![0.0; 4], vec![1.0, 3.0, 3.0, 2.0]],
+            vec![vec![0.0; 2], vec![0.0, 1.0]],
+        ];
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let histogram = tree_depth_histogram(&draws).unwrap();
+        assert_eq!(histogram.depths, vec![0, 1, 2, 3]);
+        assert_eq!(histogram.counts, vec![vec![0, 1, 1, 2], vec![1, 1, 0, 0]]);
+        assert_eq!(histogram.total(), vec![1, 2, 1, 2]);
+        assert_abs_diff_eq!(histogram.mean_leapfrog_steps[0], 18.0 / 4.0);
+        assert_abs_diff_eq!(histogram.mean_leapfrog_steps[1], 0.5);
+
+        let d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let stan = crate::io::stan::read_files(&[
+            d.join("test/stan/blocker.1.csv"),
+            d.join("test/stan/blocker.2.csv"),
+        ])
+        .unwrap();
+        let histogram = tree_depth_histogram(&stan).unwrap();
+        assert_eq!(histogram.counts.len(), 2);
+        assert_eq!(histogram.total().iter().sum::<usize>(), 2000);
+
+        let bad = Draws::from_chains(vec!["tree_depth".to_string()], vec![vec![vec![1.5]]]);
+        assert!(tree_depth_histogram(&bad.unwrap()).is_err());
+        let deep = Draws::from_chains(vec!["tree_depth".to_string()], vec![vec![vec![1e12]]]);
+        assert!(tree_depth_histogram(&deep.unwrap()).is_err());
+        assert!(tree_depth_histogram(&Draws::new(vec!["theta".to_string()])).is_err());
+    }
+
+    #[test]
+    fn test_divergence_locations() {
+        let names = vec![
+            "log_tau".to_string(),
+            "theta".to_string(),
+            "divergent__".to_string(),
+        ];
+        // divergences happen where log_tau is smallest, as in a funnel
+        let chains = (0..2)
+            .map(|c| {
+                let log_tau = normal_draws(200, c);
+                let divergent = log_tau
+                    .iter()
+                    .map(|&x| if x < -1.5 { 1.0 } else { 0.0 })
+                    .collect();
+                vec![log_tau, normal_draws(200, 10 + c), divergent]
+            })
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let locations = divergence_locations(&draws, &["theta", "log_tau"]).unwrap();
+        assert_eq!(locations.parameters, vec!["theta", "log_tau"]);
+        assert!(!locations.divergent.is_empty());
+        for d in locations.divergent.iter() {
+            assert_eq!(d.values[1], draws.parameter(0)[d.chain][d.iteration]);
+            assert!(d.values[1] < -1.5);
+        }
+        assert!(locations.comparison[1].standardized_difference < -1.5);
+        assert!(locations.comparison[0].standardized_difference.abs() < 1.0);
+
+        let names = vec!["theta".to_string(), "numerical_error".to_string()];
+        let chains = vec![vec![normal_draws(10, 1), vec![0.0; 10]]];
+        let none = Draws::from_chains(names, chains).unwrap();
+        let locations = divergence_locations(&none, &["theta"]).unwrap();
+        assert!(locations.divergent.is_empty());
+        assert!(locations.comparison[0].divergent_mean.is_nan());
+        assert!(locations.comparison[0].other_sd > 0.0);
+        assert!(divergence_locations(&draws, &["sigma"]).is_err());
+        assert!(divergence_locations(&Draws::new(vec!["theta".to_string()]), &[]).is_err());
+    }
+
+    #[test]
+    fn test_pair_points() {
+        let names = vec!["a".to_string(), "b".to_string(), "divergent__".to_string()];
+        let chains = vec![
+            vec![
+                vec![1.0, 2.0, 3.0],
+                vec![4.0, 5.0, 6.0],
+                vec![0.0, 1.0, 0.0],
+            ],
+            vec![vec![7.0, 8.0], vec![9.0, 10.0], vec![0.0, 0.0]],
+        ];
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let pairs =
+            pair_points(&draws, &[("a", "b"), ("b", "a")], &PairOptions::default()).unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].points.len(), 5);
+        assert_eq!(
+            pairs[0].points[1],
+            PairPoint {
+                x: 2.0,
+                y: 5.0,
+                divergent: true
+            }
+        );
+        assert_eq!(pairs[1].points[4].x, 10.0);
+        assert_eq!(
+            pairs[0].to_csv(),
+            "a,b,divergent\n1,4,0\n2,5,1\n3,6,0\n7,9,0\n8,10,0\n"
+        );
+
+        let options = PairOptions {
+            max_points: Some(2),
+            ..PairOptions::default()
+        };
+        let pairs = pair_points(&draws, &[("a", "b")], &options).unwrap();
+        assert_eq!(pairs[0].points.len(), 2);
+        assert!(pairs[0].points.iter().any(|p| p.divergent));
+        // the same seed gives the same subsample
+        assert_eq!(pairs, pair_points(&draws, &[("a", "b")], &options).unwrap());
+        assert!(pair_points(&draws, &[("a", "c")], &options).is_err());
+    }
+
+    #[test]
+    fn test_adaptation_windows() {
+        let options = AdaptationOptions::default();
+        assert_eq!(
+            adaptation_windows(1000, &options),
+            vec![75..100, 100..150, 150..250, 250..450, 450..950]
+        );
+        assert_eq!(
+            adaptation_windows(2000, &options),
+            vec![75..100, 100..150, 150..250, 250..450, 450..850, 850..1950]
+        );
+        // as computed by Stan's windowed_adaptation
+        assert_eq!(
+            adaptation_windows(500, &options),
+            vec![75..100, 100..150, 150..250, 250..450]
+        );
+        assert_eq!(
+            adaptation_windows(300, &options),
+            vec![75..100, 100..150, 150..250]
+        );
+        assert_eq!(adaptation_windows(200, &options), vec![75..100, 100..150]);
+        assert_eq!(adaptation_windows(100, &options), vec![15..90]);
+        assert_eq!(adaptation_windows(150, &options), vec![75..100]);
+        assert!(adaptation_windows(19, &options).is_empty());
+    }
+
+    #[test]
+    fn test_adaptation_diagnostics() {
+        let names = vec!["stepsize__".to_string(), "theta".to_string()];
+        // the first chain settles, while in the second theta keeps spreading
+        // out and the step size is still far from its final value
+        let warmup_step: Array1 = (0..1000).map(|i| 0.5 + 2.0 / (1.0 + i as f64)).collect();
+        let growing: Array1 = normal_draws(1000, 2)
+            .iter()
+            .enumerate()
+            .map(|(i, x)| x * (1.0 + i as f64 / 100.0))
+            .collect();
+        let warmup = Draws::from_chains(
+            names.clone(),
+            vec![
+                vec![warmup_step.clone(), normal_draws(1000, 1)],
+                vec![vec![0.1; 1000], growing],
+            ],
+        )
+        .unwrap();
+        let sampling = Draws::from_chains(
+            names.clone(),
+            vec![
+                vec![vec![0.5; 10], normal_draws(10, 3)],
+                vec![vec![0.5; 10], normal_draws(10, 4)],
+            ],
+        )
+        .unwrap();
+
+        let report =
+            adaptation_diagnostics(&warmup, &sampling, &AdaptationOptions::default()).unwrap();
+        assert_eq!(report.parameters, vec!["theta".to_string()]);
+        assert_eq!(report.windows.len(), 5);
+        let settled = &report.chains[0];
+        assert_eq!(settled.step_size, warmup_step);
+        assert_abs_diff_eq!(settled.final_step_size, 0.5);
+        assert!(settled.step_size_change < 0.01);
+        assert_eq!(settled.window_variances.len(), 5);
+        assert_abs_diff_eq!(
+            settled.window_variances[4][0],
+            sample_variance(&warmup.parameter(1)[0][450..950]).unwrap()
+        );
+        assert!(settled.settled);
+        let unsettled = &report.chains[1];
+        assert_abs_diff_eq!(unsettled.step_size_change, 0.8, epsilon = 1e-12);
+        assert!(unsettled.metric_ratio[0] > 2.0);
+        assert!(!unsettled.settled);
+        assert!(!report.settled());
+
+        let lenient = AdaptationOptions {
+            max_metric_change: 10.0,
+            max_step_size_change: 1.0,
+            ..AdaptationOptions::default()
+        };
+        assert!(adaptation_diagnostics(&warmup, &sampling, &lenient)
+            .unwrap()
+            .settled());
+        assert!(adaptation_diagnostics(&warmup, &Draws::new(vec![]), &lenient).is_err());
+        for step in [0.0, -0.5, f64::NAN] {
+            let invalid = Draws::from_chains(
+                names.clone(),
+                vec![
+                    vec![vec![0.5; 10], normal_draws(10, 3)],
+                    vec![vec![step; 10], normal_draws(10, 4)],
+                ],
+            )
+            .unwrap();
+            assert!(adaptation_diagnostics(&warmup, &invalid, &lenient).is_err());
+        }
+    }
+}
@@ -0,0 +1,535 @@
+//! Kernel discrepancies between sets of draws.  The kernel Stein
+//! discrepancy compares draws with the target distribution itself, so unlike
+//! R hat and ESS, which only compare chains with each other, it detects draws
+//! that agree but are biased, e.g. from approximate samplers such as
+//! stochastic gradient MCMC or variational approximations.  The maximum mean
+//! discrepancy compares the joint distribution of two chains or runs.
+use crate::draws::Draws;
+use crate::utils::{quantile_sorted, Rng};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Error, Result};
+
+/// Settings of the inverse multiquadric kernel
+/// `k(x, y) = (c^2 + |x - y|^2)^beta` used by
+/// [`kernel_stein_discrepancy`](fn.kernel_stein_discrepancy.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KsdOptions {
+    /// Scale `c` of the kernel, which should be comparable to the posterior
+    /// scale of the parameters
+    pub scale: f64,
+    /// Exponent `beta` of the kernel, in `(-1, 0)`; the default of `-1/2`
+    /// is the choice of Gorham and Mackey (2017) for which the discrepancy
+    /// detects non-convergence
+    pub beta: f64,
+    /// The cost is quadratic in the number of draws, so larger sets of draws
+    /// are thinned evenly to at most this many
+    pub max_draws: usize,
+}
+
+impl Default for KsdOptions {
+    fn default() -> KsdOptions {
+        KsdOptions {
+            scale: 1.0,
+            beta: -0.5,
+            max_draws: 2000,
+        }
+    }
+}
+
+/// Pools the chains of every parameter into one point per draw, keeping at
+/// most `max_draws` evenly spaced ones.
+fn pooled_points(parameters: &[Array2], max_draws: usize) -> Result<Vec<Array1>, Error> {
+    if parameters.is_empty() {
+        return Err(anyhow!("Need at least one parameter"));
+    }
+    let columns: Vec<Array1> = parameters.iter().map(|chains| chains.concat()).collect();
+    let n = columns[0].len();
+    if columns.iter().any(|c| c.len() != n) {
+        return Err(anyhow!("Every parameter needs the same number of draws"));
+    }
+    if n == 0 {
+        return Err(anyhow!("Need at least one draw"));
+    }
+    let kept = n.min(max_draws.max(1));
+    Ok((0..kept)
+        .map(|i| {
+            let row = i * n / kept;
+            columns.iter().map(|c| c[row]).collect()
+        })
+        .collect())
+}
+
+/// Computes the kernel Stein discrepancy between the draws and the
+/// distribution whose score function `∇ log p` the sampler evaluated at
+/// every draw (Gorham and Mackey, 2017; Liu, Lee and Jordan, 2016).  It is
+/// zero exactly when the draws follow the target, so unlike R hat it flags
+/// samplers whose chains agree on the wrong distribution.  Only the score is
+/// needed, so the normalizing constant of the posterior does not matter.
+///
+/// With the Stein kernel
+/// `k_p(x, y) = s(x)·s(y) k(x, y) + s(x)·∇_y k + s(y)·∇_x k + tr(∇_x ∇_y k)`
+/// for scores `s` and the inverse multiquadric kernel `k`, the result is the
+/// square root of the V-statistic `mean_ij k_p(x_i, x_j)` over the pooled
+/// draws of all chains.  Its scale depends on the kernel and the dimension,
+/// so compare values between samplers or runs rather than with a fixed
+/// threshold.
+///
+/// # Arguments
+/// * `parameters` - One vector of chains per parameter of the block
+/// * `scores` - Derivative of the log density with respect to each parameter
+///   at every draw, with the same shape as `parameters`
+/// * `options` - Kernel settings and the number of draws to use
+pub fn kernel_stein_discrepancy(
+    parameters: &[Array2],
+    scores: &[Array2],
+    options: &KsdOptions,
+) -> Result<f64, Error> {
+    if parameters.len() != scores.len() {
+        return Err(anyhow!(
+            "Got scores of {} parameters for {} parameters",
+            scores.len(),
+            parameters.len()
+        ));
+    }
+    let same_shape = parameters.iter().zip(scores.iter()).all(|(p, s)| {
+        p.len() == s.len() && p.iter().zip(s.iter()).all(|(a, b)| a.len() == b.len())
+    });
+    if !same_shape {
+        return Err(anyhow!("Scores must have the same shape as the draws"));
+    }
+    let valid_kernel = options.scale > 0.0 && options.beta > -1.0 && options.beta < 0.0;
+    if !valid_kernel {
+        return Err(anyhow!("Kernel scale must be positive and beta in (-1, 0)"));
+    }
+    let x = pooled_points(parameters, options.max_draws)?;
+    let s = pooled_points(scores, options.max_draws)?;
+    if x.iter().chain(s.iter()).flatten().any(|v| !v.is_finite()) {
+        return Err(anyhow!("Draws and scores must be finite"));
+    }
+
+    let (n, d) = (x.len(), parameters.len());
+    let (c2, beta) = (options.scale * options.scale, options.beta);
+    let mut total = 0.0;
+    for i in 0..n {
+        for j in i..n {
+            let r: Array1 = x[i].iter().zip(x[j].iter()).map(|(a, b)| a - b).collect();
+            let r2: f64 = r.iter().map(|v| v * v).sum();
+            let u = c2 + r2;
+            let k = u.powf(beta);
+            let dk = 2.0 * beta * u.powf(beta - 1.0);
+            let scores_dot: f64 = s[i].iter().zip(s[j].iter()).map(|(a, b)| a * b).sum();
+            // s(x)·∇_y k + s(y)·∇_x k with ∇_x k = dk r = -∇_y k
+            let cross: f64 = r
+                .iter()
+                .zip(s[i].iter().zip(s[j].iter()))
+                .map(|(r, (si, sj))| dk * r * (sj - si))
+                .sum();
+            let trace = -dk * d as f64 - 4.0 * beta * (beta - 1.0) * u.powf(beta - 2.0) * r2;
+            let kp = scores_dot * k + cross + trace;
+            total += if i == j { kp } else { 2.0 * kp };
+        }
+    }
+    Ok((total / (n * n) as f64).max(0.0).sqrt())
+}
+
+/// Looks up the parameters matching the patterns in `draws`, and the columns
+/// of the same names in `other`, described by `what` in errors.
+fn matching_blocks(
+    draws: &Draws,
+    other: &Draws,
+    patterns: &[&str],
+    what: &str,
+) -> Result<(Vec<Array2>, Vec<Array2>), Error> {
+    let selected = draws.select(patterns);
+    if selected.is_empty() {
+        return Err(anyhow!("No parameters match {:?}", patterns));
+    }
+    let mut block = Vec::with_capacity(selected.len());
+    let mut other_block = Vec::with_capacity(selected.len());
+    for idx in selected {
+        let name = &draws.names()[idx];
+        let other_idx = other
+            .index_of(name)
+            .ok_or_else(|| anyhow!("Parameter {} is missing from {}", name, what))?;
+        block.push(draws.target_parameter(idx).into_owned());
+        other_block.push(other.target_parameter(other_idx).into_owned());
+    }
+    Ok((block, other_block))
+}
+
+/// Computes the [kernel Stein discrepancy](fn.kernel_stein_discrepancy.html)
+/// of every block of parameters, e.g. `&[&["mu"], &["theta[*]"]]`.  The scores
+/// are columns of `scores` named like the parameters, e.g. gradients written
+/// by the sampler alongside the draws.  The discrepancy of a block uses the
+/// derivatives of the joint log density with respect to the parameters of
+/// the block, which are the scores of its marginal only when the block is
+/// independent of the other parameters, so a single block of all parameters
+/// is the one that is exact.
+///
+/// # Arguments
+/// * `draws` - Draws of all the parameters
+/// * `scores` - Derivatives of the log density at the same draws
+/// * `blocks` - Names or wildcard patterns of the parameters of each block
+/// * `options` - Kernel settings and the number of draws to use
+pub fn kernel_stein_discrepancy_blocks(
+    draws: &Draws,
+    scores: &Draws,
+    blocks: &[&[&str]],
+    options: &KsdOptions,
+) -> Result<Vec<f64>, Error> {
+    blocks
+        .iter()
+        .map(|patterns| {
+            let (block, block_scores) = matching_blocks(draws, scores, patterns, "the scores")?;
+            kernel_stein_discrepancy(&block, &block_scores, options)
+        })
+        .collect()
+}
+
+/// Settings of [`maximum_mean_discrepancy`](fn.maximum_mean_discrepancy.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MmdOptions {
+    /// Bandwidth `h` of the Gaussian kernel `exp(-|x - y|^2 / (2 h^2))`, or
+    /// `None` for the median distance between the pooled draws
+    pub bandwidth: Option<f64>,
+    /// Number of random relabellings of the pooled draws for the p-value
+    pub num_permutations: usize,
+    /// Each set of draws is thinned evenly to at most this many, since the
+    /// cost is quadratic in the number of draws times the number of
+    /// permutations
+    pub max_draws: usize,
+    /// Seed of the permutations
+    pub seed: u64,
+}
+
+impl Default for MmdOptions {
+    fn default() -> MmdOptions {
+        MmdOptions {
+            bandwidth: None,
+            num_permutations: 200,
+            max_draws: 500,
+            seed: 0,
+        }
+    }
+}
+
+/// Result of a permutation test of two sets of draws for equal
+/// distributions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MmdTest {
+    /// Unbiased estimate of the squared maximum mean discrepancy, which may
+    /// be slightly negative when the distributions agree
+    pub mmd2: f64,
+    /// Bandwidth of the kernel used
+    pub bandwidth: f64,
+    /// Share of relabellings with a discrepancy at least as large as the
+    /// observed one; small values mean the two sets of draws come from
+    /// different distributions
+    pub p_value: f64,
+}
+
+/// Unbiased squared MMD of the points labelled `true` against those
+/// labelled `false`, from the kernel matrix of all points.
+fn mmd2_from_kernel(kernel: &[Array1], labels: &[bool]) -> f64 {
+    let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+    for (i, row) in kernel.iter().enumerate() {
+        for (j, &k) in row.iter().enumerate().skip(i + 1) {
+            match (labels[i], labels[j]) {
+                (true, true) => xx += k,
+                (false, false) => yy += k,
+                _ => xy += k,
+            }
+        }
+    }
+    let m = labels.iter().filter(|&&l| l).count() as f64;
+    let n = labels.len() as f64 - m;
+    2.0 * xx / (m * (m - 1.0)) + 2.0 * yy / (n * (n - 1.0)) - 2.0 * xy / (m * n)
+}
+
+/// Tests whether two sets of draws of the same parameters come from the same
+/// joint distribution with the maximum mean discrepancy of Gretton et al.
+/// (2012) under a Gaussian kernel.  It compares every moment of the joint
+/// distribution at once, so it catches chains or runs that agree on each
+/// margin, and so pass per-parameter checks, but not on the dependence
+/// between parameters.  Since the kernel uses Euclidean distances,
+/// parameters on very different scales should be standardized first.
+///
+/// The draws of all chains of each set are pooled, and the p-value counts
+/// random relabellings of the pooled draws with a larger discrepancy.  It
+/// assumes independent draws, so autocorrelated chains make it too small;
+/// thin them to roughly independent draws, e.g. by `max_draws`.
+///
+/// # Arguments
+/// * `a` - One vector of chains per parameter of the first set of draws
+/// * `b` - One vector of chains per parameter of the second set, in the same
+///   order
+/// * `options` - Kernel bandwidth, permutations and draws to use
+pub fn maximum_mean_discrepancy(
+    a: &[Array2],
+    b: &[Array2],
+    options: &MmdOptions,
+) -> Result<MmdTest, Error> {
+    if a.len() != b.len() {
+        return Err(anyhow!(
+            "Both sets need the same parameters, got {} and {}",
+            a.len(),
+            b.len()
+        ));
+    }
+    let x = pooled_points(a, options.max_draws)?;
+    let y = pooled_points(b, options.max_draws)?;
+    if x.len() < 2 || y.len() < 2 {
+        return Err(anyhow!("Need at least 2 draws in each set"));
+    }
+    let num_x = x.len();
+    let points: Vec<Array1> = x.into_iter().chain(y).collect();
+    if points.iter().flatten().any(|v| !v.is_finite()) {
+        return Err(anyhow!("Draws must be finite"));
+    }
+
+    let n = points.len();
+    let mut distances = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let d2: f64 = points[i]
+                .iter()
+                .zip(points[j].iter())
+                .map(|(p, q)| (p - q) * (p - q))
+                .sum();
+            distances[i][j] = d2;
+            distances[j][i] = d2;
+        }
+    }
+    let bandwidth = match options.bandwidth {
+        Some(h) if h > 0.0 => h,
+        Some(h) => return Err(anyhow!("Bandwidth must be positive, got {}", h)),
+        None => {
+            let mut pairs: Array1 = distances
+                .iter()
+                .enumerate()
+                .flat_map(|(i, row)| row[i + 1..].iter().map(|d2| d2.sqrt()))
+                .collect();
+            pairs.sort_by(|p, q| p.partial_cmp(q).unwrap());
+            let median = quantile_sorted(&pairs, 0.5)?;
+            if median > 0.0 {
+                median
+            } else {
+                1.0
+            }
+        }
+    };
+    let kernel: Vec<Array1> = distances
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|d2| (-d2 / (2.0 * bandwidth * bandwidth)).exp())
+                .collect()
+        })
+        .collect();
+
+    let mut labels: Vec<bool> = (0..n).map(|i| i < num_x).collect();
+    let mmd2 = mmd2_from_kernel(&kernel, &labels);
+    let mut rng = Rng::new(options.seed);
+    let mut exceed = 0;
+    for _ in 0..options.num_permutations {
+        for i in (1..n).rev() {
+            labels.swap(i, rng.below(i + 1));
+        }
+        if mmd2_from_kernel(&kernel, &labels) >= mmd2 {
+            exceed += 1;
+        }
+    }
+    Ok(MmdTest {
+        mmd2,
+        bandwidth,
+        p_value: (exceed + 1) as f64 / (options.num_permutations + 1) as f64,
+    })
+}
+
+/// Compares the joint distribution of the selected parameters between two
+/// chains, see [`maximum_mean_discrepancy`](fn.maximum_mean_discrepancy.html).
+///
+/// # Arguments
+/// * `draws` - Draws of all the parameters
+/// * `patterns` - Names or wildcard patterns such as `theta[*]`
+/// * `chains` - Indices of the two chains to compare
+/// * `options` - Kernel bandwidth, permutations and draws to use
+pub fn maximum_mean_discrepancy_chains(
+    draws: &Draws,
+    patterns: &[&str],
+    chains: (usize, usize),
+    options: &MmdOptions,
+) -> Result<MmdTest, Error> {
+    let selected = draws.select(patterns);
+    if selected.is_empty() {
+        return Err(anyhow!("No parameters match {:?}", patterns));
+    }
+    if chains.0 >= draws.num_chains() || chains.1 >= draws.num_chains() {
+        return Err(anyhow!(
+            "Chains {:?} out of range for {} chains",
+            chains,
+            draws.num_chains()
+        ));
+    }
+    let chain = |c: usize| -> Vec<Array2> {
+        selected
+            .iter()
+            .map(|&idx| vec![draws.parameter(idx)[c].clone()])
+            .collect()
+    };
+    maximum_mean_discrepancy(&chain(chains.0), &chain(chains.1), options)
+}
+
+/// Compares the joint distribution of the selected parameters between two
+/// runs of the same model, e.g. with different seeds or sampler settings,
+/// see [`maximum_mean_discrepancy`](fn.maximum_mean_discrepancy.html).
+/// Parameters are matched by name; tempered chains are left out.
+///
+/// # Arguments
+/// * `a` - Draws of the first run
+/// * `b` - Draws of the second run
+/// * `patterns` - Names or wildcard patterns of the parameters in `a`
+/// * `options` - Kernel bandwidth, permutations and draws to use
+pub fn maximum_mean_discrepancy_runs(
+    a: &Draws,
+    b: &Draws,
+    patterns: &[&str],
+    options: &MmdOptions,
+) -> Result<MmdTest, Error> {
+    let (first, second) = matching_blocks(a, b, patterns, "the second run")?;
+    maximum_mean_discrepancy(&first, &second, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    /// Draws of a standard normal shifted by `shift`, with the scores `-x`
+    /// of the standard normal.
+    fn normal_chains(shift: f64, seed: u64) -> (Array2, Array2) {
+        let chains: Array2 = (0..2)
+            .map(|c| {
+                normal_draws(300, seed + c)
+                    .iter()
+                    .map(|x| x + shift)
+                    .collect()
+            })
+            .collect();
+        let scores = chains
+            .iter()
+            .map(|chain| chain.iter().map(|x| -x).collect())
+            .collect();
+        (chains, scores)
+    }
+
+    #[test]
+    fn test_kernel_stein_discrepancy() {
+        // a single draw at the mode: only the trace term -2 beta d c^(2 beta - 2) is left
+        let ksd = kernel_stein_discrepancy(
+            &[vec![vec![0.0]]],
+            &[vec![vec![0.0]]],
+            &KsdOptions::default(),
+        )
+        .unwrap();
+        assert_abs_diff_eq!(ksd, 1.0, epsilon = 1e-12);
+
+        let options = KsdOptions::default();
+        let (exact, exact_scores) = normal_chains(0.0, 1);
+        let (biased, biased_scores) = normal_chains(1.0, 1);
+        let good = kernel_stein_discrepancy(&[exact], &[exact_scores], &options).unwrap();
+        let bad = kernel_stein_discrepancy(&[biased], &[biased_scores], &options).unwrap();
+        assert!(good < 0.1, "{}", good);
+        assert!(bad > 3.0 * good, "{} {}", bad, good);
+
+        let (x, s) = normal_chains(0.0, 3);
+        assert!(kernel_stein_discrepancy(std::slice::from_ref(&x), &[], &options).is_err());
+        assert!(kernel_stein_discrepancy(
+            std::slice::from_ref(&x),
+            &[vec![s[0].clone()]],
+            &options
+        )
+        .is_err());
+        let bad_kernel = KsdOptions {
+            beta: 0.5,
+            ..options
+        };
+        assert!(kernel_stein_discrepancy(&[x], &[s], &bad_kernel).is_err());
+    }
+
+    #[test]
+    fn test_kernel_stein_discrepancy_blocks() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let (a, a_scores) = normal_chains(0.0, 5);
+        let (b, b_scores) = normal_chains(1.5, 7);
+        let to_draws = |a: &Array2, b: &Array2| {
+            let chains = (0..2).map(|c| vec![a[c].clone(), b[c].clone()]).collect();
+            Draws::from_chains(names.clone(), chains).unwrap()
+        };
+        let draws = to_draws(&a, &b);
+        let scores = to_draws(&a_scores, &b_scores);
+        let ksd = kernel_stein_discrepancy_blocks(
+            &draws,
+            &scores,
+            &[&["a"], &["b"], &["*"]],
+            &KsdOptions::default(),
+        )
+        .unwrap();
+        assert!(ksd[0] < ksd[1]);
+        assert!(ksd[2] > ksd[0]);
+        assert!(kernel_stein_discrepancy_blocks(
+            &draws,
+            &scores,
+            &[&["c"]],
+            &KsdOptions::default()
+        )
+        .is_err());
+    }
+
+    /// Two correlated normals per chain, with correlation `rho`.
+    fn correlated(rho: f64, seed: u64) -> Draws {
+        let chains = (0..2)
+            .map(|c| {
+                let z1 = normal_draws(300, seed + 2 * c);
+                let z2 = normal_draws(300, seed + 2 * c + 1);
+                let y = z1
+                    .iter()
+                    .zip(z2.iter())
+                    .map(|(a, b)| rho * a + (1.0 - rho * rho).sqrt() * b)
+                    .collect();
+                vec![z1, y]
+            })
+            .collect();
+        Draws::from_chains(vec!["x".to_string(), "y".to_string()], chains).unwrap()
+    }
+
+    #[test]
+    fn test_maximum_mean_discrepancy() {
+        let options = MmdOptions {
+            max_draws: 200,
+            ..MmdOptions::default()
+        };
+        // same margins, opposite dependence
+        let positive = correlated(0.9, 1);
+        let negative = correlated(-0.9, 11);
+        let same = maximum_mean_discrepancy_runs(&positive, &correlated(0.9, 21), &["*"], &options)
+            .unwrap();
+        let different =
+            maximum_mean_discrepancy_runs(&positive, &negative, &["*"], &options).unwrap();
+        assert!(same.p_value > 0.01, "{:?}", same);
+        assert!(different.p_value < 0.01, "{:?}", different);
+        assert!(different.mmd2 > same.mmd2);
+        assert!(different.bandwidth > 0.0);
+
+        let chains =
+            maximum_mean_discrepancy_chains(&positive, &["x", "y"], (0, 1), &options).unwrap();
+        assert!(chains.p_value > 0.01, "{:?}", chains);
+        assert!(maximum_mean_discrepancy_chains(&positive, &["x"], (0, 2), &options).is_err());
+        assert!(maximum_mean_discrepancy_runs(&positive, &negative, &["z"], &options).is_err());
+        let bad = MmdOptions {
+            bandwidth: Some(0.0),
+            ..options
+        };
+        assert!(maximum_mean_discrepancy_runs(&positive, &negative, &["*"], &bad).is_err());
+    }
+}
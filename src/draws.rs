@@ -0,0 +1,1385 @@
+use crate::expr::Expr;
+use crate::online::{OnlineMonitor, Snapshot};
+use crate::stats::hdi;
+use crate::utils::{flatten, quantile_sorted};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Floating point precision used to store draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    /// Double precision, the same as the diagnostics use
+    #[default]
+    F64,
+    /// Single precision, which halves memory use at about 7 significant
+    /// digits.  Values are converted back to `f64` when a parameter is
+    /// accessed, so all statistics still accumulate in double precision.
+    /// Finite values beyond the range of `f32` are rejected.
+    F32,
+}
+
+/// Storage of the draws, laid out as values[parameter][chain][draw].
+#[derive(Debug, Clone, PartialEq)]
+enum Values {
+    F64(Vec<Array2>),
+    F32(Vec<Vec<Vec<f32>>>),
+}
+
+impl Default for Values {
+    fn default() -> Values {
+        Values::F64(Vec::new())
+    }
+}
+
+impl Values {
+    fn new(precision: Precision, num_parameters: usize) -> Values {
+        match precision {
+            Precision::F64 => Values::F64(vec![Vec::new(); num_parameters]),
+            Precision::F32 => Values::F32(vec![Vec::new(); num_parameters]),
+        }
+    }
+
+    fn precision(&self) -> Precision {
+        match self {
+            Values::F64(_) => Precision::F64,
+            Values::F32(_) => Precision::F32,
+        }
+    }
+
+    fn push_chain(&mut self, columns: Array2) {
+        match self {
+            Values::F64(values) => {
+                for (chains, column) in values.iter_mut().zip(columns) {
+                    chains.push(column);
+                }
+            }
+            Values::F32(values) => {
+                for (chains, column) in values.iter_mut().zip(columns) {
+                    chains.push(column.iter().map(|&x| x as f32).collect());
+                }
+            }
+        }
+    }
+
+    fn extend(&mut self, chain: usize, columns: Array2) {
+        match self {
+            Values::F64(values) => {
+                for (chains, column) in values.iter_mut().zip(columns) {
+                    chains[chain].extend(column);
+                }
+            }
+            Values::F32(values) => {
+                for (chains, column) in values.iter_mut().zip(columns) {
+                    chains[chain].extend(column.iter().map(|&x| x as f32));
+                }
+            }
+        }
+    }
+
+    fn push_parameter(&mut self, chains: Array2) {
+        match self {
+            Values::F64(values) => values.push(chains),
+            Values::F32(values) => values.push(
+                chains
+                    .iter()
+                    .map(|c| c.iter().map(|&x| x as f32).collect())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// First finite draw of a column that is too large for the precision,
+    /// which would otherwise be stored as an infinity.
+    fn overflowing(&self, column: &[f64]) -> Option<f64> {
+        match self {
+            Values::F64(_) => None,
+            Values::F32(_) => column
+                .iter()
+                .copied()
+                .find(|x| x.is_finite() && (*x as f32).is_infinite()),
+        }
+    }
+
+    /// Number of draws of each (parameter, chain) pair.
+    fn lengths(&self) -> Vec<usize> {
+        match self {
+            Values::F64(values) => values.iter().flatten().map(|c| c.len()).collect(),
+            Values::F32(values) => values.iter().flatten().map(|c| c.len()).collect(),
+        }
+    }
+
+    fn chain_len(&self, parameter: usize, chain: usize) -> usize {
+        match self {
+            Values::F64(values) => values[parameter][chain].len(),
+            Values::F32(values) => values[parameter][chain].len(),
+        }
+    }
+
+    fn value(&self, parameter: usize, chain: usize, draw: usize) -> f64 {
+        match self {
+            Values::F64(values) => values[parameter][chain][draw],
+            Values::F32(values) => values[parameter][chain][draw] as f64,
+        }
+    }
+
+    fn parameter(&self, idx: usize) -> Cow<'_, Array2> {
+        match self {
+            Values::F64(values) => Cow::Borrowed(&values[idx]),
+            Values::F32(values) => Cow::Owned(
+                values[idx]
+                    .iter()
+                    .map(|c| c.iter().map(|&x| x as f64).collect())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// User facing identity of a chain, carried through merging, splitting and
+/// thinning so that warnings refer to the chains the way the user knows
+/// them, e.g. by sampler chain id rather than by position.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChainLabel {
+    /// Identifier of the chain shown in messages, e.g. `"3"` or `"worker-7"`
+    pub id: String,
+    /// Random seed the chain was started with, if known
+    pub seed: Option<u64>,
+    /// Machine the chain ran on, if known
+    pub host: Option<String>,
+}
+
+impl ChainLabel {
+    /// Label with the given id and no seed or host.
+    pub fn new(id: &str) -> ChainLabel {
+        ChainLabel {
+            id: id.to_string(),
+            ..ChainLabel::default()
+        }
+    }
+}
+
+/// Program that wrote the draws, as far as the reader knows.  It decides
+/// which columns with plain names hold sampler diagnostics rather than
+/// parameters: Stan's end in two underscores and are recognized anywhere,
+/// but a column named `energy` is only taken for PyMC's Hamiltonian energy
+/// in draws known to come from PyMC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sampler {
+    /// Any sampler, or one whose diagnostics are recognized by name alone
+    #[default]
+    Other,
+    /// PyMC, with the `sample_stats` group of its ArviZ trace, e.g. read by
+    /// [`io::arviz`](../io/arviz/index.html)
+    PyMc,
+}
+
+/// Named draws for one or more parameters across one or more chains.
+///
+/// Values are stored parameter-major, so the chains for a single parameter
+/// are available as an `Array2` that can be passed straight to the
+/// diagnostics in this crate.  Draws can optionally be stored in single
+/// precision to save memory, see [`Precision`](enum.Precision.html).
+///
+/// For parallel tempering output each chain can carry an inverse
+/// temperature, and the summaries of whole containers only use the chains
+/// at inverse temperature one, see
+/// [`target_parameter`](#method.target_parameter).
+///
+/// Chains can also carry a [`ChainLabel`](struct.ChainLabel.html) that
+/// messages use instead of the chain's position, and the container can
+/// record the [`Sampler`](enum.Sampler.html) that wrote it.
+#[derive(Debug, Clone, Default)]
+pub struct Draws {
+    names: Vec<String>,
+    values: Values,
+    num_chains: usize,
+    // inverse temperature of each chain, one for chains that sample the
+    // target distribution
+    inverse_temperatures: Vec<f64>,
+    // label of each chain, if one was set
+    labels: Vec<Option<ChainLabel>>,
+    sampler: Sampler,
+    // online diagnostics kept up to date by push_chain and append_draws once
+    // cached_diagnostics has been called
+    cache: Option<OnlineMonitor>,
+    // pooled target draws of parameters sorted by sorted_draws, dropped
+    // whenever the draws or temperatures change
+    sorted: HashMap<usize, Array1>,
+}
+
+/// The draw with the highest log density found by
+/// [`Draws::best_draw`](struct.Draws.html#method.best_draw).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestDraw {
+    /// Index of the chain the draw is from
+    pub chain: usize,
+    /// Index of the draw within its chain, counting from zero
+    pub iteration: usize,
+    /// Log density of the draw
+    pub lp: f64,
+    /// Value of every parameter at the draw, in the same order as the names
+    pub values: Vec<f64>,
+}
+
+impl PartialEq for Draws {
+    fn eq(&self, other: &Draws) -> bool {
+        // the cache is derived from the values, so it doesn't take part
+        self.names == other.names
+            && self.values == other.values
+            && self.num_chains == other.num_chains
+            && self.inverse_temperatures == other.inverse_temperatures
+            && self.labels == other.labels
+            && self.sampler == other.sampler
+    }
+}
+
+impl Draws {
+    /// Creates an empty container with no chains for the given parameter names.
+    pub fn new(names: Vec<String>) -> Draws {
+        Draws::with_precision(names, Precision::F64)
+    }
+
+    /// Creates an empty container with no chains for the given parameter
+    /// names, storing draws with the given precision.
+    pub fn with_precision(names: Vec<String>, precision: Precision) -> Draws {
+        let values = Values::new(precision, names.len());
+        Draws {
+            names,
+            values,
+            num_chains: 0,
+            inverse_temperatures: Vec::new(),
+            labels: Vec::new(),
+            sampler: Sampler::Other,
+            cache: None,
+            sorted: HashMap::new(),
+        }
+    }
+
+    /// Creates a container from chains given in chain-major order, i.e.
+    /// `chains[chain][parameter][draw]`, which is how a sampler output file
+    /// is usually laid out.
+    ///
+    /// # Arguments
+    /// * `names` - Parameter names, one per column of each chain
+    /// * `chains` - Vector of chains, each of which is a vector of columns
+    pub fn from_chains(names: Vec<String>, chains: Vec<Array2>) -> Result<Draws, Error> {
+        let mut draws = Draws::new(names);
+        for chain in chains {
+            draws.push_chain(chain)?;
+        }
+        Ok(draws)
+    }
+
+    /// Adds a chain given as one column of draws per parameter, in the same
+    /// order as the parameter names.
+    pub fn push_chain(&mut self, columns: Array2) -> Result<(), Error> {
+        self.check_chain(&columns)?;
+        let chain = self.num_chains;
+        self.values.push_chain(columns);
+        self.num_chains += 1;
+        self.inverse_temperatures.push(1.0);
+        self.labels.push(None);
+        self.update_cache(chain, 0);
+        Ok(())
+    }
+
+    /// Checks that a new chain has a column of the same length for every
+    /// parameter, and that the draws fit the precision.
+    fn check_chain(&self, columns: &Array2) -> Result<(), Error> {
+        if columns.len() != self.names.len() {
+            return Err(anyhow!(
+                "Chain has {} columns but there are {} parameters",
+                columns.len(),
+                self.names.len()
+            ));
+        }
+        if let Some((idx, column)) = columns
+            .iter()
+            .enumerate()
+            .find(|(_, c)| c.len() != columns[0].len())
+        {
+            return Err(anyhow!(
+                "Column {} of the chain has {} draws but {} has {}",
+                self.names[idx],
+                column.len(),
+                self.names[0],
+                columns[0].len()
+            ));
+        }
+        for (name, column) in self.names.iter().zip(columns.iter()) {
+            self.check_range(name, column)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the finite draws of a parameter stay finite in the
+    /// precision they are stored with.
+    fn check_range(&self, name: &str, column: &[f64]) -> Result<(), Error> {
+        match self.values.overflowing(column) {
+            Some(x) => Err(anyhow!(
+                "Draw {:e} of {} is too large for single precision",
+                x,
+                name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds the chains of another container, e.g. an independent run of the
+    /// same model, as new chains after the existing ones.  Both containers
+    /// must have the same parameters, though not necessarily in the same
+    /// order.  Chain labels and inverse temperatures are kept.  Keeping the
+    /// runs as separate chains lets the diagnostics compare them; use
+    /// [`concat_chains`](../utils/fn.concat_chains.html) to pool them for
+    /// estimation.  Every chain is checked before any is added, so the
+    /// draws are left unchanged on error.
+    pub fn merge(&mut self, other: &Draws) -> Result<(), Error> {
+        if other.names.len() != self.names.len() {
+            return Err(anyhow!(
+                "Other draws have {} parameters but there are {}",
+                other.names.len(),
+                self.names.len()
+            ));
+        }
+        let positions = self
+            .names
+            .iter()
+            .map(|name| {
+                other
+                    .index_of(name)
+                    .ok_or_else(|| anyhow!("Other draws are missing parameter {:?}", name))
+            })
+            .collect::<Result<Vec<usize>, Error>>()?;
+        let parameters: Vec<_> = positions.iter().map(|&p| other.parameter(p)).collect();
+        let chains: Vec<Array2> = (0..other.num_chains)
+            .map(|chain| parameters.iter().map(|p| p[chain].clone()).collect())
+            .collect();
+        for (chain, columns) in chains.iter().enumerate() {
+            self.check_chain(columns)
+                .with_context(|| format!("Chain {} of the other draws", other.chain_name(chain)))?;
+        }
+        for (chain, columns) in chains.into_iter().enumerate() {
+            self.push_chain(columns)?;
+            self.set_inverse_temperature(self.num_chains - 1, other.inverse_temperatures[chain])?;
+            *self.labels.last_mut().unwrap() = other.labels[chain].clone();
+        }
+        self.sorted.clear();
+        Ok(())
+    }
+
+    /// Appends new draws to the end of an existing chain, e.g. after each
+    /// round of an iterate-until-converged sampling loop.  If diagnostics are
+    /// cached they are updated with just the new draws.
+    ///
+    /// # Arguments
+    /// * `chain_idx` - Index of the chain to extend
+    /// * `columns` - New draws, one column per parameter in the same order as
+    ///   the parameter names, all of the same length
+    pub fn append_draws(&mut self, chain_idx: usize, columns: Array2) -> Result<(), Error> {
+        if chain_idx >= self.num_chains {
+            return Err(anyhow!(
+                "Chain {} does not exist, there are {} chains",
+                chain_idx,
+                self.num_chains
+            ));
+        }
+        if columns.len() != self.names.len() {
+            return Err(anyhow!(
+                "Draws have {} columns but there are {} parameters",
+                columns.len(),
+                self.names.len()
+            ));
+        }
+        let num_new = columns.first().map_or(0, |c| c.len());
+        if columns.iter().any(|c| c.len() != num_new) {
+            return Err(anyhow!("All columns must have the same number of draws"));
+        }
+        for (name, column) in self.names.iter().zip(columns.iter()) {
+            self.check_range(name, column)?;
+        }
+        let start = if self.names.is_empty() {
+            0
+        } else {
+            self.values.chain_len(0, chain_idx)
+        };
+        self.values.extend(chain_idx, columns);
+        self.update_cache(chain_idx, start);
+        Ok(())
+    }
+
+    /// Returns the means, standard deviations, approximate effective sample
+    /// sizes and (non-split) R hat of every parameter from online statistics
+    /// that are kept up to date as draws are added, so that calling this
+    /// after every `append_draws` costs time proportional to the new draws
+    /// only.  The first call computes the statistics from all stored draws.
+    ///
+    /// The effective sample size uses batch means, see
+    /// [`OnlineMonitor`](../online/struct.OnlineMonitor.html); use the
+    /// functions in [`ess`](../ess/index.html) for the final numbers.  The
+    /// cached statistics only cover the chains at inverse temperature one,
+    /// and leave out draws with NaN or infinite values.
+    pub fn cached_diagnostics(&mut self) -> Snapshot {
+        if self.cache.is_none() {
+            let mut monitor = OnlineMonitor::new(self.names.clone());
+            for (slot, chain) in self.target_chains().into_iter().enumerate() {
+                push_rows(&mut monitor, &self.values, chain, slot, 0);
+            }
+            self.cache = Some(monitor);
+        }
+        self.cache.as_ref().unwrap().snapshot()
+    }
+
+    /// Pooled draws of the chains at inverse temperature one for the
+    /// parameter at the given position, sorted in increasing order.  The
+    /// sort is done on the first call and cached until draws are added or
+    /// temperatures change, so that [`quantiles`](#method.quantiles),
+    /// [`hdi`](#method.hdi) and
+    /// [`rope_probability`](#method.rope_probability) share one sort per
+    /// parameter instead of each sorting a copy of the draws.  Fails if a
+    /// draw is NaN or infinite.
+    pub fn sorted_draws(&mut self, idx: usize) -> Result<&[f64], Error> {
+        if idx >= self.names.len() {
+            return Err(anyhow!(
+                "Parameter {} does not exist, there are {} parameters",
+                idx,
+                self.names.len()
+            ));
+        }
+        if !self.sorted.contains_key(&idx) {
+            let mut pooled = flatten(&self.target_parameter(idx));
+            if pooled.iter().any(|x| !x.is_finite()) {
+                return Err(anyhow!(
+                    "Draws of {} contain NaN or infinite values",
+                    self.names[idx]
+                ));
+            }
+            pooled.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            self.sorted.insert(idx, pooled);
+        }
+        Ok(&self.sorted[&idx])
+    }
+
+    /// Quantiles of a parameter over the chains at inverse temperature one,
+    /// interpolated as by R's default type 7, using the cached sorted draws.
+    ///
+    /// # Arguments
+    /// * `idx` - Position of the parameter
+    /// * `probs` - Probabilities of the quantiles, each in [0, 1]
+    pub fn quantiles(&mut self, idx: usize, probs: &[f64]) -> Result<Array1, Error> {
+        let sorted = self.sorted_draws(idx)?;
+        probs.iter().map(|&p| quantile_sorted(sorted, p)).collect()
+    }
+
+    /// Highest density interval of a parameter over the chains at inverse
+    /// temperature one, the shortest interval between two draws containing
+    /// at least the given fraction of them, using the cached sorted draws.
+    ///
+    /// # Arguments
+    /// * `idx` - Position of the parameter
+    /// * `prob` - Fraction of the draws in the interval, in (0, 1)
+    pub fn hdi(&mut self, idx: usize, prob: f64) -> Result<(f64, f64), Error> {
+        hdi(self.sorted_draws(idx)?, prob)
+    }
+
+    /// Fraction of the draws of a parameter over the chains at inverse
+    /// temperature one inside a region of practical equivalence
+    /// `[lower, upper]`, found by binary search in the cached sorted draws.
+    ///
+    /// # Arguments
+    /// * `idx` - Position of the parameter
+    /// * `lower` - Lower end of the region
+    /// * `upper` - Upper end of the region, at least `lower`
+    pub fn rope_probability(&mut self, idx: usize, lower: f64, upper: f64) -> Result<f64, Error> {
+        if lower.is_nan() || upper.is_nan() || lower > upper {
+            return Err(anyhow!("Region [{}, {}] must not be empty", lower, upper));
+        }
+        let sorted = self.sorted_draws(idx)?;
+        if sorted.is_empty() {
+            return Err(anyhow!("No draws of parameter {}", idx));
+        }
+        let inside =
+            sorted.partition_point(|&x| x <= upper) - sorted.partition_point(|&x| x < lower);
+        Ok(inside as f64 / sorted.len() as f64)
+    }
+
+    /// Feeds the draws of a chain from position `start` onwards to the cache,
+    /// if there is one and the chain is at inverse temperature one, and
+    /// drops the sorted draws.
+    fn update_cache(&mut self, chain: usize, start: usize) {
+        self.sorted.clear();
+        let target = self.target_chains();
+        if let Some(ref mut monitor) = self.cache {
+            if let Some(slot) = target.iter().position(|&c| c == chain) {
+                push_rows(monitor, &self.values, chain, slot, start);
+            }
+        }
+    }
+
+    /// Parameter names in column order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Number of parameters.
+    pub fn num_parameters(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Number of chains.
+    pub fn num_chains(&self) -> usize {
+        self.num_chains
+    }
+
+    /// Number of draws in the shortest chain, or zero without any chains.
+    pub fn num_draws(&self) -> usize {
+        self.values.lengths().into_iter().min().unwrap_or(0)
+    }
+
+    /// Precision the draws are stored with.
+    pub fn precision(&self) -> Precision {
+        self.values.precision()
+    }
+
+    /// Converts the container to store draws with the given precision.
+    /// Converting to `F32` rounds every value to single precision, and fails
+    /// if a finite value is too large for it.
+    pub fn into_precision(self, precision: Precision) -> Result<Draws, Error> {
+        if precision == self.precision() {
+            return Ok(self);
+        }
+        let target = Values::new(precision, 0);
+        for (idx, name) in self.names.iter().enumerate() {
+            for chain in self.values.parameter(idx).iter() {
+                if let Some(x) = target.overflowing(chain) {
+                    return Err(anyhow!(
+                        "Draw {:e} of {} is too large for single precision",
+                        x,
+                        name
+                    ));
+                }
+            }
+        }
+        let values = match precision {
+            Precision::F64 => Values::F64(
+                (0..self.names.len())
+                    .map(|p| self.values.parameter(p).into_owned())
+                    .collect(),
+            ),
+            Precision::F32 => Values::F32(
+                (0..self.names.len())
+                    .map(|p| {
+                        self.values
+                            .parameter(p)
+                            .iter()
+                            .map(|c| c.iter().map(|&x| x as f32).collect())
+                            .collect()
+                    })
+                    .collect(),
+            ),
+        };
+        Ok(Draws {
+            values,
+            // the caches hold f64 statistics of the old values
+            cache: None,
+            sorted: HashMap::new(),
+            ..self
+        })
+    }
+
+    /// Position of the parameter with the given name.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Positions of the parameters whose names match any of the patterns, in
+    /// column order.  A `*` in a pattern matches any run of characters, so
+    /// `theta[*]` selects every element of `theta` and a pattern without one
+    /// matches that name only.
+    pub fn select(&self, patterns: &[&str]) -> Vec<usize> {
+        (0..self.names.len())
+            .filter(|&idx| {
+                patterns
+                    .iter()
+                    .any(|pattern| wildcard_match(pattern, &self.names[idx]))
+            })
+            .collect()
+    }
+
+    /// Marks a chain as sampling a tempered distribution, as in parallel
+    /// tempering where only the chains at inverse temperature one sample the
+    /// target.  Chains start out at inverse temperature one.
+    ///
+    /// # Arguments
+    /// * `chain_idx` - Index of the chain
+    /// * `beta` - Inverse temperature of the chain, in (0, 1]
+    pub fn set_inverse_temperature(&mut self, chain_idx: usize, beta: f64) -> Result<(), Error> {
+        if !(beta > 0.0 && beta <= 1.0) {
+            return Err(anyhow!(
+                "Inverse temperature must be in (0, 1], got {}",
+                beta
+            ));
+        }
+        if chain_idx >= self.num_chains {
+            return Err(anyhow!(
+                "Chain {} does not exist, there are {} chains",
+                chain_idx,
+                self.num_chains
+            ));
+        }
+        if (self.inverse_temperatures[chain_idx] == 1.0) != (beta == 1.0) {
+            // the cached statistics are of the chains at inverse temperature one
+            self.cache = None;
+        }
+        self.inverse_temperatures[chain_idx] = beta;
+        self.sorted.clear();
+        Ok(())
+    }
+
+    /// Labels a chain, e.g. with the chain id and seed the sampler used.
+    ///
+    /// # Arguments
+    /// * `chain_idx` - Index of the chain
+    /// * `label` - Identity of the chain to show in messages
+    pub fn set_chain_label(&mut self, chain_idx: usize, label: ChainLabel) -> Result<(), Error> {
+        if chain_idx >= self.num_chains {
+            return Err(anyhow!(
+                "Chain {} does not exist, there are {} chains",
+                chain_idx,
+                self.num_chains
+            ));
+        }
+        self.labels[chain_idx] = Some(label);
+        Ok(())
+    }
+
+    /// Label of a chain, if one was set.
+    pub fn chain_label(&self, chain_idx: usize) -> Option<&ChainLabel> {
+        self.labels.get(chain_idx)?.as_ref()
+    }
+
+    /// Records the sampler that wrote the draws, see
+    /// [`Sampler`](enum.Sampler.html).
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.sampler = sampler;
+    }
+
+    /// Sampler that wrote the draws, `Sampler::Other` unless set.
+    pub fn sampler(&self) -> Sampler {
+        self.sampler
+    }
+
+    /// Name of a chain for messages: the id of its label, or its position
+    /// counting from one if it has none.
+    pub fn chain_name(&self, chain_idx: usize) -> String {
+        match self.chain_label(chain_idx) {
+            Some(label) => label.id.clone(),
+            None => (chain_idx + 1).to_string(),
+        }
+    }
+
+    /// Name of the chain at the given position among the
+    /// [`target_chains`](#method.target_chains), for messages about results
+    /// computed from [`target_parameter`](#method.target_parameter).
+    pub(crate) fn target_chain_name(&self, target_idx: usize) -> String {
+        match self.target_chains().get(target_idx) {
+            Some(&chain_idx) => self.chain_name(chain_idx),
+            None => (target_idx + 1).to_string(),
+        }
+    }
+
+    /// Keeps every `step`-th draw of every chain, starting with the first,
+    /// e.g. to save memory on long runs.  Chain labels and inverse
+    /// temperatures are kept.
+    ///
+    /// # Arguments
+    /// * `step` - Keep one draw out of this many, at least 1
+    pub fn thin(&self, step: usize) -> Result<Draws, Error> {
+        if step == 0 {
+            return Err(anyhow!("Thinning step must be at least 1"));
+        }
+        self.map_chains(|_, columns| {
+            let thinned = columns
+                .iter()
+                .map(|c| c.iter().step_by(step).copied().collect())
+                .collect();
+            Ok(vec![(None, thinned)])
+        })
+    }
+
+    /// Splits every chain into its first and second half, as split R hat
+    /// does, dropping the middle draw of chains of odd length.  Halves keep
+    /// the label of their chain with `a` or `b` appended to its name, so
+    /// messages about chain `3a` refer to the first half of chain 3.
+    pub fn split_chains(&self) -> Result<Draws, Error> {
+        self.map_chains(|label, columns| {
+            let n = columns.first().map_or(0, |c| c.len());
+            if n < 2 {
+                return Err(anyhow!(
+                    "Chain {} needs at least 2 draws to split",
+                    label.id
+                ));
+            }
+            let (first, second): (Array2, Array2) = columns
+                .into_iter()
+                .map(|c| (c[..n / 2].to_vec(), c[n - n / 2..].to_vec()))
+                .unzip();
+            let half = |suffix: &str| ChainLabel {
+                id: format!("{}{}", label.id, suffix),
+                ..label.clone()
+            };
+            Ok(vec![(Some(half("a")), first), (Some(half("b")), second)])
+        })
+    }
+
+    /// Builds a new container by replacing every chain with the chains
+    /// returned by `f`, given the chain's label, or one with its name if it
+    /// has none, and its columns.  New chains get the returned label, or
+    /// keep that of the original chain for `None`, and keep its inverse
+    /// temperature.
+    fn map_chains<F>(&self, f: F) -> Result<Draws, Error>
+    where
+        F: Fn(&ChainLabel, Array2) -> Result<Vec<(Option<ChainLabel>, Array2)>, Error>,
+    {
+        let parameters: Vec<Cow<'_, Array2>> = (0..self.names.len())
+            .map(|p| self.values.parameter(p))
+            .collect();
+        let mut result = Draws::with_precision(self.names.clone(), self.precision());
+        result.sampler = self.sampler;
+        for chain in 0..self.num_chains {
+            let columns: Array2 = parameters.iter().map(|p| p[chain].clone()).collect();
+            let label = self
+                .chain_label(chain)
+                .cloned()
+                .unwrap_or_else(|| ChainLabel::new(&self.chain_name(chain)));
+            for (new_label, new_columns) in f(&label, columns)? {
+                result.push_chain(new_columns)?;
+                let idx = result.num_chains - 1;
+                result.inverse_temperatures[idx] = self.inverse_temperatures[chain];
+                result.labels[idx] = new_label.or_else(|| self.labels[chain].clone());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Inverse temperature of every chain.
+    pub fn inverse_temperatures(&self) -> &[f64] {
+        &self.inverse_temperatures
+    }
+
+    /// Indices of the chains at inverse temperature one, which are all of
+    /// them unless some were marked as tempered.
+    pub fn target_chains(&self) -> Vec<usize> {
+        (0..self.num_chains)
+            .filter(|&c| self.inverse_temperatures[c] == 1.0)
+            .collect()
+    }
+
+    /// Chains at the given inverse temperature for the parameter at the
+    /// given position, e.g. to check the mixing of the hotter chains of a
+    /// parallel tempering run.
+    pub fn parameter_at_temperature(&self, idx: usize, beta: f64) -> Array2 {
+        let chains = self.values.parameter(idx);
+        (0..self.num_chains)
+            .filter(|&c| self.inverse_temperatures[c] == beta)
+            .map(|c| chains[c].clone())
+            .collect()
+    }
+
+    /// Chains that sample the target distribution for the parameter at the
+    /// given position, which is what the summaries and diagnostics of whole
+    /// containers use.  Borrows the chains when none are tempered.
+    pub fn target_parameter(&self, idx: usize) -> Cow<'_, Array2> {
+        if self.inverse_temperatures.iter().all(|&b| b == 1.0) {
+            self.values.parameter(idx)
+        } else {
+            Cow::Owned(self.parameter_at_temperature(idx, 1.0))
+        }
+    }
+
+    /// Regroups the output of a parallel tempering sampler that records the
+    /// inverse temperature of each draw in a column, e.g. when chains swap
+    /// temperatures instead of states.  Returns one chain per distinct
+    /// inverse temperature, from coldest to hottest, holding the draws of all
+    /// chains made at that temperature in order, with the temperature column
+    /// removed.  Chains at the same temperature are concatenated, so the
+    /// result only makes sense when each temperature is held by one replica
+    /// at a time.
+    ///
+    /// # Arguments
+    /// * `column` - Name of the column with the inverse temperature of each draw
+    pub fn by_temperature(&self, column: &str) -> Result<Draws, Error> {
+        let beta_idx = self
+            .index_of(column)
+            .ok_or_else(|| anyhow!("No parameter named {:?}", column))?;
+        let betas = self.values.parameter(beta_idx);
+        let mut levels: Vec<f64> = Vec::new();
+        for &beta in betas.iter().flatten() {
+            if !(beta > 0.0 && beta <= 1.0) {
+                return Err(anyhow!(
+                    "Inverse temperature must be in (0, 1], got {}",
+                    beta
+                ));
+            }
+            if !levels.contains(&beta) {
+                levels.push(beta);
+            }
+        }
+        levels.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let kept: Vec<usize> = (0..self.names.len()).filter(|&p| p != beta_idx).collect();
+        let mut result = Draws::with_precision(
+            kept.iter().map(|&p| self.names[p].clone()).collect(),
+            self.precision(),
+        );
+        result.sampler = self.sampler;
+        for (level_idx, &level) in levels.iter().enumerate() {
+            let mut columns = vec![Vec::new(); kept.len()];
+            for (chain, chain_betas) in betas.iter().enumerate() {
+                for (i, &beta) in chain_betas.iter().enumerate() {
+                    if beta == level {
+                        for (column, &p) in columns.iter_mut().zip(kept.iter()) {
+                            column.push(self.values.value(p, chain, i));
+                        }
+                    }
+                }
+            }
+            result.push_chain(columns)?;
+            result.set_inverse_temperature(level_idx, level)?;
+        }
+        Ok(result)
+    }
+
+    /// Chains for the parameter with the given name.  With single precision
+    /// storage the chains are converted to `f64` on each call.
+    pub fn get(&self, name: &str) -> Option<Cow<'_, Array2>> {
+        self.index_of(name).map(|idx| self.values.parameter(idx))
+    }
+
+    /// Chains for the parameter at the given position.  With single
+    /// precision storage the chains are converted to `f64` on each call.
+    pub fn parameter(&self, idx: usize) -> Cow<'_, Array2> {
+        self.values.parameter(idx)
+    }
+
+    /// Finds the draw with the highest log density, e.g. as a starting point
+    /// for an optimizer.  This is the best point the sampler happened to
+    /// visit, not the maximum a posteriori estimate: the mode itself is
+    /// almost never sampled, especially in high dimensions.  Draws with a
+    /// `NaN` log density are ignored and ties go to the earliest draw.
+    ///
+    /// # Arguments
+    /// * `lp_name` - Name of the log density parameter, e.g. `lp__` for Stan
+    ///   or `lp` for Turing.jl
+    pub fn best_draw(&self, lp_name: &str) -> Result<BestDraw, Error> {
+        let lp_idx = self
+            .index_of(lp_name)
+            .ok_or_else(|| anyhow!("No parameter named {:?}", lp_name))?;
+        let mut best: Option<(usize, usize, f64)> = None;
+        for chain in 0..self.num_chains {
+            // only draws where every parameter has a value
+            let len = (0..self.names.len())
+                .map(|p| self.values.chain_len(p, chain))
+                .min()
+                .unwrap_or(0);
+            for i in 0..len {
+                let lp = self.values.value(lp_idx, chain, i);
+                if best.map_or(!lp.is_nan(), |(_, _, max)| lp > max) {
+                    best = Some((chain, i, lp));
+                }
+            }
+        }
+        let (chain, iteration, lp) = best.ok_or_else(|| anyhow!("No draws of {:?}", lp_name))?;
+        Ok(BestDraw {
+            chain,
+            iteration,
+            lp,
+            values: (0..self.names.len())
+                .map(|p| self.values.value(p, chain, iteration))
+                .collect(),
+        })
+    }
+
+    /// Adds a new parameter after the existing ones, with one chain of draws
+    /// for each existing chain.  Cached diagnostics are dropped and computed
+    /// again, including the new parameter, on the next call to
+    /// [`cached_diagnostics`](#method.cached_diagnostics).
+    ///
+    /// # Arguments
+    /// * `name` - Name of the new parameter, which must not exist yet
+    /// * `chains` - Draws of the new parameter in each chain
+    pub fn add_parameter(&mut self, name: &str, chains: Array2) -> Result<(), Error> {
+        if self.index_of(name).is_some() {
+            return Err(anyhow!("Parameter {:?} already exists", name));
+        }
+        if chains.len() != self.num_chains {
+            return Err(anyhow!(
+                "Parameter has {} chains but there are {}",
+                chains.len(),
+                self.num_chains
+            ));
+        }
+        if !self.names.is_empty() {
+            for (chain, draws) in chains.iter().enumerate() {
+                let len = self.values.chain_len(0, chain);
+                if draws.len() != len {
+                    return Err(anyhow!(
+                        "Chain {} of the parameter has {} draws but {} are expected",
+                        chain,
+                        draws.len(),
+                        len
+                    ));
+                }
+            }
+        }
+        for chain in chains.iter() {
+            self.check_range(name, chain)?;
+        }
+        self.names.push(name.to_string());
+        self.values.push_parameter(chains);
+        self.cache = None;
+        Ok(())
+    }
+
+    /// Adds a parameter computed draw by draw from the existing ones, so that
+    /// summaries and diagnostics apply to derived quantities just as to
+    /// sampled ones.  The expression can use `+`, `-`, `*`, `/`, parentheses,
+    /// numbers, the functions `exp`, `log` and `pow` and parameter names
+    /// such as `theta[1]`, `sigma.y` or `lp__`, nested at most 256 levels
+    /// deep.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the new parameter, which must not exist yet
+    /// * `expression` - Expression to evaluate, e.g. `theta[1] - theta[2]`
+    pub fn derive(&mut self, name: &str, expression: &str) -> Result<(), Error> {
+        let expr = Expr::parse(expression, |n| self.index_of(n))
+            .with_context(|| format!("Failed to parse {:?}", expression))?;
+        let chains = (0..self.num_chains)
+            .map(|chain| {
+                // only draws where every parameter has a value
+                let len = (0..self.names.len())
+                    .map(|p| self.values.chain_len(p, chain))
+                    .min()
+                    .unwrap_or(0);
+                (0..len)
+                    .map(|i| expr.eval(&|p| self.values.value(p, chain, i)))
+                    .collect()
+            })
+            .collect();
+        self.add_parameter(name, chains)
+    }
+}
+
+/// Matches a name against a pattern in which `*` stands for any run of
+/// characters.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Pushes the draws of one chain from position `start` onwards into a monitor
+/// row by row, as the monitor's chain `slot`.
+fn push_rows(
+    monitor: &mut OnlineMonitor,
+    values: &Values,
+    chain: usize,
+    slot: usize,
+    start: usize,
+) {
+    let num_parameters = monitor.names().len();
+    let len = (0..num_parameters)
+        .map(|p| values.chain_len(p, chain))
+        .min()
+        .unwrap_or(0);
+    let mut draw = vec![0.0; num_parameters];
+    for i in start..len {
+        for (p, x) in draw.iter_mut().enumerate() {
+            *x = values.value(p, chain, i);
+        }
+        // the draw has one value per monitored parameter, so this only
+        // fails for draws with non-finite values, which are left out
+        let _ = monitor.push(slot, &draw);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_chains_layout() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chain1 = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+        let chain2 = vec![vec![7.0, 8.0], vec![9.0, 10.0]];
+        let draws = Draws::from_chains(names, vec![chain1, chain2]).unwrap();
+        assert_eq!(draws.num_parameters(), 2);
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(draws.num_draws(), 2);
+        assert_eq!(draws.index_of("b"), Some(1));
+        assert_eq!(
+            *draws.get("a").unwrap(),
+            vec![vec![1.0, 2.0, 3.0], vec![7.0, 8.0]]
+        );
+        assert_eq!(
+            *draws.parameter(1),
+            vec![vec![4.0, 5.0, 6.0], vec![9.0, 10.0]]
+        );
+        assert!(draws.get("c").is_none());
+    }
+
+    #[test]
+    fn test_best_draw() {
+        let names = vec!["lp__".to_string(), "theta".to_string()];
+        let chain1 = vec![vec![-3.0, f64::NAN, -1.5], vec![0.1, 0.2, 0.3]];
+        let chain2 = vec![vec![-2.0, -1.0], vec![0.4, 0.5]];
+        let draws = Draws::from_chains(names, vec![chain1, chain2]).unwrap();
+        let best = draws.best_draw("lp__").unwrap();
+        assert_eq!(
+            best,
+            BestDraw {
+                chain: 1,
+                iteration: 1,
+                lp: -1.0,
+                values: vec![-1.0, 0.5],
+            }
+        );
+        assert!(draws.best_draw("lp").is_err());
+        assert!(Draws::new(vec!["lp__".to_string()])
+            .best_draw("lp__")
+            .is_err());
+    }
+
+    #[test]
+    fn test_merge_reorders_parameters() {
+        let mut draws = Draws::from_chains(
+            vec!["a".to_string(), "b".to_string()],
+            vec![vec![vec![1.0, 2.0], vec![3.0, 4.0]]],
+        )
+        .unwrap();
+        let other = Draws::from_chains(
+            vec!["b".to_string(), "a".to_string()],
+            vec![vec![vec![7.0], vec![5.0]], vec![vec![8.0], vec![6.0]]],
+        )
+        .unwrap();
+        draws.merge(&other).unwrap();
+        assert_eq!(draws.num_chains(), 3);
+        assert_eq!(
+            *draws.get("a").unwrap(),
+            vec![vec![1.0, 2.0], vec![5.0], vec![6.0]]
+        );
+
+        let missing = Draws::new(vec!["a".to_string(), "c".to_string()]);
+        assert!(draws.merge(&missing).is_err());
+        assert!(draws.merge(&Draws::new(vec!["a".to_string()])).is_err());
+        assert_eq!(draws.num_chains(), 3);
+    }
+
+    #[test]
+    fn test_select() {
+        let names = ["mu", "theta[1]", "theta[2]", "tau", "theta_raw[1]"];
+        let draws = Draws::new(names.iter().map(|n| n.to_string()).collect());
+        assert_eq!(draws.select(&["theta[*]"]), vec![1, 2]);
+        assert_eq!(draws.select(&["t*u", "mu"]), vec![0, 3]);
+        assert_eq!(draws.select(&["*[1]"]), vec![1, 4]);
+        assert_eq!(draws.select(&["*"]).len(), 5);
+        assert!(draws.select(&["theta"]).is_empty());
+        assert!(wildcard_match("a*a", "aa"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+
+    #[test]
+    fn test_inverse_temperatures() {
+        let names = vec!["a".to_string()];
+        let chains = vec![vec![vec![1.0, 2.0]], vec![vec![3.0, 4.0]], vec![vec![5.0]]];
+        let mut draws = Draws::from_chains(names, chains).unwrap();
+        assert_eq!(draws.target_chains(), vec![0, 1, 2]);
+        assert!(matches!(draws.target_parameter(0), Cow::Borrowed(_)));
+        draws.set_inverse_temperature(1, 0.5).unwrap();
+        assert_eq!(draws.inverse_temperatures(), &[1.0, 0.5, 1.0]);
+        assert_eq!(draws.target_chains(), vec![0, 2]);
+        assert_eq!(*draws.target_parameter(0), vec![vec![1.0, 2.0], vec![5.0]]);
+        assert_eq!(draws.parameter_at_temperature(0, 0.5), vec![vec![3.0, 4.0]]);
+        assert!(draws.set_inverse_temperature(3, 0.5).is_err());
+        assert!(draws.set_inverse_temperature(0, 1.5).is_err());
+
+        let mut merged = Draws::new(vec!["a".to_string()]);
+        merged.merge(&draws).unwrap();
+        assert_eq!(merged, draws);
+    }
+
+    #[test]
+    fn test_by_temperature() {
+        let names = vec!["a".to_string(), "beta".to_string()];
+        // two replicas that swap temperatures after the second draw
+        let chains = vec![
+            vec![vec![1.0, 2.0, 3.0], vec![1.0, 1.0, 0.5]],
+            vec![vec![10.0, 20.0, 30.0], vec![0.5, 0.5, 1.0]],
+        ];
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let tempered = draws.by_temperature("beta").unwrap();
+        assert_eq!(tempered.names(), &["a".to_string()]);
+        assert_eq!(tempered.inverse_temperatures(), &[1.0, 0.5]);
+        assert_eq!(
+            *tempered.parameter(0),
+            vec![vec![1.0, 2.0, 30.0], vec![3.0, 10.0, 20.0]]
+        );
+        assert!(draws.by_temperature("a").is_err());
+        assert!(draws.by_temperature("b").is_err());
+    }
+
+    #[test]
+    fn test_push_chain_wrong_width() {
+        let mut draws = Draws::new(vec!["a".to_string()]);
+        assert_eq!(draws.num_draws(), 0);
+        assert!(draws.push_chain(vec![vec![1.0], vec![2.0]]).is_err());
+        assert_eq!(draws.num_chains(), 0);
+
+        let mut draws = Draws::new(vec!["a".to_string(), "b".to_string()]);
+        let ragged = vec![vec![1.0, 2.0], vec![3.0]];
+        assert!(draws.push_chain(ragged).is_err());
+        assert_eq!(draws.num_chains(), 0);
+    }
+
+    #[test]
+    fn test_append_draws_updates_cached_diagnostics() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chain1 = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+        let chain2 = vec![vec![7.0, 8.0, 9.0], vec![9.0, 10.0, 11.0]];
+        let mut draws = Draws::from_chains(names.clone(), vec![chain1, chain2]).unwrap();
+        draws.cached_diagnostics();
+        draws
+            .append_draws(0, vec![vec![4.0, 5.0], vec![7.0, 8.0]])
+            .unwrap();
+        draws.push_chain(vec![vec![0.5], vec![1.5]]).unwrap();
+        assert_eq!(draws.get("a").unwrap()[0], vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        // the incrementally updated cache matches one built from scratch
+        let mut fresh = Draws::from_chains(
+            names,
+            (0..3)
+                .map(|c| vec![draws.parameter(0)[c].clone(), draws.parameter(1)[c].clone()])
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(fresh, draws);
+        // streaming quantiles depend on the order draws arrive in, so only
+        // compare the statistics that don't
+        let (cached, expected) = (draws.cached_diagnostics(), fresh.cached_diagnostics());
+        for (c, e) in cached.parameters.iter().zip(expected.parameters.iter()) {
+            assert_eq!((c.num_draws, c.ess, c.rhat), (e.num_draws, e.ess, e.rhat));
+            assert_abs_diff_eq!(c.mean, e.mean, epsilon = 1e-12);
+            assert_abs_diff_eq!(c.sd, e.sd, epsilon = 1e-12);
+        }
+        let a = &draws.cached_diagnostics().parameters[0];
+        assert_eq!(a.num_draws, 9);
+        assert_abs_diff_eq!(a.mean, 39.5 / 9.0, epsilon = 1e-12);
+
+        // tempered chains are left out, also of the updates
+        draws.set_inverse_temperature(1, 0.5).unwrap();
+        assert_eq!(draws.cached_diagnostics().parameters[0].num_draws, 6);
+        draws
+            .append_draws(1, vec![vec![100.0], vec![100.0]])
+            .unwrap();
+        draws.append_draws(2, vec![vec![2.5], vec![3.5]]).unwrap();
+        let a = &draws.cached_diagnostics().parameters[0];
+        assert_eq!(a.num_draws, 7);
+        assert_abs_diff_eq!(a.mean, 18.0 / 7.0, epsilon = 1e-12);
+        draws.set_inverse_temperature(1, 1.0).unwrap();
+        assert_eq!(draws.cached_diagnostics().parameters[0].num_draws, 11);
+    }
+
+    #[test]
+    fn test_append_draws_errors() {
+        let mut draws = Draws::new(vec!["a".to_string(), "b".to_string()]);
+        assert!(draws.append_draws(0, vec![vec![1.0], vec![2.0]]).is_err());
+        draws.push_chain(vec![vec![1.0], vec![2.0]]).unwrap();
+        assert!(draws.append_draws(0, vec![vec![1.0]]).is_err());
+        assert!(draws
+            .append_draws(0, vec![vec![1.0, 2.0], vec![3.0]])
+            .is_err());
+        assert_eq!(draws.num_draws(), 1);
+    }
+
+    #[test]
+    fn test_f32_storage() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chain = vec![vec![0.1, 0.25, 1e10], vec![1.0, 2.0, 3.0]];
+        let mut draws = Draws::with_precision(names.clone(), Precision::F32);
+        draws.push_chain(chain.clone()).unwrap();
+        draws.append_draws(0, vec![vec![0.5], vec![4.0]]).unwrap();
+        assert_eq!(draws.precision(), Precision::F32);
+        assert_eq!(draws.num_draws(), 4);
+        let a = draws.get("a").unwrap();
+        assert_eq!(a[0][0], 0.1f32 as f64);
+        assert_eq!(a[0][1], 0.25);
+        assert_abs_diff_eq!(a[0][2], 1e10, epsilon = 1e3);
+        assert_abs_diff_eq!(draws.cached_diagnostics().parameters[1].mean, 2.5);
+        // draws with non-finite values are left out of the cached statistics
+        draws
+            .append_draws(0, vec![vec![f64::NAN], vec![100.0]])
+            .unwrap();
+        let snapshot = draws.cached_diagnostics();
+        assert_eq!(snapshot.num_draws, 4);
+        assert_abs_diff_eq!(snapshot.parameters[1].mean, 2.5);
+
+        let double = Draws::from_chains(names.clone(), vec![chain.clone()]).unwrap();
+        let single = double.clone().into_precision(Precision::F32).unwrap();
+        assert_eq!(single.parameter(1), double.parameter(1));
+        assert_ne!(single, double);
+        assert_eq!(
+            single.into_precision(Precision::F64).unwrap().precision(),
+            Precision::F64
+        );
+
+        // finite draws beyond the range of f32 are an error rather than an
+        // infinity, while infinite ones are kept
+        let mut draws = Draws::with_precision(names.clone(), Precision::F32);
+        let err = draws
+            .push_chain(vec![vec![1.0, 1e39, 3.0], vec![1.0; 3]])
+            .unwrap_err();
+        assert!(err.to_string().contains("Draw 1e39 of a is too large"));
+        draws.push_chain(chain.clone()).unwrap();
+        assert!(draws
+            .append_draws(0, vec![vec![1.0], vec![-1e300]])
+            .is_err());
+        assert!(draws
+            .add_parameter("c", vec![vec![0.0, 0.0, 1e40]])
+            .is_err());
+        draws
+            .append_draws(0, vec![vec![f64::INFINITY], vec![1.0]])
+            .unwrap();
+        assert_eq!(draws.num_draws(), 4);
+        assert_eq!(draws.names().len(), 2);
+        let huge = vec![chain.clone(), vec![vec![1.0, 2.0, 3.0], vec![f64::MAX; 3]]];
+        let double = Draws::from_chains(names.clone(), huge).unwrap();
+        assert!(double.clone().into_precision(Precision::F32).is_err());
+        // nothing is merged if any chain doesn't fit
+        assert!(draws.merge(&double).is_err());
+        assert_eq!(draws.num_chains(), 1);
+    }
+
+    #[test]
+    fn test_derive() {
+        let names = vec!["theta[1]".to_string(), "theta[2]".to_string()];
+        let chains = vec![
+            vec![vec![1.0, 2.0, 3.0], vec![0.5, 0.5, 1.0]],
+            vec![vec![4.0, 5.0, 6.0], vec![2.0, 1.0, 0.0]],
+        ];
+        let mut draws = Draws::from_chains(names, chains).unwrap();
+        assert_eq!(draws.cached_diagnostics().parameters.len(), 2);
+        draws.derive("theta_diff", "theta[1] - theta[2]").unwrap();
+        draws.derive("scaled", "pow(theta_diff, 2) / 2").unwrap();
+        assert_eq!(draws.num_parameters(), 4);
+        assert_eq!(
+            *draws.get("theta_diff").unwrap(),
+            vec![vec![0.5, 1.5, 2.0], vec![2.0, 4.0, 6.0]]
+        );
+        assert_eq!(draws.get("scaled").unwrap()[1], vec![2.0, 8.0, 18.0]);
+        // the cache is rebuilt with the new parameters
+        assert_eq!(draws.cached_diagnostics().parameters.len(), 4);
+
+        assert!(draws.derive("scaled", "1").is_err());
+        assert!(draws.derive("bad", "theta[3] + 1").is_err());
+        assert!(draws.derive("bad", "log(").is_err());
+        assert!(draws.add_parameter("short", vec![vec![1.0]; 2]).is_err());
+        assert!(draws.add_parameter("one", vec![vec![1.0; 3]]).is_err());
+        assert_eq!(draws.num_parameters(), 4);
+    }
+
+    #[test]
+    fn test_sorted_draws_cache() {
+        let names = vec!["a".to_string()];
+        let chains = vec![vec![vec![3.0, 1.0, 4.0]], vec![vec![1.5, 9.0, 2.0]]];
+        let mut draws = Draws::from_chains(names, chains).unwrap();
+        assert_eq!(
+            draws.sorted_draws(0).unwrap(),
+            &[1.0, 1.5, 2.0, 3.0, 4.0, 9.0]
+        );
+        assert_eq!(
+            draws.quantiles(0, &[0.0, 0.5, 1.0]).unwrap(),
+            vec![1.0, 2.5, 9.0]
+        );
+        assert_eq!(draws.hdi(0, 0.5).unwrap(), (1.0, 3.0));
+        assert_abs_diff_eq!(draws.rope_probability(0, 1.5, 3.0).unwrap(), 0.5);
+        assert!(draws.rope_probability(0, 2.0, 1.0).is_err());
+        assert!(draws.sorted_draws(1).is_err());
+
+        // adding draws or tempering a chain drops the cached sort
+        draws.append_draws(0, vec![vec![0.0]]).unwrap();
+        assert_eq!(draws.sorted_draws(0).unwrap()[0], 0.0);
+        draws.set_inverse_temperature(1, 0.5).unwrap();
+        assert_eq!(draws.sorted_draws(0).unwrap(), &[0.0, 1.0, 3.0, 4.0]);
+        draws
+            .add_parameter("b", vec![vec![f64::NAN; 4], vec![0.0; 3]])
+            .unwrap();
+        assert!(draws.quantiles(1, &[0.5]).is_err());
+        let inf = f64::INFINITY;
+        draws
+            .add_parameter("c", vec![vec![0.0, inf, inf, inf], vec![0.0; 3]])
+            .unwrap();
+        assert!(draws.hdi(2, 0.3).is_err());
+    }
+
+    #[test]
+    fn test_chain_labels() {
+        let names = vec!["a".to_string()];
+        let chains = vec![vec![vec![1.0, 2.0, 3.0, 4.0, 5.0]], vec![vec![6.0, 7.0]]];
+        let mut draws = Draws::from_chains(names, chains).unwrap();
+        assert_eq!(draws.chain_name(1), "2");
+        assert!(draws.chain_label(0).is_none());
+        let label = ChainLabel {
+            id: "3".to_string(),
+            seed: Some(42),
+            host: Some("node-1".to_string()),
+        };
+        draws.set_chain_label(0, label.clone()).unwrap();
+        assert!(draws.set_chain_label(2, ChainLabel::new("x")).is_err());
+        assert_eq!(draws.chain_name(0), "3");
+        draws.set_inverse_temperature(1, 0.5).unwrap();
+
+        let thinned = draws.thin(2).unwrap();
+        assert_eq!(*thinned.parameter(0), vec![vec![1.0, 3.0, 5.0], vec![6.0]]);
+        assert_eq!(thinned.chain_label(0), Some(&label));
+        assert_eq!(thinned.inverse_temperatures(), &[1.0, 0.5]);
+        assert!(draws.thin(0).is_err());
+
+        let split = draws.split_chains().unwrap();
+        assert_eq!(split.num_chains(), 4);
+        assert_eq!(
+            *split.parameter(0),
+            vec![vec![1.0, 2.0], vec![4.0, 5.0], vec![6.0], vec![7.0]]
+        );
+        let names: Vec<String> = (0..4).map(|c| split.chain_name(c)).collect();
+        assert_eq!(names, vec!["3a", "3b", "2a", "2b"]);
+        assert_eq!(split.chain_label(1).unwrap().seed, Some(42));
+        assert_eq!(
+            split.chain_label(1).unwrap().host.as_deref(),
+            Some("node-1")
+        );
+        assert_eq!(split.target_chain_name(1), "3b");
+        assert_eq!(split.inverse_temperatures(), &[1.0, 1.0, 0.5, 0.5]);
+        assert!(thinned.split_chains().is_err());
+
+        let mut merged = draws.clone();
+        merged.merge(&draws).unwrap();
+        assert_eq!(merged.chain_label(2), Some(&label));
+        assert_eq!(merged.chain_name(3), "4");
+    }
+}
@@ -1,7 +1,23 @@
-use crate::utils::{flatten, mean, sample_variance, split_chains};
+use crate::spectral::{fft, spectrum0};
+use crate::utils::{
+    autocovariance, beta_quantile, flatten, log_sum_exp, mean, normal_quantile, quantile_sorted,
+    quantiles, sample_variance, sorted_ranks, split_slices,
+};
 use crate::{Array1, Array2};
 use anyhow::{anyhow, Error, Result};
-use arima::acf;
+
+/// Options for the Geyer effective sample size estimators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EssOptions {
+    /// Largest autocovariance lag to compute, or `None` for all of them.
+    /// Computing the autocovariances is quadratic in the number of draws, so
+    /// capping the lag saves most of the work for long, well mixing chains
+    /// whose autocorrelations die off quickly.  If the initial positive
+    /// sequence has not terminated by `max_lag` the sum is truncated there,
+    /// which overestimates the ESS, so the cap should be well beyond the lag
+    /// where autocorrelations become negligible.  Must be at least 3.
+    pub max_lag: Option<usize>,
+}
 
 /// Computes the effective sample size (ESS) for the specified
 /// parameter across all kept samples.  The value returned is the
@@ -21,21 +37,50 @@ use arima::acf;
 ///
 /// # Arguments
 /// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
-///              the same parameter
+///   the same parameter
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_chains = chains.len()))
+)]
 pub fn compute_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
-    let num_chains = chains.len();
+    compute_effective_sample_size_with_options(chains, &EssOptions::default())
+}
+
+/// Computes the effective sample size (ESS) for the specified parameter like
+/// [`compute_effective_sample_size`](fn.compute_effective_sample_size.html),
+/// with options such as a cap on the number of autocovariance lags.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `options` - Estimator options
+pub fn compute_effective_sample_size_with_options(
+    chains: &Array2,
+    options: &EssOptions,
+) -> Result<f64, Error> {
+    let chains: Vec<&[f64]> = chains.iter().map(|c| c.as_slice()).collect();
+    effective_sample_size(&chains, options)
+}
+
+/// Geyer estimator behind both the plain and split effective sample size,
+/// operating on borrowed chains so that splitting needs no copies.
+fn effective_sample_size(chains: &[&[f64]], options: &EssOptions) -> Result<f64, Error> {
     let num_draws = chains.iter().map(|c| c.len()).min().unwrap();
 
     if num_draws < 4 {
         return Err(anyhow!("Must have at least 4 samples to compute ESS"));
     }
+    let max_lag = options.max_lag.unwrap_or(num_draws);
+    if max_lag < 3 {
+        return Err(anyhow!("Maximum lag must be at least 3 to compute ESS"));
+    }
 
     let mut curr = chains[0][0];
     let mut prev = chains[0][0];
     let mut all_same = true;
-    for c in 0..chains.len() {
-        for i in 0..chains[0].len() {
-            curr = chains[c][i];
+    for chain in chains.iter() {
+        for &value in chain.iter() {
+            curr = value;
             if !curr.is_finite() {
                 return Err(anyhow!("All values must be finite to compute ESS"));
             }
@@ -52,18 +97,32 @@ pub fn compute_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
 
     let mut chain_acov: Array2 = Vec::new();
     let mut chain_mean: Array1 = Vec::new();
-    let mut chain_var: Array1 = Vec::new();
     for chain in chains.iter() {
-        let acov = acf::acf(&chain, None, true).unwrap();
-        chain_mean.push(mean(&chain)?);
-        chain_var.push(acov[0] * num_draws as f64 / (num_draws as f64 - 1.0));
-        chain_acov.push(acov);
+        chain_acov.push(autocovariance(chain, max_lag)?);
+        chain_mean.push(mean(chain)?);
     }
+    ess_from_autocovariances(&chain_acov, &chain_mean, num_draws, max_lag)
+}
+
+/// Combines the biased autocovariances of chains with `num_draws` draws
+/// each, for lags up to `max_lag`, into Geyer's initial monotone sequence
+/// estimate of the effective sample size.
+pub(crate) fn ess_from_autocovariances(
+    chain_acov: &Array2,
+    chain_mean: &[f64],
+    num_draws: usize,
+    max_lag: usize,
+) -> Result<f64, Error> {
+    let num_chains = chain_acov.len();
+    let chain_var: Array1 = chain_acov
+        .iter()
+        .map(|acov| acov[0] * num_draws as f64 / (num_draws as f64 - 1.0))
+        .collect();
 
     let mean_var = mean(&chain_var)?;
     let mut var_plus = mean_var * (num_draws as f64 - 1.0) / num_draws as f64;
     if num_chains > 1 {
-        var_plus += sample_variance(&chain_mean)?;
+        var_plus += sample_variance(chain_mean)?;
     }
 
     let mut rho_hat_s: Array1 = vec![0.0; num_draws];
@@ -81,7 +140,7 @@ pub fn compute_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
     // leave the last pair of autocorrelations as a bias term that
     // reduces variance in the case of antithetical chains.
     let mut s = 1;
-    while s < (num_draws - 4) && (rho_hat_even + rho_hat_odd) > 0.0 {
+    while s < (num_draws - 4) && s + 2 <= max_lag && (rho_hat_even + rho_hat_odd) > 0.0 {
         for c in 0..num_chains {
             acov_s[c] = chain_acov[c][s + 1];
         }
@@ -143,16 +202,230 @@ pub fn compute_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
 ///
 /// # Arguments
 /// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
-///              the same parameter
+///   the same parameter
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_chains = chains.len()))
+)]
 pub fn compute_split_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
-    let num_draws = chains.iter().map(|c| c.len()).min().unwrap();
-    // trim chains to the length of the shortest chain
-    let mut trimmed = Vec::new();
-    for chain in chains.iter() {
-        trimmed.push(chain[..num_draws].to_vec());
+    compute_split_effective_sample_size_with_options(chains, &EssOptions::default())
+}
+
+/// Computes the split effective sample size (ESS) for the specified parameter
+/// like [`compute_split_effective_sample_size`](fn.compute_split_effective_sample_size.html),
+/// with options such as a cap on the number of autocovariance lags.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `options` - Estimator options
+pub fn compute_split_effective_sample_size_with_options(
+    chains: &Array2,
+    options: &EssOptions,
+) -> Result<f64, Error> {
+    let split = split_slices(chains)?;
+    effective_sample_size(&split, options)
+}
+
+/// Computes the effective sample size for estimating the `prob` quantile of
+/// the specified parameter, i.e. the split effective sample size of the
+/// indicator `I(x <= q)` where `q` is the empirical quantile over all chains.
+/// With `prob = 0.5` this is the ESS of the median, and the smaller of the
+/// values for 0.05 and 0.95 is the tail ESS.
+///
+/// See Vehtari et al. (2021)
+/// ["Rank-normalization, folding, and localization"](https://doi.org/10.1214/20-BA1221),
+/// as implemented by `ess_quantile` in the R package posterior.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `prob` - Probability of the quantile, strictly between 0 and 1
+pub fn compute_ess_quantile(chains: &Array2, prob: f64) -> Result<f64, Error> {
+    if !(prob > 0.0 && prob < 1.0) {
+        return Err(anyhow!(
+            "Quantile probability must be in (0, 1), got {}",
+            prob
+        ));
     }
-    let split = split_chains(trimmed)?;
-    compute_effective_sample_size(&split)
+    let flattened = flatten(chains);
+    if flattened.iter().any(|x| x.is_nan()) {
+        return Err(anyhow!("Cannot compute quantiles of NaN draws"));
+    }
+    let q = quantiles(&flattened, &[prob])?[0];
+    let indicators: Array2 = chains
+        .iter()
+        .map(|c| c.iter().map(|&x| if x <= q { 1.0 } else { 0.0 }).collect())
+        .collect();
+    compute_split_effective_sample_size(&indicators)
+}
+
+/// Effective sample size of one quantile, see
+/// [`compute_ess_quantile_profile`](fn.compute_ess_quantile_profile.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantileEfficiency {
+    /// Probability of the quantile
+    pub prob: f64,
+    /// Effective sample size of the quantile
+    pub ess: f64,
+    /// Effective sample size per draw, one for independent draws
+    pub efficiency: f64,
+}
+
+/// Computes the [quantile ESS](fn.compute_ess_quantile.html) over a grid of
+/// probabilities, e.g. `0.05, 0.10, ..., 0.95`, the efficiency profile of
+/// Vehtari et al. (2021) that shows where in the distribution the sampler
+/// mixes poorly.  Samplers often mix well in the bulk but slowly in a tail,
+/// e.g. in funnels, which a single bulk ESS hides.  The pooled draws are only
+/// sorted once for the whole grid.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `probs` - Probabilities of the quantiles, each strictly between 0 and 1
+pub fn compute_ess_quantile_profile(
+    chains: &Array2,
+    probs: &[f64],
+) -> Result<Vec<QuantileEfficiency>, Error> {
+    if let Some(p) = probs.iter().find(|&&p| !(p > 0.0 && p < 1.0)) {
+        return Err(anyhow!("Quantile probability must be in (0, 1), got {}", p));
+    }
+    let mut sorted = flatten(chains);
+    if sorted.iter().any(|x| x.is_nan()) {
+        return Err(anyhow!("Cannot compute quantiles of NaN draws"));
+    }
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let num_draws = sorted.len() as f64;
+    probs
+        .iter()
+        .map(|&prob| {
+            let q = quantile_sorted(&sorted, prob)?;
+            let indicators: Array2 = chains
+                .iter()
+                .map(|c| c.iter().map(|&x| if x <= q { 1.0 } else { 0.0 }).collect())
+                .collect();
+            let ess = compute_split_effective_sample_size(&indicators)?;
+            Ok(QuantileEfficiency {
+                prob,
+                ess,
+                efficiency: ess / num_draws,
+            })
+        })
+        .collect()
+}
+
+/// Bulk and tail effective sample sizes of one parameter, see
+/// [`compute_bulk_tail_ess`](fn.compute_bulk_tail_ess.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BulkTailEss {
+    /// Split ESS of the rank normalized draws
+    pub bulk: f64,
+    /// Smaller of the quantile ESS at 5% and 95%
+    pub tail: f64,
+}
+
+/// Normal scores of the pooled split draws, `qnorm((r - 3/8) / (S + 1/4))`
+/// for rank `r` among `S` draws, together with the sorted draws.
+pub(crate) fn rank_normalize(pooled: &[f64]) -> Result<(Array1, Array1), Error> {
+    if pooled.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to compute ESS"));
+    }
+    let (ranks, sorted) = sorted_ranks(pooled);
+    let s = pooled.len() as f64;
+    let z = ranks
+        .iter()
+        .map(|r| normal_quantile((r - 0.375) / (s + 0.25)))
+        .collect();
+    Ok((z, sorted))
+}
+
+/// Computes the bulk effective sample size of the specified parameter, the
+/// split ESS of the draws after replacing them by the normal scores of their
+/// ranks over all chains.  Unlike the plain split ESS it is well defined for
+/// heavy tailed distributions and doesn't change under monotone transforms
+/// of the parameter.
+///
+/// See Vehtari et al. (2021)
+/// ["Rank-normalization, folding, and localization"](https://doi.org/10.1214/20-BA1221),
+/// as implemented by `ess_bulk` in the R package posterior.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn compute_bulk_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
+    let split = split_slices(chains)?;
+    let (z, _) = rank_normalize(&split.concat())?;
+    effective_sample_size(
+        &z.chunks(split[0].len()).collect::<Vec<_>>(),
+        &EssOptions::default(),
+    )
+}
+
+/// Computes the bulk and tail effective sample sizes of the specified
+/// parameter together, sorting and ranking the draws only once instead of
+/// once for the bulk ESS and again for each tail quantile.  The bulk ESS is
+/// the one of
+/// [`compute_bulk_effective_sample_size`](fn.compute_bulk_effective_sample_size.html)
+/// and the tail ESS is the smaller of
+/// [`compute_ess_quantile`](fn.compute_ess_quantile.html) at 5% and 95%.
+/// Quantiles are taken over the draws used by the split ESS, so when the
+/// number of draws is odd the middle draw of each chain is left out of them.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn compute_bulk_tail_ess(chains: &Array2) -> Result<BulkTailEss, Error> {
+    let split = split_slices(chains)?;
+    let pooled = split.concat();
+    let half = split[0].len();
+    let (z, sorted) = rank_normalize(&pooled)?;
+    let options = EssOptions::default();
+    let bulk = effective_sample_size(&z.chunks(half).collect::<Vec<_>>(), &options)?;
+    let mut tail = f64::INFINITY;
+    for &prob in [0.05, 0.95].iter() {
+        let q = quantile_sorted(&sorted, prob)?;
+        let indicators: Array1 = pooled
+            .iter()
+            .map(|&x| if x <= q { 1.0 } else { 0.0 })
+            .collect();
+        tail = tail.min(effective_sample_size(
+            &indicators.chunks(half).collect::<Vec<_>>(),
+            &options,
+        )?);
+    }
+    Ok(BulkTailEss { bulk, tail })
+}
+
+/// Computes the Monte Carlo standard error of a quantile the way recent
+/// versions of Stan (2.33 and later) and the R package posterior do, rather
+/// than scaling the posterior sd by the square root of the ESS.  The quantile
+/// ESS gives a beta distribution for the probability of the order statistic
+/// at the quantile, and the MCSE is half the width of the interval between
+/// the order statistics at its 15.9% and 84.1% quantiles, which is one
+/// standard error either side for a normal distribution.
+///
+/// See Vehtari et al. (2021)
+/// ["Rank-normalization, folding, and localization"](https://doi.org/10.1214/20-BA1221),
+/// as implemented by `mcse_quantile` in posterior.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `prob` - Probability of the quantile, strictly between 0 and 1
+pub fn compute_mcse_quantile(chains: &Array2, prob: f64) -> Result<f64, Error> {
+    let ess = compute_ess_quantile(chains, prob)?;
+    let mut sorted = flatten(chains);
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len();
+    // the normal probabilities one sd either side of the mean, rounded as in
+    // posterior so that results match it exactly
+    let (a, b) = (ess * prob + 1.0, ess * (1.0 - prob) + 1.0);
+    let lower = beta_quantile(0.1586553, a, b);
+    let upper = beta_quantile(0.8413447, a, b);
+    // one based order statistics as in R
+    let lo = ((lower * n as f64).floor() as usize).max(1);
+    let hi = ((upper * n as f64).ceil() as usize).min(n);
+    Ok((sorted[hi - 1] - sorted[lo - 1]) / 2.0)
 }
 
 /// Computes the Monte Carlo Standard Error (MCSE) for the specified parameter
@@ -165,19 +438,519 @@ pub fn compute_split_effective_sample_size(chains: &Array2) -> Result<f64, Error
 ///
 /// # Arguments
 /// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
-///              the same parameter
+///   the same parameter
 pub fn compute_estimated_mcse(chains: &Array2) -> Result<f64, Error> {
-    let ess = compute_effective_sample_size(&chains)?;
+    let ess = compute_effective_sample_size(chains)?;
     let var = sample_variance(&flatten(chains))?;
     Ok((var / ess).sqrt())
 }
 
+/// Computes the Monte Carlo Standard Error (MCSE) of the posterior
+/// expectation of `f` applied to the specified parameter, e.g. `|x| x * x`
+/// for the second moment or `|x| (x > 0.0) as u8 as f64` for a probability.
+/// The transformed draws have their own autocorrelation, so the standard
+/// error uses the effective sample size of the transformed series rather
+/// than that of the parameter.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `f` - Function whose expectation is estimated
+pub fn compute_mcse_fn<F>(chains: &Array2, f: F) -> Result<f64, Error>
+where
+    F: Fn(f64) -> f64,
+{
+    let transformed: Array2 = chains
+        .iter()
+        .map(|c| c.iter().map(|&x| f(x)).collect())
+        .collect();
+    compute_estimated_mcse(&transformed)
+}
+
+/// Computes the effective sample size (ESS) for the specified parameter from
+/// the spectral density at frequency zero of each chain, estimated by fitting
+/// an autoregressive model.  Each chain contributes `n * var / spectrum0` and
+/// the contributions are summed, as in `effectiveSize` from the R package
+/// [coda](https://cran.r-project.org/package=coda).
+///
+/// Unlike the Stan estimators above this does not account for differences
+/// between chains, so it is mostly useful for comparison with coda output.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_chains = chains.len()))
+)]
+pub fn compute_spectrum0_effective_sample_size(chains: &Array2) -> Result<f64, Error> {
+    let mut ess = 0.0;
+    for chain in chains.iter() {
+        let spec = spectrum0(chain)?;
+        if spec > 0.0 {
+            ess += chain.len() as f64 * sample_variance(chain)? / spec;
+        }
+    }
+    Ok(ess)
+}
+
+/// Computes Kish's effective sample size `(sum w)^2 / sum w^2` of a set of
+/// importance weights, which need not be normalized.  It treats the draws
+/// as independent, so for weighted MCMC draws see
+/// [`compute_weighted_effective_sample_size`](fn.compute_weighted_effective_sample_size.html).
+///
+/// # Arguments
+/// * `weights` - Non-negative importance weights, not all zero
+pub fn kish_effective_sample_size(weights: &[f64]) -> Result<f64, Error> {
+    if weights.iter().any(|w| !(*w >= 0.0 && w.is_finite())) {
+        return Err(anyhow!("Weights must be finite and non-negative"));
+    }
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return Err(anyhow!("Weights must not all be zero"));
+    }
+    let squares: f64 = weights.iter().map(|w| w * w).sum();
+    Ok(total * total / squares)
+}
+
+/// Turns per-draw log-weights, e.g. the incremental weights of SMC or
+/// annealed importance sampling, into weights that sum to one over all
+/// chains.  The weights are shifted by their log-sum-exp before
+/// exponentiating, so log-weights in the thousands don't overflow.  A
+/// log-weight of minus infinity gives a weight of zero.
+///
+/// # Arguments
+/// * `log_weights` - Log-weights, one vector per chain, not all minus
+///   infinity
+pub fn normalize_log_weights(log_weights: &Array2) -> Result<Array2, Error> {
+    let flat = flatten(log_weights);
+    if flat.iter().any(|w| w.is_nan() || *w == f64::INFINITY) {
+        return Err(anyhow!("Log-weights must not be NaN or infinite"));
+    }
+    let total = log_sum_exp(&flat);
+    if total == f64::NEG_INFINITY {
+        return Err(anyhow!("Log-weights must not all be minus infinity"));
+    }
+    Ok(log_weights
+        .iter()
+        .map(|c| c.iter().map(|w| (w - total).exp()).collect())
+        .collect())
+}
+
+/// Computes the effective sample size of the self-normalized importance
+/// sampling estimate of the mean of the specified parameter from weighted
+/// MCMC draws, e.g. after reweighting for power-scaling sensitivity
+/// analysis.  This accounts for both the spread of the weights, like
+/// [`kish_effective_sample_size`](fn.kish_effective_sample_size.html), and
+/// the autocorrelation of the chains.
+///
+/// The error of the estimate is approximately the mean of
+/// `z = S w (x - mean)` with normalized weights `w`, so its variance is
+/// `var(z) / ess(z)` and the returned ESS is the weighted posterior variance
+/// divided by that.  With equal weights it reduces to the ESS of the
+/// parameter from
+/// [`compute_effective_sample_size`](fn.compute_effective_sample_size.html).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `weights` - Non-negative importance weights with the same shape as `chains`
+pub fn compute_weighted_effective_sample_size(
+    chains: &Array2,
+    weights: &Array2,
+) -> Result<f64, Error> {
+    if chains.len() != weights.len() || chains.iter().zip(weights).any(|(c, w)| c.len() != w.len())
+    {
+        return Err(anyhow!("Weights must have the same shape as the chains"));
+    }
+    let flat_weights = flatten(weights);
+    kish_effective_sample_size(&flat_weights)?;
+    let total: f64 = flat_weights.iter().sum();
+    let num_draws = flat_weights.len() as f64;
+    let weighted_mean: f64 = chains
+        .iter()
+        .zip(weights)
+        .flat_map(|(c, w)| c.iter().zip(w))
+        .map(|(x, w)| w * x)
+        .sum::<f64>()
+        / total;
+    let z: Array2 = chains
+        .iter()
+        .zip(weights)
+        .map(|(c, w)| {
+            c.iter()
+                .zip(w)
+                .map(|(x, w)| num_draws * w / total * (x - weighted_mean))
+                .collect()
+        })
+        .collect();
+    let weighted_var: f64 = chains
+        .iter()
+        .zip(weights)
+        .flat_map(|(c, w)| c.iter().zip(w))
+        .map(|(x, w)| w / total * (x - weighted_mean).powi(2))
+        .sum();
+    let z_var = flatten(&z).iter().map(|z| z * z).sum::<f64>() / num_draws;
+    Ok(weighted_var * compute_effective_sample_size(&z)? / z_var)
+}
+
+/// Ratio of two posterior expectations estimated from the same draws, with
+/// its Monte Carlo standard error, see
+/// [`compute_ratio_estimate`](fn.compute_ratio_estimate.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatioEstimate {
+    /// Ratio of the means of the numerator and the denominator
+    pub ratio: f64,
+    /// Delta method Monte Carlo standard error of the ratio
+    pub mcse: f64,
+    /// Effective sample size of the linearized ratio, i.e. its variance per
+    /// draw divided by the squared MCSE
+    pub ess: f64,
+}
+
+/// Estimates the ratio `E[f] / E[g]` of two expectations computed from the
+/// same draws, e.g. a normalized expectation, a self-normalized importance
+/// sampling estimate with `f = w h` and `g = w`, or a ratio of marginal
+/// likelihood estimates.  The numerator and denominator are correlated, so
+/// their standard errors can't simply be combined.  Instead the error of
+/// the ratio is, by the delta method, approximately the mean of
+/// `z = (f - ratio g) / mean(g)`, and the MCSE is `sqrt(var(z) / ess(z))`
+/// with the Geyer ESS of `z`, which accounts for the cross-correlation of
+/// `f` and `g` both within and across lags.  When `f` is exactly
+/// proportional to `g` the ratio is known without error and the ESS is the
+/// number of draws.
+///
+/// # Arguments
+/// * `numerator` - Draws of `f`, one vector per chain
+/// * `denominator` - Draws of `g` with the same shape as `numerator`; its
+///   mean must not be zero
+pub fn compute_ratio_estimate(
+    numerator: &Array2,
+    denominator: &Array2,
+) -> Result<RatioEstimate, Error> {
+    if numerator.len() != denominator.len()
+        || numerator
+            .iter()
+            .zip(denominator)
+            .any(|(f, g)| f.len() != g.len())
+    {
+        return Err(anyhow!(
+            "Numerator and denominator must have the same shape"
+        ));
+    }
+    let f_mean = mean(&flatten(numerator))?;
+    let g_mean = mean(&flatten(denominator))?;
+    if g_mean == 0.0 || !g_mean.is_finite() {
+        return Err(anyhow!(
+            "Mean of the denominator must be finite and non-zero, got {}",
+            g_mean
+        ));
+    }
+    let ratio = f_mean / g_mean;
+    let z: Array2 = numerator
+        .iter()
+        .zip(denominator)
+        .map(|(f, g)| {
+            f.iter()
+                .zip(g)
+                .map(|(f, g)| (f - ratio * g) / g_mean)
+                .collect()
+        })
+        .collect();
+    let flat = flatten(&z);
+    let z_var = sample_variance(&flat)?;
+    if z_var == 0.0 {
+        return Ok(RatioEstimate {
+            ratio,
+            mcse: 0.0,
+            ess: flat.len() as f64,
+        });
+    }
+    let ess = compute_effective_sample_size(&z)?;
+    Ok(RatioEstimate {
+        ratio,
+        mcse: (z_var / ess).sqrt(),
+        ess,
+    })
+}
+
+/// Options for the automatically windowed autocorrelation time, with the
+/// same defaults as emcee's `get_autocorr_time`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutocorrTimeOptions {
+    /// The window is the smallest lag `M` with `M >= c * tau(M)`
+    pub c: f64,
+    /// The chains must be at least `tol` times longer than the estimate
+    pub tol: f64,
+    /// Return the estimate even when the chains are too short for it to
+    /// be reliable, instead of an error.  With the `tracing` feature a
+    /// warning is emitted in that case.
+    pub quiet: bool,
+}
+
+impl Default for AutocorrTimeOptions {
+    fn default() -> AutocorrTimeOptions {
+        AutocorrTimeOptions {
+            c: 5.0,
+            tol: 50.0,
+            quiet: false,
+        }
+    }
+}
+
+/// Estimates the integrated autocorrelation time tau of the specified
+/// parameter with Sokal's automatic windowing, an alternative to the Geyer
+/// initial sequence used by the ESS functions above that matches emcee's
+/// `get_autocorr_time`.  The autocorrelation function is averaged over
+/// chains, then `tau(M) = 1 + 2 * sum(rho[1..=M])` is evaluated at the
+/// smallest window `M` with `M >= c * tau(M)`, or at the last lag if there
+/// is none.  Fails if the chains are shorter than `tol * tau`, since the
+/// estimate is not reliable then.
+///
+/// See Sokal (1997), "Monte Carlo Methods in Statistical Mechanics:
+/// Foundations and New Algorithms" and the
+/// [emcee documentation](https://emcee.readthedocs.io/en/stable/tutorials/autocorr/).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn integrated_autocorrelation_time(chains: &Array2) -> Result<f64, Error> {
+    integrated_autocorrelation_time_with_options(chains, &AutocorrTimeOptions::default())
+}
+
+/// Estimates the integrated autocorrelation time like
+/// [`integrated_autocorrelation_time`](fn.integrated_autocorrelation_time.html)
+/// with a different window constant or tolerance.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `options` - Window constant, tolerance and whether short chains are an error
+pub fn integrated_autocorrelation_time_with_options(
+    chains: &Array2,
+    options: &AutocorrTimeOptions,
+) -> Result<f64, Error> {
+    let num_draws = chains.iter().map(|c| c.len()).min().unwrap_or(0);
+    if num_draws < 2 {
+        return Err(anyhow!(
+            "Must have at least 2 samples to compute the autocorrelation time"
+        ));
+    }
+    let mut rho = vec![0.0; num_draws];
+    for chain in chains.iter() {
+        let acf = fft_autocorrelation(&chain[..num_draws])?;
+        for (r, a) in rho.iter_mut().zip(acf.iter()) {
+            *r += a / chains.len() as f64;
+        }
+    }
+    let mut tau = 1.0;
+    for (lag, r) in rho.iter().enumerate().skip(1) {
+        tau += 2.0 * r;
+        if lag as f64 >= options.c * tau {
+            break;
+        }
+    }
+    let too_short = options.tol * tau > num_draws as f64;
+    if too_short && !options.quiet {
+        return Err(anyhow!(
+            "The chains are shorter than {} times the autocorrelation time \
+             estimate {:.1}, run them for longer",
+            options.tol,
+            tau
+        ));
+    }
+    #[cfg(feature = "tracing")]
+    if too_short {
+        tracing::warn!(
+            tau,
+            num_draws,
+            "chains are too short for a reliable autocorrelation time"
+        );
+    }
+    Ok(tau)
+}
+
+/// Autocorrelations of a chain at all lags, computed with a zero padded FFT
+/// so that long chains stay cheap.
+fn fft_autocorrelation(chain: &[f64]) -> Result<Array1, Error> {
+    let n = chain.len();
+    let chain_mean = mean(chain)?;
+    let padded = (2 * n).next_power_of_two();
+    let mut re: Array1 = chain.iter().map(|x| x - chain_mean).collect();
+    re.resize(padded, 0.0);
+    let mut im = vec![0.0; padded];
+    fft(&mut re, &mut im);
+    // the power spectrum is real and symmetric, so transforming it forward
+    // again gives the autocovariances up to a constant factor
+    for (r, i) in re.iter_mut().zip(im.iter_mut()) {
+        *r = *r * *r + *i * *i;
+        *i = 0.0;
+    }
+    fft(&mut re, &mut im);
+    if re[0] <= 0.0 {
+        return Err(anyhow!("No autocorrelation when elements are all constant"));
+    }
+    Ok(re[..n].iter().map(|x| x / re[0]).collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::read_csv;
+    #[cfg(feature = "fs")]
+    use crate::utils::{dot, read_csv};
+    use arima::acf;
+    #[cfg(feature = "fs")]
     use std::path::PathBuf;
 
+    #[test]
+    fn test_kish_effective_sample_size() {
+        assert_abs_diff_eq!(kish_effective_sample_size(&[2.0; 10]).unwrap(), 10.0);
+        assert_abs_diff_eq!(
+            kish_effective_sample_size(&[1.0, 0.0, 0.0, 1.0]).unwrap(),
+            2.0
+        );
+        assert!(kish_effective_sample_size(&[0.0, 0.0]).is_err());
+        assert!(kish_effective_sample_size(&[1.0, -1.0]).is_err());
+    }
+
+    #[test]
+    fn test_normalize_log_weights() {
+        let weights = normalize_log_weights(&vec![vec![1000.0, 1000.0], vec![999.0]]).unwrap();
+        let e = std::f64::consts::E;
+        assert_abs_diff_eq!(weights[0][0], e / (2.0 * e + 1.0), epsilon = 1e-12);
+        assert_abs_diff_eq!(weights[1][0], 1.0 / (2.0 * e + 1.0), epsilon = 1e-12);
+        let weights = normalize_log_weights(&vec![vec![f64::NEG_INFINITY, -5.0]]).unwrap();
+        assert_eq!(weights, vec![vec![0.0, 1.0]]);
+        assert!(normalize_log_weights(&vec![vec![f64::NEG_INFINITY; 2]]).is_err());
+        assert!(normalize_log_weights(&vec![vec![0.0, f64::NAN]]).is_err());
+        assert!(normalize_log_weights(&vec![vec![0.0, f64::INFINITY]]).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_weighted_effective_sample_size() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+        let equal = vec![vec![0.5; 1000]; 2];
+        assert_abs_diff_eq!(
+            compute_weighted_effective_sample_size(&chains, &equal).unwrap(),
+            compute_effective_sample_size(&chains).unwrap(),
+            epsilon = 1e-6
+        );
+
+        // weights that favour the centre make the tails count for less
+        let uneven: Array2 = chains
+            .iter()
+            .map(|c| c.iter().map(|x| (-x.abs()).exp()).collect())
+            .collect();
+        let weighted = compute_weighted_effective_sample_size(&chains, &uneven).unwrap();
+        assert!(weighted < compute_effective_sample_size(&chains).unwrap());
+        assert!(compute_weighted_effective_sample_size(&chains, &equal[..1].to_vec()).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_ratio_estimate() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+        let weights: Array2 = chains
+            .iter()
+            .map(|c| c.iter().map(|x| (-x.abs()).exp()).collect())
+            .collect();
+        let weighted: Array2 = chains
+            .iter()
+            .zip(weights.iter())
+            .map(|(c, w)| c.iter().zip(w).map(|(x, w)| x * w).collect())
+            .collect();
+
+        // a self-normalized estimate has the MCSE implied by the weighted
+        // ESS, up to its divisor of the variance of z
+        let estimate = compute_ratio_estimate(&weighted, &weights).unwrap();
+        let (flat, flat_weights) = (flatten(&chains), flatten(&weights));
+        let total: f64 = flat_weights.iter().sum();
+        let weighted_mean = dot(&flat, &flat_weights) / total;
+        assert_abs_diff_eq!(estimate.ratio, weighted_mean, epsilon = 1e-12);
+        let weighted_var: f64 = flat
+            .iter()
+            .zip(flat_weights.iter())
+            .map(|(x, w)| w / total * (x - weighted_mean).powi(2))
+            .sum();
+        let ess = compute_weighted_effective_sample_size(&chains, &weights).unwrap();
+        assert_abs_diff_eq!(
+            estimate.mcse,
+            (weighted_var / ess).sqrt(),
+            epsilon = 1e-3 * estimate.mcse
+        );
+
+        // ignoring the correlation of numerator and denominator would
+        // overstate the error about forty times here
+        let g = vec![crate::utils::normal_draws(4000, 1)
+            .iter()
+            .map(|e| 10.0 + e)
+            .collect::<Array1>()];
+        let u = crate::utils::normal_draws(4000, 2);
+        let f = vec![g[0].iter().zip(u).map(|(g, u)| 3.0 * g + 0.1 * u).collect()];
+        let correlated = compute_ratio_estimate(&f, &g).unwrap();
+        assert_abs_diff_eq!(correlated.ratio, 3.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(correlated.mcse, 0.01 / 4000f64.sqrt(), epsilon = 2e-5);
+
+        let doubled: Array2 = weights
+            .iter()
+            .map(|w| w.iter().map(|w| 2.0 * w).collect())
+            .collect();
+        let exact = compute_ratio_estimate(&doubled, &weights).unwrap();
+        assert_abs_diff_eq!(exact.ratio, 2.0, epsilon = 1e-12);
+        assert_eq!((exact.mcse, exact.ess), (0.0, 2000.0));
+
+        assert!(compute_ratio_estimate(&weighted, &weights[..1].to_vec()).is_err());
+        let centered = vec![vec![-1.0, 1.0, -1.0, 1.0]];
+        assert!(compute_ratio_estimate(&centered, &centered).is_err());
+    }
+
+    #[test]
+    fn test_integrated_autocorrelation_time_ar1() {
+        // AR(1) with coefficient phi has tau = (1 + phi) / (1 - phi) = 3
+        let ar1 = |seed| {
+            let noise = crate::utils::normal_draws(4000, seed);
+            let mut x = 0.0;
+            noise
+                .iter()
+                .map(|e| {
+                    x = 0.5 * x + e;
+                    x
+                })
+                .collect::<Array1>()
+        };
+        let chains = vec![ar1(1), ar1(2)];
+        let acov = autocovariance(&chains[0], 10).unwrap();
+        let acf = fft_autocorrelation(&chains[0]).unwrap();
+        for lag in 0..=10 {
+            assert_abs_diff_eq!(acf[lag], acov[lag] / acov[0], epsilon = 1e-10);
+        }
+        let tau = integrated_autocorrelation_time(&chains).unwrap();
+        assert_abs_diff_eq!(tau, 3.0, epsilon = 0.2);
+
+        // 4000 draws are fewer than 2000 times tau
+        let strict = AutocorrTimeOptions {
+            tol: 2000.0,
+            ..AutocorrTimeOptions::default()
+        };
+        assert!(integrated_autocorrelation_time_with_options(&chains, &strict).is_err());
+        let quiet = AutocorrTimeOptions {
+            quiet: true,
+            ..strict
+        };
+        assert_eq!(
+            integrated_autocorrelation_time_with_options(&chains, &quiet).unwrap(),
+            tau
+        );
+        assert!(integrated_autocorrelation_time(&vec![vec![1.0; 10]]).is_err());
+    }
+
     #[test]
     fn test_identical_autocovariance_in_arima_library_and_stan() {
         let arr = vec![
@@ -231,6 +1004,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_compute_effective_sample_size_one_chain() {
         // Based on the unit test in Stan 2.2.4 but with more digits of precision
@@ -296,6 +1070,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_compute_effective_sample_size_two_chains() {
         // Based on the unit test in Stan 2.2.4 but with more digits of precision
@@ -362,6 +1137,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_compute_split_effective_sample_size_two_chains() {
         // Based on the unit test in Stan 2.2.4 but with more digits of precision
@@ -456,6 +1232,7 @@ mod tests {
         assert!(ess.is_err());
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_compute_estimated_mcse() {
         // Based on running [stansummary](https://mc-stan.org/docs/2_24/cmdstan-guide/stansummary.html) from the
@@ -524,4 +1301,243 @@ mod tests {
             assert_abs_diff_eq!(actual, expected, epsilon = 1e-8);
         }
     }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_spectrum0_effective_sample_size() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+
+        // the two estimators differ, but should broadly agree on these chains
+        let chains = vec![samples1[6].clone(), samples2[6].clone()];
+        let stan = compute_effective_sample_size(&chains).unwrap();
+        let coda = compute_spectrum0_effective_sample_size(&chains).unwrap();
+        assert!((coda / stan - 1.0).abs() < 0.3);
+
+        let constant = vec![vec![1.0, 1.0, 1.0, 1.0]];
+        assert_abs_diff_eq!(
+            compute_spectrum0_effective_sample_size(&constant).unwrap(),
+            0.0
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_ess_quantile() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+
+        // Reference values of ess_quantile in the R package posterior 1.5,
+        // computed like those of test_compute_mcse_quantile_posterior
+        let expected = [
+            (0.05, 782.2115331652204),
+            (0.25, 520.539588275958),
+            (0.5, 311.8063197924595),
+            (0.75, 481.1571210258042),
+            (0.95, 349.13527043831795),
+        ];
+        for &(prob, ess) in expected.iter() {
+            assert_abs_diff_eq!(
+                compute_ess_quantile(&chains, prob).unwrap(),
+                ess,
+                epsilon = 1e-8
+            );
+        }
+
+        // independent draws have an ESS close to the number of draws
+        let iid = vec![crate::utils::normal_draws(2000, 11)];
+        let ess = compute_ess_quantile(&iid, 0.05).unwrap();
+        assert!((ess / 2000.0 - 1.0).abs() < 0.2);
+
+        assert!(compute_ess_quantile(&chains, 0.0).is_err());
+        assert!(compute_ess_quantile(&chains, 1.5).is_err());
+        let mut with_nan = iid.clone();
+        with_nan[0][3] = f64::NAN;
+        assert!(compute_ess_quantile(&with_nan, 0.5).is_err());
+        assert!(compute_mcse_quantile(&with_nan, 0.5).is_err());
+        assert!(compute_ess_quantile_profile(&with_nan, &[0.5]).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_ess_quantile_profile() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+
+        let probs: Vec<f64> = (1..20).map(|i| i as f64 * 0.05).collect();
+        let profile = compute_ess_quantile_profile(&chains, &probs).unwrap();
+        assert_eq!(profile.len(), 19);
+        for point in profile.iter() {
+            assert_abs_diff_eq!(
+                point.ess,
+                compute_ess_quantile(&chains, point.prob).unwrap(),
+                epsilon = 1e-9
+            );
+            assert_abs_diff_eq!(point.efficiency, point.ess / 2000.0);
+        }
+
+        assert!(compute_ess_quantile_profile(&chains, &[0.5, 1.0]).is_err());
+        assert!(compute_ess_quantile_profile(&vec![vec![f64::NAN; 10]], &[0.5]).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_bulk_tail_ess() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+
+        let ess = compute_bulk_tail_ess(&chains).unwrap();
+        assert_abs_diff_eq!(
+            ess.bulk,
+            compute_bulk_effective_sample_size(&chains).unwrap()
+        );
+        // with chains of equal even length the quantiles are the same
+        assert_abs_diff_eq!(
+            ess.tail,
+            compute_ess_quantile(&chains, 0.05)
+                .unwrap()
+                .min(compute_ess_quantile(&chains, 0.95).unwrap())
+        );
+        // ranks don't change under monotone transforms
+        let transformed: Array2 = chains
+            .iter()
+            .map(|c| c.iter().map(|x| (3.0 * x).exp()).collect())
+            .collect();
+        assert_eq!(compute_bulk_tail_ess(&transformed).unwrap(), ess);
+
+        // close to the split ESS for a normal distribution
+        let iid: Array2 = (0..4)
+            .map(|c| crate::utils::normal_draws(1000, 40 + c))
+            .collect();
+        let iid_ess = compute_bulk_tail_ess(&iid).unwrap();
+        let split = compute_split_effective_sample_size(&iid).unwrap();
+        assert!((iid_ess.bulk / split - 1.0).abs() < 0.05);
+        assert!((iid_ess.tail / 4000.0 - 1.0).abs() < 0.2);
+
+        assert!(compute_bulk_tail_ess(&vec![vec![1.0, f64::NAN, 2.0, 3.0, 4.0]]).is_err());
+        assert!(compute_bulk_effective_sample_size(&vec![vec![1.0; 10]]).is_err());
+    }
+
+    #[test]
+    fn test_compute_mcse_quantile() {
+        // for independent normal draws the MCSE of a quantile approaches
+        // sqrt(p (1 - p) / n) / density(quantile)
+        let n = 20000;
+        let iid: Array2 = (0..4)
+            .map(|c| crate::utils::normal_draws(n / 4, 21 + c))
+            .collect();
+        for &(prob, density) in [(0.5, 0.398942), (0.05, 0.103136)].iter() {
+            let expected = (prob * (1.0 - prob) / n as f64).sqrt() / density;
+            let mcse = compute_mcse_quantile(&iid, prob).unwrap();
+            assert!(
+                (mcse / expected - 1.0).abs() < 0.2,
+                "{} vs {}",
+                mcse,
+                expected
+            );
+        }
+
+        // the interval is between order statistics, so shifting and scaling
+        // the draws carries over exactly
+        let scaled: Array2 = iid
+            .iter()
+            .map(|c| c.iter().map(|x| 3.0 * x + 1.0).collect())
+            .collect();
+        assert_abs_diff_eq!(
+            compute_mcse_quantile(&scaled, 0.25).unwrap(),
+            3.0 * compute_mcse_quantile(&iid, 0.25).unwrap(),
+            epsilon = 1e-12
+        );
+
+        assert!(compute_mcse_quantile(&vec![vec![1.0; 100]; 2], 0.5).is_err());
+        assert!(compute_mcse_quantile(&iid, 1.0).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_mcse_quantile_posterior() {
+        // Reference values of mcse_quantile in the R package posterior 1.5
+        // for d of the blocker chains, computed with a line by line
+        // transcription of its R code to double precision
+        // https://github.com/stan-dev/posterior/blob/v1.5.0/R/convergence.R
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+
+        let expected = [
+            (0.05, 0.0066225),
+            (0.25, 0.002149),
+            (0.5, 0.005242),
+            (0.75, 0.004295),
+            (0.95, 0.0074295),
+        ];
+        for &(prob, mcse) in expected.iter() {
+            assert_abs_diff_eq!(
+                compute_mcse_quantile(&chains, prob).unwrap(),
+                mcse,
+                epsilon = 1e-10
+            );
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compute_mcse_fn() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+
+        assert_abs_diff_eq!(
+            compute_mcse_fn(&chains, |x| x).unwrap(),
+            compute_estimated_mcse(&chains).unwrap(),
+            epsilon = 1e-15
+        );
+        // scaling the function scales the standard error
+        assert_abs_diff_eq!(
+            compute_mcse_fn(&chains, |x| 2.0 * x * x).unwrap(),
+            2.0 * compute_mcse_fn(&chains, |x| x * x).unwrap(),
+            epsilon = 1e-12
+        );
+        // a constant function has no Monte Carlo error to estimate
+        assert!(compute_mcse_fn(&chains, |_| 1.0).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_effective_sample_size_max_lag() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+
+        // a cap beyond where the positive sequence terminates changes nothing
+        let options = EssOptions { max_lag: Some(100) };
+        assert_abs_diff_eq!(
+            compute_effective_sample_size_with_options(&chains, &options).unwrap(),
+            compute_effective_sample_size(&chains).unwrap(),
+            epsilon = 1e-10
+        );
+        assert_abs_diff_eq!(
+            compute_split_effective_sample_size_with_options(&chains, &options).unwrap(),
+            compute_split_effective_sample_size(&chains).unwrap(),
+            epsilon = 1e-10
+        );
+        // truncating the sum early can only increase the estimate
+        let short = EssOptions { max_lag: Some(3) };
+        assert!(
+            compute_effective_sample_size_with_options(&chains, &short).unwrap()
+                >= compute_effective_sample_size(&chains).unwrap()
+        );
+        let invalid = EssOptions { max_lag: Some(2) };
+        assert!(compute_effective_sample_size_with_options(&chains, &invalid).is_err());
+    }
 }
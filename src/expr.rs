@@ -0,0 +1,369 @@
+use anyhow::{anyhow, Error, Result};
+
+/// Binary operators, in the usual precedence classes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Functions that can be called in an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Function {
+    Exp,
+    Log,
+    Pow,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "exp" => Some(Function::Exp),
+            "log" => Some(Function::Log),
+            "pow" => Some(Function::Pow),
+            _ => None,
+        }
+    }
+
+    fn num_args(self) -> usize {
+        match self {
+            Function::Exp | Function::Log => 1,
+            Function::Pow => 2,
+        }
+    }
+}
+
+/// Parsed arithmetic expression over parameters, which are referred to by
+/// their column index.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Number(f64),
+    Parameter(usize),
+    Neg(Box<Expr>),
+    /// Operand followed by operators of the same precedence class and
+    /// their operands, applied from left to right.  Keeping a sum or
+    /// product flat rather than as a tree as deep as it has terms lets
+    /// long ones be evaluated and dropped without deep recursion.
+    Chain(Box<Expr>, Vec<(Op, Expr)>),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    /// Parses an expression such as `exp(theta[1]) - 2 * theta[2]`.  Names
+    /// can contain letters, digits, `_` and `.` and end with an index in
+    /// square brackets, and are resolved to column indices by `lookup`.
+    pub(crate) fn parse<F>(input: &str, lookup: F) -> Result<Expr, Error>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+            lookup,
+        };
+        let expr = parser.sum()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some((token, at)) => Err(anyhow!("Unexpected {:?} at position {}", token, at)),
+        }
+    }
+
+    /// Evaluates the expression with the parameter values returned by
+    /// `value`.
+    pub(crate) fn eval<F: Fn(usize) -> f64>(&self, value: &F) -> f64 {
+        match self {
+            Expr::Number(x) => *x,
+            Expr::Parameter(idx) => value(*idx),
+            Expr::Neg(e) => -e.eval(value),
+            Expr::Chain(first, rest) => rest.iter().fold(first.eval(value), |a, (op, e)| {
+                let b = e.eval(value);
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                }
+            }),
+            Expr::Call(f, args) => match f {
+                Function::Exp => args[0].eval(value).exp(),
+                Function::Log => args[0].eval(value).ln(),
+                Function::Pow => args[0].eval(value).powf(args[1].eval(value)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+/// Splits the input into tokens, each with its character position.
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // exponent, e.g. 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let x = text
+                .parse()
+                .map_err(|_| anyhow!("Invalid number {:?} at position {}", text, start))?;
+            tokens.push((Token::Number(x), start));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            if i < chars.len() && chars[i] == '[' {
+                match chars[i..].iter().position(|&c| c == ']') {
+                    Some(end) => i += end + 1,
+                    None => return Err(anyhow!("Unclosed '[' at position {}", i)),
+                }
+            }
+            tokens.push((Token::Name(chars[start..i].iter().collect()), start));
+        } else if "+-*/(),".contains(c) {
+            tokens.push((Token::Symbol(c), start));
+            i += 1;
+        } else if c == '\u{2212}' {
+            // the minus sign, as in typeset formulas
+            tokens.push((Token::Symbol('-'), start));
+            i += 1;
+        } else {
+            return Err(anyhow!("Unexpected {:?} at position {}", c, start));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Operand on its own, or a chain of operators applied to it.
+fn chain(first: Expr, rest: Vec<(Op, Expr)>) -> Expr {
+    if rest.is_empty() {
+        first
+    } else {
+        Expr::Chain(Box::new(first), rest)
+    }
+}
+
+/// Deepest nesting of parentheses, function calls and negations accepted,
+/// so that parsing doesn't overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Recursive descent parser over the tokens.
+struct Parser<F> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Nesting level of the unary expression being parsed
+    depth: usize,
+    lookup: F,
+}
+
+impl<F: Fn(&str) -> Option<usize>> Parser<F> {
+    /// Consumes the next token if it is the given symbol.
+    fn eat(&mut self, symbol: char) -> bool {
+        if let Some((Token::Symbol(c), _)) = self.tokens.get(self.pos) {
+            if *c == symbol {
+                self.pos += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), Error> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+        match self.tokens.get(self.pos) {
+            Some((token, at)) => Err(anyhow!(
+                "Expected '{}' at position {} but found {:?}",
+                symbol,
+                at,
+                token
+            )),
+            None => Err(anyhow!("Expected '{}' at the end", symbol)),
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Expr, Error> {
+        let first = self.product()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                return Ok(chain(first, rest));
+            };
+            rest.push((op, self.product()?));
+        }
+    }
+
+    // product := unary (('*' | '/') unary)*
+    fn product(&mut self) -> Result<Expr, Error> {
+        let first = self.unary()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else {
+                return Ok(chain(first, rest));
+            };
+            rest.push((op, self.unary()?));
+        }
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(anyhow!(
+                "Expression nested more than {} levels deep",
+                MAX_DEPTH
+            ));
+        }
+        self.depth += 1;
+        let expr = if self.eat('-') {
+            self.unary().map(|e| Expr::Neg(Box::new(e)))
+        } else {
+            self.primary()
+        };
+        self.depth -= 1;
+        expr
+    }
+
+    // primary := number | name | function '(' sum (',' sum)* ')' | '(' sum ')'
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let (token, at) = match self.tokens.get(self.pos) {
+            Some(t) => t.clone(),
+            None => return Err(anyhow!("Unexpected end of expression")),
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(x) => Ok(Expr::Number(x)),
+            Token::Symbol('(') => {
+                let expr = self.sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Token::Name(name) if self.eat('(') => {
+                let f = Function::from_name(&name)
+                    .ok_or_else(|| anyhow!("Unknown function {:?} at position {}", name, at))?;
+                let mut args = vec![self.sum()?];
+                while self.eat(',') {
+                    args.push(self.sum()?);
+                }
+                self.expect(')')?;
+                if args.len() != f.num_args() {
+                    return Err(anyhow!(
+                        "{} takes {} arguments but got {}",
+                        name,
+                        f.num_args(),
+                        args.len()
+                    ));
+                }
+                Ok(Expr::Call(f, args))
+            }
+            Token::Name(name) => (self.lookup)(&name)
+                .map(Expr::Parameter)
+                .ok_or_else(|| anyhow!("No parameter named {:?}", name)),
+            Token::Symbol(c) => Err(anyhow!("Unexpected '{}' at position {}", c, at)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Result<f64, Error> {
+        let names = ["theta[1]", "theta[2]", "sigma.y", "log_lik"];
+        let values = [1.5, -2.0, 4.0, 0.25];
+        let expr = Expr::parse(input, |n| names.iter().position(|&m| m == n))?;
+        Ok(expr.eval(&|idx| values[idx]))
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_abs_diff_eq!(eval("theta[1] - theta[2]").unwrap(), 3.5);
+        assert_abs_diff_eq!(eval("1 + 2 * 3 - 4 / 2").unwrap(), 5.0);
+        assert_abs_diff_eq!(eval("(1 + 2) * -3").unwrap(), -9.0);
+        assert_abs_diff_eq!(eval("- -2 - 1").unwrap(), 1.0);
+        assert_abs_diff_eq!(eval("2 \u{2212} 3").unwrap(), -1.0);
+        assert_abs_diff_eq!(eval("pow(sigma.y, 0.5) * 1e1").unwrap(), 20.0);
+        assert_abs_diff_eq!(eval("log(exp(log_lik))").unwrap(), 0.25, epsilon = 1e-15);
+        assert_abs_diff_eq!(eval(".5*theta[2]").unwrap(), -1.0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for input in [
+            "theta[3]",
+            "theta[1] +",
+            "(1 + 2",
+            "1 2",
+            "sqrt(4)",
+            "pow(2)",
+            "exp(1, 2)",
+            "theta[1",
+            "1 $ 2",
+            "",
+        ]
+        .iter()
+        {
+            assert!(eval(input).is_err(), "{:?} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert_abs_diff_eq!(eval(&nested(100)).unwrap(), 1.0);
+        assert!(eval(&nested(100_000)).is_err());
+        assert!(eval(&"-".repeat(100_000)).is_err());
+        assert!(eval(&format!(
+            "{}1{}",
+            "exp(".repeat(100_000),
+            ")".repeat(100_000)
+        ))
+        .is_err());
+
+        // long sums and products stay flat
+        let sum = vec!["theta[1]"; 300_000].join("+");
+        assert_abs_diff_eq!(eval(&sum).unwrap(), 450_000.0);
+        let product = vec!["1"; 300_000].join("*");
+        assert_abs_diff_eq!(eval(&format!("2-{}/2", product)).unwrap(), 1.5);
+    }
+}
@@ -0,0 +1,203 @@
+//! C interface for calling the diagnostics from other languages.
+//!
+//! All functions take draws for a single parameter as one contiguous buffer
+//! of `num_chains * num_draws` doubles in chain-major order, i.e. draw `i` of
+//! chain `c` is at index `c * num_draws + i`.  They return `0` on success and
+//! a non-zero status otherwise, in which case the output is left untouched.
+//! The matching declarations are in `include/mcmc_rs.h`.  The crate builds
+//! as a Rust library only; build the shared library with `cargo rustc
+//! --release --lib --features ffi --crate-type cdylib`.
+use crate::ess::compute_effective_sample_size;
+use crate::rhat::split_potential_scale_reduction_factor;
+use crate::summary::{summarize, Summary};
+use crate::Array2;
+use anyhow::{anyhow, Error, Result};
+use std::os::raw::c_int;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Success
+pub const MCMC_RS_OK: c_int = 0;
+/// A pointer argument was null or the dimensions were zero
+pub const MCMC_RS_INVALID_ARGUMENT: c_int = 1;
+/// The diagnostic could not be computed for the given draws
+pub const MCMC_RS_COMPUTATION_FAILED: c_int = 2;
+
+/// C layout of [`Summary`](../summary/struct.Summary.html).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct McmcRsSummary {
+    pub mean: f64,
+    pub mcse: f64,
+    pub sd: f64,
+    pub q5: f64,
+    pub q50: f64,
+    pub q95: f64,
+    pub ess: f64,
+    pub rhat: f64,
+}
+
+impl From<Summary> for McmcRsSummary {
+    fn from(s: Summary) -> McmcRsSummary {
+        McmcRsSummary {
+            mean: s.mean,
+            mcse: s.mcse,
+            sd: s.sd,
+            q5: s.q5,
+            q50: s.q50,
+            q95: s.q95,
+            ess: s.ess,
+            rhat: s.rhat,
+        }
+    }
+}
+
+/// Computes the effective sample size, as in
+/// [`compute_effective_sample_size`](../ess/fn.compute_effective_sample_size.html).
+///
+/// # Safety
+/// `draws` must point to `num_chains * num_draws` readable doubles and `out`
+/// must point to one writable double.
+#[no_mangle]
+pub unsafe extern "C" fn mcmc_rs_ess(
+    draws: *const f64,
+    num_chains: usize,
+    num_draws: usize,
+    out: *mut f64,
+) -> c_int {
+    run(draws, num_chains, num_draws, out, |chains| {
+        compute_effective_sample_size(chains)
+    })
+}
+
+/// Computes the split potential scale reduction factor, as in
+/// [`split_potential_scale_reduction_factor`](../rhat/fn.split_potential_scale_reduction_factor.html).
+///
+/// # Safety
+/// `draws` must point to `num_chains * num_draws` readable doubles and `out`
+/// must point to one writable double.
+#[no_mangle]
+pub unsafe extern "C" fn mcmc_rs_rhat(
+    draws: *const f64,
+    num_chains: usize,
+    num_draws: usize,
+    out: *mut f64,
+) -> c_int {
+    run(draws, num_chains, num_draws, out, |chains| {
+        split_potential_scale_reduction_factor(chains)
+    })
+}
+
+/// Computes the posterior summary, as in
+/// [`summarize`](../summary/fn.summarize.html).
+///
+/// # Safety
+/// `draws` must point to `num_chains * num_draws` readable doubles and `out`
+/// must point to one writable `mcmc_rs_summary`.
+#[no_mangle]
+pub unsafe extern "C" fn mcmc_rs_summarize(
+    draws: *const f64,
+    num_chains: usize,
+    num_draws: usize,
+    out: *mut McmcRsSummary,
+) -> c_int {
+    run(draws, num_chains, num_draws, out, |chains| {
+        summarize(chains).map(McmcRsSummary::from)
+    })
+}
+
+/// Copies the raw buffer into chains, runs `f` without letting a panic cross
+/// the FFI boundary and writes the result to `out`.
+unsafe fn run<T, F>(
+    draws: *const f64,
+    num_chains: usize,
+    num_draws: usize,
+    out: *mut T,
+    f: F,
+) -> c_int
+where
+    F: FnOnce(&Array2) -> Result<T, Error>,
+{
+    if draws.is_null() || out.is_null() || num_chains == 0 || num_draws == 0 {
+        return MCMC_RS_INVALID_ARGUMENT;
+    }
+    let total = match num_chains.checked_mul(num_draws) {
+        Some(total) => total,
+        None => return MCMC_RS_INVALID_ARGUMENT,
+    };
+    let buffer = std::slice::from_raw_parts(draws, total);
+    let chains: Array2 = buffer.chunks(num_draws).map(|c| c.to_vec()).collect();
+    let result = catch_unwind(AssertUnwindSafe(|| f(&chains)))
+        .unwrap_or_else(|_| Err(anyhow!("Panic while computing diagnostic")));
+    match result {
+        Ok(value) => {
+            *out = value;
+            MCMC_RS_OK
+        }
+        Err(_) => MCMC_RS_COMPUTATION_FAILED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::read_csv;
+    use std::path::PathBuf;
+
+    fn blocker_buffer() -> (Vec<f64>, Array2) {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+        (crate::utils::flatten(&chains), chains)
+    }
+
+    #[test]
+    fn test_ffi_matches_rust_api() {
+        let (buffer, chains) = blocker_buffer();
+        let mut ess = 0.0;
+        let mut rhat = 0.0;
+        let mut summary = McmcRsSummary::default();
+        unsafe {
+            assert_eq!(mcmc_rs_ess(buffer.as_ptr(), 2, 1000, &mut ess), MCMC_RS_OK);
+            assert_eq!(
+                mcmc_rs_rhat(buffer.as_ptr(), 2, 1000, &mut rhat),
+                MCMC_RS_OK
+            );
+            assert_eq!(
+                mcmc_rs_summarize(buffer.as_ptr(), 2, 1000, &mut summary),
+                MCMC_RS_OK
+            );
+        }
+        assert_eq!(ess, compute_effective_sample_size(&chains).unwrap());
+        assert_eq!(
+            rhat,
+            split_potential_scale_reduction_factor(&chains).unwrap()
+        );
+        assert_eq!(summary, McmcRsSummary::from(summarize(&chains).unwrap()));
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let buffer = [1.0, 2.0, 3.0];
+        let mut out = -1.0;
+        unsafe {
+            assert_eq!(
+                mcmc_rs_ess(std::ptr::null(), 1, 3, &mut out),
+                MCMC_RS_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                mcmc_rs_ess(buffer.as_ptr(), 1, 3, std::ptr::null_mut()),
+                MCMC_RS_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                mcmc_rs_ess(buffer.as_ptr(), 0, 3, &mut out),
+                MCMC_RS_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                mcmc_rs_ess(buffer.as_ptr(), 1, 3, &mut out),
+                MCMC_RS_COMPUTATION_FAILED
+            );
+        }
+        assert_abs_diff_eq!(out, -1.0);
+    }
+}
@@ -0,0 +1,313 @@
+//! Just enough HTTP/1.1 for the built-in servers: one request per
+//! connection, with the body sized by `Content-Length`.
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Largest accepted request line and headers in bytes.
+const MAX_HEAD: usize = 16384;
+
+/// HTTP request with the headers that matter to the servers.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Request {
+    /// Method, e.g. `GET`
+    pub method: String,
+    /// Path without the query string, e.g. `/draws/1/summary`
+    pub path: String,
+    /// Media type of the body without parameters, e.g. `text/csv`, in
+    /// lower case
+    pub content_type: Option<String>,
+    /// Request body
+    pub body: Vec<u8>,
+}
+
+/// HTTP response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// Status code, e.g. 200
+    pub status: u16,
+    /// Media type of the body
+    pub content_type: String,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Plain text response.
+    pub fn text(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Reason phrase of a status code.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Reads a request, or returns the error response to send back if it is
+/// malformed, its body is larger than `max_body` bytes or it hasn't fully
+/// arrived by `deadline`.  The deadline is checked before every read, so
+/// the read timeout of the stream bounds how far it can be overrun, and a
+/// client trickling in a byte at a time can't hold the connection longer.
+/// A connection closed before the end of the request is reported as a bad
+/// request, too; writing the response then simply fails.
+pub(crate) fn read_request<R: Read>(
+    stream: &mut R,
+    max_body: usize,
+    deadline: Instant,
+) -> Result<Request, Response> {
+    let bad_request = |e: io::Error| Response::text(400, &format!("{}\n", e));
+    let mut read = |buf: &mut [u8]| {
+        if Instant::now() >= deadline {
+            return Err(Response::text(408, "Request timed out\n"));
+        }
+        stream.read(buf).map_err(bad_request)
+    };
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+    let head_end = loop {
+        if let Some(idx) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break idx;
+        }
+        if data.len() > MAX_HEAD {
+            return Err(Response::text(400, "Request headers too large\n"));
+        }
+        let n = read(&mut buf)?;
+        if n == 0 {
+            return Err(Response::text(400, "Incomplete request\n"));
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut words = lines.next().unwrap_or("").split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err(Response::text(400, "Malformed request line\n")),
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or("").to_string(),
+        ..Request::default()
+    };
+    let mut content_length = 0;
+    for line in lines {
+        let (name, value) = match line.find(':') {
+            Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| Response::text(400, "Invalid Content-Length\n"))?;
+        } else if name.eq_ignore_ascii_case("content-type") {
+            let media_type = value.split(';').next().unwrap_or("").trim();
+            request.content_type = Some(media_type.to_ascii_lowercase());
+        }
+    }
+    if content_length > max_body {
+        return Err(Response::text(
+            413,
+            &format!("Request body larger than {} bytes\n", max_body),
+        ));
+    }
+    let mut body = data.split_off(head_end + 4);
+    // grow the body as data arrives rather than trusting the header with an
+    // allocation of its full size up front
+    while body.len() < content_length {
+        let want = (content_length - body.len()).min(buf.len());
+        let n = read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(Response::text(400, "Incomplete request\n"));
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    body.truncate(content_length);
+    request.body = body;
+    Ok(request)
+}
+
+/// Count of the connections being answered, shared by the threads of a
+/// server to bound how many it answers at once.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionLimit {
+    active: Arc<AtomicUsize>,
+    max: usize,
+}
+
+/// Slot of a connection being answered, released when dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionSlot {
+    active: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimit {
+    /// Limit of `max` connections at once.
+    pub(crate) fn new(max: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            active: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Takes a slot for a new connection, or `None` if `max` connections
+    /// are being answered already.
+    pub(crate) fn acquire(&self) -> Option<ConnectionSlot> {
+        let taken = self
+            .active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                if n < self.max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            });
+        taken.ok().map(|_| ConnectionSlot {
+            active: self.active.clone(),
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Writes a response and asks the client to close the connection.
+pub(crate) fn write_response<W: Write>(stream: &mut W, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Answers `503` to a connection beyond the limit without waiting for the
+/// client.  The part of the request that has arrived is read first, since
+/// closing a socket with unread data resets the connection, which can
+/// discard the response before the client reads it.
+pub(crate) fn reject(mut stream: TcpStream) {
+    let busy = Response::text(503, "Too many connections\n");
+    let _ = stream
+        .set_write_timeout(Some(Duration::from_secs(1)))
+        .and_then(|_| write_response(&mut stream, &busy));
+    let _ = stream.shutdown(Shutdown::Write);
+    if stream.set_nonblocking(true).is_ok() {
+        let mut buf = [0; 4096];
+        while matches!(stream.read(&mut buf), Ok(n) if n > 0) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /draws?x=1 HTTP/1.1\r\nContent-Type: Text/CSV; charset=utf-8\r\ncontent-length: 5\r\n\r\na,b\n1";
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let request = read_request(&mut &raw[..], 100, deadline).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/draws");
+        assert_eq!(request.content_type.as_deref(), Some("text/csv"));
+        assert_eq!(request.body, b"a,b\n1");
+
+        assert_eq!(
+            read_request(&mut &raw[..], 4, deadline).unwrap_err().status,
+            413
+        );
+        assert_eq!(
+            read_request(&mut &raw[..20], 100, deadline)
+                .unwrap_err()
+                .status,
+            400
+        );
+        let truncated = b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nab";
+        assert_eq!(
+            read_request(&mut &truncated[..], 100, deadline)
+                .unwrap_err()
+                .status,
+            400
+        );
+
+        let mut out = Vec::new();
+        write_response(&mut out, &Response::text(404, "Not found\n")).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.ends_with("Content-Length: 10\r\nConnection: close\r\n\r\nNot found\n"));
+    }
+
+    #[test]
+    fn test_large_content_length() {
+        // the body is read as it arrives, so a huge declared length of a
+        // short request fails without allocating it
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 268435456\r\n\r\nab";
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            read_request(&mut &raw[..], 256 << 20, deadline)
+                .unwrap_err()
+                .status,
+            400
+        );
+
+        let limit = ConnectionLimit::new(2);
+        let first = limit.acquire().unwrap();
+        let _second = limit.acquire().unwrap();
+        assert!(limit.acquire().is_none());
+        drop(first);
+        assert!(limit.acquire().is_some());
+    }
+
+    /// Reader handing out one byte at a time, slowly.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(10));
+            (&mut self.0).take(1).read(buf)
+        }
+    }
+
+    #[test]
+    fn test_request_deadline() {
+        let raw = b"GET /metrics HTTP/1.1\r\n\r\n";
+        let past = Instant::now();
+        assert_eq!(
+            read_request(&mut &raw[..], 0, past).unwrap_err().status,
+            408
+        );
+        let soon = Instant::now() + Duration::from_millis(100);
+        assert_eq!(
+            read_request(&mut Trickle(&raw[..]), 0, soon)
+                .unwrap_err()
+                .status,
+            408
+        );
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(read_request(&mut Trickle(&raw[..]), 0, later).is_ok());
+    }
+}
@@ -0,0 +1,458 @@
+use crate::draws::Draws;
+use crate::io::stream::ChainColumns;
+use crate::online::{OnlineMonitor, Snapshot};
+use crate::summary::SummaryColumns;
+use anyhow::{anyhow, Context, Error, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Float64Type, Int32Type, Int64Type, UInt32Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, Float64Array, RecordBatch, StringArray};
+use arrow_schema::{ArrowError, DataType};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Feeds Arrow record batches, each holding a block of iterations, into an
+/// [`OnlineMonitor`](../../online/struct.OnlineMonitor.html).
+///
+/// Every column except the chain column is a parameter, and must be a
+/// floating point, integer or boolean column without nulls.  The chain column
+/// may be an integer or string column; when a batch does not have one all of
+/// its draws belong to a single chain.  Chains are numbered in order of first
+/// appearance.  Parameter names are taken from the schema of the first batch
+/// and later batches must contain the same columns, in any order.
+#[derive(Debug, Clone)]
+pub struct RecordBatchSink {
+    chain_column: String,
+    chain_ids: HashMap<String, usize>,
+    monitor: Option<OnlineMonitor>,
+}
+
+impl RecordBatchSink {
+    /// Creates a sink that has not seen any batches yet.
+    ///
+    /// # Arguments
+    /// * `chain_column` - Name of the column identifying the chain of each draw
+    pub fn new(chain_column: &str) -> RecordBatchSink {
+        RecordBatchSink {
+            chain_column: chain_column.to_string(),
+            chain_ids: HashMap::new(),
+            monitor: None,
+        }
+    }
+
+    /// Adds every row of the batch as one draw.  All columns are checked
+    /// before any draw is pushed, so a batch that fails leaves the sink as
+    /// it was.
+    pub fn push_batch(&mut self, batch: &RecordBatch) -> Result<(), Error> {
+        let schema = batch.schema();
+        let names: Vec<String> = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .filter(|n| *n != self.chain_column)
+            .collect();
+        if let Some(monitor) = &self.monitor {
+            if names.len() != monitor.names().len() {
+                return Err(anyhow!(
+                    "Batch has {} parameter columns but the first batch had {}",
+                    names.len(),
+                    monitor.names().len()
+                ));
+            }
+        }
+
+        let expected = self.monitor.as_ref().map_or(&names[..], |m| m.names());
+        let mut chain_ids = self.chain_ids.clone();
+        let (chains, columns) = batch_columns(batch, expected, &self.chain_column, &mut chain_ids)?;
+        self.chain_ids = chain_ids;
+        let monitor = self
+            .monitor
+            .get_or_insert_with(|| OnlineMonitor::new(names));
+        let mut draw = vec![0.0; columns.len()];
+        for (row, chain) in chains.into_iter().enumerate() {
+            for (value, column) in draw.iter_mut().zip(columns.iter()) {
+                *value = column[row];
+            }
+            monitor.push(chain, &draw)?;
+        }
+        Ok(())
+    }
+
+    /// Monitor with all draws pushed so far, available after the first batch.
+    pub fn monitor(&self) -> Option<&OnlineMonitor> {
+        self.monitor.as_ref()
+    }
+
+    /// Consumes the sink, returning its monitor.
+    pub fn into_monitor(self) -> Option<OnlineMonitor> {
+        self.monitor
+    }
+}
+
+/// Reads record batches as they are produced, e.g. by an Arrow IPC stream
+/// reader or a Flight client, calling `on_snapshot` with the status of the
+/// monitor after every batch.  Returns the monitor once the batches are
+/// exhausted.
+///
+/// # Arguments
+/// * `batches` - Iterator over record batches, one block of iterations each
+/// * `chain_column` - Name of the column identifying the chain of each draw
+/// * `on_snapshot` - Callback receiving a status snapshot after each batch
+pub fn from_batches<I, F>(
+    batches: I,
+    chain_column: &str,
+    mut on_snapshot: F,
+) -> Result<OnlineMonitor, Error>
+where
+    I: IntoIterator<Item = Result<RecordBatch, ArrowError>>,
+    F: FnMut(&Snapshot),
+{
+    let mut sink = RecordBatchSink::new(chain_column);
+    for (idx, batch) in batches.into_iter().enumerate() {
+        let batch = batch.with_context(|| format!("Failed to read batch {}", idx + 1))?;
+        sink.push_batch(&batch)
+            .with_context(|| format!("Failed to process batch {}", idx + 1))?;
+        on_snapshot(&sink.monitor().unwrap().snapshot());
+    }
+    sink.into_monitor()
+        .ok_or_else(|| anyhow!("No record batches found"))
+}
+
+/// Chain index of every row and the values of every parameter column of a
+/// batch, numbering new chain ids in order of first appearance.
+fn batch_columns(
+    batch: &RecordBatch,
+    names: &[String],
+    chain_column: &str,
+    chain_ids: &mut HashMap<String, usize>,
+) -> Result<(Vec<usize>, Vec<Vec<f64>>), Error> {
+    let mut columns = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        let column = batch
+            .column_by_name(name)
+            .ok_or_else(|| anyhow!("Batch is missing column {:?}", name))?;
+        columns.push(column_values(name, column.as_ref())?);
+    }
+    let chains = match batch.column_by_name(chain_column) {
+        Some(column) => chain_keys(chain_column, column.as_ref())?
+            .into_iter()
+            .map(|key| {
+                let next = chain_ids.len();
+                *chain_ids.entry(key).or_insert(next)
+            })
+            .collect(),
+        None => vec![0; batch.num_rows()],
+    };
+    Ok((chains, columns))
+}
+
+/// Collects record batches into a container with one chain per chain id,
+/// in order of first appearance, e.g. from an Arrow IPC stream holding a
+/// finished run.  Columns are interpreted as by
+/// [`RecordBatchSink`](struct.RecordBatchSink.html).
+///
+/// # Arguments
+/// * `batches` - Iterator over record batches
+/// * `chain_column` - Name of the column identifying the chain of each draw
+pub fn draws_from_batches<I>(batches: I, chain_column: &str) -> Result<Draws, Error>
+where
+    I: IntoIterator<Item = Result<RecordBatch, ArrowError>>,
+{
+    let mut names: Option<Vec<String>> = None;
+    let mut chain_ids = HashMap::new();
+    let mut chains = ChainColumns::default();
+    for (idx, batch) in batches.into_iter().enumerate() {
+        let batch = batch.with_context(|| format!("Failed to read batch {}", idx + 1))?;
+        let names = names.get_or_insert_with(|| {
+            batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.name().clone())
+                .filter(|n| n != chain_column)
+                .collect()
+        });
+        let (rows, columns) = batch_columns(&batch, names, chain_column, &mut chain_ids)
+            .with_context(|| format!("Failed to process batch {}", idx + 1))?;
+        let mut draw = vec![0.0; columns.len()];
+        for (row, chain) in rows.into_iter().enumerate() {
+            for (value, column) in draw.iter_mut().zip(columns.iter()) {
+                *value = column[row];
+            }
+            chains.push(chain, &draw);
+        }
+    }
+    let names = names.ok_or_else(|| anyhow!("No record batches found"))?;
+    if chains.is_empty() {
+        return Err(anyhow!("No draws found in record batches"));
+    }
+    chains.into_draws(names)
+}
+
+/// Converts columnar posterior summaries into a record batch with a
+/// `parameter` string column followed by one `Float64` column per summary
+/// column, named as the fields of
+/// [`SummaryColumns`](../../summary/struct.SummaryColumns.html).  The
+/// optional columns are only included when present.  The value vectors
+/// become the buffers of the arrays without being copied.
+pub fn summary_batch(columns: SummaryColumns) -> Result<RecordBatch, Error> {
+    let mut fields: Vec<(&str, ArrayRef)> = vec![
+        ("parameter", Arc::new(StringArray::from(columns.names))),
+        ("mean", Arc::new(Float64Array::from(columns.mean))),
+        ("mcse", Arc::new(Float64Array::from(columns.mcse))),
+        ("sd", Arc::new(Float64Array::from(columns.sd))),
+        ("q5", Arc::new(Float64Array::from(columns.q5))),
+        ("q50", Arc::new(Float64Array::from(columns.q50))),
+        ("q95", Arc::new(Float64Array::from(columns.q95))),
+        ("ess", Arc::new(Float64Array::from(columns.ess))),
+        ("rhat", Arc::new(Float64Array::from(columns.rhat))),
+    ];
+    let optional = [
+        ("rhat_sd", columns.rhat_sd),
+        ("tail_ess", columns.tail_ess),
+        ("mcse_q5", columns.mcse_q5),
+        ("mcse_q95", columns.mcse_q95),
+    ];
+    for (name, column) in optional {
+        if let Some(values) = column {
+            fields.push((name, Arc::new(Float64Array::from(values))));
+        }
+    }
+    RecordBatch::try_from_iter(fields).context("Failed to build summary record batch")
+}
+
+/// Converts a parameter column to floating point values.
+pub(crate) fn column_values(name: &str, column: &dyn Array) -> Result<Vec<f64>, Error> {
+    if column.null_count() > 0 {
+        return Err(anyhow!("Column {:?} contains nulls", name));
+    }
+    let values = match column.data_type() {
+        DataType::Float64 => column.as_primitive::<Float64Type>().values().to_vec(),
+        DataType::Float32 => column
+            .as_primitive::<Float32Type>()
+            .values()
+            .iter()
+            .map(|&x| x as f64)
+            .collect(),
+        DataType::Int32 => column
+            .as_primitive::<Int32Type>()
+            .values()
+            .iter()
+            .map(|&x| x as f64)
+            .collect(),
+        DataType::Int64 => column
+            .as_primitive::<Int64Type>()
+            .values()
+            .iter()
+            .map(|&x| x as f64)
+            .collect(),
+        DataType::Boolean => column
+            .as_boolean()
+            .values()
+            .iter()
+            .map(|b| if b { 1.0 } else { 0.0 })
+            .collect(),
+        other => {
+            return Err(anyhow!(
+                "Column {:?} has unsupported type {} for draws",
+                name,
+                other
+            ))
+        }
+    };
+    Ok(values)
+}
+
+/// Converts a chain column to one key per row.
+pub(crate) fn chain_keys(name: &str, column: &dyn Array) -> Result<Vec<String>, Error> {
+    if column.null_count() > 0 {
+        return Err(anyhow!("Chain column {:?} contains nulls", name));
+    }
+    let keys = match column.data_type() {
+        DataType::Int32 => column
+            .as_primitive::<Int32Type>()
+            .values()
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        DataType::Int64 => column
+            .as_primitive::<Int64Type>()
+            .values()
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        DataType::UInt32 => column
+            .as_primitive::<UInt32Type>()
+            .values()
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        DataType::UInt64 => column
+            .as_primitive::<UInt64Type>()
+            .values()
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        DataType::Utf8 => column
+            .as_string::<i32>()
+            .iter()
+            .map(|s| s.unwrap_or_default().to_string())
+            .collect(),
+        other => {
+            return Err(anyhow!(
+                "Chain column {:?} has unsupported type {}",
+                name,
+                other
+            ))
+        }
+    };
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::{summarize_draws_columnar, summarize_draws_with_options, SummaryOptions};
+    use arrow_array::{BooleanArray, Float32Array, Int64Array};
+
+    fn batch(chain: Vec<i64>, theta: Vec<f64>, lp: Vec<f32>) -> RecordBatch {
+        RecordBatch::try_from_iter(vec![
+            ("chain", Arc::new(Int64Array::from(chain)) as ArrayRef),
+            ("theta", Arc::new(Float64Array::from(theta)) as ArrayRef),
+            ("lp__", Arc::new(Float32Array::from(lp)) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_batches_matches_monitor() {
+        let batches = vec![
+            Ok(batch(vec![1, 2], vec![0.1, 0.2], vec![-1.0, -1.5])),
+            Ok(batch(
+                vec![2, 1, 1],
+                vec![0.4, 0.3, 0.5],
+                vec![-2.5, -2.0, -1.0],
+            )),
+        ];
+        let mut snapshots = Vec::new();
+        let monitor = from_batches(batches, "chain", |s| snapshots.push(s.clone())).unwrap();
+        assert_eq!(monitor.names(), &["theta".to_string(), "lp__".to_string()]);
+        assert_eq!(monitor.num_chains(), 2);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].num_draws, 2);
+
+        let mut expected = OnlineMonitor::new(vec!["theta".to_string(), "lp__".to_string()]);
+        for (chain, draw) in [
+            (0, [0.1, -1.0]),
+            (1, [0.2, -1.5]),
+            (1, [0.4, -2.5]),
+            (0, [0.3, -2.0]),
+            (0, [0.5, -1.0]),
+        ] {
+            expected.push(chain, &draw).unwrap();
+        }
+        assert_eq!(monitor.snapshot(), expected.snapshot());
+    }
+
+    #[test]
+    fn test_push_batch_without_chain_column() {
+        let mut sink = RecordBatchSink::new("chain");
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "theta",
+                Arc::new(Float64Array::from(vec![1.0, 2.0])) as ArrayRef,
+            ),
+            (
+                "divergent__",
+                Arc::new(BooleanArray::from(vec![false, true])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        sink.push_batch(&batch).unwrap();
+        let snapshot = sink.monitor().unwrap().snapshot();
+        assert_eq!(sink.monitor().unwrap().num_chains(), 1);
+        assert_abs_diff_eq!(snapshot.parameters[1].mean, 0.5);
+    }
+
+    #[test]
+    fn test_push_batch_errors() {
+        let mut sink = RecordBatchSink::new("chain");
+        sink.push_batch(&batch(vec![1], vec![0.1], vec![-1.0]))
+            .unwrap();
+        let other = RecordBatch::try_from_iter(vec![
+            ("theta", Arc::new(Float64Array::from(vec![1.0])) as ArrayRef),
+            ("sigma", Arc::new(Float64Array::from(vec![1.0])) as ArrayRef),
+        ])
+        .unwrap();
+        assert!(sink.push_batch(&other).is_err());
+        let nulls = RecordBatch::try_from_iter(vec![(
+            "theta",
+            Arc::new(Float64Array::from(vec![Some(1.0), None])) as ArrayRef,
+        )])
+        .unwrap();
+        let mut sink = RecordBatchSink::new("chain");
+        assert!(sink.push_batch(&nulls).is_err());
+        // the failed first batch doesn't fix the parameters
+        assert!(sink.monitor().is_none());
+        sink.push_batch(&batch(vec![1], vec![0.1], vec![-1.0]))
+            .unwrap();
+        assert_eq!(sink.monitor().unwrap().names().len(), 2);
+        assert!(from_batches(Vec::new(), "chain", |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_draws_from_batches() {
+        let batches = vec![
+            Ok(batch(vec![1, 2], vec![0.1, 0.2], vec![-1.0, -1.5])),
+            Ok(batch(vec![2, 1], vec![0.4, 0.3], vec![-2.5, -2.0])),
+        ];
+        let draws = draws_from_batches(batches, "chain").unwrap();
+        assert_eq!(draws.names(), &["theta".to_string(), "lp__".to_string()]);
+        assert_eq!(*draws.parameter(0), vec![vec![0.1, 0.3], vec![0.2, 0.4]]);
+        assert!(draws_from_batches(Vec::new(), "chain").is_err());
+    }
+
+    #[test]
+    fn test_summary_batch() {
+        let names = vec!["theta".to_string(), "lp__".to_string()];
+        let chains = (0..2)
+            .map(|c| {
+                vec![
+                    crate::utils::normal_draws(100, c),
+                    crate::utils::normal_draws(100, c + 2),
+                ]
+            })
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let options = SummaryOptions {
+            mcse_quantiles: true,
+            ..SummaryOptions::default()
+        };
+        let columns = summarize_draws_columnar(&draws, &options).unwrap();
+        let batch = summary_batch(columns).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "parameter",
+                "mean",
+                "mcse",
+                "sd",
+                "q5",
+                "q50",
+                "q95",
+                "ess",
+                "rhat",
+                "mcse_q5",
+                "mcse_q95"
+            ]
+        );
+        assert_eq!(batch.column(0).as_string::<i32>().value(1), "lp__");
+        let means = batch.column(1).as_primitive::<Float64Type>();
+        assert_eq!(
+            means.value(0),
+            summarize_draws_with_options(&draws, &SummaryOptions::default()).unwrap()[0].mean
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::draws::{Draws, Sampler};
+use crate::io::tidy::{self, TidyOptions};
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use std::io::BufRead;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Reads the `sample_stats` group of a PyMC trace in ArviZ format, written
+/// with `idata.sample_stats.to_dataframe().to_csv(path)`, one row per draw
+/// with `chain` and `draw` columns.  The draws are tagged as coming from
+/// PyMC, so the diagnostics read the `diverging`, `energy`, `tree_depth`
+/// and `step_size` stats like Stan's `divergent__` and friends.  The
+/// `True` and `False` of boolean stats are read as 1 and 0, and the empty
+/// fields pandas writes for missing values as NaN.
+///
+/// # Arguments
+/// * `reader` - Any buffered reader over the contents of the CSV file
+pub fn sample_stats_from_reader<R: BufRead>(reader: R) -> Result<Draws, Error> {
+    let mut draws = from_pandas_reader(reader)?;
+    draws.set_sampler(Sampler::PyMc);
+    Ok(draws)
+}
+
+/// Reads a PyMC trace in ArviZ format from the tables of its `posterior`
+/// and `sample_stats` groups, each written with
+/// `to_dataframe().to_csv(path)`.  The posterior must have one row per
+/// draw, i.e. scalar variables only or vector ones flattened to a column
+/// per element.  Returns the parameters followed by the sample stats in one
+/// container tagged as coming from PyMC, see
+/// [`sample_stats_from_reader`](fn.sample_stats_from_reader.html).  Both
+/// tables must hold the same chains with the same number of draws, and no
+/// variable may be in both.
+///
+/// # Arguments
+/// * `posterior` - Buffered reader over the CSV of the posterior group
+/// * `sample_stats` - Buffered reader over the CSV of the sample stats group
+pub fn from_readers<P: BufRead, S: BufRead>(posterior: P, sample_stats: S) -> Result<Draws, Error> {
+    let posterior = from_pandas_reader(posterior).context("Failed to read posterior")?;
+    let stats = sample_stats_from_reader(sample_stats).context("Failed to read sample stats")?;
+    combine(&posterior, &stats)
+}
+
+/// Reads a PyMC trace from the bytes of its posterior and sample stats
+/// CSV files, see [`from_readers`](fn.from_readers.html).
+pub fn from_bytes(posterior: &[u8], sample_stats: &[u8]) -> Result<Draws, Error> {
+    from_readers(posterior, sample_stats)
+}
+
+/// Reads a PyMC trace from its posterior and sample stats CSV files on
+/// disk, see [`from_readers`](fn.from_readers.html).
+#[cfg(feature = "fs")]
+pub fn read_files<P: AsRef<Path>, S: AsRef<Path>>(
+    posterior: P,
+    sample_stats: S,
+) -> Result<Draws, Error> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    };
+    let (posterior, sample_stats) = (posterior.as_ref(), sample_stats.as_ref());
+    from_readers(open(posterior)?, open(sample_stats)?).with_context(|| {
+        format!(
+            "Failed to parse {} and {}",
+            posterior.display(),
+            sample_stats.display()
+        )
+    })
+}
+
+/// Reads a table written by pandas' `to_csv`, with one row per draw.
+fn from_pandas_reader<R: BufRead>(reader: R) -> Result<Draws, Error> {
+    tidy::from_reader_with_parser(reader, &TidyOptions::default(), parse_pandas_value)
+}
+
+/// Parses a value, accepting the `True` and `False` of Python and the empty
+/// field pandas writes for a missing value.
+fn parse_pandas_value(field: &str) -> Result<f64, Error> {
+    match field {
+        "True" => Ok(1.0),
+        "False" => Ok(0.0),
+        "" => Ok(f64::NAN),
+        _ => Ok(field.parse::<f64>()?),
+    }
+}
+
+/// Joins the parameters and sample stats of the same chains.
+fn combine(posterior: &Draws, stats: &Draws) -> Result<Draws, Error> {
+    if posterior.num_chains() != stats.num_chains() {
+        return Err(anyhow!(
+            "Posterior has {} chains but sample stats have {}",
+            posterior.num_chains(),
+            stats.num_chains()
+        ));
+    }
+    if let Some(name) = stats
+        .names()
+        .iter()
+        .find(|n| posterior.index_of(n).is_some())
+    {
+        return Err(anyhow!(
+            "Variable {:?} is in both the posterior and the sample stats",
+            name
+        ));
+    }
+    let mut names = posterior.names().to_vec();
+    names.extend_from_slice(stats.names());
+    let parameters: Vec<_> = (0..posterior.num_parameters())
+        .map(|p| posterior.parameter(p))
+        .collect();
+    let sampler_stats: Vec<_> = (0..stats.num_parameters())
+        .map(|p| stats.parameter(p))
+        .collect();
+    let mut chains = Vec::with_capacity(posterior.num_chains());
+    for chain in 0..posterior.num_chains() {
+        if posterior.chain_name(chain) != stats.chain_name(chain) {
+            return Err(anyhow!(
+                "Chain {} of the posterior is chain {} of the sample stats",
+                posterior.chain_name(chain),
+                stats.chain_name(chain)
+            ));
+        }
+        let columns: Array2 = parameters
+            .iter()
+            .chain(sampler_stats.iter())
+            .map(|p| p[chain].clone())
+            .collect();
+        if columns.iter().any(|c| c.len() != columns[0].len()) {
+            return Err(anyhow!(
+                "Chain {} has a different number of draws in the posterior and the sample stats",
+                posterior.chain_name(chain)
+            ));
+        }
+        chains.push(columns);
+    }
+    let mut draws = Draws::from_chains(names, chains)?;
+    for chain in 0..posterior.num_chains() {
+        if let Some(label) = posterior.chain_label(chain) {
+            draws.set_chain_label(chain, label.clone())?;
+        }
+    }
+    draws.set_sampler(Sampler::PyMc);
+    Ok(draws)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::{verdict, Evidence};
+
+    #[test]
+    fn test_from_bytes_tags_pymc() {
+        // as written by idata.posterior.to_dataframe().to_csv() and
+        // idata.sample_stats.to_dataframe().to_csv()
+        let posterior = "chain,draw,mu,energy\n0,0,0.1,5\n0,1,0.2,6\n1,0,0.3,7\n1,1,0.4,8\n";
+        let stats = "chain,draw,diverging,energy,largest_eigval\n\
+                     0,0,False,10.5,\n0,1,True,11.0,\n1,0,False,9.5,\n1,1,False,9.0,\n";
+        assert!(from_bytes(posterior.as_bytes(), stats.as_bytes()).is_err());
+
+        let posterior = posterior.replace("energy", "sigma");
+        let draws = from_bytes(posterior.as_bytes(), stats.as_bytes()).unwrap();
+        assert_eq!(draws.sampler(), Sampler::PyMc);
+        assert_eq!(
+            draws.names(),
+            &["mu", "sigma", "diverging", "energy", "largest_eigval"]
+        );
+        assert_eq!(
+            *draws.get("diverging").unwrap(),
+            vec![vec![0.0, 1.0], vec![0.0, 0.0]]
+        );
+        assert!(draws.get("largest_eigval").unwrap()[0][0].is_nan());
+        assert_eq!(draws.chain_name(1), "1");
+
+        let short = "chain,draw,diverging\n0,0,False\n1,0,False\n1,1,False\n";
+        assert!(from_bytes(posterior.as_bytes(), short.as_bytes()).is_err());
+        let one_chain = "chain,draw,diverging\n0,0,False\n0,1,False\n";
+        assert!(from_bytes(posterior.as_bytes(), one_chain.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_sample_stats_feed_verdict() {
+        let mut stats = "chain,draw,diverging,energy\n".to_string();
+        for chain in 0..2 {
+            for draw in 0..100 {
+                let divergent = if chain == 1 && draw == 7 {
+                    "True"
+                } else {
+                    "False"
+                };
+                let energy = ((draw * 37 + chain * 11) % 17) as f64;
+                stats.push_str(&format!("{},{},{},{}\n", chain, draw, divergent, energy));
+            }
+        }
+        let draws = sample_stats_from_reader(stats.as_bytes()).unwrap();
+        let evidence = verdict(&draws).unwrap().evidence().to_vec();
+        assert_eq!(evidence.len(), 1);
+        assert!(matches!(
+            evidence[0],
+            Evidence::Divergences { count: 1, .. }
+        ));
+    }
+}
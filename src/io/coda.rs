@@ -0,0 +1,256 @@
+use crate::draws::Draws;
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use std::io::BufRead;
+use std::ops::Range;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Variable of a CODA index file with the lines of the chain files that
+/// hold its draws.
+#[derive(Debug, Clone, PartialEq)]
+struct IndexEntry {
+    name: String,
+    // zero based, end exclusive
+    lines: Range<usize>,
+}
+
+/// Reads a CODA index file, where every line names a variable followed by
+/// the first and last line, counting from one, of its draws in each chain
+/// file, e.g. `beta[2]  1001  2000`.
+fn read_index<R: BufRead>(reader: R) -> Result<Vec<IndexEntry>, Error> {
+    let mut entries = Vec::new();
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read index line {}", line_idx + 1))?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() != 3 {
+            return Err(anyhow!(
+                "Expected a name and two line numbers at index line {} but found {:?}",
+                line_idx + 1,
+                line
+            ));
+        }
+        let bound = |field: &str| {
+            field
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Invalid line number {:?} at index line {}",
+                        field,
+                        line_idx + 1
+                    )
+                })
+        };
+        let (start, end) = (bound(fields[1])?, bound(fields[2])?);
+        if end < start {
+            return Err(anyhow!(
+                "Range of {} at index line {} ends before it starts",
+                fields[0],
+                line_idx + 1
+            ));
+        }
+        entries.push(IndexEntry {
+            name: fields[0].to_string(),
+            lines: start - 1..end,
+        });
+    }
+    if entries.is_empty() {
+        return Err(anyhow!("No variables found in CODA index"));
+    }
+    Ok(entries)
+}
+
+/// Reads the iteration numbers and draws of a CODA chain file, one
+/// `iteration value` pair per line.  `NA` values are read as NaN.
+fn read_chain<R: BufRead>(reader: R) -> Result<(Vec<String>, Vec<f64>), Error> {
+    let mut iterations = Vec::new();
+    let mut values = Vec::new();
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            return Err(anyhow!(
+                "Expected an iteration and a value at line {} but found {:?}",
+                line_idx + 1,
+                line
+            ));
+        }
+        let value = match fields[1] {
+            "NA" => f64::NAN,
+            field => field
+                .parse::<f64>()
+                .with_context(|| format!("Invalid value {:?} at line {}", field, line_idx + 1))?,
+        };
+        iterations.push(fields[0].to_string());
+        values.push(value);
+    }
+    Ok((iterations, values))
+}
+
+/// Picks the draws of every variable in the index out of a chain file.  All
+/// variables must have been recorded at the same iterations.
+fn chain_columns<R: BufRead>(index: &[IndexEntry], reader: R) -> Result<Array2, Error> {
+    let (iterations, values) = read_chain(reader)?;
+    let mut columns = Vec::with_capacity(index.len());
+    for entry in index.iter() {
+        if entry.lines.end > values.len() {
+            return Err(anyhow!(
+                "Index expects lines {} to {} for {} but the chain has {} lines",
+                entry.lines.start + 1,
+                entry.lines.end,
+                entry.name,
+                values.len()
+            ));
+        }
+        let first = &index[0].lines;
+        if iterations[entry.lines.clone()] != iterations[first.clone()] {
+            return Err(anyhow!(
+                "Iterations of {} do not match those of {}",
+                entry.name,
+                index[0].name
+            ));
+        }
+        columns.push(values[entry.lines.clone()].to_vec());
+    }
+    Ok(columns)
+}
+
+/// Reads draws in the CODA format written by JAGS and WinBUGS/OpenBUGS: an
+/// index file mapping every variable, e.g. `beta[2]`, to a range of lines,
+/// and one file per chain with an `iteration value` pair per line, where
+/// each variable's draws fill the lines the index gives it.  Every variable
+/// becomes a parameter, in index order, and chains are numbered in the
+/// order of `chains`.
+///
+/// # Arguments
+/// * `index` - Buffered reader over the index file, e.g. `CODAindex.txt`
+/// * `chains` - Buffered readers over the chain files, e.g. `CODAchain1.txt`
+pub fn from_readers<I, C>(index: I, chains: Vec<C>) -> Result<Draws, Error>
+where
+    I: BufRead,
+    C: BufRead,
+{
+    let index = read_index(index).context("Failed to parse CODA index")?;
+    if chains.is_empty() {
+        return Err(anyhow!("Need at least one CODA chain file"));
+    }
+    let chains = chains
+        .into_iter()
+        .enumerate()
+        .map(|(idx, reader)| {
+            chain_columns(&index, reader)
+                .with_context(|| format!("Failed to parse CODA chain {}", idx + 1))
+        })
+        .collect::<Result<Vec<Array2>, Error>>()?;
+    let names = index.into_iter().map(|entry| entry.name).collect();
+    Draws::from_chains(names, chains)
+}
+
+/// Reads CODA draws from the bytes of an index file and its chain files.
+pub fn from_bytes(index: &[u8], chains: &[&[u8]]) -> Result<Draws, Error> {
+    from_readers(index, chains.to_vec())
+}
+
+/// Reads CODA draws from an index file and chain files on disk, see
+/// [`from_readers`](fn.from_readers.html).
+#[cfg(feature = "fs")]
+pub fn read_files<P: AsRef<Path>>(index: P, chains: &[P]) -> Result<Draws, Error> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    };
+    let index = index.as_ref();
+    let chains = chains
+        .iter()
+        .map(|path| open(path.as_ref()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    from_readers(open(index)?, chains)
+        .with_context(|| format!("Failed to read CODA output indexed by {}", index.display()))
+}
+
+/// Reads the CODA files JAGS writes for a file stem, i.e. `{stem}index.txt`
+/// and `{stem}chain1.txt`, `{stem}chain2.txt` and so on for as long as
+/// they exist.  JAGS uses the stem `CODA` unless told otherwise.
+///
+/// # Arguments
+/// * `stem` - Path prefix of the files, e.g. `output/CODA`
+#[cfg(feature = "fs")]
+pub fn read_stem(stem: &str) -> Result<Draws, Error> {
+    let index = format!("{}index.txt", stem);
+    let chains: Vec<String> = (1..)
+        .map(|chain| format!("{}chain{}.txt", stem, chain))
+        .take_while(|path| Path::new(path).is_file())
+        .collect();
+    read_files(index, &chains)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX: &str = "mu\t1\t3\nbeta[1]\t4\t6\n\nbeta[2]  7  9\n";
+    const CHAIN1: &str = "1001 0.5\n1002 0.6\n1003 0.7\n\
+                          1001 1\n1002 2\n1003 3\n\
+                          1001 -1.5\n1002 NA\n1003 2e-1\n";
+    const CHAIN2: &str = "1001 0.1\n1002 0.2\n1003 0.3\n\
+                          1001 4\n1002 5\n1003 6\n\
+                          1001 7\n1002 8\n1003 9\n";
+
+    #[test]
+    fn test_from_bytes() {
+        let draws = from_bytes(INDEX.as_bytes(), &[CHAIN1.as_bytes(), CHAIN2.as_bytes()]).unwrap();
+        assert_eq!(draws.names(), &["mu", "beta[1]", "beta[2]"]);
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(draws.num_draws(), 3);
+        assert_eq!(
+            *draws.get("beta[1]").unwrap(),
+            vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]
+        );
+        let beta2 = draws.get("beta[2]").unwrap();
+        assert_eq!(beta2[0][0], -1.5);
+        assert!(beta2[0][1].is_nan());
+        assert_eq!(beta2[1], vec![7.0, 8.0, 9.0]);
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        let chains = [CHAIN1.as_bytes()];
+        assert!(from_bytes(b"", &chains).is_err());
+        assert!(from_bytes(b"mu 1\n", &chains).is_err());
+        assert!(from_bytes(b"mu 0 3\n", &chains).is_err());
+        assert!(from_bytes(b"mu 3 1\n", &chains).is_err());
+        assert!(from_bytes(INDEX.as_bytes(), &[]).is_err());
+        // ranges past the end of the chain file
+        assert!(from_bytes(b"mu 1 10\n", &chains).is_err());
+        // ranges that straddle two variables
+        let err = from_bytes(b"mu 1 3\nbeta 3 5\n", &chains).unwrap_err();
+        assert!(format!("{:#}", err).contains("Iterations of beta do not match those of mu"));
+        assert!(from_bytes(b"mu 1 1\n", &[&b"1 x\n"[..]]).is_err());
+        assert!(from_bytes(b"mu 1 1\n", &[&b"1\n"[..]]).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_read_stem() {
+        let dir = std::env::temp_dir().join(format!("mcmc-coda-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("CODAindex.txt"), INDEX).unwrap();
+        std::fs::write(dir.join("CODAchain1.txt"), CHAIN1).unwrap();
+        std::fs::write(dir.join("CODAchain2.txt"), CHAIN2).unwrap();
+        let stem = dir.join("CODA");
+        let draws = read_stem(&stem.to_string_lossy()).unwrap();
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(
+            *draws.get("mu").unwrap(),
+            vec![vec![0.5, 0.6, 0.7], vec![0.1, 0.2, 0.3]]
+        );
+        assert!(read_stem(&dir.join("other").to_string_lossy()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+use crate::io::arrow::RecordBatchSink;
+use crate::online::{OnlineMonitor, Snapshot};
+use anyhow::{Context, Error, Result};
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_descriptor::DescriptorType;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tonic::{Request, Response, Status, Streaming};
+
+/// Arrow Flight service that receives draws from remote workers with
+/// `DoPut` and feeds them into online diagnostics, for distributed samplers
+/// where writing files is awkward.
+///
+/// Every put stream names its run with its flight descriptor: the path
+/// segments joined by `/`, or the command as text.  All streams with the
+/// same name, e.g. one per worker, feed the same
+/// [`RecordBatchSink`](../arrow/struct.RecordBatchSink.html), so the batches
+/// need a chain column with ids that are unique across workers.  Once a
+/// stream ends the service answers with a single `PutResult` whose
+/// `app_metadata` holds the number of draws of the run received so far.
+///
+/// The service is cheap to clone and every clone shares the same runs, so
+/// one clone can be served while another one reads the diagnostics:
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use mcmc::io::flight::FlightIntake;
+///
+/// let intake = FlightIntake::new("chain");
+/// tokio::spawn(intake.clone().serve("0.0.0.0:50051".parse()?));
+/// // ... later
+/// if let Some(snapshot) = intake.snapshot("eight_schools") {
+///     println!("{} draws so far", snapshot.num_draws);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlightIntake {
+    chain_column: String,
+    runs: Arc<Mutex<BTreeMap<String, RecordBatchSink>>>,
+}
+
+/// Name of the run a flight descriptor stands for.
+fn run_name(descriptor: Option<&FlightDescriptor>) -> String {
+    match descriptor {
+        Some(d) if d.r#type == DescriptorType::Cmd as i32 => {
+            String::from_utf8_lossy(&d.cmd).into_owned()
+        }
+        Some(d) => d.path.join("/"),
+        None => String::new(),
+    }
+}
+
+impl FlightIntake {
+    /// Creates a service without any runs.
+    ///
+    /// # Arguments
+    /// * `chain_column` - Name of the column identifying the chain of each draw
+    pub fn new(chain_column: &str) -> FlightIntake {
+        FlightIntake {
+            chain_column: chain_column.to_string(),
+            runs: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Names of the runs that received at least one batch, in sorted order.
+    pub fn runs(&self) -> Vec<String> {
+        let runs = self.runs.lock().unwrap();
+        runs.iter()
+            .filter(|(_, sink)| sink.monitor().is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Current status of the diagnostics of a run, if it received any
+    /// batches.
+    pub fn snapshot(&self, run: &str) -> Option<Snapshot> {
+        let runs = self.runs.lock().unwrap();
+        runs.get(run)?.monitor().map(|m| m.snapshot())
+    }
+
+    /// Removes a run, returning its monitor, e.g. once sampling finished.
+    /// Batches put later under the same name start a new run.
+    pub fn take_monitor(&self, run: &str) -> Option<OnlineMonitor> {
+        self.runs.lock().unwrap().remove(run)?.into_monitor()
+    }
+
+    /// Wraps the service for a tonic server, e.g. to serve it next to other
+    /// gRPC services.
+    pub fn into_server(self) -> FlightServiceServer<FlightIntake> {
+        FlightServiceServer::new(self)
+    }
+
+    /// Serves the service on the given address until the server fails.
+    /// Must be run on a tokio runtime.
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_server())
+            .serve(addr)
+            .await
+            .with_context(|| format!("Failed to serve Arrow Flight on {}", addr))
+    }
+
+    /// Adds a batch to the named run.
+    fn push(&self, run: &str, batch: &arrow_array::RecordBatch) -> Result<(), Error> {
+        let mut runs = self.runs.lock().unwrap();
+        let sink = runs
+            .entry(run.to_string())
+            .or_insert_with(|| RecordBatchSink::new(&self.chain_column));
+        sink.push_batch(batch)
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for FlightIntake {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+
+    async fn do_put(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        let mut messages = request.into_inner();
+        // the descriptor comes with the first message, which also holds the
+        // schema and must go on to the decoder
+        let first = match messages.message().await? {
+            Some(first) => first,
+            None => return Err(Status::invalid_argument("Empty put stream")),
+        };
+        let run = run_name(first.flight_descriptor.as_ref());
+        let messages = stream::iter(Some(Ok(first)))
+            .chain(messages)
+            .map_err(FlightError::from);
+        let mut batches = FlightRecordBatchStream::new_from_flight_data(messages);
+        while let Some(batch) = batches.next().await {
+            let batch = batch.map_err(Status::from)?;
+            self.push(&run, &batch)
+                .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        }
+        let num_draws = self.snapshot(&run).map_or(0, |s| s.num_draws);
+        let result = PutResult {
+            app_metadata: num_draws.to_string().into(),
+        };
+        Ok(Response::new(stream::iter(Some(Ok(result))).boxed()))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn do_get(
+        &self,
+        _request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("Only DoPut is supported"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Float64Array, Int64Array, RecordBatch};
+    use arrow_flight::client::FlightClient;
+    use arrow_flight::encode::FlightDataEncoderBuilder;
+    use arrow_schema::{DataType, Field, Schema};
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::{Endpoint, Server};
+
+    fn batch(chain: i64, theta: Vec<f64>) -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("chain", DataType::Int64, false),
+            Field::new("theta", DataType::Float64, false),
+        ]);
+        let chains = vec![chain; theta.len()];
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int64Array::from(chains)),
+                Arc::new(Float64Array::from(theta)),
+            ],
+        )
+        .unwrap()
+    }
+
+    async fn put(
+        client: &mut FlightClient,
+        run: &str,
+        batches: Vec<RecordBatch>,
+    ) -> Result<String, FlightError> {
+        let descriptor = FlightDescriptor::new_path(vec![run.to_string()]);
+        let data = FlightDataEncoderBuilder::new()
+            .with_flight_descriptor(Some(descriptor))
+            .build(stream::iter(batches.into_iter().map(Ok)));
+        let results: Vec<PutResult> = client.do_put(data).await?.try_collect().await?;
+        Ok(String::from_utf8_lossy(&results[0].app_metadata).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_flight_intake() {
+        let intake = FlightIntake::new("chain");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::builder()
+            .add_service(intake.clone().into_server())
+            .serve_with_incoming(TcpIncoming::from(listener));
+        tokio::spawn(server);
+
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = FlightClient::new(channel);
+        // two workers with one chain each
+        let first = put(
+            &mut client,
+            "model",
+            vec![batch(1, vec![0.1, 0.2]), batch(1, vec![0.3])],
+        );
+        assert_eq!(first.await.unwrap(), "3");
+        let second = put(&mut client, "model", vec![batch(2, vec![0.4, 0.5])]);
+        assert_eq!(second.await.unwrap(), "5");
+
+        assert_eq!(intake.runs(), vec!["model".to_string()]);
+        let snapshot = intake.snapshot("model").unwrap();
+        assert_eq!(snapshot.num_draws, 5);
+        assert_abs_diff_eq!(snapshot.parameters[0].mean, 0.3, epsilon = 1e-12);
+        assert!(intake.snapshot("other").is_none());
+
+        // batches must match the columns of the first one
+        let schema = Schema::new(vec![Field::new("other", DataType::Float64, false)]);
+        let other = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(Float64Array::from(vec![1.0]))],
+        )
+        .unwrap();
+        assert!(put(&mut client, "model", vec![other]).await.is_err());
+
+        let monitor = intake.take_monitor("model").unwrap();
+        assert_eq!(monitor.num_chains(), 2);
+        assert!(intake.runs().is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::draws::Draws;
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use matfile::{MatFile, NumericData};
+use std::io::Read;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Reads draws from a MATLAB Level 5 `.mat` file, as written by `save` with
+/// the `-v6` or (compressed) `-v7` options.  Files saved with `-v7.3` are
+/// HDF5 files and are not supported.
+///
+/// Every numeric variable holds the draws of one parameter, with iterations
+/// along the first dimension:
+///
+/// * a vector of length `n` is a single chain of `n` draws,
+/// * an `n x m` matrix holds `m` chains of `n` draws,
+/// * an `n x k x m` array holds `m` chains of `n` draws for `k` parameters,
+///   which are named `name[1]` to `name[k]`.
+///
+/// All variables must have the same number of draws and chains.  Parameters
+/// are ordered as the variables appear in the file.
+///
+/// # Arguments
+/// * `reader` - Any reader over the contents of a `.mat` file
+pub fn from_reader<R: Read>(mut reader: R) -> Result<Draws, Error> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .context("Failed to read MAT-file")?;
+    from_bytes(&bytes)
+}
+
+/// Reads draws from the bytes of a MATLAB `.mat` file, see
+/// [`from_reader`](fn.from_reader.html) for the expected layout.
+pub fn from_bytes(bytes: &[u8]) -> Result<Draws, Error> {
+    if bytes.starts_with(b"MATLAB 7.3") {
+        return Err(anyhow!(
+            "MAT-file version 7.3 (HDF5) is not supported, save with -v7 instead"
+        ));
+    }
+    let mat = MatFile::parse(bytes).map_err(|e| anyhow!("Failed to parse MAT-file: {}", e))?;
+    let mut names = Vec::new();
+    // columns[parameter][chain]
+    let mut columns: Vec<Array2> = Vec::new();
+    let mut shape: Option<(usize, usize)> = None;
+    for array in mat.arrays().iter() {
+        let values = to_f64(array.data())
+            .with_context(|| format!("Unsupported data in variable {}", array.name()))?;
+        let size = array.size();
+        let (num_draws, num_parameters, num_chains) = match size.as_slice() {
+            [1, n] | [n, 1] => (*n, 1, 1),
+            [n, m] => (*n, 1, *m),
+            [n, k, m] => (*n, *k, *m),
+            _ => {
+                return Err(anyhow!(
+                    "Variable {} has {} dimensions, expected at most 3",
+                    array.name(),
+                    size.len()
+                ))
+            }
+        };
+        match shape {
+            None => shape = Some((num_draws, num_chains)),
+            Some(expected) if expected != (num_draws, num_chains) => {
+                return Err(anyhow!(
+                    "Variable {} has {} draws in {} chains but the first variable has {} in {}",
+                    array.name(),
+                    num_draws,
+                    num_chains,
+                    expected.0,
+                    expected.1
+                ))
+            }
+            Some(_) => {}
+        }
+        for p in 0..num_parameters {
+            names.push(if size.len() == 3 {
+                format!("{}[{}]", array.name(), p + 1)
+            } else {
+                array.name().to_string()
+            });
+            // column-major, so iterations vary fastest
+            columns.push(
+                (0..num_chains)
+                    .map(|c| {
+                        let start = num_draws * (p + num_parameters * c);
+                        values[start..start + num_draws].to_vec()
+                    })
+                    .collect(),
+            );
+        }
+    }
+    let (_, num_chains) = shape.ok_or_else(|| anyhow!("No numeric variables found in MAT-file"))?;
+    let chains = (0..num_chains)
+        .map(|c| columns.iter().map(|p| p[c].clone()).collect())
+        .collect();
+    Draws::from_chains(names, chains)
+}
+
+/// Reads draws from a MATLAB `.mat` file on disk, see
+/// [`from_reader`](fn.from_reader.html) for the expected layout.
+#[cfg(feature = "fs")]
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Draws, Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    from_reader(BufReader::new(f)).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Converts the real part of a numeric array to `f64`, rejecting complex
+/// values.
+fn to_f64(data: &NumericData) -> Result<Vec<f64>, Error> {
+    fn convert<T: Copy + Into<f64>>(real: &[T], imag: &Option<Vec<T>>) -> Result<Vec<f64>, Error> {
+        if imag.is_some() {
+            return Err(anyhow!("Complex values are not supported"));
+        }
+        Ok(real.iter().map(|&x| x.into()).collect())
+    }
+    match data {
+        NumericData::Double { real, imag } => convert(real, imag),
+        NumericData::Single { real, imag } => convert(real, imag),
+        NumericData::Int8 { real, imag } => convert(real, imag),
+        NumericData::UInt8 { real, imag } => convert(real, imag),
+        NumericData::Int16 { real, imag } => convert(real, imag),
+        NumericData::UInt16 { real, imag } => convert(real, imag),
+        NumericData::Int32 { real, imag } => convert(real, imag),
+        NumericData::UInt32 { real, imag } => convert(real, imag),
+        NumericData::Int64 { real, imag } if imag.is_none() => {
+            Ok(real.iter().map(|&x| x as f64).collect())
+        }
+        NumericData::UInt64 { real, imag } if imag.is_none() => {
+            Ok(real.iter().map(|&x| x as f64).collect())
+        }
+        _ => Err(anyhow!("Complex values are not supported")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_read_file_compressed() {
+        // test/mat/draws.mat holds theta as a 50 x 2 matrix with value
+        // d + 100 c and beta as a 50 x 3 x 2 array with value
+        // d + 10 p + 1000 c, for zero based draw d, parameter p and chain c
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let draws = read_file(d.join("test/mat/draws.mat")).unwrap();
+        assert_eq!(
+            draws.names(),
+            &["theta", "beta[1]", "beta[2]", "beta[3]"].map(String::from)
+        );
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(draws.num_draws(), 50);
+        let theta = draws.get("theta").unwrap();
+        assert_eq!(theta[1][3], 103.0);
+        let beta = draws.get("beta[3]").unwrap();
+        assert_eq!(beta[0][0], 20.0);
+        assert_eq!(beta[1][49], 1069.0);
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        assert!(from_bytes(b"MATLAB 7.3 MAT-file, Platform: GLNXA64").is_err());
+        assert!(from_bytes(b"not a mat file").is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use crate::draws::Draws;
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use std::collections::HashMap;
+use std::io::BufRead;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Sampler statistics that Turing.jl puts in the `:internals` section of a
+/// chain.
+pub const TURING_INTERNALS: &[&str] = &[
+    "lp",
+    "logprior",
+    "loglikelihood",
+    "n_steps",
+    "is_accept",
+    "acceptance_rate",
+    "log_density",
+    "hamiltonian_energy",
+    "hamiltonian_energy_error",
+    "max_hamiltonian_energy_error",
+    "tree_depth",
+    "numerical_error",
+    "step_size",
+    "nom_step_size",
+];
+
+/// Options for reading serialized `MCMCChains.Chains` objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainsOptions {
+    /// Columns that belong to the `:internals` section.  The section names
+    /// are not part of the serialized table, so internals are recognized by
+    /// name.
+    pub internals: Vec<String>,
+}
+
+impl Default for ChainsOptions {
+    fn default() -> ChainsOptions {
+        ChainsOptions {
+            internals: TURING_INTERNALS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+/// Draws of an `MCMCChains.Chains` object, split into its sections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chains {
+    /// Draws of the `:parameters` section
+    pub parameters: Draws,
+    /// Sampler statistics of the `:internals` section, e.g. `lp` and
+    /// `tree_depth`
+    pub internals: Draws,
+}
+
+/// Reads the CSV written for a chain by `CSV.write(path, DataFrame(chain))`,
+/// using the internals sampled by Turing.jl.  See
+/// [`from_reader_with_options`](fn.from_reader_with_options.html).
+pub fn from_reader<R: BufRead>(reader: R) -> Result<Chains, Error> {
+    from_reader_with_options(reader, &ChainsOptions::default())
+}
+
+/// Reads the CSV written for a chain by `CSV.write(path, DataFrame(chain))`.
+/// The table is in long format with one row per draw: the `chain` column
+/// identifies the chain of each row and the `iteration` column is dropped.
+/// Chains are numbered in order of first appearance.  Without a `chain`
+/// column all rows belong to a single chain.
+///
+/// # Arguments
+/// * `reader` - Any buffered reader over the contents of the CSV file
+/// * `options` - Which columns belong to the internals section
+pub fn from_reader_with_options<R: BufRead>(
+    reader: R,
+    options: &ChainsOptions,
+) -> Result<Chains, Error> {
+    let mut table: Option<Table> = None;
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_fields(&line);
+        match table {
+            None => table = Some(Table::new(fields)),
+            Some(ref mut table) => {
+                if fields.len() != table.names.len() + table.num_dropped() {
+                    return Err(anyhow!(
+                        "Expected {} values at line {} but found {}",
+                        table.names.len() + table.num_dropped(),
+                        line_idx + 1,
+                        fields.len()
+                    ));
+                }
+                let mut draw = Vec::with_capacity(table.names.len());
+                for &idx in table.columns.iter() {
+                    draw.push(parse_value(&fields[idx]).with_context(|| {
+                        format!(
+                            "Invalid value {:?} at line {}, column {}",
+                            fields[idx],
+                            line_idx + 1,
+                            idx + 1
+                        )
+                    })?);
+                }
+                let chain = table.chain_column.map(|idx| fields[idx].clone());
+                table.push(chain, draw);
+            }
+        }
+    }
+    table
+        .ok_or_else(|| anyhow!("No header found in MCMCChains CSV"))?
+        .into_chains(options)
+}
+
+/// Reads a chain from the bytes of an MCMCChains CSV file.
+pub fn from_bytes(bytes: &[u8]) -> Result<Chains, Error> {
+    from_reader(bytes)
+}
+
+/// Reads a chain from an MCMCChains CSV file on disk.
+#[cfg(feature = "fs")]
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Chains, Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    from_reader(BufReader::new(f)).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Reads the record batches of a chain written by
+/// `Arrow.write(path, DataFrame(chain))`, e.g. from an Arrow IPC file
+/// reader.  The columns are interpreted as in
+/// [`from_reader_with_options`](fn.from_reader_with_options.html).
+/// Requires the `arrow` feature.
+///
+/// # Arguments
+/// * `batches` - Iterator over the record batches of the table
+/// * `options` - Which columns belong to the internals section
+#[cfg(feature = "arrow")]
+pub fn from_batches<I>(batches: I, options: &ChainsOptions) -> Result<Chains, Error>
+where
+    I: IntoIterator<Item = Result<arrow_array::RecordBatch, arrow_schema::ArrowError>>,
+{
+    use crate::io::arrow::{chain_keys, column_values};
+
+    let mut table: Option<Table> = None;
+    for (idx, batch) in batches.into_iter().enumerate() {
+        let batch = batch.with_context(|| format!("Failed to read batch {}", idx + 1))?;
+        let schema = batch.schema();
+        let header = schema.fields().iter().map(|f| f.name().clone()).collect();
+        let table = table.get_or_insert_with(|| Table::new(header));
+        let mut columns = Vec::with_capacity(table.names.len());
+        for name in table.names.iter() {
+            let column = batch
+                .column_by_name(name)
+                .ok_or_else(|| anyhow!("Batch {} is missing column {:?}", idx + 1, name))?;
+            columns.push(column_values(name, column.as_ref())?);
+        }
+        let chains = match batch.column_by_name("chain") {
+            Some(column) => Some(chain_keys("chain", column.as_ref())?),
+            None => None,
+        };
+        for row in 0..batch.num_rows() {
+            let draw = columns.iter().map(|c| c[row]).collect();
+            table.push(chains.as_ref().map(|c| c[row].clone()), draw);
+        }
+    }
+    table
+        .ok_or_else(|| anyhow!("No record batches found"))?
+        .into_chains(options)
+}
+
+/// Rows of a long format table grouped by chain.
+struct Table {
+    names: Vec<String>,
+    // positions of the value columns and the chain column in the header
+    columns: Vec<usize>,
+    chain_column: Option<usize>,
+    iteration_column: Option<usize>,
+    chain_ids: HashMap<String, usize>,
+    // chains[chain][parameter][draw]
+    chains: Vec<Array2>,
+}
+
+impl Table {
+    fn new(header: Vec<String>) -> Table {
+        let mut table = Table {
+            names: Vec::new(),
+            columns: Vec::new(),
+            chain_column: None,
+            iteration_column: None,
+            chain_ids: HashMap::new(),
+            chains: Vec::new(),
+        };
+        for (idx, name) in header.into_iter().enumerate() {
+            match name.as_str() {
+                "chain" => table.chain_column = Some(idx),
+                "iteration" => table.iteration_column = Some(idx),
+                _ => {
+                    table.names.push(name);
+                    table.columns.push(idx);
+                }
+            }
+        }
+        table
+    }
+
+    fn num_dropped(&self) -> usize {
+        self.chain_column.map_or(0, |_| 1) + self.iteration_column.map_or(0, |_| 1)
+    }
+
+    fn push(&mut self, chain: Option<String>, draw: Vec<f64>) {
+        let next = self.chain_ids.len();
+        let chain = *self
+            .chain_ids
+            .entry(chain.unwrap_or_default())
+            .or_insert(next);
+        if chain == self.chains.len() {
+            self.chains.push(vec![Vec::new(); self.names.len()]);
+        }
+        for (column, value) in self.chains[chain].iter_mut().zip(draw) {
+            column.push(value);
+        }
+    }
+
+    fn into_chains(self, options: &ChainsOptions) -> Result<Chains, Error> {
+        if self.names.is_empty() {
+            return Err(anyhow!("No parameter columns found"));
+        }
+        let lengths: Vec<usize> = self.chains.iter().map(|c| c[0].len()).collect();
+        if let Some(len) = lengths.iter().find(|&&len| len != lengths[0]) {
+            return Err(anyhow!(
+                "Chains have different numbers of draws ({} and {})",
+                lengths[0],
+                len
+            ));
+        }
+        let is_internal: Vec<bool> = self
+            .names
+            .iter()
+            .map(|n| options.internals.contains(n))
+            .collect();
+        let select = |internal: bool| {
+            let names = self
+                .names
+                .iter()
+                .zip(is_internal.iter())
+                .filter(|(_, &i)| i == internal)
+                .map(|(n, _)| n.clone())
+                .collect();
+            let chains = self
+                .chains
+                .iter()
+                .map(|chain| {
+                    chain
+                        .iter()
+                        .zip(is_internal.iter())
+                        .filter(|(_, &i)| i == internal)
+                        .map(|(c, _)| c.clone())
+                        .collect()
+                })
+                .collect();
+            Draws::from_chains(names, chains)
+        };
+        Ok(Chains {
+            parameters: select(false)?,
+            internals: select(true)?,
+        })
+    }
+}
+
+/// Splits a CSV line into fields, removing the quotes CSV.jl puts around
+/// names such as `"x[1, 2]"`.
+pub(crate) fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+/// Parses a value, accepting the `true` and `false` Julia writes for
+/// boolean internals such as `numerical_error`, and the `TRUE`, `FALSE` and
+/// `NA` of R.
+pub(crate) fn parse_value(field: &str) -> Result<f64, Error> {
+    match field {
+        "true" | "TRUE" => Ok(1.0),
+        "false" | "FALSE" => Ok(0.0),
+        "NA" => Ok(f64::NAN),
+        _ => Ok(field.parse::<f64>()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "iteration,chain,mu,\"x[1, 2]\",lp,numerical_error\n\
+                       1,1,0.5,1.0,-3.0,false\n\
+                       2,1,0.7,2.0,-3.5,true\n\
+                       1,2,-0.5,3.0,-4.0,0.0\n\
+                       2,2,-0.1,4.0,-4.5,0.0\n";
+
+    #[test]
+    fn test_from_bytes_splits_sections() {
+        let chains = from_bytes(CSV.as_bytes()).unwrap();
+        assert_eq!(
+            chains.parameters.names(),
+            &["mu".to_string(), "x[1, 2]".to_string()]
+        );
+        assert_eq!(
+            chains.internals.names(),
+            &["lp".to_string(), "numerical_error".to_string()]
+        );
+        assert_eq!(chains.parameters.num_chains(), 2);
+        assert_eq!(chains.parameters.get("mu").unwrap()[1], vec![-0.5, -0.1]);
+        assert_eq!(
+            chains.internals.get("numerical_error").unwrap()[0],
+            vec![0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_custom_internals_and_single_chain() {
+        let options = ChainsOptions {
+            internals: vec!["mu".to_string()],
+        };
+        let input = "mu,sigma\n1.0,2.0\n3.0,4.0\n";
+        let chains = from_reader_with_options(input.as_bytes(), &options).unwrap();
+        assert_eq!(chains.internals.names(), &["mu".to_string()]);
+        assert_eq!(chains.parameters.num_chains(), 1);
+        assert_eq!(chains.parameters.get("sigma").unwrap()[0], vec![2.0, 4.0]);
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        assert!(from_bytes(b"").is_err());
+        assert!(from_bytes(b"iteration,chain\n1,1\n").is_err());
+        assert!(from_bytes(b"chain,mu\n1,0.5\n1\n").is_err());
+        assert!(from_bytes(b"chain,mu\n1,missing\n").is_err());
+        assert!(from_bytes(b"chain,mu\n1,0.5\n1,0.6\n2,0.7\n").is_err());
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_from_batches_matches_csv() {
+        use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch};
+        use std::sync::Arc;
+
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "iteration",
+                Arc::new(Int64Array::from(vec![1, 2, 1, 2])) as ArrayRef,
+            ),
+            (
+                "chain",
+                Arc::new(Int64Array::from(vec![1, 1, 2, 2])) as ArrayRef,
+            ),
+            (
+                "mu",
+                Arc::new(Float64Array::from(vec![0.5, 0.7, -0.5, -0.1])) as ArrayRef,
+            ),
+            (
+                "lp",
+                Arc::new(Float64Array::from(vec![-3.0, -3.5, -4.0, -4.5])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let chains = from_batches(vec![Ok(batch)], &ChainsOptions::default()).unwrap();
+        let csv = from_bytes(CSV.as_bytes()).unwrap();
+        assert_eq!(chains.parameters.get("mu"), csv.parameters.get("mu"));
+        assert_eq!(chains.internals.get("lp"), csv.internals.get("lp"));
+        assert!(from_batches(Vec::new(), &ChainsOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+//! Loaders for sampler output.  Every loader accepts in-memory input (any
+//! `BufRead` or a byte slice), and loaders that touch the filesystem are only
+//! available with the default `fs` feature, so the crate can be built for
+//! targets without one such as `wasm32-unknown-unknown`.
+
+/// Online diagnostics fed from Arrow record batches
+#[cfg(feature = "arrow")]
+pub mod arrow;
+/// PyMC traces in ArviZ format, flattened into CSV tables
+pub mod arviz;
+/// CODA index and chain files written by JAGS and WinBUGS
+pub mod coda;
+/// Arrow Flight service receiving draws from distributed samplers
+#[cfg(feature = "flight")]
+pub mod flight;
+/// MATLAB .mat files with one variable per parameter
+#[cfg(feature = "mat")]
+pub mod mat;
+/// Serialized `MCMCChains.Chains` objects, e.g. from Turing.jl
+pub mod mcmcchains;
+/// NumPy .npy and .npz writers for handing draws to Python
+pub mod npy;
+/// Two pass summaries of draws files too large to load into memory
+pub mod outofcore;
+/// Summaries and warnings of runs appended to SQLite databases
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+/// Stan CSV output files, as written by CmdStan and its interfaces
+pub mod stan;
+/// Newline delimited draws read while a sampler is still running
+pub mod stream;
+/// Wide CSV files with a chain column, such as tidy draws exported from R
+pub mod tidy;
@@ -0,0 +1,619 @@
+use crate::draws::Draws;
+use crate::ess::compute_bulk_effective_sample_size;
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use std::convert::TryFrom;
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// Rule for choosing the thinning interval when writing, instead of a fixed
+/// one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoThin {
+    /// Thin as much as possible while keeping at least this fraction, in
+    /// (0, 1], of the bulk ESS of every written parameter, e.g. 0.95
+    EssRetention(f64),
+    /// Thin as little as possible so that the values written take at most
+    /// this many bytes, failing if even the four draws per chain kept by
+    /// the largest interval take more
+    MaxBytes(usize),
+}
+
+/// Options for writing draws in NumPy formats.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NpyOptions {
+    /// Keep every this many draws of each chain, starting with the first
+    pub thin: usize,
+    /// Names of the parameters to write, or `None` for all of them
+    pub parameters: Option<Vec<String>>,
+    /// Choose the thinning interval with this rule, ignoring `thin`, see
+    /// [`choose_thinning`](fn.choose_thinning.html)
+    pub auto_thin: Option<AutoThin>,
+}
+
+impl Default for NpyOptions {
+    fn default() -> NpyOptions {
+        NpyOptions {
+            thin: 1,
+            parameters: None,
+            auto_thin: None,
+        }
+    }
+}
+
+/// Thinning interval used when writing draws.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thinning {
+    /// Keep every this many draws of each chain
+    pub interval: usize,
+    /// Number of draws kept per chain
+    pub num_kept: usize,
+    /// Smallest ratio of the bulk ESS after and before thinning over the
+    /// written parameters, measured when the interval was chosen
+    /// automatically and some parameter has a finite ESS
+    pub ess_retained: Option<f64>,
+}
+
+impl Thinning {
+    /// The decision as a JSON object, as stored in `.npz` archives.
+    fn to_json(self, rule: &AutoThin) -> String {
+        let rule = match rule {
+            AutoThin::EssRetention(fraction) => format!("{{\"ess_retention\": {}}}", fraction),
+            AutoThin::MaxBytes(bytes) => format!("{{\"max_bytes\": {}}}", bytes),
+        };
+        let retained = self
+            .ess_retained
+            .map_or_else(|| "null".to_string(), |r| r.to_string());
+        format!(
+            "{{\"rule\": {}, \"interval\": {}, \"num_kept\": {}, \"ess_retained\": {}}}\n",
+            rule, self.interval, self.num_kept, retained
+        )
+    }
+}
+
+/// Positions of the parameters to write.
+fn selected_parameters(draws: &Draws, options: &NpyOptions) -> Result<Vec<usize>, Error> {
+    match options.parameters {
+        Some(ref names) => names
+            .iter()
+            .map(|name| {
+                draws
+                    .index_of(name)
+                    .ok_or_else(|| anyhow!("No parameter named {:?}", name))
+            })
+            .collect(),
+        None => Ok((0..draws.num_parameters()).collect()),
+    }
+}
+
+/// Chains of a parameter trimmed to `num_draws` and thinned.
+fn thinned(chains: &Array2, num_draws: usize, interval: usize) -> Array2 {
+    chains
+        .iter()
+        .map(|c| c[..num_draws].iter().step_by(interval).copied().collect())
+        .collect()
+}
+
+/// Chooses the thinning interval for writing draws with the given options:
+/// `thin` unless `auto_thin` is set.  The interval for
+/// [`AutoThin::EssRetention`](enum.AutoThin.html) comes from the integrated
+/// autocorrelation time `tau = draws / ESS` of every written parameter,
+/// treating each as an AR(1) series with `rho = (tau - 1) / (tau + 1)`:
+/// thinning by `k` then keeps the fraction `tau / (k tau_k)` of its ESS,
+/// with `tau_k` the time of the thinned series.  Either way, at least four
+/// draws per chain are kept, and the ESS actually retained is measured on
+/// the thinned draws.  Parameters without a finite ESS, e.g. constant ones,
+/// are left out.
+///
+/// # Arguments
+/// * `draws` - Draws to write
+/// * `options` - Parameters to write and the thinning rule
+pub fn choose_thinning(draws: &Draws, options: &NpyOptions) -> Result<Thinning, Error> {
+    if options.thin == 0 {
+        return Err(anyhow!("Thinning interval must be positive"));
+    }
+    let num_draws = draws.num_draws();
+    let rule = match options.auto_thin {
+        Some(rule) => rule,
+        None => {
+            return Ok(Thinning {
+                interval: options.thin,
+                num_kept: num_draws.div_ceil(options.thin),
+                ess_retained: None,
+            })
+        }
+    };
+    let parameters = selected_parameters(draws, options)?;
+    // largest interval that keeps at least four draws, i.e. with
+    // ceil(num_draws / k) >= 4
+    let max_interval = (num_draws.saturating_sub(1) / 3).max(1);
+    let columns: Vec<Array2> = parameters
+        .iter()
+        .map(|&p| thinned(&draws.parameter(p), num_draws, 1))
+        .collect();
+    let ess: Vec<Option<f64>> = columns
+        .iter()
+        .map(|c| {
+            compute_bulk_effective_sample_size(c)
+                .ok()
+                .filter(|e| e.is_finite() && *e > 0.0)
+        })
+        .collect();
+    let interval = match rule {
+        AutoThin::EssRetention(fraction) => {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                return Err(anyhow!("ESS retention must be in (0, 1], got {}", fraction));
+            }
+            let total = (num_draws * draws.num_chains()) as f64;
+            let rhos: Vec<f64> = ess
+                .iter()
+                .flatten()
+                .map(|e| {
+                    let tau = (total / e).max(1.0);
+                    (tau - 1.0) / (tau + 1.0)
+                })
+                .collect();
+            let retained = |k: usize| {
+                rhos.iter()
+                    .map(|rho| {
+                        let tau = (1.0 + rho) / (1.0 - rho);
+                        let rho_k = rho.powi(k as i32);
+                        let tau_k = (1.0 + rho_k) / (1.0 - rho_k);
+                        tau / (k as f64 * tau_k)
+                    })
+                    .fold(1.0, f64::min)
+            };
+            (1..=max_interval)
+                .take_while(|&k| retained(k) >= fraction)
+                .last()
+                .unwrap_or(1)
+        }
+        AutoThin::MaxBytes(bytes) => {
+            let per_draw = 8 * parameters.len().max(1) * draws.num_chains().max(1);
+            let max_kept = bytes / per_draw;
+            (1..=max_interval)
+                .find(|k| num_draws.div_ceil(*k) <= max_kept)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} bytes can't hold {} draws of {} parameters in {} chains",
+                        bytes,
+                        num_draws.div_ceil(max_interval),
+                        parameters.len(),
+                        draws.num_chains()
+                    )
+                })?
+        }
+    };
+    let ess_retained = columns
+        .iter()
+        .zip(ess.iter())
+        .filter_map(|(chains, ess)| {
+            let after = compute_bulk_effective_sample_size(&thinned(chains, num_draws, interval));
+            Some(after.ok()? / (*ess)?)
+        })
+        .reduce(f64::min);
+    Ok(Thinning {
+        interval,
+        num_kept: num_draws.div_ceil(interval),
+        ess_retained,
+    })
+}
+
+/// Positions of the parameters to write and the indices of the draws kept
+/// in every chain, which are trimmed to the length of the shortest one.
+fn selection(
+    draws: &Draws,
+    options: &NpyOptions,
+) -> Result<(Vec<usize>, Vec<usize>, Thinning), Error> {
+    let thinning = choose_thinning(draws, options)?;
+    let parameters = selected_parameters(draws, options)?;
+    let kept = (0..draws.num_draws()).step_by(thinning.interval).collect();
+    Ok((parameters, kept, thinning))
+}
+
+/// Encodes a float64 array in C order as a `.npy` file of format version
+/// 1.0, with the header padded so that the data is 64 byte aligned.
+fn npy_bytes(shape: &[usize], data: &[f64]) -> Vec<u8> {
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let shape = match dims.len() {
+        1 => format!("({},)", dims[0]),
+        _ => format!("({})", dims.join(", ")),
+    };
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    // magic, version and header length take 10 bytes, and the header ends
+    // with a newline
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(10 + header.len() + 8 * data.len());
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for x in data.iter() {
+        bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    bytes
+}
+
+/// Writes draws as a single `.npy` array of shape `(chains, draws,
+/// parameters)`, the layout ArviZ and most Python plotting code expect,
+/// e.g. for `numpy.load("draws.npy")`.  The parameter names are not part of
+/// the file; use [`write_npz`](fn.write_npz.html) to keep them.  Chains are
+/// trimmed to the length of the shortest one, tempered chains included.
+///
+/// # Arguments
+/// * `draws` - Draws to write, e.g. after adding derived parameters
+/// * `writer` - Destination of the file contents
+/// * `options` - Thinning and the parameters to write
+pub fn write_npy<W: Write>(
+    draws: &Draws,
+    mut writer: W,
+    options: &NpyOptions,
+) -> Result<(), Error> {
+    let (parameters, kept, _) = selection(draws, options)?;
+    let columns: Vec<_> = parameters.iter().map(|&p| draws.parameter(p)).collect();
+    let mut data = Vec::with_capacity(draws.num_chains() * kept.len() * parameters.len());
+    for chain in 0..draws.num_chains() {
+        for &i in kept.iter() {
+            data.extend(columns.iter().map(|c| c[chain][i]));
+        }
+    }
+    let shape = [draws.num_chains(), kept.len(), parameters.len()];
+    writer.write_all(&npy_bytes(&shape, &data))?;
+    Ok(())
+}
+
+/// Writes draws as an uncompressed `.npz` archive with one array of shape
+/// `(chains, draws)` per parameter, named after the parameter, so that
+/// `numpy.load("draws.npz")["theta[1]"]` returns its chains.  Chains are
+/// trimmed to the length of the shortest one, tempered chains included.
+/// With `auto_thin` the chosen interval is recorded in a `thinning.json`
+/// entry, e.g. `json.loads(numpy.load("draws.npz")["thinning.json"])`.
+///
+/// # Arguments
+/// * `draws` - Draws to write, e.g. after adding derived parameters
+/// * `writer` - Destination of the archive contents
+/// * `options` - Thinning and the parameters to write
+pub fn write_npz<W: Write>(draws: &Draws, writer: W, options: &NpyOptions) -> Result<(), Error> {
+    let (parameters, kept, thinning) = selection(draws, options)?;
+    let mut archive = ZipWriter::new(writer);
+    for &p in parameters.iter() {
+        let chains = draws.parameter(p);
+        let data: Vec<f64> = chains
+            .iter()
+            .flat_map(|c| kept.iter().map(move |&i| c[i]))
+            .collect();
+        let bytes = npy_bytes(&[draws.num_chains(), kept.len()], &data);
+        archive
+            .add(&format!("{}.npy", draws.names()[p]), &bytes)
+            .with_context(|| format!("Failed to write {}", draws.names()[p]))?;
+    }
+    if let Some(ref rule) = options.auto_thin {
+        archive.add("thinning.json", thinning.to_json(rule).as_bytes())?;
+    }
+    archive.finish()
+}
+
+/// Writes draws to a `.npy` file on disk, see
+/// [`write_npy`](fn.write_npy.html).
+#[cfg(feature = "fs")]
+pub fn write_npy_file<P: AsRef<Path>>(
+    draws: &Draws,
+    path: P,
+    options: &NpyOptions,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let f = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(f);
+    write_npy(draws, &mut writer, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes draws to a `.npz` file on disk, see
+/// [`write_npz`](fn.write_npz.html).
+#[cfg(feature = "fs")]
+pub fn write_npz_file<P: AsRef<Path>>(
+    draws: &Draws,
+    path: P,
+    options: &NpyOptions,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let f = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(f);
+    write_npz(draws, &mut writer, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// CRC-32 of the bytes as used by zip archives (IEEE polynomial).
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Minimal writer of zip archives with stored (uncompressed) entries, which
+/// is all `numpy.load` needs to read an `.npz` file.
+struct ZipWriter<W> {
+    writer: W,
+    offset: u64,
+    // name, CRC, size and local header offset of every entry
+    entries: Vec<(String, u32, u32, u32)>,
+}
+
+/// Last modification time and date of every entry, 1980-01-01 00:00 in
+/// MS-DOS format, so that the archive only depends on its contents.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+impl<W: Write> ZipWriter<W> {
+    fn new(writer: W) -> ZipWriter<W> {
+        ZipWriter {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Offset that has to fit the 32 bit fields of a zip archive without
+    /// the zip64 extensions.
+    fn offset32(&self) -> Result<u32, Error> {
+        u32::try_from(self.offset).map_err(|_| anyhow!("Archive is larger than 4 GiB"))
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let size = u32::try_from(data.len()).map_err(|_| anyhow!("Entry is larger than 4 GiB"))?;
+        let name_len = u16::try_from(name.len()).map_err(|_| anyhow!("Entry name is too long"))?;
+        let crc = crc32(data);
+        let offset = self.offset32()?;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        header.extend_from_slice(&0u16.to_le_bytes()); // flags
+        header.extend_from_slice(&0u16.to_le_bytes()); // stored
+        header.extend_from_slice(&DOS_TIME.to_le_bytes());
+        header.extend_from_slice(&DOS_DATE.to_le_bytes());
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes()); // compressed
+        header.extend_from_slice(&size.to_le_bytes()); // uncompressed
+        header.extend_from_slice(&name_len.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // extra field
+        header.extend_from_slice(name.as_bytes());
+        self.write(&header)?;
+        self.write(data)?;
+        self.entries.push((name.to_string(), crc, size, offset));
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        let start = self.offset32()?;
+        let entries = std::mem::take(&mut self.entries);
+        for (name, crc, size, offset) in entries.iter() {
+            let mut header = Vec::with_capacity(46 + name.len());
+            header.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            header.extend_from_slice(&20u16.to_le_bytes()); // version made by
+            header.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            header.extend_from_slice(&0u16.to_le_bytes()); // flags
+            header.extend_from_slice(&0u16.to_le_bytes()); // stored
+            header.extend_from_slice(&DOS_TIME.to_le_bytes());
+            header.extend_from_slice(&DOS_DATE.to_le_bytes());
+            header.extend_from_slice(&crc.to_le_bytes());
+            header.extend_from_slice(&size.to_le_bytes());
+            header.extend_from_slice(&size.to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            // extra field, comment, disk, internal and external attributes
+            header.extend_from_slice(&[0; 12]);
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(name.as_bytes());
+            self.write(&header)?;
+        }
+        let size = self.offset32()? - start;
+        let count = u16::try_from(entries.len()).map_err(|_| anyhow!("Too many entries"))?;
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]); // disk numbers
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&start.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // comment
+        self.write(&end)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn draws() -> Draws {
+        let names = vec!["a".to_string(), "theta[1]".to_string()];
+        let chain1 = vec![vec![1.0, 2.0, 3.0, 4.0], vec![5.0, 6.0, 7.0, 8.0]];
+        let chain2 = vec![vec![-1.0, -2.0, -3.0], vec![-5.0, -6.0, -7.0]];
+        Draws::from_chains(names, vec![chain1, chain2]).unwrap()
+    }
+
+    /// Splits a `.npy` file into its header and data.
+    fn parse_npy(bytes: &[u8]) -> (String, Vec<f64>) {
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0);
+        let header = String::from_utf8(bytes[10..10 + len].to_vec()).unwrap();
+        let data = bytes[10 + len..]
+            .chunks(8)
+            .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        (header, data)
+    }
+
+    #[test]
+    fn test_write_npy() {
+        let mut bytes = Vec::new();
+        write_npy(&draws(), &mut bytes, &NpyOptions::default()).unwrap();
+        let (header, data) = parse_npy(&bytes);
+        assert!(
+            header.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (2, 3, 2), }")
+        );
+        assert!(header.ends_with(" \n"));
+        assert_eq!(
+            data,
+            vec![1.0, 5.0, 2.0, 6.0, 3.0, 7.0, -1.0, -5.0, -2.0, -6.0, -3.0, -7.0]
+        );
+
+        let options = NpyOptions {
+            thin: 2,
+            parameters: Some(vec!["theta[1]".to_string()]),
+            ..NpyOptions::default()
+        };
+        let mut bytes = Vec::new();
+        write_npy(&draws(), &mut bytes, &options).unwrap();
+        let (header, data) = parse_npy(&bytes);
+        assert!(header.contains("'shape': (2, 2, 1)"));
+        assert_eq!(data, vec![5.0, 7.0, -5.0, -7.0]);
+
+        let missing = NpyOptions {
+            parameters: Some(vec!["b".to_string()]),
+            ..NpyOptions::default()
+        };
+        assert!(write_npy(&draws(), &mut Vec::new(), &missing).is_err());
+        let unthinned = NpyOptions {
+            thin: 0,
+            ..NpyOptions::default()
+        };
+        assert!(write_npy(&draws(), &mut Vec::new(), &unthinned).is_err());
+    }
+
+    #[test]
+    fn test_write_npz() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let mut bytes = Vec::new();
+        write_npz(&draws(), &mut bytes, &NpyOptions::default()).unwrap();
+
+        // walk the local headers and check them against the end record
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as usize;
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let mut pos = 0;
+        let mut names = Vec::new();
+        while u32_at(pos) == 0x0403_4b50 {
+            let size = u32_at(pos + 18) as usize;
+            let name_len = u16_at(pos + 26);
+            let name = String::from_utf8(bytes[pos + 30..pos + 30 + name_len].to_vec()).unwrap();
+            let data = &bytes[pos + 30 + name_len..pos + 30 + name_len + size];
+            assert_eq!(u32_at(pos + 14), crc32(data));
+            let (header, values) = parse_npy(data);
+            assert!(header.contains("'shape': (2, 3)"));
+            names.push((name, values));
+            pos += 30 + name_len + size;
+        }
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[1].0, "theta[1].npy");
+        assert_eq!(names[1].1, vec![5.0, 6.0, 7.0, -5.0, -6.0, -7.0]);
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16_at(end + 10), 2);
+        assert_eq!(u32_at(end + 16) as usize, pos);
+        assert_eq!(u32_at(pos), 0x0201_4b50);
+    }
+
+    #[test]
+    fn test_choose_thinning() {
+        // AR(1) chains with rho = 0.9, i.e. tau = 19, and a white noise one
+        let chains = (0..4)
+            .map(|c| {
+                let mut x = 0.0;
+                let ar: Vec<f64> = crate::utils::normal_draws(1000, c + 1)
+                    .into_iter()
+                    .map(|e| {
+                        x = 0.9 * x + e;
+                        x
+                    })
+                    .collect();
+                vec![
+                    ar,
+                    crate::utils::normal_draws(1000, c + 11),
+                    vec![1.0; 1000],
+                ]
+            })
+            .collect();
+        let names = vec!["ar".to_string(), "noise".to_string(), "one".to_string()];
+        let draws = Draws::from_chains(names, chains).unwrap();
+
+        let fixed = choose_thinning(&draws, &NpyOptions::default()).unwrap();
+        assert_eq!((fixed.interval, fixed.num_kept), (1, 1000));
+        assert!(fixed.ess_retained.is_none());
+
+        // the white noise parameter loses ESS with any thinning
+        let ess = |fraction| NpyOptions {
+            auto_thin: Some(AutoThin::EssRetention(fraction)),
+            ..NpyOptions::default()
+        };
+        assert_eq!(choose_thinning(&draws, &ess(0.95)).unwrap().interval, 1);
+        let correlated = NpyOptions {
+            parameters: Some(vec!["ar".to_string(), "one".to_string()]),
+            ..ess(0.95)
+        };
+        let thinning = choose_thinning(&draws, &correlated).unwrap();
+        assert!(thinning.interval >= 3 && thinning.interval <= 8);
+        assert_eq!(thinning.num_kept, 1000_usize.div_ceil(thinning.interval));
+        assert!(thinning.ess_retained.unwrap() > 0.85);
+        assert!(choose_thinning(&draws, &ess(0.0)).is_err());
+
+        let budget = |bytes| NpyOptions {
+            auto_thin: Some(AutoThin::MaxBytes(bytes)),
+            ..NpyOptions::default()
+        };
+        // 3 parameters in 4 chains take 96 bytes per draw
+        let thinning = choose_thinning(&draws, &budget(96 * 250)).unwrap();
+        assert_eq!((thinning.interval, thinning.num_kept), (4, 250));
+        assert_eq!(
+            choose_thinning(&draws, &budget(96 * 251)).unwrap().interval,
+            4
+        );
+        assert_eq!(
+            choose_thinning(&draws, &budget(usize::MAX))
+                .unwrap()
+                .interval,
+            1
+        );
+        assert!(choose_thinning(&draws, &budget(96 * 3)).is_err());
+
+        // ten draws of one parameter fit in four values with interval 3
+        let names = vec!["a".to_string()];
+        let short = Draws::from_chains(names, vec![vec![(0..10).map(f64::from).collect()]]);
+        let thinning = choose_thinning(&short.unwrap(), &budget(32)).unwrap();
+        assert_eq!((thinning.interval, thinning.num_kept), (3, 4));
+        let names = vec!["a".to_string()];
+        let six = Draws::from_chains(names, vec![vec![vec![0.0; 6]]]).unwrap();
+        assert!(choose_thinning(&six, &budget(32)).is_err());
+
+        let mut bytes = Vec::new();
+        write_npy(&draws, &mut bytes, &budget(96 * 250)).unwrap();
+        assert!(parse_npy(&bytes).0.contains("'shape': (4, 250, 3)"));
+        let mut bytes = Vec::new();
+        write_npz(&draws, &mut bytes, &budget(96 * 250)).unwrap();
+        let json = "{\"rule\": {\"max_bytes\": 24000}, \"interval\": 4, \"num_kept\": 250";
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("thinning.json"));
+        assert!(text.contains(json));
+    }
+}
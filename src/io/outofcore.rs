@@ -0,0 +1,373 @@
+//! Summaries of draws files too large to load into memory.  The input is
+//! read twice in chunks of a fixed number of draws: the first pass collects
+//! running means, variances and quantile sketches, and the second pass the
+//! autocovariances up to a capped lag around the means of the first pass.
+//! Memory use is bounded by the chunk size and the number of parameters,
+//! chains and lags rather than the number of draws.
+use crate::ess::ess_from_autocovariances;
+use crate::io::stream::{Format, StreamOptions, StreamParser};
+use crate::online::{rhat_from_stats, RunningStats};
+use crate::stats::P2Quantile;
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+use std::io::{BufRead, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Options for summarizing draws out of core.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfCoreOptions {
+    /// Line format of the input
+    pub format: Format,
+    /// Name of the column (or JSON key) identifying the chain of each draw.
+    /// When it is absent every draw belongs to a single chain.
+    pub chain_column: String,
+    /// Number of draws parsed and processed at a time
+    pub chunk_size: usize,
+    /// Largest autocovariance lag used for the effective sample size.  The
+    /// second pass costs `max_lag` multiply-adds per value, and the sum of
+    /// autocorrelations is truncated at this lag, see
+    /// [`EssOptions::max_lag`](../../ess/struct.EssOptions.html#structfield.max_lag).
+    /// Must be at least 3.
+    pub max_lag: usize,
+    /// Probabilities of the quantiles to estimate with streaming sketches
+    pub probs: Vec<f64>,
+}
+
+impl Default for OutOfCoreOptions {
+    fn default() -> OutOfCoreOptions {
+        OutOfCoreOptions {
+            format: Format::Csv,
+            chain_column: "chain".to_string(),
+            chunk_size: 65536,
+            max_lag: 200,
+            probs: vec![0.05, 0.5, 0.95],
+        }
+    }
+}
+
+/// Summary of one parameter computed out of core.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfCoreSummary {
+    /// Parameter name
+    pub name: String,
+    /// Mean over all chains
+    pub mean: f64,
+    /// Standard deviation over all chains
+    pub sd: f64,
+    /// Approximate quantiles from P-square sketches, one for each of the
+    /// requested probabilities
+    pub quantiles: Vec<f64>,
+    /// Effective sample size from autocovariances up to the capped lag,
+    /// available when every chain has the same number of draws, at least
+    /// four of them, and the parameter is not constant
+    pub ess: Option<f64>,
+    /// Monte Carlo standard error of the mean, `sd / sqrt(ess)`
+    pub mcse: Option<f64>,
+    /// Potential scale reduction factor, available with at least two chains
+    pub rhat: Option<f64>,
+}
+
+/// Autocovariance sums of one parameter in one chain, keeping only the last
+/// `max_lag` centered values.
+#[derive(Debug, Clone)]
+struct LaggedSums {
+    recent: Array1,
+    sums: Array1,
+    count: usize,
+}
+
+impl LaggedSums {
+    fn new(max_lag: usize) -> LaggedSums {
+        LaggedSums {
+            recent: vec![0.0; max_lag + 1],
+            sums: vec![0.0; max_lag + 1],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, centered: f64) {
+        let len = self.recent.len();
+        let slot = self.count % len;
+        self.recent[slot] = centered;
+        for (lag, sum) in self.sums.iter_mut().enumerate().take(self.count + 1) {
+            *sum += centered * self.recent[(slot + len - lag) % len];
+        }
+        self.count += 1;
+    }
+
+    /// Biased autocovariances for lags `0..=max_lag`.
+    fn autocovariance(&self, max_lag: usize) -> Array1 {
+        self.sums[..=max_lag]
+            .iter()
+            .map(|s| s / self.count as f64)
+            .collect()
+    }
+}
+
+/// Reads the input in chunks of at most `chunk_size` draws, calling `f`
+/// with every chunk, and returns the parameter names.
+fn for_each_chunk<R, F>(
+    reader: R,
+    options: &OutOfCoreOptions,
+    mut f: F,
+) -> Result<Vec<String>, Error>
+where
+    R: BufRead,
+    F: FnMut(&[(usize, Array1)]) -> Result<(), Error>,
+{
+    let mut parser = StreamParser::new(StreamOptions {
+        format: options.format,
+        chain_column: options.chain_column.clone(),
+        snapshot_every: 0,
+    });
+    let mut chunk = Vec::with_capacity(options.chunk_size);
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        let parsed = parser
+            .parse_line(&line)
+            .with_context(|| format!("Failed to parse line {}", line_idx + 1))?;
+        if let Some(draw) = parsed {
+            chunk.push(draw);
+            if chunk.len() == options.chunk_size {
+                f(&chunk)?;
+                chunk.clear();
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        f(&chunk)?;
+    }
+    Ok(parser.names().to_vec())
+}
+
+/// Summarizes every parameter of draws that do not fit in memory, reading
+/// the input twice.  The input holds newline delimited draws as read by
+/// [`stream::from_reader`](../stream/fn.from_reader.html), e.g. a CSV file
+/// with a header, one draw per line and an optional chain column; Stan CSV
+/// files qualify when warmup draws were not saved.  Means and standard
+/// deviations are exact, quantiles approximate, and the effective sample
+/// size is the Geyer estimator of
+/// [`compute_effective_sample_size_with_options`](../../ess/fn.compute_effective_sample_size_with_options.html)
+/// with the lag capped at `max_lag`.  Draws must be finite.
+///
+/// # Arguments
+/// * `reader` - Buffered reader over the draws, which is rewound for the
+///   second pass
+/// * `options` - Format of the input, chunk size, lag cap and quantiles
+pub fn summarize_reader<R: BufRead + Seek>(
+    mut reader: R,
+    options: &OutOfCoreOptions,
+) -> Result<Vec<OutOfCoreSummary>, Error> {
+    if options.chunk_size == 0 {
+        return Err(anyhow!("Chunk size must be positive"));
+    }
+    if options.max_lag < 3 {
+        return Err(anyhow!("Maximum lag must be at least 3 to compute ESS"));
+    }
+    let start = reader.stream_position()?;
+
+    // first pass: running statistics of every chain and quantile sketches
+    let mut stats: Vec<Vec<RunningStats>> = Vec::new();
+    let mut sketches: Vec<Vec<P2Quantile>> = Vec::new();
+    let names = for_each_chunk(&mut reader, options, |chunk| {
+        for (chain, draw) in chunk.iter() {
+            if sketches.is_empty() {
+                let sketch = options
+                    .probs
+                    .iter()
+                    .map(|&p| P2Quantile::new(p))
+                    .collect::<Result<Vec<P2Quantile>, Error>>()?;
+                sketches = vec![sketch; draw.len()];
+            }
+            while stats.len() <= *chain {
+                stats.push(vec![RunningStats::new(); draw.len()]);
+            }
+            for (p, &x) in draw.iter().enumerate() {
+                if !x.is_finite() {
+                    return Err(anyhow!("Non-finite value {} in column {}", x, p + 1));
+                }
+                stats[*chain][p].push(x);
+                sketches[p].iter_mut().for_each(|q| q.push(x));
+            }
+        }
+        Ok(())
+    })?;
+    if stats.is_empty() || names.is_empty() {
+        return Err(anyhow!("No draws found"));
+    }
+
+    // second pass: autocovariances around the chain means
+    let lengths: Vec<usize> = stats.iter().map(|c| c[0].count()).collect();
+    let num_draws = lengths[0];
+    let equal_lengths = lengths.iter().all(|&n| n == num_draws);
+    let max_lag = options.max_lag.min(num_draws.saturating_sub(1));
+    let mut lagged: Vec<Vec<LaggedSums>> = Vec::new();
+    if equal_lengths && num_draws >= 4 {
+        lagged = vec![vec![LaggedSums::new(max_lag); names.len()]; stats.len()];
+        reader.seek(SeekFrom::Start(start))?;
+        for_each_chunk(&mut reader, options, |chunk| {
+            for (chain, draw) in chunk.iter() {
+                let sums = lagged
+                    .get_mut(*chain)
+                    .ok_or_else(|| anyhow!("Input changed between passes"))?;
+                for (p, &x) in draw.iter().enumerate() {
+                    sums[p].push(x - stats[*chain][p].mean());
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(names
+        .iter()
+        .enumerate()
+        .map(|(p, name)| {
+            let chains: Vec<RunningStats> = stats.iter().map(|c| c[p]).collect();
+            let total: usize = lengths.iter().sum();
+            let mean = chains
+                .iter()
+                .map(|c| c.mean() * c.count() as f64)
+                .sum::<f64>()
+                / total as f64;
+            let ss: f64 = chains
+                .iter()
+                .map(|c| {
+                    let n = c.count() as f64;
+                    c.sample_variance() * (n - 1.0) + n * (c.mean() - mean).powi(2)
+                })
+                .sum();
+            let sd = if total > 1 {
+                (ss / (total - 1) as f64).sqrt()
+            } else {
+                0.0
+            };
+            let ess = if lagged.is_empty() || sd == 0.0 {
+                None
+            } else {
+                let acov: Array2 = lagged
+                    .iter()
+                    .map(|c| c[p].autocovariance(max_lag))
+                    .collect();
+                let means: Array1 = chains.iter().map(|c| c.mean()).collect();
+                ess_from_autocovariances(&acov, &means, num_draws, max_lag).ok()
+            };
+            OutOfCoreSummary {
+                name: name.clone(),
+                mean,
+                sd,
+                quantiles: sketches[p].iter().map(|q| q.estimate().unwrap()).collect(),
+                ess,
+                mcse: ess.map(|e| sd / e.sqrt()),
+                rhat: rhat_from_stats(&chains),
+            }
+        })
+        .collect())
+}
+
+/// Summarizes every parameter of a draws file on disk out of core, see
+/// [`summarize_reader`](fn.summarize_reader.html).
+#[cfg(feature = "fs")]
+pub fn summarize_file<P: AsRef<Path>>(
+    path: P,
+    options: &OutOfCoreOptions,
+) -> Result<Vec<OutOfCoreSummary>, Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    summarize_reader(BufReader::new(f), options)
+        .with_context(|| format!("Failed to summarize {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ess::{compute_effective_sample_size_with_options, EssOptions};
+    use crate::utils::normal_draws;
+    use std::io::Cursor;
+
+    /// Two AR(1) chains with autocorrelation 0.8 as CSV with a chain column.
+    fn ar1_csv() -> (String, Array2) {
+        let chains: Array2 = (0..2)
+            .map(|c| {
+                let mut x = 0.0;
+                normal_draws(3000, 20 + c)
+                    .iter()
+                    .map(|e| {
+                        x = 0.8 * x + e;
+                        x
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut csv = "# comment\nchain,theta,scaled\n".to_string();
+        for i in 0..3000 {
+            for (c, chain) in chains.iter().enumerate() {
+                csv.push_str(&format!("{},{},{}\n", c + 1, chain[i], 2.0 * chain[i]));
+            }
+        }
+        (csv, chains)
+    }
+
+    #[test]
+    fn test_summarize_reader() {
+        let (csv, chains) = ar1_csv();
+        let options = OutOfCoreOptions {
+            chunk_size: 97,
+            max_lag: 50,
+            ..OutOfCoreOptions::default()
+        };
+        let summaries = summarize_reader(Cursor::new(csv.as_bytes()), &options).unwrap();
+        assert_eq!(summaries.len(), 2);
+        let theta = &summaries[0];
+        assert_eq!(theta.name, "theta");
+
+        let mut pooled = RunningStats::new();
+        chains.iter().flatten().for_each(|&x| pooled.push(x));
+        assert_abs_diff_eq!(theta.mean, pooled.mean(), epsilon = 1e-10);
+        assert_abs_diff_eq!(theta.sd, pooled.sample_variance().sqrt(), epsilon = 1e-10);
+        let expected =
+            compute_effective_sample_size_with_options(&chains, &EssOptions { max_lag: Some(50) })
+                .unwrap();
+        assert_abs_diff_eq!(theta.ess.unwrap(), expected, epsilon = 1e-6);
+        assert!(theta.rhat.unwrap() < 1.05);
+        assert_abs_diff_eq!(theta.quantiles[1], 0.0, epsilon = 0.3);
+        assert_abs_diff_eq!(summaries[1].sd, 2.0 * theta.sd, epsilon = 1e-10);
+        assert_abs_diff_eq!(
+            summaries[1].ess.unwrap(),
+            theta.ess.unwrap(),
+            epsilon = 1e-6
+        );
+
+        // the chunk size does not change the result
+        let whole = summarize_reader(
+            Cursor::new(csv.as_bytes()),
+            &OutOfCoreOptions {
+                max_lag: 50,
+                ..OutOfCoreOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(whole, summaries);
+    }
+
+    #[test]
+    fn test_summarize_reader_unequal_chains_and_errors() {
+        let csv = "chain,a\n1,1.0\n1,2.0\n1,4.0\n1,3.0\n1,5.0\n2,1.5\n2,2.5\n2,0.5\n2,3.0\n";
+        let summaries =
+            summarize_reader(Cursor::new(csv.as_bytes()), &OutOfCoreOptions::default()).unwrap();
+        assert_eq!(summaries[0].ess, None);
+        assert!(summaries[0].rhat.is_some());
+        assert_abs_diff_eq!(summaries[0].mean, 22.5 / 9.0, epsilon = 1e-12);
+
+        let options = OutOfCoreOptions::default();
+        assert!(summarize_reader(Cursor::new("a\n".as_bytes()), &options).is_err());
+        assert!(summarize_reader(Cursor::new("chain\n1\n".as_bytes()), &options).is_err());
+        assert!(summarize_reader(Cursor::new("a\nx\n".as_bytes()), &options).is_err());
+        assert!(summarize_reader(Cursor::new("a\n1\nnan\n".as_bytes()), &options).is_err());
+        let no_lags = OutOfCoreOptions {
+            max_lag: 2,
+            ..OutOfCoreOptions::default()
+        };
+        assert!(summarize_reader(Cursor::new(csv.as_bytes()), &no_lags).is_err());
+    }
+}
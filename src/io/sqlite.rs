@@ -0,0 +1,267 @@
+use crate::diagnostics::{verdict_with_thresholds, Thresholds};
+use crate::draws::Draws;
+use crate::summary::summarize;
+use anyhow::{Error, Result};
+use rusqlite::{params, Connection};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "fs")]
+use {anyhow::Context, std::path::Path};
+
+/// Tables created by [`export`](fn.export.html) if they do not exist yet.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    label TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    num_parameters INTEGER NOT NULL,
+    num_chains INTEGER NOT NULL,
+    num_draws INTEGER NOT NULL,
+    verdict TEXT,
+    metadata TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS summaries (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    parameter TEXT NOT NULL,
+    mean REAL,
+    mcse REAL,
+    sd REAL,
+    q5 REAL,
+    q50 REAL,
+    q95 REAL,
+    ess REAL,
+    rhat REAL
+);
+CREATE TABLE IF NOT EXISTS warnings (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    kind TEXT NOT NULL,
+    parameter TEXT,
+    chain INTEGER,
+    value REAL,
+    message TEXT NOT NULL
+);
+";
+
+/// Description of a run stored alongside its diagnostics.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunInfo {
+    /// Name identifying the model or experiment, e.g. to group runs in
+    /// queries
+    pub label: String,
+    /// Free form key value pairs, stored as a JSON object that SQLite's
+    /// `json_extract` can query, e.g. the sampler version or the seed
+    pub metadata: Vec<(String, String)>,
+    /// Limits beyond which the draws get a warning
+    pub thresholds: Thresholds,
+}
+
+/// Quotes a string as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                // writing to a String can't fail
+                write!(quoted, "\\u{:04x}", c as u32).unwrap();
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Appends the posterior summary of every parameter, the warnings of
+/// [`verdict_with_thresholds`](../../diagnostics/fn.verdict_with_thresholds.html)
+/// and a description of the run to a SQLite database, creating the `runs`,
+/// `summaries` and `warnings` tables if needed.  Exporting every run of a
+/// model to the same database keeps its diagnostics queryable over time
+/// with plain SQL, e.g.
+///
+/// ```sql
+/// SELECT runs.created_at, summaries.rhat FROM summaries
+/// JOIN runs ON runs.id = summaries.run_id
+/// WHERE runs.label = 'eight schools' AND summaries.parameter = 'tau';
+/// ```
+///
+/// Parameters that can't be summarized get a warning of kind
+/// `not_summarized` instead of a row in `summaries`, and a verdict that
+/// fails leaves the `verdict` of the run `NULL` with a warning of kind
+/// `not_checked`.  Undefined values such as the R hat of a single draw are
+/// stored as `NULL`.  The run is written in a single transaction.  Returns
+/// the id of the new row in `runs`.
+///
+/// # Arguments
+/// * `connection` - Open database to write to
+/// * `draws` - Draws to summarize; tempered chains are left out
+/// * `info` - Label, metadata and warning thresholds of the run
+pub fn export(connection: &Connection, draws: &Draws, info: &RunInfo) -> Result<i64, Error> {
+    connection.execute_batch(SCHEMA)?;
+    let (verdict, mut warnings) = match verdict_with_thresholds(draws, &info.thresholds) {
+        Ok(verdict) => {
+            let warnings: Vec<_> = verdict
+                .evidence()
+                .iter()
+                .map(|e| {
+                    let (kind, parameter, chain, value) = e.columns();
+                    (
+                        kind,
+                        parameter.map(String::from),
+                        chain,
+                        value,
+                        e.to_string(),
+                    )
+                })
+                .collect();
+            (Some(verdict.name()), warnings)
+        }
+        Err(err) => (
+            None,
+            vec![("not_checked", None, None, None, format!("{:#}", err))],
+        ),
+    };
+    let mut summaries = Vec::new();
+    for (idx, name) in draws.names().iter().enumerate() {
+        match summarize(&draws.target_parameter(idx)) {
+            Ok(summary) => summaries.push((name, summary)),
+            Err(err) => warnings.push((
+                "not_summarized",
+                Some(name.clone()),
+                None,
+                None,
+                format!("{}: could not be summarized: {:#}", name, err),
+            )),
+        }
+    }
+    let metadata: Vec<String> = info
+        .metadata
+        .iter()
+        .map(|(k, v)| format!("{}:{}", json_string(k), json_string(v)))
+        .collect();
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+
+    let tx = connection.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO runs (label, created_at, num_parameters, num_chains, num_draws, verdict, metadata) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            info.label,
+            created_at,
+            draws.num_parameters() as i64,
+            draws.target_chains().len() as i64,
+            draws.num_draws() as i64,
+            verdict,
+            format!("{{{}}}", metadata.join(",")),
+        ],
+    )?;
+    let run_id = tx.last_insert_rowid();
+    // SQLite stores NaN as NULL
+    for (name, s) in summaries.iter() {
+        tx.execute(
+            "INSERT INTO summaries (run_id, parameter, mean, mcse, sd, q5, q50, q95, ess, rhat) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![run_id, name, s.mean, s.mcse, s.sd, s.q5, s.q50, s.q95, s.ess, s.rhat],
+        )?;
+    }
+    for (kind, parameter, chain, value, message) in warnings.iter() {
+        tx.execute(
+            "INSERT INTO warnings (run_id, kind, parameter, chain, value, message) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                run_id,
+                kind,
+                parameter,
+                chain.map(|c| c as i64),
+                value,
+                message
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(run_id)
+}
+
+/// Appends the diagnostics of a run to a SQLite database file, creating it
+/// if needed, see [`export`](fn.export.html).
+///
+/// # Arguments
+/// * `path` - Database file to open or create
+/// * `draws` - Draws to summarize
+/// * `info` - Label, metadata and warning thresholds of the run
+#[cfg(feature = "fs")]
+pub fn export_file<P: AsRef<Path>>(path: P, draws: &Draws, info: &RunInfo) -> Result<i64, Error> {
+    let path = path.as_ref();
+    let connection =
+        Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    export(&connection, draws, info)
+        .with_context(|| format!("Failed to export to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    fn draws() -> Draws {
+        let chains = (0..2)
+            .map(|c| {
+                let shifted: Vec<f64> = normal_draws(200, c)
+                    .iter()
+                    .map(|x| x + 3.0 * c as f64)
+                    .collect();
+                vec![normal_draws(200, 10 + c), shifted, vec![1.0; 200]]
+            })
+            .collect();
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        Draws::from_chains(names, chains).unwrap()
+    }
+
+    #[test]
+    fn test_export() {
+        let connection = Connection::open_in_memory().unwrap();
+        let info = RunInfo {
+            label: "test".to_string(),
+            metadata: vec![("seed".to_string(), "4\"2".to_string())],
+            ..RunInfo::default()
+        };
+        let first = export(&connection, &draws(), &info).unwrap();
+        let second = export(&connection, &draws(), &info).unwrap();
+        assert_ne!(first, second);
+
+        let count = |sql: &str| -> i64 { connection.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM runs"), 2);
+        // the constant parameter can't be summarized
+        assert_eq!(count("SELECT COUNT(*) FROM summaries"), 4);
+        let (verdict, num_draws, seed): (String, i64, String) = connection
+            .query_row(
+                "SELECT verdict, num_draws, json_extract(metadata, '$.seed') FROM runs WHERE id = ?1",
+                [first],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(verdict, "not converged");
+        assert_eq!(num_draws, 200);
+        assert_eq!(seed, "4\"2");
+
+        let rhat: f64 = connection
+            .query_row(
+                "SELECT value FROM warnings WHERE run_id = ?1 AND kind = 'high_rhat' AND parameter = 'b'",
+                [first],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(rhat > 1.5);
+        assert_eq!(
+            count(
+                "SELECT COUNT(*) FROM warnings WHERE kind = 'not_summarized' AND parameter = 'c'"
+            ),
+            2
+        );
+    }
+}
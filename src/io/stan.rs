@@ -0,0 +1,362 @@
+use crate::draws::Draws;
+#[cfg(feature = "fs")]
+use crate::utils::natural_key;
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use std::io::BufRead;
+#[cfg(feature = "fs")]
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+/// Draws of a single chain split into the warmup draws, which are only
+/// present when the sampler was run with `save_warmup`, and the sampling
+/// draws after adaptation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmupSplit {
+    /// Warmup draws, without any chains when warmup was not saved
+    pub warmup: Draws,
+    /// Draws after warmup
+    pub sampling: Draws,
+}
+
+/// Reads a single chain from Stan CSV output, keeping only the draws after
+/// warmup.  Comment lines starting with `#` (configuration, adaptation info
+/// and timing) and blank lines are skipped, the first remaining line is the
+/// header with the column names and every following line is one draw.  See
+/// [`from_reader_split`](fn.from_reader_split.html) for how warmup draws are
+/// detected.
+///
+/// # Arguments
+/// * `reader` - Any buffered reader over the contents of one Stan CSV file
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn from_reader<R: BufRead>(reader: R) -> Result<Draws, Error> {
+    Ok(from_reader_split(reader)?.sampling)
+}
+
+/// Reads a single chain from Stan CSV output, keeping the warmup draws
+/// written with `save_warmup` separate from the sampling draws.  Draws
+/// before the `# Adaptation terminated` comment are warmup.  Without that
+/// comment, e.g. when adaptation was switched off, the first
+/// `num_warmup / thin` draws are warmup if the configuration comments say
+/// that warmup was saved.
+///
+/// # Arguments
+/// * `reader` - Any buffered reader over the contents of one Stan CSV file
+pub fn from_reader_split<R: BufRead>(reader: R) -> Result<WarmupSplit, Error> {
+    let mut names: Option<Vec<String>> = None;
+    let mut columns: Array2 = Vec::new();
+    let mut config = WarmupConfig::default();
+    // number of draws read before the end of adaptation was marked
+    let mut adaptation_end: Option<usize> = None;
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            if names.is_some() && comment.trim() == "Adaptation terminated" {
+                adaptation_end.get_or_insert(columns.first().map_or(0, |c| c.len()));
+            } else if names.is_none() {
+                config.parse_comment(comment);
+            }
+            continue;
+        }
+        match names {
+            None => {
+                let header: Vec<String> = line.split(',').map(|s| s.trim().to_string()).collect();
+                columns = vec![Vec::new(); header.len()];
+                names = Some(header);
+            }
+            Some(ref header) => {
+                let mut num_values = 0;
+                for (col_idx, value) in line.split(',').enumerate() {
+                    let value = value.trim().parse::<f64>().with_context(|| {
+                        format!(
+                            "Invalid value {:?} at line {}, column {}",
+                            value,
+                            line_idx + 1,
+                            col_idx + 1
+                        )
+                    })?;
+                    if col_idx < columns.len() {
+                        columns[col_idx].push(value);
+                    }
+                    num_values += 1;
+                }
+                if num_values != header.len() {
+                    return Err(anyhow!(
+                        "Expected {} values at line {} but found {}",
+                        header.len(),
+                        line_idx + 1,
+                        num_values
+                    ));
+                }
+            }
+        }
+    }
+    let names = names.ok_or_else(|| anyhow!("No header found in Stan CSV"))?;
+    let num_draws = columns.first().map_or(0, |c| c.len());
+    let num_warmup = adaptation_end
+        .unwrap_or_else(|| config.num_saved_warmup())
+        .min(num_draws);
+    let mut warmup = Draws::new(names.clone());
+    if num_warmup > 0 {
+        let sampling = columns
+            .iter_mut()
+            .map(|c| c.split_off(num_warmup))
+            .collect();
+        warmup.push_chain(std::mem::replace(&mut columns, sampling))?;
+    }
+    Ok(WarmupSplit {
+        warmup,
+        sampling: Draws::from_chains(names, vec![columns])?,
+    })
+}
+
+/// Warmup settings from the configuration comments at the top of a file.
+#[derive(Debug, Clone, Copy, Default)]
+struct WarmupConfig {
+    num_warmup: usize,
+    save_warmup: bool,
+    thin: Option<usize>,
+}
+
+impl WarmupConfig {
+    /// Picks up settings from comments such as `#   num_warmup = 1000`.
+    fn parse_comment(&mut self, comment: &str) {
+        let (key, value) = match comment.split_once('=') {
+            Some(kv) => kv,
+            None => return,
+        };
+        let value = value.split_whitespace().next().unwrap_or("");
+        match key.trim() {
+            "num_warmup" => self.num_warmup = value.parse().unwrap_or(0),
+            "save_warmup" => self.save_warmup = value == "1" || value == "true",
+            "thin" => self.thin = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    /// Number of warmup draws written to the file.
+    fn num_saved_warmup(&self) -> usize {
+        if !self.save_warmup {
+            return 0;
+        }
+        let thin = self.thin.unwrap_or(1).max(1);
+        self.num_warmup.div_ceil(thin)
+    }
+}
+
+/// Reads a single chain from the bytes of a Stan CSV file, e.g. one that was
+/// uploaded to a browser.
+pub fn from_bytes(bytes: &[u8]) -> Result<Draws, Error> {
+    from_reader(bytes)
+}
+
+/// Reads several chains from the bytes of Stan CSV files, one chain per
+/// file.  All files must have the same header.
+pub fn from_bytes_multiple(files: &[&[u8]]) -> Result<Draws, Error> {
+    let mut chains = Vec::new();
+    for bytes in files.iter() {
+        chains.push(from_bytes(bytes)?);
+    }
+    combine(chains)
+}
+
+/// Reads a single chain from a Stan CSV file on disk.
+#[cfg(feature = "fs")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(path = %path.as_ref().display()))
+)]
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Draws, Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    from_reader(BufReader::new(f)).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Reads a single chain from a Stan CSV file on disk, keeping the warmup
+/// draws separate, see [`from_reader_split`](fn.from_reader_split.html).
+#[cfg(feature = "fs")]
+pub fn read_file_split<P: AsRef<Path>>(path: P) -> Result<WarmupSplit, Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    from_reader_split(BufReader::new(f))
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Reads several chains from Stan CSV files on disk, one chain per file.
+/// All files must have the same header.
+#[cfg(feature = "fs")]
+pub fn read_files<P: AsRef<Path>>(paths: &[P]) -> Result<Draws, Error> {
+    let mut chains = Vec::new();
+    for path in paths.iter() {
+        chains.push(read_file(path)?);
+    }
+    combine(chains)
+}
+
+/// Reads every Stan CSV file matching a glob pattern such as
+/// `output_*.csv`, one chain per file, and returns the paths alongside the
+/// draws so that chain `i` came from `paths[i]`.  Files are ordered by name,
+/// comparing runs of digits by their value so that `output_2.csv` comes
+/// before `output_10.csv`.  All files must have the same header.
+///
+/// # Arguments
+/// * `pattern` - Glob pattern matching the output files, see the
+///   [glob](https://docs.rs/glob) crate for the syntax
+#[cfg(feature = "fs")]
+pub fn read_glob(pattern: &str) -> Result<(Vec<PathBuf>, Draws), Error> {
+    let mut paths = glob::glob(pattern)
+        .with_context(|| format!("Invalid glob pattern {:?}", pattern))?
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .with_context(|| format!("Failed to list files matching {:?}", pattern))?;
+    if paths.is_empty() {
+        return Err(anyhow!("No files match {:?}", pattern));
+    }
+    paths.sort_by_cached_key(|p| natural_key(&p.to_string_lossy()));
+    let mut chains: Vec<Draws> = Vec::new();
+    for path in paths.iter() {
+        let chain = read_file(path)?;
+        if let Some(first) = chains.first() {
+            if chain.names() != first.names() {
+                return Err(anyhow!(
+                    "Header of {} does not match {}",
+                    path.display(),
+                    paths[0].display()
+                ));
+            }
+        }
+        chains.push(chain);
+    }
+    Ok((paths, combine(chains)?))
+}
+
+/// Combines single chain containers into one container with all the chains.
+fn combine(chains: Vec<Draws>) -> Result<Draws, Error> {
+    let mut chains = chains.into_iter();
+    let mut combined = chains
+        .next()
+        .ok_or_else(|| anyhow!("Need at least one Stan CSV file"))?;
+    for (idx, chain) in chains.enumerate() {
+        if chain.names() != combined.names() {
+            return Err(anyhow!(
+                "Header of chain {} does not match the first chain",
+                idx + 2
+            ));
+        }
+        let columns = (0..chain.num_parameters())
+            .map(|p| chain.parameter(p)[0].clone())
+            .collect();
+        combined.push_chain(columns)?;
+    }
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use crate::utils::read_csv;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    const SMALL: &str = "# model = example\nlp__,theta\n# Adaptation terminated\n-1.5,0.25\n-2.0,0.5\n\n-1.0,0.75\n# Elapsed Time\n";
+
+    #[test]
+    fn test_from_bytes() {
+        let draws = from_bytes(SMALL.as_bytes()).unwrap();
+        assert_eq!(draws.names(), &["lp__".to_string(), "theta".to_string()]);
+        assert_eq!(draws.num_chains(), 1);
+        assert_eq!(draws.get("theta").unwrap()[0], vec![0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn test_warmup_detection() {
+        let saved = "#   num_warmup = 2\n#   save_warmup = 1\nlp__,theta\n-5.0,0.1\n-4.0,0.2\n# Adaptation terminated\n# Step size = 0.9\n-1.0,0.3\n";
+        let split = from_reader_split(saved.as_bytes()).unwrap();
+        assert_eq!(split.warmup.get("theta").unwrap()[0], vec![0.1, 0.2]);
+        assert_eq!(split.sampling.get("theta").unwrap()[0], vec![0.3]);
+        assert_eq!(from_bytes(saved.as_bytes()).unwrap(), split.sampling);
+
+        // without adaptation there is no marker, so fall back to the config
+        let no_adapt = "#   num_warmup = 3\n#   save_warmup = true\n#   thin = 2\nlp__\n-5.0\n-4.0\n-1.0\n-2.0\n";
+        let split = from_reader_split(no_adapt.as_bytes()).unwrap();
+        assert_eq!(split.warmup.get("lp__").unwrap()[0], vec![-5.0, -4.0]);
+        assert_eq!(split.sampling.get("lp__").unwrap()[0], vec![-1.0, -2.0]);
+
+        let split = from_reader_split(SMALL.as_bytes()).unwrap();
+        assert_eq!(split.warmup.num_chains(), 0);
+        assert_eq!(split.sampling.num_draws(), 3);
+    }
+
+    #[test]
+    fn test_from_bytes_multiple() {
+        let other = "lp__,theta\n-3.0,1.0\n-4.0,2.0\n";
+        let draws = from_bytes_multiple(&[SMALL.as_bytes(), other.as_bytes()]).unwrap();
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(draws.num_draws(), 2);
+        assert_eq!(draws.get("lp__").unwrap()[1], vec![-3.0, -4.0]);
+
+        let mismatched = "lp__,phi\n-3.0,1.0\n";
+        assert!(from_bytes_multiple(&[SMALL.as_bytes(), mismatched.as_bytes()]).is_err());
+        assert!(from_bytes_multiple(&[]).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        let err = from_bytes(b"a,b\n1.0,x\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("line 2, column 2"));
+        assert!(from_bytes(b"a,b\n1.0\n").is_err());
+        assert!(from_bytes(b"# only comments\n").is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_read_file_matches_read_csv() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let path = d.join("test/stan/blocker.1.csv");
+        let draws = read_file(&path).unwrap();
+        let samples = read_csv(&path, 41, 1000);
+        assert_eq!(draws.num_parameters(), samples.len());
+        assert_eq!(draws.num_draws(), 1000);
+        assert_eq!(draws.index_of("d"), Some(4));
+        for (idx, column) in samples.iter().enumerate() {
+            assert_eq!(&draws.parameter(idx)[0], column);
+        }
+
+        let split = read_file_split(&path).unwrap();
+        assert_eq!(split.warmup.num_chains(), 0);
+        assert_eq!(split.sampling, draws);
+
+        let both = read_files(&[path.clone(), d.join("test/stan/blocker.2.csv")]).unwrap();
+        assert_eq!(both.num_chains(), 2);
+        assert!(read_file(d.join("test/stan/missing.csv")).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_read_glob() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let pattern = d.join("test/stan/blocker.*.csv");
+        let (paths, draws) = read_glob(&pattern.to_string_lossy()).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                d.join("test/stan/blocker.1.csv"),
+                d.join("test/stan/blocker.2.csv")
+            ]
+        );
+        assert_eq!(draws, read_files(&paths).unwrap());
+        assert!(read_glob(&d.join("test/stan/missing.*.csv").to_string_lossy()).is_err());
+        assert!(read_glob("[").is_err());
+
+        let mut names = vec!["out_10.csv", "out_2.csv", "out_1.csv"];
+        names.sort_by_key(|n| natural_key(n));
+        assert_eq!(names, vec!["out_1.csv", "out_2.csv", "out_10.csv"]);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::draws::Draws;
+use crate::online::{OnlineMonitor, Snapshot};
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Line format of a stream of draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Comma separated values with a header line, e.g. Stan CSV output.
+    /// Lines starting with `#` are skipped.
+    Csv,
+    /// One JSON object per line mapping parameter names to values, e.g.
+    /// `{"chain": 1, "theta": 0.25}`.  Parameter names are taken from the
+    /// first object.  Requires the `json` feature.
+    Json,
+}
+
+/// Options for reading a stream of draws.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamOptions {
+    /// Line format of the stream
+    pub format: Format,
+    /// Name of the column (or JSON key) identifying the chain of each draw.
+    /// When it is absent every draw belongs to a single chain.  Chains are
+    /// numbered in order of first appearance.
+    pub chain_column: String,
+    /// Call the snapshot callback every this many draws; zero disables
+    /// periodic snapshots
+    pub snapshot_every: usize,
+}
+
+impl Default for StreamOptions {
+    fn default() -> StreamOptions {
+        StreamOptions {
+            format: Format::Csv,
+            chain_column: "chain".to_string(),
+            snapshot_every: 100,
+        }
+    }
+}
+
+/// Reads newline delimited draws as they are produced, feeding each one to
+/// an [`OnlineMonitor`](../../online/struct.OnlineMonitor.html) and calling
+/// `on_snapshot` with its status every `snapshot_every` draws.  Returns the
+/// monitor once the reader is exhausted, e.g. when the sampler closes its
+/// output.
+///
+/// To monitor a sampler piping its output into the current process, pass
+/// `std::io::stdin().lock()` as the reader.
+///
+/// # Arguments
+/// * `reader` - Buffered reader over the stream of draws
+/// * `options` - Format of the stream and how often to emit snapshots
+/// * `on_snapshot` - Callback receiving periodic status snapshots
+pub fn from_reader<R, F>(
+    reader: R,
+    options: &StreamOptions,
+    mut on_snapshot: F,
+) -> Result<OnlineMonitor, Error>
+where
+    R: BufRead,
+    F: FnMut(&Snapshot),
+{
+    let mut parser = StreamParser::new(options.clone());
+    let mut monitor: Option<OnlineMonitor> = None;
+    let mut num_draws = 0;
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        let parsed = parser
+            .parse_line(&line)
+            .with_context(|| format!("Failed to parse line {}", line_idx + 1))?;
+        if let Some((chain, draw)) = parsed {
+            let monitor =
+                monitor.get_or_insert_with(|| OnlineMonitor::new(parser.names().to_vec()));
+            monitor.push(chain, &draw)?;
+            num_draws += 1;
+            if options.snapshot_every > 0 && num_draws % options.snapshot_every == 0 {
+                on_snapshot(&monitor.snapshot());
+            }
+        }
+    }
+    monitor.ok_or_else(|| anyhow!("No draws found in stream"))
+}
+
+/// Reads a complete stream of draws, e.g. a finished output file or a
+/// request body, into a container with one chain per chain id in order of
+/// first appearance.  Draws of different chains may be interleaved.
+///
+/// # Arguments
+/// * `reader` - Buffered reader over the draws
+/// * `options` - Format of the stream; `snapshot_every` is ignored
+pub fn read_draws<R: BufRead>(reader: R, options: &StreamOptions) -> Result<Draws, Error> {
+    let mut parser = StreamParser::new(options.clone());
+    let mut chains = ChainColumns::default();
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        let parsed = parser
+            .parse_line(&line)
+            .with_context(|| format!("Failed to parse line {}", line_idx + 1))?;
+        if let Some((chain, draw)) = parsed {
+            chains.push(chain, &draw);
+        }
+    }
+    if chains.is_empty() {
+        return Err(anyhow!("No draws found in stream"));
+    }
+    chains.into_draws(parser.names().to_vec())
+}
+
+/// Draws collected row by row into columns, one set per chain index.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChainColumns {
+    chains: Vec<Array2>,
+}
+
+impl ChainColumns {
+    pub(crate) fn push(&mut self, chain: usize, draw: &[f64]) {
+        while self.chains.len() <= chain {
+            self.chains.push(vec![Vec::new(); draw.len()]);
+        }
+        for (column, &x) in self.chains[chain].iter_mut().zip(draw.iter()) {
+            column.push(x);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    pub(crate) fn into_draws(self, names: Vec<String>) -> Result<Draws, Error> {
+        Draws::from_chains(names, self.chains)
+    }
+}
+
+/// Incremental parser turning lines of a stream into draws, for callers that
+/// read the stream themselves, e.g. by tailing a file that is still being
+/// written.
+#[derive(Debug, Clone)]
+pub struct StreamParser {
+    options: StreamOptions,
+    names: Vec<String>,
+    // CSV column positions of the parameters and the chain column
+    columns: Vec<usize>,
+    chain_column: Option<usize>,
+    has_header: bool,
+    chain_ids: HashMap<String, usize>,
+}
+
+impl StreamParser {
+    /// Creates a parser that has not seen a header yet.
+    pub fn new(options: StreamOptions) -> StreamParser {
+        StreamParser {
+            options,
+            names: Vec::new(),
+            columns: Vec::new(),
+            chain_column: None,
+            has_header: false,
+            chain_ids: HashMap::new(),
+        }
+    }
+
+    /// Parameter names, available once the header (or first JSON object)
+    /// has been parsed.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Parses one line, returning the chain index and the value of every
+    /// parameter if the line holds a draw, or `None` for headers, comments
+    /// and blank lines.
+    pub fn parse_line(&mut self, line: &str) -> Result<Option<(usize, Vec<f64>)>, Error> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        match self.options.format {
+            Format::Csv => self.parse_csv(line),
+            Format::Json => self.parse_json(line),
+        }
+    }
+
+    fn chain_index(&mut self, id: String) -> usize {
+        let next = self.chain_ids.len();
+        *self.chain_ids.entry(id).or_insert(next)
+    }
+
+    fn parse_csv(&mut self, line: &str) -> Result<Option<(usize, Vec<f64>)>, Error> {
+        if line.starts_with('#') {
+            return Ok(None);
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if !self.has_header {
+            for (idx, field) in fields.iter().enumerate() {
+                if *field == self.options.chain_column {
+                    self.chain_column = Some(idx);
+                } else {
+                    self.names.push(field.to_string());
+                    self.columns.push(idx);
+                }
+            }
+            self.has_header = true;
+            return Ok(None);
+        }
+        if fields.len() != self.names.len() + self.chain_column.map_or(0, |_| 1) {
+            return Err(anyhow!("Number of values does not match the header"));
+        }
+        let mut draw = Vec::with_capacity(self.columns.len());
+        for &idx in self.columns.iter() {
+            let value = fields[idx].parse::<f64>().with_context(|| {
+                format!("Invalid value {:?} in column {}", fields[idx], idx + 1)
+            })?;
+            draw.push(value);
+        }
+        let chain = match self.chain_column {
+            Some(idx) => self.chain_index(fields[idx].to_string()),
+            None => 0,
+        };
+        Ok(Some((chain, draw)))
+    }
+
+    #[cfg(feature = "json")]
+    fn parse_json(&mut self, line: &str) -> Result<Option<(usize, Vec<f64>)>, Error> {
+        let value: serde_json::Value = serde_json::from_str(line)?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("Expected a JSON object"))?;
+        if !self.has_header {
+            self.names = object
+                .keys()
+                .filter(|k| **k != self.options.chain_column)
+                .cloned()
+                .collect();
+            self.has_header = true;
+        }
+        let mut draw = Vec::with_capacity(self.names.len());
+        for name in self.names.iter() {
+            let value = object
+                .get(name)
+                .and_then(|v| v.as_f64())
+                .ok_or_else(|| anyhow!("Missing or non-numeric value for {:?}", name))?;
+            draw.push(value);
+        }
+        let chain = match object.get(&self.options.chain_column) {
+            Some(id) => self.chain_index(id.to_string()),
+            None => 0,
+        };
+        Ok(Some((chain, draw)))
+    }
+
+    #[cfg(not(feature = "json"))]
+    fn parse_json(&mut self, _line: &str) -> Result<Option<(usize, Vec<f64>)>, Error> {
+        Err(anyhow!("Reading JSON streams requires the json feature"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_stream_with_chains_and_snapshots() {
+        let input = "# comment\nchain,lp__,theta\n1,-1.0,0.1\n2,-1.5,0.2\n1,-2.0,0.3\n2,-2.5,0.4\n\n1,-1.0,0.5\n";
+        let options = StreamOptions {
+            snapshot_every: 2,
+            ..StreamOptions::default()
+        };
+        let mut snapshots = Vec::new();
+        let monitor =
+            from_reader(input.as_bytes(), &options, |s| snapshots.push(s.clone())).unwrap();
+        assert_eq!(monitor.names(), &["lp__".to_string(), "theta".to_string()]);
+        assert_eq!(monitor.num_chains(), 2);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].num_draws, 4);
+        let theta = &monitor.snapshot().parameters[1];
+        assert_abs_diff_eq!(theta.mean, 0.3, epsilon = 1e-12);
+        assert!(snapshots[0].parameters[1].rhat.is_none());
+        assert!(snapshots[1].parameters[1].rhat.is_some());
+    }
+
+    #[test]
+    fn test_stream_parser_incremental() {
+        let mut parser = StreamParser::new(StreamOptions::default());
+        assert_eq!(parser.parse_line("# comment").unwrap(), None);
+        assert_eq!(parser.parse_line("lp__,theta\n").unwrap(), None);
+        assert_eq!(parser.names(), &["lp__".to_string(), "theta".to_string()]);
+        assert_eq!(
+            parser.parse_line("-1.0,0.5\n").unwrap(),
+            Some((0, vec![-1.0, 0.5]))
+        );
+        assert!(parser.parse_line("-1.0").is_err());
+    }
+
+    #[test]
+    fn test_csv_stream_errors() {
+        let options = StreamOptions::default();
+        assert!(from_reader("a,b\n1.0,x\n".as_bytes(), &options, |_| {}).is_err());
+        assert!(from_reader("a,b\n1.0\n".as_bytes(), &options, |_| {}).is_err());
+        assert!(from_reader("a,b\n".as_bytes(), &options, |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_read_draws() {
+        let input = "chain,a,b\n1,1.0,2.0\n2,3.0,4.0\n1,5.0,6.0\n";
+        let draws = read_draws(input.as_bytes(), &StreamOptions::default()).unwrap();
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(*draws.parameter(1), vec![vec![2.0, 6.0], vec![4.0]]);
+        assert!(read_draws("a,b\n".as_bytes(), &StreamOptions::default()).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_stream() {
+        let input = "{\"chain\": 1, \"theta\": 0.5, \"lp__\": -1.0}\n{\"theta\": 1.5, \"chain\": 1, \"lp__\": -2.0}\n{\"chain\": 2, \"theta\": 1.0, \"lp__\": -3.0}\n";
+        let options = StreamOptions {
+            format: Format::Json,
+            snapshot_every: 0,
+            ..StreamOptions::default()
+        };
+        let monitor = from_reader(input.as_bytes(), &options, |_| panic!()).unwrap();
+        assert_eq!(monitor.names(), &["theta".to_string(), "lp__".to_string()]);
+        assert_eq!(monitor.num_chains(), 2);
+        assert_abs_diff_eq!(monitor.snapshot().parameters[0].mean, 1.0);
+        assert!(from_reader("{\"theta\": \"x\"}\n".as_bytes(), &options, |_| {}).is_err());
+        assert!(from_reader("[1.0]\n".as_bytes(), &options, |_| {}).is_err());
+    }
+}
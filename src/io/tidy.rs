@@ -0,0 +1,277 @@
+use crate::draws::{ChainLabel, Draws};
+use crate::io::mcmcchains::{parse_value, split_fields};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+use std::collections::HashMap;
+use std::io::BufRead;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Names tried for the chain column, as written by `posterior::as_draws_df`
+/// and `tidybayes`, and by hand.
+pub const CHAIN_COLUMNS: &[&str] = &[".chain", "chain", "Chain", "CHAIN"];
+
+/// Names of the columns numbering the draws, which are dropped, in the order
+/// they are tried for sorting the draws of each chain.
+pub const INDEX_COLUMNS: &[&str] = &[
+    ".iteration",
+    "iteration",
+    "Iteration",
+    "iter",
+    ".draw",
+    "draw",
+    "Draw",
+];
+
+/// Options for reading draws in wide format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TidyOptions {
+    /// Names tried for the chain column; the first one in the header is
+    /// used
+    pub chain_columns: Vec<String>,
+    /// Names of the columns numbering the draws.  They are not parameters;
+    /// the first of them found in the header orders the draws of each chain
+    pub index_columns: Vec<String>,
+}
+
+impl Default for TidyOptions {
+    fn default() -> TidyOptions {
+        TidyOptions {
+            chain_columns: CHAIN_COLUMNS.iter().map(|s| s.to_string()).collect(),
+            index_columns: INDEX_COLUMNS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+/// Reads a wide CSV with one row per draw and one column per parameter,
+/// detecting the chain and iteration columns by name, see
+/// [`from_reader_with_options`](fn.from_reader_with_options.html).
+pub fn from_reader<R: BufRead>(reader: R) -> Result<Draws, Error> {
+    from_reader_with_options(reader, &TidyOptions::default())
+}
+
+/// Reads a wide CSV with one row per draw and one column per parameter, such
+/// as `write.csv(posterior::as_draws_df(fit))` or the combined samples of
+/// Nimble's `runMCMC` with a chain column added.  Rows of all chains may be
+/// interleaved: the chain column, if any, assigns each row to a chain, and
+/// chains are numbered in order of first appearance and labelled with
+/// their id in the file.  Within a chain, draws are sorted by the first
+/// index column, e.g. `.iteration`, and otherwise kept in file order.  The
+/// unnamed row name column R writes first is dropped, and `TRUE`, `FALSE`
+/// and `NA` are read as 1, 0 and NaN.
+///
+/// # Arguments
+/// * `reader` - Any buffered reader over the contents of the CSV file
+/// * `options` - Names of the chain and index columns
+pub fn from_reader_with_options<R: BufRead>(
+    reader: R,
+    options: &TidyOptions,
+) -> Result<Draws, Error> {
+    from_reader_with_parser(reader, options, parse_value)
+}
+
+/// Reads a wide CSV like
+/// [`from_reader_with_options`](fn.from_reader_with_options.html), parsing
+/// every value with `parse_value`, for files that spell booleans and
+/// missing values differently.
+pub(crate) fn from_reader_with_parser<R, P>(
+    reader: R,
+    options: &TidyOptions,
+    parse_value: P,
+) -> Result<Draws, Error>
+where
+    R: BufRead,
+    P: Fn(&str) -> Result<f64, Error>,
+{
+    let mut header: Option<Header> = None;
+    let mut chain_ids: HashMap<String, usize> = HashMap::new();
+    let mut ids: Vec<String> = Vec::new();
+    // rows[chain] holds the sort key and the values of each draw
+    let mut rows: Vec<Vec<(f64, Array1)>> = Vec::new();
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_fields(&line);
+        let header = match header {
+            None => {
+                header = Some(Header::new(fields, options));
+                continue;
+            }
+            Some(ref header) => header,
+        };
+        if fields.len() != header.width {
+            return Err(anyhow!(
+                "Expected {} values at line {} but found {}",
+                header.width,
+                line_idx + 1,
+                fields.len()
+            ));
+        }
+        let parse = |idx: usize| {
+            parse_value(&fields[idx]).with_context(|| {
+                format!(
+                    "Invalid value {:?} at line {}, column {}",
+                    fields[idx],
+                    line_idx + 1,
+                    idx + 1
+                )
+            })
+        };
+        let draw = header
+            .columns
+            .iter()
+            .map(|&idx| parse(idx))
+            .collect::<Result<Array1, Error>>()?;
+        let key = match header.order_column {
+            Some(idx) => parse(idx)?,
+            None => line_idx as f64,
+        };
+        let id = header
+            .chain_column
+            .map_or_else(String::new, |idx| fields[idx].clone());
+        let next = chain_ids.len();
+        let chain = *chain_ids.entry(id.clone()).or_insert(next);
+        if chain == rows.len() {
+            ids.push(id);
+            rows.push(Vec::new());
+        }
+        rows[chain].push((key, draw));
+    }
+    let header = header.ok_or_else(|| anyhow!("No header found in CSV"))?;
+    if header.names.is_empty() {
+        return Err(anyhow!("No parameter columns found"));
+    }
+    if rows.is_empty() {
+        return Err(anyhow!("No draws found in CSV"));
+    }
+    let chains: Vec<Array2> = rows
+        .into_iter()
+        .map(|mut chain| {
+            chain.sort_by(|a, b| a.0.total_cmp(&b.0));
+            (0..header.names.len())
+                .map(|p| chain.iter().map(|(_, draw)| draw[p]).collect())
+                .collect()
+        })
+        .collect();
+    let mut draws = Draws::from_chains(header.names, chains)?;
+    if header.chain_column.is_some() {
+        for (chain, id) in ids.iter().enumerate() {
+            draws.set_chain_label(chain, ChainLabel::new(id))?;
+        }
+    }
+    Ok(draws)
+}
+
+/// Reads draws from the bytes of a wide CSV file.
+pub fn from_bytes(bytes: &[u8]) -> Result<Draws, Error> {
+    from_reader(bytes)
+}
+
+/// Reads draws from a wide CSV file on disk.
+#[cfg(feature = "fs")]
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Draws, Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    from_reader(BufReader::new(f)).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Roles of the columns of a wide CSV.
+struct Header {
+    names: Vec<String>,
+    // positions of the parameter columns
+    columns: Vec<usize>,
+    chain_column: Option<usize>,
+    order_column: Option<usize>,
+    width: usize,
+}
+
+impl Header {
+    fn new(fields: Vec<String>, options: &TidyOptions) -> Header {
+        let position = |candidates: &[String]| {
+            fields
+                .iter()
+                .position(|f| candidates.iter().any(|c| c == f))
+        };
+        let chain_column = position(&options.chain_columns);
+        let order_column = options
+            .index_columns
+            .iter()
+            .find_map(|c| fields.iter().position(|f| f == c));
+        let mut header = Header {
+            names: Vec::new(),
+            columns: Vec::new(),
+            chain_column,
+            order_column,
+            width: fields.len(),
+        };
+        for (idx, name) in fields.into_iter().enumerate() {
+            let dropped = Some(idx) == chain_column
+                || name.is_empty()
+                || options.index_columns.contains(&name);
+            if !dropped {
+                header.names.push(name);
+                header.columns.push(idx);
+            }
+        }
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bytes_pivots_chains() {
+        // as written by write.csv(posterior::as_draws_df(fit)), with the
+        // rows of the chains shuffled
+        let csv = "\"\",\"mu\",\"theta[1]\",\"divergent__\",\".chain\",\".iteration\",\".draw\"\n\
+                   \"1\",0.5,1,FALSE,\"2\",2,4\n\
+                   \"2\",0.1,2,FALSE,\"1\",1,1\n\
+                   \"3\",0.3,NA,TRUE,\"2\",1,3\n\
+                   \"4\",0.2,4,FALSE,\"1\",2,2\n";
+        let draws = from_bytes(csv.as_bytes()).unwrap();
+        assert_eq!(draws.names(), &["mu", "theta[1]", "divergent__"]);
+        assert_eq!(draws.num_chains(), 2);
+        assert_eq!(
+            *draws.get("mu").unwrap(),
+            vec![vec![0.3, 0.5], vec![0.1, 0.2]]
+        );
+        assert!(draws.get("theta[1]").unwrap()[0][0].is_nan());
+        assert_eq!(draws.get("divergent__").unwrap()[0], vec![1.0, 0.0]);
+        assert_eq!(draws.chain_name(0), "2");
+        assert_eq!(draws.chain_name(1), "1");
+    }
+
+    #[test]
+    fn test_single_chain_and_options() {
+        let draws = from_bytes(b"a,b\n1,2\n3,4\n\n5,6\n").unwrap();
+        assert_eq!(draws.num_chains(), 1);
+        assert_eq!(*draws.get("a").unwrap(), vec![vec![1.0, 3.0, 5.0]]);
+        assert!(draws.chain_label(0).is_none());
+
+        let options = TidyOptions {
+            chain_columns: vec!["run".to_string()],
+            index_columns: vec!["step".to_string()],
+        };
+        let csv = b"step,run,x,chain\n2,a,1,9\n1,b,2,9\n1,a,3,9\n";
+        let draws = from_reader_with_options(&csv[..], &options).unwrap();
+        assert_eq!(draws.names(), &["x", "chain"]);
+        assert_eq!(*draws.get("x").unwrap(), vec![vec![3.0, 1.0], vec![2.0]]);
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        assert!(from_bytes(b"").is_err());
+        assert!(from_bytes(b"a,chain\n").is_err());
+        assert!(from_bytes(b"chain,.draw\n1,1\n").is_err());
+        assert!(from_bytes(b"a,b\n1\n").is_err());
+        assert!(from_bytes(b"a,b\n1,x\n").is_err());
+        // the empty fields and booleans of pandas are only read by io::arviz
+        assert!(from_bytes(b"a,b\n1,\n").is_err());
+        assert!(from_bytes(b"a,b\n1,True\n").is_err());
+        assert!(from_bytes(b"a,iteration\n1,first\n").is_err());
+    }
+}
@@ -4,13 +4,61 @@
 //!
 //! This crate is language agnostic and intended to work with the outputs of any MCMC sampler
 //! (e.g. Stan, PyMC3, Turing.jl, etc.)
+#[cfg(test)]
 #[macro_use]
 extern crate approx;
 
+/// Coverage of posterior intervals in simulation studies
+pub mod calibration;
+/// Binary encoding of online accumulator states for checkpoints
+mod checkpoint;
+/// Further convergence diagnostics (Pareto tails, rank uniformity)
+pub mod diagnostics;
+/// Kernel discrepancies of draws from a target distribution and between runs
+pub mod discrepancy;
+/// Container for named draws of many parameters across chains
+pub mod draws;
 /// Effective Sample Size (ESS)
 pub mod ess;
+/// Arithmetic expressions over parameters, used to derive new quantities
+mod expr;
+/// C interface for embedding the diagnostics in other languages
+#[cfg(feature = "ffi")]
+pub mod ffi;
+/// Minimal HTTP handling shared by the metrics and diagnostics servers
+#[cfg(any(feature = "watch", feature = "server"))]
+mod http;
+/// Loaders for sampler output files and in-memory buffers
+pub mod io;
+/// Pointwise log likelihood for approximate leave-one-out cross-validation
+pub mod loo;
+/// Prometheus metrics of online diagnostics served over HTTP
+#[cfg(feature = "watch")]
+pub mod metrics;
+/// Online diagnostics updated one draw at a time
+pub mod online;
+/// Pluggable diagnostics run together as a configurable pipeline
+pub mod pipeline;
+/// Diagnostic reports in a versioned JSON and CSV format, and as
+/// self-contained HTML with inline SVG plots
+pub mod report;
 /// Gelman-Rubin split potential scale reducation (Rhat)
 pub mod rhat;
+/// Several independent runs of a model kept apart to check seed robustness
+pub mod runs;
+/// HTTP service computing diagnostics of posted draws
+#[cfg(feature = "server")]
+pub mod server;
+/// Spectral analysis utilities (periodogram, smoothed spectral density, spectrum at zero)
+pub mod spectral;
+/// Stationarity tests (KPSS, augmented Dickey-Fuller) applied per chain
+pub mod stationarity;
+/// Summary statistics of chains such as autocorrelations at chosen lags
+pub mod stats;
+/// Posterior summaries combining the individual diagnostics
+pub mod summary;
+/// Swap and round trip diagnostics for parallel tempering (replica exchange)
+pub mod tempering;
 /// Convenience utilities like chain splitting and certain helper functions
 /// intended mostly for internal use to avoid external dependencies (e.g.
 /// summary statistics and lightweight CSV reading)
@@ -0,0 +1,980 @@
+use crate::diagnostics::gpdfit;
+use crate::draws::Draws;
+use crate::ess::compute_effective_sample_size;
+use crate::rhat::{cholesky, forward_solve};
+use crate::utils::{flatten, log_sum_exp, sample_variance, Rng};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+
+/// Pointwise log likelihood of every posterior draw and observation, the
+/// input of information criteria and approximate leave-one-out
+/// cross-validation.  Each observation keeps its draws split by chain, as
+/// for the parameters of [`Draws`](../draws/struct.Draws.html), so that the
+/// relative efficiency of the importance sampling can account for the
+/// autocorrelation of the chains.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLikMatrix {
+    observations: Vec<Array2>,
+}
+
+impl LogLikMatrix {
+    /// Creates the matrix from the draws of each observation,
+    /// `observations[observation][chain][draw]`.  Every observation must
+    /// have the same chains with the same lengths, and no value can be `NaN`.
+    pub fn new(observations: Vec<Array2>) -> Result<LogLikMatrix, Error> {
+        let first = observations
+            .first()
+            .ok_or_else(|| anyhow!("Need at least one observation"))?;
+        let lengths: Vec<usize> = first.iter().map(|c| c.len()).collect();
+        if lengths.is_empty() || lengths.contains(&0) {
+            return Err(anyhow!("Need at least one draw in every chain"));
+        }
+        for (idx, chains) in observations.iter().enumerate() {
+            if chains.iter().map(|c| c.len()).ne(lengths.iter().cloned()) {
+                return Err(anyhow!(
+                    "Chains of observation {} don't match those of the first",
+                    idx + 1
+                ));
+            }
+            if chains.iter().flatten().any(|x| x.is_nan()) {
+                return Err(anyhow!("Log likelihood of observation {} is NaN", idx + 1));
+            }
+        }
+        Ok(LogLikMatrix { observations })
+    }
+
+    /// Creates the matrix from rows of draws, `rows[draw][observation]`, as
+    /// returned by most samplers' generated quantities, where the first
+    /// `chain_lengths[0]` rows are the first chain and so on.
+    pub fn from_rows(rows: &[Vec<f64>], chain_lengths: &[usize]) -> Result<LogLikMatrix, Error> {
+        if chain_lengths.iter().sum::<usize>() != rows.len() {
+            return Err(anyhow!(
+                "Chain lengths add up to {} draws but there are {} rows",
+                chain_lengths.iter().sum::<usize>(),
+                rows.len()
+            ));
+        }
+        let num_observations = rows.first().map_or(0, |r| r.len());
+        if let Some(idx) = rows.iter().position(|r| r.len() != num_observations) {
+            return Err(anyhow!(
+                "Row {} has {} observations but the first has {}",
+                idx + 1,
+                rows[idx].len(),
+                num_observations
+            ));
+        }
+        let observations = (0..num_observations)
+            .map(|obs| {
+                let mut start = 0;
+                chain_lengths
+                    .iter()
+                    .map(|&len| {
+                        let chain = rows[start..start + len].iter().map(|r| r[obs]).collect();
+                        start += len;
+                        chain
+                    })
+                    .collect()
+            })
+            .collect();
+        LogLikMatrix::new(observations)
+    }
+
+    /// Extracts the elements of a vector or array of pointwise log
+    /// likelihoods from the draws, e.g. `log_lik` for the columns
+    /// `log_lik.1`, `log_lik.2`, ... written by CmdStan or `log_lik[1]`,
+    /// `log_lik[2]`, ... written by other samplers, in column order.
+    /// Tempered chains are left out.
+    ///
+    /// # Arguments
+    /// * `draws` - Draws with the pointwise log likelihood among the generated quantities
+    /// * `name` - Name of the variable without indices
+    pub fn from_draws(draws: &Draws, name: &str) -> Result<LogLikMatrix, Error> {
+        let observations: Vec<Array2> = draws
+            .names()
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| {
+                column
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('['))
+            })
+            .map(|(idx, _)| draws.target_parameter(idx).into_owned())
+            .collect();
+        if observations.is_empty() {
+            return Err(anyhow!("No columns of {:?} found", name));
+        }
+        LogLikMatrix::new(observations)
+    }
+
+    /// Number of observations.
+    pub fn num_observations(&self) -> usize {
+        self.observations.len()
+    }
+
+    /// Number of chains.
+    pub fn num_chains(&self) -> usize {
+        self.observations[0].len()
+    }
+
+    /// Number of draws over all chains.
+    pub fn num_draws(&self) -> usize {
+        self.observations[0].iter().map(|c| c.len()).sum()
+    }
+
+    /// Draws of the log likelihood of one observation, one vector per chain.
+    pub fn observation(&self, idx: usize) -> &Array2 {
+        &self.observations[idx]
+    }
+
+    /// Log likelihood of one observation at one draw, counting draws over
+    /// all chains in order.
+    pub fn get(&self, draw: usize, observation: usize) -> Option<f64> {
+        let mut draw = draw;
+        for chain in self.observations.get(observation)?.iter() {
+            if draw < chain.len() {
+                return Some(chain[draw]);
+            }
+            draw -= chain.len();
+        }
+        None
+    }
+}
+
+/// Pareto smoothed importance weights, see [`psis`](fn.psis.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Psis {
+    /// Smoothed log weights, normalized so that their exponentials sum to one
+    pub log_weights: Array1,
+    /// Estimated Pareto shape of the upper tail of the importance ratios,
+    /// infinite when the tail is too short to fit
+    pub khat: f64,
+}
+
+/// Smooths importance ratios by replacing the largest ones with the
+/// expected order statistics of a generalized Pareto distribution fitted to
+/// them, and truncating them at the largest raw ratio.  Estimates with the
+/// smoothed weights are reliable when `khat` is below
+/// `min(1 - 1 / log10(S), 0.7)` for `S` draws.
+///
+/// See Vehtari et al. (2024)
+/// ["Pareto smoothed importance sampling"](https://jmlr.org/papers/v25/19-556.html),
+/// as implemented by `psis` in the R package loo.
+///
+/// # Arguments
+/// * `log_ratios` - Log importance ratios of the draws
+/// * `r_eff` - Relative efficiency of the draws, ESS over the number of draws
+pub fn psis(log_ratios: &[f64], r_eff: f64) -> Result<Psis, Error> {
+    let n = log_ratios.len();
+    if n < 2 {
+        return Err(anyhow!("Need at least 2 draws for importance sampling"));
+    }
+    if log_ratios.iter().any(|x| x.is_nan() || *x == f64::INFINITY) {
+        return Err(anyhow!("Log importance ratios must not be NaN or infinite"));
+    }
+    let max = log_ratios.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let mut log_weights: Array1 = log_ratios.iter().map(|x| x - max).collect();
+    let r_eff = if r_eff > 0.0 { r_eff } else { 1.0 };
+    let tail_len = (0.2 * n as f64).min(3.0 * (n as f64 / r_eff).sqrt()).ceil() as usize;
+    let tail_len = tail_len.min(n - 1);
+    let mut khat = f64::INFINITY;
+    if tail_len >= 5 {
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| log_weights[a].partial_cmp(&log_weights[b]).unwrap());
+        let tail = &order[n - tail_len..];
+        let cutoff = log_weights[order[n - tail_len - 1]];
+        let (lowest, highest) = (log_weights[tail[0]], log_weights[tail[tail_len - 1]]);
+        if highest - lowest >= f64::EPSILON / 100.0 && cutoff.is_finite() {
+            let exp_cutoff = cutoff.exp();
+            let exceedances: Array1 = tail
+                .iter()
+                .map(|&i| log_weights[i].exp() - exp_cutoff)
+                .collect();
+            let (k, sigma) = gpdfit(&exceedances);
+            if k.is_finite() {
+                for (rank, &i) in tail.iter().enumerate() {
+                    let p = (rank as f64 + 0.5) / tail_len as f64;
+                    let q = sigma * (-k * (-p).ln_1p()).exp_m1() / k;
+                    log_weights[i] = (q + exp_cutoff).ln();
+                }
+            }
+            khat = k;
+        }
+    }
+    // truncate at the largest raw ratio, then normalize
+    for w in log_weights.iter_mut() {
+        *w = w.min(0.0);
+    }
+    let total = log_sum_exp(&log_weights);
+    for w in log_weights.iter_mut() {
+        *w -= total;
+    }
+    Ok(Psis { log_weights, khat })
+}
+
+/// Leave-one-out estimates of one observation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LooPoint {
+    /// Expected log predictive density of the observation given the others
+    pub elpd: f64,
+    /// Effective number of parameters, the log predictive density given all
+    /// observations minus `elpd`
+    pub p_loo: f64,
+    /// Pareto shape of the importance ratios of the observation
+    pub khat: f64,
+    /// Whether the estimate was improved by moment matching
+    pub moment_matched: bool,
+}
+
+/// Approximate leave-one-out cross-validation estimates, as reported by
+/// the R package loo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loo {
+    /// Expected log pointwise predictive density summed over observations
+    pub elpd_loo: f64,
+    /// Standard error of `elpd_loo`
+    pub se_elpd_loo: f64,
+    /// Effective number of parameters
+    pub p_loo: f64,
+    /// Standard error of `p_loo`
+    pub se_p_loo: f64,
+    /// Information criterion on the deviance scale, `-2 elpd_loo`
+    pub looic: f64,
+    /// Standard error of `looic`
+    pub se_looic: f64,
+    /// Largest `khat` for which an observation's estimate is reliable,
+    /// `min(1 - 1 / log10(S), 0.7)` for `S` draws
+    pub khat_threshold: f64,
+    /// Estimates of every observation
+    pub pointwise: Vec<LooPoint>,
+}
+
+impl Loo {
+    /// Number of observations whose `khat` is above the threshold, so that
+    /// their estimates are unreliable.
+    pub fn num_bad_khat(&self) -> usize {
+        self.pointwise
+            .iter()
+            .filter(|p| p.khat > self.khat_threshold)
+            .count()
+    }
+
+    fn from_pointwise(pointwise: Vec<LooPoint>, khat_threshold: f64) -> Result<Loo, Error> {
+        let (elpd_loo, se_elpd_loo) =
+            total_and_se(&pointwise.iter().map(|p| p.elpd).collect::<Array1>())?;
+        let (p_loo, se_p_loo) =
+            total_and_se(&pointwise.iter().map(|p| p.p_loo).collect::<Array1>())?;
+        Ok(Loo {
+            elpd_loo,
+            se_elpd_loo,
+            p_loo,
+            se_p_loo,
+            looic: -2.0 * elpd_loo,
+            se_looic: 2.0 * se_elpd_loo,
+            khat_threshold,
+            pointwise,
+        })
+    }
+
+    /// Expected log predictive density of every observation, for
+    /// [`compare_elpd`](fn.compare_elpd.html).
+    pub fn elpd_pointwise(&self) -> Array1 {
+        self.pointwise.iter().map(|p| p.elpd).collect()
+    }
+}
+
+/// Sum of pointwise values and its standard error, `sqrt(n var)`.
+fn total_and_se(values: &[f64]) -> Result<(f64, f64), Error> {
+    let n = values.len() as f64;
+    Ok((values.iter().sum(), (n * sample_variance(values)?).sqrt()))
+}
+
+/// Largest reliable Pareto shape for `S` draws.
+fn khat_threshold(num_draws: usize) -> f64 {
+    (1.0 - 1.0 / (num_draws as f64).log10()).min(0.7)
+}
+
+/// Relative efficiency of the likelihood of one observation, the ESS of
+/// its exponential over the number of draws.
+fn relative_efficiency(chains: &Array2) -> f64 {
+    let max = chains
+        .iter()
+        .flatten()
+        .cloned()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let likelihood: Array2 = chains
+        .iter()
+        .map(|c| c.iter().map(|x| (x - max).exp()).collect())
+        .collect();
+    let num_draws = flatten(chains).len() as f64;
+    match compute_effective_sample_size(&likelihood) {
+        Ok(ess) if ess.is_finite() && ess > 0.0 => (ess / num_draws).min(1.0),
+        _ => 1.0,
+    }
+}
+
+/// Log predictive density of one observation given all of them.
+fn log_pointwise_density(log_lik: &[f64]) -> f64 {
+    log_sum_exp(log_lik) - (log_lik.len() as f64).ln()
+}
+
+/// Estimates the expected log predictive density of each observation left
+/// out of the fit with Pareto smoothed importance sampling (PSIS-LOO), from
+/// the draws of a single fit to all observations.  The relative efficiency
+/// of each observation's importance ratios is estimated from the chains.
+/// Observations with a `khat` above the threshold have unreliable
+/// estimates; see [`loo_moment_match`](fn.loo_moment_match.html) for a way
+/// to improve them without refitting.
+///
+/// See Vehtari, Gelman and Gabry (2017)
+/// ["Practical Bayesian model evaluation using leave-one-out cross-validation and WAIC"](https://doi.org/10.1007/s11222-016-9696-4).
+///
+/// # Arguments
+/// * `log_lik` - Pointwise log likelihood of the draws
+pub fn loo(log_lik: &LogLikMatrix) -> Result<Loo, Error> {
+    let pointwise = (0..log_lik.num_observations())
+        .map(|i| {
+            let chains = log_lik.observation(i);
+            let values = flatten(chains);
+            let ratios: Array1 = values.iter().map(|x| -x).collect();
+            let weights = psis(&ratios, relative_efficiency(chains))
+                .with_context(|| format!("Failed to smooth the ratios of observation {}", i + 1))?;
+            let elpd = elpd_from_weights(&weights.log_weights, &values);
+            Ok(LooPoint {
+                elpd,
+                p_loo: log_pointwise_density(&values) - elpd,
+                khat: weights.khat,
+                moment_matched: false,
+            })
+        })
+        .collect::<Result<Vec<LooPoint>, Error>>()?;
+    Loo::from_pointwise(pointwise, khat_threshold(log_lik.num_draws()))
+}
+
+/// Self-normalized importance sampling estimate of the log predictive
+/// density.
+fn elpd_from_weights(log_weights: &[f64], log_lik: &[f64]) -> f64 {
+    let terms: Array1 = log_weights
+        .iter()
+        .zip(log_lik.iter())
+        .map(|(w, l)| w + l)
+        .collect();
+    log_sum_exp(&terms)
+}
+
+/// The model functions moment matching needs to evaluate the transformed
+/// draws, in terms of the unconstrained parameters the draws are given in.
+pub trait MomentMatchModel {
+    /// Log posterior density of the parameters up to a constant, i.e. the
+    /// log density of the model given all observations.
+    fn log_prob(&self, upars: &[f64]) -> f64;
+
+    /// Log likelihood of one observation.
+    fn log_lik(&self, upars: &[f64], observation: usize) -> f64;
+}
+
+/// Options of [`loo_moment_match`](fn.loo_moment_match.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MomentMatchOptions {
+    /// Observations with a larger `khat` are moment matched, by default
+    /// `min(1 - 1 / log10(S), 0.7)` for `S` draws
+    pub khat_threshold: Option<f64>,
+    /// Largest number of accepted transformations per observation
+    pub max_iters: usize,
+    /// Also try matching the covariance, not just the means and variances
+    pub cov: bool,
+}
+
+impl Default for MomentMatchOptions {
+    fn default() -> MomentMatchOptions {
+        MomentMatchOptions {
+            khat_threshold: None,
+            max_iters: 30,
+            cov: true,
+        }
+    }
+}
+
+/// Column means of draws given as rows.
+fn column_means(rows: &[Array1]) -> Array1 {
+    let mut means = vec![0.0; rows[0].len()];
+    for row in rows.iter() {
+        for (m, x) in means.iter_mut().zip(row.iter()) {
+            *m += x / rows.len() as f64;
+        }
+    }
+    means
+}
+
+/// Column means of draws given as rows, weighted by normalized weights.
+fn weighted_means(rows: &[Array1], weights: &[f64]) -> Array1 {
+    let mut means = vec![0.0; rows[0].len()];
+    for (row, w) in rows.iter().zip(weights.iter()) {
+        for (m, x) in means.iter_mut().zip(row.iter()) {
+            *m += w * x;
+        }
+    }
+    means
+}
+
+/// Covariance of draws given as rows, weighted by normalized weights and
+/// rescaled as by R's `cov.wt`, or with equal weights if `None`.
+fn covariance(rows: &[Array1], center: &[f64], weights: Option<&[f64]>) -> Array2 {
+    let p = center.len();
+    let n = rows.len() as f64;
+    let mut cov = vec![vec![0.0; p]; p];
+    for (s, row) in rows.iter().enumerate() {
+        let w = weights.map_or(1.0, |w| w[s]);
+        for i in 0..p {
+            for j in 0..p {
+                cov[i][j] += w * (row[i] - center[i]) * (row[j] - center[j]);
+            }
+        }
+    }
+    let scale = match weights {
+        Some(w) => 1.0 - w.iter().map(|x| x * x).sum::<f64>(),
+        None => n - 1.0,
+    };
+    cov.iter_mut().flatten().for_each(|x| *x /= scale);
+    cov
+}
+
+/// The affine transformations of moment matching, each moving the draws
+/// towards the leave-one-out posterior described by the weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transformation {
+    /// Match the means
+    Shift,
+    /// Match the means and marginal variances
+    Scale,
+    /// Match the means and covariances
+    Covariance,
+}
+
+impl Transformation {
+    fn apply(self, rows: &[Array1], weights: &[f64]) -> Option<Vec<Array1>> {
+        let mean = column_means(rows);
+        let weighted = weighted_means(rows, weights);
+        match self {
+            Transformation::Shift => Some(
+                rows.iter()
+                    .map(|row| {
+                        row.iter()
+                            .zip(mean.iter().zip(weighted.iter()))
+                            .map(|(x, (m, w))| x - m + w)
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            Transformation::Scale => {
+                let s = rows.len() as f64;
+                let scaling: Array1 = (0..mean.len())
+                    .map(|j| {
+                        let column: Array1 = rows.iter().map(|r| r[j]).collect();
+                        let second: f64 = rows
+                            .iter()
+                            .zip(weights.iter())
+                            .map(|(r, w)| w * r[j] * r[j])
+                            .sum();
+                        let var = (second - weighted[j] * weighted[j]) * s / (s - 1.0);
+                        (var / sample_variance(&column).unwrap_or(f64::NAN)).sqrt()
+                    })
+                    .collect();
+                if scaling.iter().any(|x| !x.is_finite()) {
+                    return None;
+                }
+                Some(
+                    rows.iter()
+                        .map(|row| {
+                            (0..row.len())
+                                .map(|j| (row[j] - mean[j]) * scaling[j] + weighted[j])
+                                .collect()
+                        })
+                        .collect(),
+                )
+            }
+            Transformation::Covariance => {
+                let target = cholesky(&covariance(rows, &weighted, Some(weights)))?;
+                let current = cholesky(&covariance(rows, &mean, None))?;
+                Some(
+                    rows.iter()
+                        .map(|row| {
+                            let centered: Array1 =
+                                row.iter().zip(mean.iter()).map(|(x, m)| x - m).collect();
+                            let z = forward_solve(&current, &centered);
+                            (0..row.len())
+                                .map(|i| {
+                                    (0..=i).map(|k| target[i][k] * z[k]).sum::<f64>() + weighted[i]
+                                })
+                                .collect()
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Improves the PSIS-LOO estimates of observations with a large `khat` by
+/// moment matching: the draws are moved by affine transformations matching
+/// their mean, then also their marginal variances and finally their
+/// covariance to the importance weighted moments of the leave-one-out
+/// posterior, and the importance ratios of the transformed draws are
+/// computed from the model.  Each transformation is kept only if it lowers
+/// `khat`, and they are applied repeatedly until `khat` is below the
+/// threshold or none helps.  This often avoids refitting the model without
+/// the observation.  The split proposal of the paper, which corrects a
+/// small bias when the transformations leave `khat` high, is not applied.
+///
+/// See Paananen et al. (2021)
+/// ["Implicitly adaptive importance sampling"](https://doi.org/10.1007/s11222-020-09982-2),
+/// as implemented by `loo_moment_match` in the R package loo.
+///
+/// # Arguments
+/// * `log_lik` - Pointwise log likelihood of the draws
+/// * `upars` - Unconstrained parameters of every draw, `upars[draw][parameter]`,
+///   with the draws in the same order as in `log_lik`
+/// * `model` - Log posterior density and log likelihood of the model
+/// * `options` - Threshold, number of iterations and transformations to use
+pub fn loo_moment_match<M: MomentMatchModel>(
+    log_lik: &LogLikMatrix,
+    upars: &[Array1],
+    model: &M,
+    options: &MomentMatchOptions,
+) -> Result<Loo, Error> {
+    if upars.len() != log_lik.num_draws() {
+        return Err(anyhow!(
+            "Got {} parameter draws for {} log likelihood draws",
+            upars.len(),
+            log_lik.num_draws()
+        ));
+    }
+    if upars.iter().any(|row| row.len() != upars[0].len()) || upars[0].is_empty() {
+        return Err(anyhow!("Every draw must have the same parameters"));
+    }
+    let mut result = loo(log_lik)?;
+    let threshold = options.khat_threshold.unwrap_or(result.khat_threshold);
+    let original_lp: Array1 = upars.iter().map(|x| model.log_prob(x)).collect();
+    let mut transformations = vec![Transformation::Shift, Transformation::Scale];
+    if options.cov {
+        transformations.push(Transformation::Covariance);
+    }
+
+    for (i, point) in result.pointwise.iter_mut().enumerate() {
+        if point.khat <= threshold {
+            continue;
+        }
+        let chains = log_lik.observation(i);
+        let r_eff = relative_efficiency(chains);
+        let mut values = flatten(chains);
+        let mut rows = upars.to_vec();
+        let mut log_weights = psis(&values.iter().map(|x| -x).collect::<Array1>(), r_eff)?;
+        let mut iters = 0;
+        'outer: while iters < options.max_iters && log_weights.khat > threshold {
+            let weights: Array1 = log_weights.log_weights.iter().map(|w| w.exp()).collect();
+            for transformation in transformations.iter() {
+                let moved = match transformation.apply(&rows, &weights) {
+                    Some(moved) => moved,
+                    None => continue,
+                };
+                let moved_values: Array1 = moved.iter().map(|x| model.log_lik(x, i)).collect();
+                let ratios: Array1 = moved
+                    .iter()
+                    .zip(moved_values.iter().zip(original_lp.iter()))
+                    .map(|(x, (l, lp))| model.log_prob(x) - lp - l)
+                    .collect();
+                let moved_weights = match psis(&ratios, r_eff) {
+                    Ok(w) => w,
+                    Err(_) => continue,
+                };
+                if moved_weights.khat < log_weights.khat {
+                    rows = moved;
+                    values = moved_values;
+                    log_weights = moved_weights;
+                    iters += 1;
+                    continue 'outer;
+                }
+            }
+            break;
+        }
+        if iters > 0 {
+            let elpd = elpd_from_weights(&log_weights.log_weights, &values);
+            point.p_loo += point.elpd - elpd;
+            point.elpd = elpd;
+            point.khat = log_weights.khat;
+            point.moment_matched = true;
+        }
+    }
+    Loo::from_pointwise(result.pointwise, result.khat_threshold)
+}
+
+/// Randomly assigns observations to `k` folds of as equal size as
+/// possible, returning the fold of every observation in `0..k`.  Fit the
+/// model once per fold leaving out its observations, then combine the
+/// results with [`kfold`](fn.kfold.html).
+///
+/// # Arguments
+/// * `num_observations` - Number of observations to split
+/// * `k` - Number of folds, at least 2 and at most `num_observations`
+/// * `seed` - Seed of the random assignment
+pub fn kfold_split(num_observations: usize, k: usize, seed: u64) -> Result<Vec<usize>, Error> {
+    if k < 2 || k > num_observations {
+        return Err(anyhow!(
+            "Cannot split {} observations into {} folds",
+            num_observations,
+            k
+        ));
+    }
+    let mut folds: Vec<usize> = (0..num_observations).map(|i| i % k).collect();
+    let mut rng = Rng::new(seed);
+    for i in (1..num_observations).rev() {
+        folds.swap(i, rng.below(i + 1));
+    }
+    Ok(folds)
+}
+
+/// K-fold estimates of one observation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KfoldPoint {
+    /// Expected log predictive density of the observation from the fit
+    /// leaving out its fold
+    pub elpd: f64,
+    /// Effective number of parameters, the log predictive density given all
+    /// observations minus `elpd`
+    pub p_kfold: f64,
+    /// Fold the observation was left out in
+    pub fold: usize,
+}
+
+/// K-fold cross-validation estimates, with the same meaning as the fields
+/// of [`Loo`](struct.Loo.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kfold {
+    /// Expected log pointwise predictive density summed over observations
+    pub elpd_kfold: f64,
+    /// Standard error of `elpd_kfold`
+    pub se_elpd_kfold: f64,
+    /// Effective number of parameters
+    pub p_kfold: f64,
+    /// Standard error of `p_kfold`
+    pub se_p_kfold: f64,
+    /// Information criterion on the deviance scale, `-2 elpd_kfold`
+    pub kfoldic: f64,
+    /// Standard error of `kfoldic`
+    pub se_kfoldic: f64,
+    /// Estimates of every observation
+    pub pointwise: Vec<KfoldPoint>,
+}
+
+impl Kfold {
+    /// Expected log predictive density of every observation, for
+    /// [`compare_elpd`](fn.compare_elpd.html).
+    pub fn elpd_pointwise(&self) -> Array1 {
+        self.pointwise.iter().map(|p| p.elpd).collect()
+    }
+}
+
+/// Combines the fits of K-fold cross-validation into estimates comparable
+/// with [`loo`](fn.loo.html), for models where PSIS-LOO is unreliable for
+/// too many observations.  The log predictive density of each observation
+/// is computed from the draws of the fit that left out its fold.
+///
+/// # Arguments
+/// * `log_lik` - Pointwise log likelihood of the fit to all observations
+/// * `folds` - Fold of every observation, e.g. from
+///   [`kfold_split`](fn.kfold_split.html)
+/// * `held_out` - For every fold, the log likelihood of the observations of
+///   the fold, in increasing order, under the fit leaving them out
+pub fn kfold(
+    log_lik: &LogLikMatrix,
+    folds: &[usize],
+    held_out: &[LogLikMatrix],
+) -> Result<Kfold, Error> {
+    let n = log_lik.num_observations();
+    if folds.len() != n {
+        return Err(anyhow!(
+            "Got folds for {} observations but the log likelihood has {}",
+            folds.len(),
+            n
+        ));
+    }
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); held_out.len()];
+    for (i, &fold) in folds.iter().enumerate() {
+        members
+            .get_mut(fold)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Observation {} is in fold {} but there are {} fits",
+                    i + 1,
+                    fold,
+                    held_out.len()
+                )
+            })?
+            .push(i);
+    }
+    let mut elpd = vec![0.0; n];
+    for (fold, (observations, fit)) in members.iter().zip(held_out.iter()).enumerate() {
+        if fit.num_observations() != observations.len() {
+            return Err(anyhow!(
+                "Fold {} has {} observations but its fit has {}",
+                fold,
+                observations.len(),
+                fit.num_observations()
+            ));
+        }
+        for (idx, &i) in observations.iter().enumerate() {
+            elpd[i] = log_pointwise_density(&flatten(fit.observation(idx)));
+        }
+    }
+    let pointwise: Vec<KfoldPoint> = (0..n)
+        .map(|i| KfoldPoint {
+            elpd: elpd[i],
+            p_kfold: log_pointwise_density(&flatten(log_lik.observation(i))) - elpd[i],
+            fold: folds[i],
+        })
+        .collect();
+    let (elpd_kfold, se_elpd_kfold) = total_and_se(&elpd)?;
+    let (p_kfold, se_p_kfold) =
+        total_and_se(&pointwise.iter().map(|p| p.p_kfold).collect::<Array1>())?;
+    Ok(Kfold {
+        elpd_kfold,
+        se_elpd_kfold,
+        p_kfold,
+        se_p_kfold,
+        kfoldic: -2.0 * elpd_kfold,
+        se_kfoldic: 2.0 * se_elpd_kfold,
+        pointwise,
+    })
+}
+
+/// Difference of the expected log predictive densities of two models,
+/// with the standard error of the paired pointwise differences.  The
+/// pointwise values can come from [`Loo`](struct.Loo.html) or
+/// [`Kfold`](struct.Kfold.html) estimates alike, as long as both cover the
+/// same observations in the same order.  Returns the difference `a - b`
+/// and its standard error.
+///
+/// # Arguments
+/// * `a` - Pointwise expected log predictive densities of the first model
+/// * `b` - Pointwise expected log predictive densities of the second model
+pub fn compare_elpd(a: &[f64], b: &[f64]) -> Result<(f64, f64), Error> {
+    if a.len() != b.len() {
+        return Err(anyhow!(
+            "Cannot compare {} observations with {}",
+            a.len(),
+            b.len()
+        ));
+    }
+    let diff: Array1 = a.iter().zip(b.iter()).map(|(x, y)| x - y).collect();
+    total_and_se(&diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    const Y: [f64; 5] = [0.1, -0.3, 0.5, 0.2, 8.0];
+    const PRIOR_SD: f64 = 10.0;
+
+    /// Normal model with a known unit scale and a normal prior on the mean.
+    struct NormalMean;
+
+    fn normal_log_density(x: f64, mean: f64, var: f64) -> f64 {
+        -0.5 * (2.0 * std::f64::consts::PI * var).ln() - 0.5 * (x - mean).powi(2) / var
+    }
+
+    impl MomentMatchModel for NormalMean {
+        fn log_prob(&self, upars: &[f64]) -> f64 {
+            let prior = normal_log_density(upars[0], 0.0, PRIOR_SD * PRIOR_SD);
+            prior + (0..Y.len()).map(|i| self.log_lik(upars, i)).sum::<f64>()
+        }
+
+        fn log_lik(&self, upars: &[f64], observation: usize) -> f64 {
+            normal_log_density(Y[observation], upars[0], 1.0)
+        }
+    }
+
+    /// Exact posterior draws of the mean given the observations in `Y`.
+    fn posterior_draws() -> Vec<Array1> {
+        let precision = Y.len() as f64 + 1.0 / (PRIOR_SD * PRIOR_SD);
+        let center = Y.iter().sum::<f64>() / precision;
+        (0..4)
+            .flat_map(|c| normal_draws(1000, 40 + c))
+            .map(|z| vec![center + z / precision.sqrt()])
+            .collect()
+    }
+
+    /// Exact log predictive density of each observation given the others.
+    fn exact_elpd(i: usize) -> f64 {
+        let precision = (Y.len() - 1) as f64 + 1.0 / (PRIOR_SD * PRIOR_SD);
+        let center = (Y.iter().sum::<f64>() - Y[i]) / precision;
+        normal_log_density(Y[i], center, 1.0 + 1.0 / precision)
+    }
+
+    fn log_lik_matrix(upars: &[Array1]) -> LogLikMatrix {
+        let rows: Vec<Array1> = upars
+            .iter()
+            .map(|x| (0..Y.len()).map(|i| NormalMean.log_lik(x, i)).collect())
+            .collect();
+        LogLikMatrix::from_rows(&rows, &[1000; 4]).unwrap()
+    }
+
+    #[test]
+    fn test_psis() {
+        let ratios = normal_draws(2000, 3);
+        let smoothed = psis(&ratios, 1.0).unwrap();
+        let total: f64 = smoothed.log_weights.iter().map(|w| w.exp()).sum();
+        assert_abs_diff_eq!(total, 1.0, epsilon = 1e-12);
+        // log normal ratios have all moments, so the tail is light
+        assert!(smoothed.khat < 0.5, "khat {}", smoothed.khat);
+        let heavy: Array1 = normal_draws(2000, 4).iter().map(|x| 3.0 * x).collect();
+        assert!(psis(&heavy, 1.0).unwrap().khat > smoothed.khat);
+
+        let constant = psis(&[0.5; 100], 1.0).unwrap();
+        assert!(constant.khat.is_infinite());
+        assert_abs_diff_eq!(constant.log_weights[0], -(100.0f64).ln(), epsilon = 1e-12);
+        assert!(psis(&[1.0], 1.0).is_err());
+        assert!(psis(&[1.0, f64::NAN], 1.0).is_err());
+    }
+
+    #[test]
+    fn test_loo() {
+        let upars = posterior_draws();
+        let result = loo(&log_lik_matrix(&upars)).unwrap();
+        assert_eq!(result.pointwise.len(), Y.len());
+        assert_abs_diff_eq!(result.khat_threshold, 0.7);
+        for (i, point) in result.pointwise.iter().enumerate().take(4) {
+            assert!(point.khat < 0.5, "khat {} of {}", point.khat, i);
+            assert_abs_diff_eq!(point.elpd, exact_elpd(i), epsilon = 0.05);
+            assert!(point.p_loo > 0.0);
+        }
+        let outlier = result.pointwise[4];
+        assert!(outlier.khat > 0.7, "khat {}", outlier.khat);
+        assert_eq!(result.num_bad_khat(), 1);
+        assert_abs_diff_eq!(result.looic, -2.0 * result.elpd_loo);
+        assert_abs_diff_eq!(
+            result.elpd_loo,
+            result.pointwise.iter().map(|p| p.elpd).sum::<f64>()
+        );
+    }
+
+    #[test]
+    fn test_loo_moment_match() {
+        let upars = posterior_draws();
+        let log_lik = log_lik_matrix(&upars);
+        let plain = loo(&log_lik).unwrap();
+        let options = MomentMatchOptions::default();
+        let matched = loo_moment_match(&log_lik, &upars, &NormalMean, &options).unwrap();
+
+        assert_eq!(&matched.pointwise[..4], &plain.pointwise[..4]);
+        let outlier = matched.pointwise[4];
+        assert!(outlier.moment_matched);
+        assert!(outlier.khat < 0.7, "khat {}", outlier.khat);
+        assert_abs_diff_eq!(outlier.elpd, exact_elpd(4), epsilon = 0.1);
+        assert!(
+            (outlier.elpd - exact_elpd(4)).abs() < (plain.pointwise[4].elpd - exact_elpd(4)).abs()
+        );
+        assert_eq!(matched.num_bad_khat(), 0);
+
+        assert!(loo_moment_match(&log_lik, &upars[1..], &NormalMean, &options).is_err());
+    }
+
+    #[test]
+    fn test_log_lik_matrix() {
+        let rows = vec![
+            vec![-1.0, -2.0, -3.0],
+            vec![-1.5, -2.5, -3.5],
+            vec![-1.1, -2.1, -3.1],
+        ];
+        let matrix = LogLikMatrix::from_rows(&rows, &[2, 1]).unwrap();
+        assert_eq!(matrix.num_observations(), 3);
+        assert_eq!(matrix.num_chains(), 2);
+        assert_eq!(matrix.num_draws(), 3);
+        assert_eq!(matrix.observation(1), &vec![vec![-2.0, -2.5], vec![-2.1]]);
+        assert_eq!(matrix.get(2, 2), Some(-3.1));
+        assert_eq!(matrix.get(3, 0), None);
+        assert_eq!(matrix.get(0, 3), None);
+
+        assert!(LogLikMatrix::from_rows(&rows, &[2, 2]).is_err());
+        assert!(LogLikMatrix::from_rows(&[vec![1.0], vec![]], &[2]).is_err());
+        assert!(LogLikMatrix::new(vec![]).is_err());
+        assert!(LogLikMatrix::new(vec![vec![vec![0.0]], vec![vec![0.0, 1.0]]]).is_err());
+        assert!(LogLikMatrix::new(vec![vec![vec![f64::NAN]]]).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_log_lik_matrix_from_draws() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let draws = crate::io::stan::read_files(&[
+            d.join("test/stan/blocker.1.csv"),
+            d.join("test/stan/blocker.2.csv"),
+        ])
+        .unwrap();
+        // the mu vector of blocker stands in for a log likelihood
+        let matrix = LogLikMatrix::from_draws(&draws, "mu").unwrap();
+        assert_eq!(matrix.num_observations(), 22);
+        assert_eq!(matrix.num_chains(), 2);
+        assert_eq!(matrix.num_draws(), 2000);
+        assert_eq!(
+            matrix.observation(9),
+            &draws.get("mu.10").unwrap().into_owned()
+        );
+        assert!(LogLikMatrix::from_draws(&draws, "m").is_err());
+        assert!(LogLikMatrix::from_draws(&draws, "log_lik").is_err());
+    }
+
+    #[test]
+    fn test_kfold_split() {
+        let folds = kfold_split(23, 5, 1).unwrap();
+        assert_eq!(folds.len(), 23);
+        for k in 0..5 {
+            let size = folds.iter().filter(|&&f| f == k).count();
+            assert!(size == 4 || size == 5, "fold {} has {}", k, size);
+        }
+        assert_eq!(folds, kfold_split(23, 5, 1).unwrap());
+        assert_ne!(folds, kfold_split(23, 5, 2).unwrap());
+        assert!(kfold_split(3, 4, 0).is_err());
+        assert!(kfold_split(3, 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_kfold() {
+        // leave-one-out as K-fold with one observation per fold, using exact
+        // posterior draws of every fit
+        let upars = posterior_draws();
+        let log_lik = log_lik_matrix(&upars);
+        let folds: Vec<usize> = (0..Y.len()).collect();
+        let held_out: Vec<LogLikMatrix> = (0..Y.len())
+            .map(|i| {
+                let precision = (Y.len() - 1) as f64 + 1.0 / (PRIOR_SD * PRIOR_SD);
+                let center = (Y.iter().sum::<f64>() - Y[i]) / precision;
+                let rows: Vec<Array1> = (0..4)
+                    .flat_map(|c| normal_draws(1000, 80 + c))
+                    .map(|z| vec![NormalMean.log_lik(&[center + z / precision.sqrt()], i)])
+                    .collect();
+                LogLikMatrix::from_rows(&rows, &[1000; 4]).unwrap()
+            })
+            .collect();
+        let result = kfold(&log_lik, &folds, &held_out).unwrap();
+        for (i, point) in result.pointwise.iter().enumerate() {
+            // the density of the outlier is dominated by a few draws
+            let epsilon = if i == 4 { 0.5 } else { 0.05 };
+            assert_abs_diff_eq!(point.elpd, exact_elpd(i), epsilon = epsilon);
+            assert_eq!(point.fold, i);
+        }
+        assert_abs_diff_eq!(result.kfoldic, -2.0 * result.elpd_kfold);
+
+        let options = MomentMatchOptions::default();
+        let matched = loo_moment_match(&log_lik, &upars, &NormalMean, &options).unwrap();
+        let (diff, se) = compare_elpd(&matched.elpd_pointwise(), &result.elpd_pointwise()).unwrap();
+        assert!(diff.abs() < 0.5, "difference {} +- {}", diff, se);
+        assert!(compare_elpd(&[1.0], &[1.0, 2.0]).is_err());
+
+        assert!(kfold(&log_lik, &folds[1..], &held_out).is_err());
+        assert!(kfold(&log_lik, &[0, 0, 1, 2, 3], &held_out[..4]).is_err());
+        assert!(kfold(&log_lik, &folds, &held_out[..4]).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Prometheus metrics of online diagnostics, for watching long sampling
+//! jobs in existing dashboards.  [`render`](fn.render.html) formats a
+//! [`Snapshot`](../online/struct.Snapshot.html) in the Prometheus text
+//! exposition format, and [`MetricsServer`](struct.MetricsServer.html)
+//! serves the latest one over HTTP.
+use crate::diagnostics::hmc::is_sampler_column;
+use crate::diagnostics::DIVERGENT_COLUMNS;
+use crate::draws::Sampler;
+use crate::http::{read_request, reject, write_response, ConnectionLimit, Response};
+use crate::online::Snapshot;
+use anyhow::{Context, Error, Result};
+use std::fmt::Write as _;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long the server thread sleeps between checks for new connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Most connections answered at once; scrapers beyond it get a `503`.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the help and type lines of a gauge.
+fn gauge_header(out: &mut String, name: &str, help: &str) {
+    // writing to a String can't fail
+    writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name).unwrap();
+}
+
+/// Formats the diagnostics of a snapshot as Prometheus gauges:
+///
+/// * `mcmc_max_rhat` and `mcmc_min_ess`, the worst R hat and effective
+///   sample size over the parameters, leaving out sampler columns such as
+///   `lp__` and `divergent__`, or absent while none is available yet,
+/// * `mcmc_divergences`, the number of divergent transitions if the draws
+///   have a `divergent__` (Stan) or `numerical_error` (Turing.jl) column,
+/// * `mcmc_draws`, the number of draws seen so far, and
+///   `mcmc_draws_per_second` if given,
+/// * `mcmc_rhat` and `mcmc_ess` of every parameter, labelled with its name.
+///
+/// # Arguments
+/// * `snapshot` - Status of the online monitor
+/// * `draws_per_second` - Current sampling speed, if known
+pub fn render(snapshot: &Snapshot, draws_per_second: Option<f64>) -> String {
+    let parameters: Vec<_> = snapshot
+        .parameters
+        .iter()
+        .filter(|p| !is_sampler_column(&p.name, Sampler::Other))
+        .collect();
+    let max_rhat = parameters
+        .iter()
+        .filter_map(|p| p.rhat)
+        .fold(None, |m: Option<f64>, r| Some(m.map_or(r, |m| m.max(r))));
+    let min_ess = parameters
+        .iter()
+        .filter_map(|p| p.ess)
+        .fold(None, |m: Option<f64>, e| Some(m.map_or(e, |m| m.min(e))));
+    let divergences = snapshot
+        .parameters
+        .iter()
+        .find(|p| DIVERGENT_COLUMNS.contains(&p.name.as_str()))
+        .map(|p| (p.mean * p.num_draws as f64).round());
+
+    let mut out = String::new();
+    let mut scalar = |name: &str, help: &str, value: Option<f64>| {
+        if let Some(value) = value {
+            gauge_header(&mut out, name, help);
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+    };
+    scalar(
+        "mcmc_max_rhat",
+        "Largest potential scale reduction factor over the parameters",
+        max_rhat,
+    );
+    scalar(
+        "mcmc_min_ess",
+        "Smallest approximate effective sample size over the parameters",
+        min_ess,
+    );
+    scalar(
+        "mcmc_divergences",
+        "Number of divergent transitions",
+        divergences,
+    );
+    scalar(
+        "mcmc_draws",
+        "Number of draws seen so far, summed over chains",
+        Some(snapshot.num_draws as f64),
+    );
+    scalar(
+        "mcmc_draws_per_second",
+        "Draws per second since monitoring started",
+        draws_per_second,
+    );
+
+    let mut labelled = |name: &str, help: &str, value: &dyn Fn(usize) -> Option<f64>| {
+        let values: Vec<(usize, f64)> = (0..parameters.len())
+            .filter_map(|i| value(i).map(|v| (i, v)))
+            .collect();
+        if values.is_empty() {
+            return;
+        }
+        gauge_header(&mut out, name, help);
+        for (i, v) in values {
+            writeln!(
+                out,
+                "{}{{parameter=\"{}\"}} {}",
+                name,
+                escape_label(&parameters[i].name),
+                v
+            )
+            .unwrap();
+        }
+    };
+    labelled(
+        "mcmc_rhat",
+        "Potential scale reduction factor of each parameter",
+        &|i| parameters[i].rhat,
+    );
+    labelled(
+        "mcmc_ess",
+        "Approximate effective sample size of each parameter",
+        &|i| parameters[i].ess,
+    );
+    out
+}
+
+/// Metrics text shared between the server thread and updates, with the
+/// time and draw count of the first update to compute the sampling speed.
+#[derive(Debug, Default)]
+struct State {
+    body: String,
+    start: Option<(Instant, usize)>,
+}
+
+/// Tiny HTTP server answering `GET /metrics` with the metrics of the most
+/// recent snapshot, for scraping by Prometheus.  It accepts connections on a
+/// background thread that stops when the server is dropped, and answers
+/// each on its own thread so a slow client doesn't hold up the others, up
+/// to 16 at once.  Feed it snapshots from the
+/// callback of [`stream::from_reader`](../io/stream/fn.from_reader.html) or
+/// from [`OnlineMonitor::snapshot`](../online/struct.OnlineMonitor.html#method.snapshot).
+#[derive(Debug)]
+pub struct MetricsServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Starts serving on the given address, e.g. `0.0.0.0:9184`, or
+    /// `127.0.0.1:0` for any free port, see
+    /// [`local_addr`](#method.local_addr).  Until the first update the
+    /// metrics are empty.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<MetricsServer, Error> {
+        let listener = TcpListener::bind(addr).context("Failed to bind metrics server")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (state, stop) = (state.clone(), stop.clone());
+            let limit = ConnectionLimit::new(MAX_CONNECTIONS);
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let slot = match limit.acquire() {
+                                Some(slot) => slot,
+                                None => {
+                                    reject(stream);
+                                    continue;
+                                }
+                            };
+                            let state = state.clone();
+                            // a client that goes away mid-request is not an
+                            // error worth stopping for
+                            std::thread::spawn(move || {
+                                let _ = respond(stream, &state);
+                                drop(slot);
+                            });
+                        }
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                            std::thread::sleep(POLL_INTERVAL)
+                        }
+                        Err(_) => std::thread::sleep(POLL_INTERVAL),
+                    }
+                }
+            })
+        };
+        Ok(MetricsServer {
+            addr,
+            state,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Replaces the served metrics with those of a new snapshot.  The
+    /// sampling speed is measured from the first update.
+    pub fn update(&self, snapshot: &Snapshot) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let (start, start_draws) = *state.start.get_or_insert((now, snapshot.num_draws));
+        let elapsed = now.duration_since(start).as_secs_f64();
+        let speed = if elapsed > 0.0 {
+            Some(snapshot.num_draws.saturating_sub(start_draws) as f64 / elapsed)
+        } else {
+            None
+        };
+        state.body = render(snapshot, speed);
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers a single HTTP request.
+fn respond(mut stream: TcpStream, state: &Mutex<State>) -> Result<(), Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let response = match read_request(&mut stream, 0, deadline) {
+        Ok(ref request) if request.method == "GET" && request.path == "/metrics" => Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4".to_string(),
+            body: state.lock().unwrap().body.clone().into_bytes(),
+        },
+        Ok(_) => Response::text(404, "Not found\n"),
+        Err(response) => response,
+    };
+    write_response(&mut stream, &response)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::online::OnlineMonitor;
+    use std::io::{Read, Write};
+
+    fn monitor() -> OnlineMonitor {
+        let names = vec![
+            "lp__".to_string(),
+            "divergent__".to_string(),
+            "theta\"1".to_string(),
+        ];
+        let mut monitor = OnlineMonitor::new(names);
+        for i in 0..200 {
+            for chain in 0..2 {
+                let x = ((i * 7 + chain * 3) % 11) as f64;
+                let divergent = if i % 50 == 0 { 1.0 } else { 0.0 };
+                monitor.push(chain, &[-x, divergent, x]).unwrap();
+            }
+        }
+        monitor
+    }
+
+    #[test]
+    fn test_render() {
+        let snapshot = monitor().snapshot();
+        let text = render(&snapshot, Some(12.5));
+        assert!(text.contains("# TYPE mcmc_max_rhat gauge\n"));
+        assert!(text.contains("\nmcmc_divergences 8\n"));
+        assert!(text.contains("\nmcmc_draws 400\n"));
+        assert!(text.contains("\nmcmc_draws_per_second 12.5\n"));
+        assert!(text.contains("mcmc_rhat{parameter=\"theta\\\"1\"} "));
+        assert!(!text.contains("parameter=\"lp__\""));
+        let theta = &snapshot.parameters[2];
+        assert!(text.contains(&format!("mcmc_max_rhat {}\n", theta.rhat.unwrap())));
+
+        let empty = OnlineMonitor::new(vec!["a".to_string()]).snapshot();
+        let text = render(&empty, None);
+        assert!(!text.contains("mcmc_max_rhat"));
+        assert!(!text.contains("mcmc_divergences"));
+        assert!(text.contains("mcmc_draws 0\n"));
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_metrics_server() {
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        server.update(&monitor().snapshot());
+        let response = get(server.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP mcmc_max_rhat"));
+        assert!(response.contains("\nmcmc_draws 400\n"));
+        assert!(get(server.local_addr(), "/").starts_with("HTTP/1.1 404"));
+
+        // a client that never finishes its request doesn't hold up the others
+        let mut idle = TcpStream::connect(server.local_addr()).unwrap();
+        idle.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        assert!(get(server.local_addr(), "/metrics").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
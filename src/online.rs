@@ -0,0 +1,698 @@
+//! Diagnostics that are updated one draw at a time, for monitoring a sampler
+//! while it is still running.  Memory use is bounded by the number of
+//! parameters and chains rather than the number of draws.
+use crate::checkpoint::{Decoder, Encoder};
+use crate::stats::{P2Quantile, Reservoir};
+use anyhow::{anyhow, Error, Result};
+#[cfg(feature = "fs")]
+use {anyhow::Context, std::path::Path};
+
+/// Number of batch means kept per chain before adjacent batches are merged.
+const MAX_BATCHES: usize = 64;
+/// Probabilities of the streaming quantiles reported for each parameter.
+const QUANTILE_PROBS: [f64; 3] = [0.05, 0.5, 0.95];
+/// First bytes of a monitor checkpoint.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"MCMCMON\0";
+/// Version of the checkpoint layout, bumped whenever it changes.
+const CHECKPOINT_VERSION: u64 = 1;
+
+/// Running count, mean and variance of a stream of values, updated with
+/// Welford's algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunningStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    /// Creates empty running statistics.
+    pub fn new() -> RunningStats {
+        RunningStats::default()
+    }
+
+    /// Adds a single value.
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Number of values seen so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Mean of the values seen so far, or zero if there are none.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample variance (with Bessel's correction) of the values seen so far,
+    /// or zero if there are fewer than two.
+    pub fn sample_variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    fn encode(&self, out: &mut Encoder) {
+        out.usize(self.count);
+        out.f64(self.mean);
+        out.f64(self.m2);
+    }
+
+    fn decode(input: &mut Decoder) -> Result<RunningStats, Error> {
+        Ok(RunningStats {
+            count: input.usize()?,
+            mean: input.f64()?,
+            m2: input.f64()?,
+        })
+    }
+}
+
+/// Batch means of a single chain with a batch size that doubles whenever
+/// `MAX_BATCHES` batches are full, so that there are always between
+/// `MAX_BATCHES / 2` and `MAX_BATCHES` of them.
+#[derive(Debug, Clone, PartialEq)]
+struct BatchMeans {
+    batch_size: usize,
+    current_sum: f64,
+    current_count: usize,
+    means: Vec<f64>,
+}
+
+impl Default for BatchMeans {
+    fn default() -> BatchMeans {
+        BatchMeans {
+            batch_size: 1,
+            current_sum: 0.0,
+            current_count: 0,
+            means: Vec::new(),
+        }
+    }
+}
+
+impl BatchMeans {
+    fn push(&mut self, x: f64) {
+        self.current_sum += x;
+        self.current_count += 1;
+        if self.current_count == self.batch_size {
+            self.means.push(self.current_sum / self.batch_size as f64);
+            self.current_sum = 0.0;
+            self.current_count = 0;
+        }
+        if self.means.len() == MAX_BATCHES {
+            self.means = self
+                .means
+                .chunks(2)
+                .map(|pair| (pair[0] + pair[1]) / 2.0)
+                .collect();
+            self.batch_size *= 2;
+        }
+    }
+
+    fn encode(&self, out: &mut Encoder) {
+        out.usize(self.batch_size);
+        out.f64(self.current_sum);
+        out.usize(self.current_count);
+        out.f64s(&self.means);
+    }
+
+    fn decode(input: &mut Decoder) -> Result<BatchMeans, Error> {
+        let batches = BatchMeans {
+            batch_size: input.usize()?,
+            current_sum: input.f64()?,
+            current_count: input.usize()?,
+            means: input.f64s()?,
+        };
+        if batches.current_count >= batches.batch_size || batches.means.len() >= MAX_BATCHES {
+            return Err(anyhow!("Invalid batch means in checkpoint"));
+        }
+        Ok(batches)
+    }
+
+    /// Batch means estimate of the asymptotic variance of the chain mean
+    /// times the number of draws, i.e. the spectral density at zero.
+    fn asymptotic_variance(&self) -> Option<f64> {
+        if self.means.len() < 2 {
+            return None;
+        }
+        let mut stats = RunningStats::new();
+        for m in self.means.iter() {
+            stats.push(*m);
+        }
+        Some(self.batch_size as f64 * stats.sample_variance())
+    }
+}
+
+/// Online state for one parameter in one chain.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct ChainState {
+    stats: RunningStats,
+    batches: BatchMeans,
+    reservoir: Option<Reservoir>,
+}
+
+impl ChainState {
+    fn push(&mut self, x: f64) {
+        self.stats.push(x);
+        self.batches.push(x);
+        if let Some(ref mut reservoir) = self.reservoir {
+            reservoir.push(x);
+        }
+    }
+
+    fn encode(&self, out: &mut Encoder) {
+        self.stats.encode(out);
+        self.batches.encode(out);
+        out.bool(self.reservoir.is_some());
+        if let Some(ref reservoir) = self.reservoir {
+            reservoir.encode(out);
+        }
+    }
+
+    fn decode(input: &mut Decoder) -> Result<ChainState, Error> {
+        let stats = RunningStats::decode(input)?;
+        let batches = BatchMeans::decode(input)?;
+        let reservoir = match input.bool()? {
+            true => Some(Reservoir::decode(input)?),
+            false => None,
+        };
+        Ok(ChainState {
+            stats,
+            batches,
+            reservoir,
+        })
+    }
+}
+
+/// Point in time status of a single parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterStatus {
+    /// Parameter name
+    pub name: String,
+    /// Number of draws seen so far, summed over chains
+    pub num_draws: usize,
+    /// Mean over all chains
+    pub mean: f64,
+    /// Standard deviation over all chains
+    pub sd: f64,
+    /// Approximate 5% quantile over all chains from a streaming sketch
+    pub q5: Option<f64>,
+    /// Approximate median over all chains from a streaming sketch
+    pub q50: Option<f64>,
+    /// Approximate 95% quantile over all chains from a streaming sketch
+    pub q95: Option<f64>,
+    /// Approximate effective sample size from batch means, available once
+    /// every chain has at least two batches
+    pub ess: Option<f64>,
+    /// Potential scale reduction factor, available with at least two chains
+    /// of at least two draws
+    pub rhat: Option<f64>,
+}
+
+/// Point in time status of all monitored parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Number of draws seen so far, summed over chains
+    pub num_draws: usize,
+    /// Status of each parameter, in the order they were given to the monitor
+    pub parameters: Vec<ParameterStatus>,
+}
+
+/// Online monitor of R hat and effective sample size for several parameters
+/// across chains.  Chains are added on demand the first time a draw for them
+/// is pushed.
+///
+/// R hat is the (non-split) potential scale reduction factor computed from
+/// running chain means and variances, which is exact.  The effective sample
+/// size uses batch means with a batch size that grows with the chain, which
+/// is only an approximation of the Geyer estimator used by
+/// [`compute_effective_sample_size`](../ess/fn.compute_effective_sample_size.html)
+/// and is unreliable for short chains.  Quantiles are estimated over all
+/// chains with the P-square algorithm, see
+/// [`P2Quantile`](../stats/struct.P2Quantile.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnlineMonitor {
+    names: Vec<String>,
+    // states[chain][parameter]
+    states: Vec<Vec<ChainState>>,
+    // quantiles[parameter], pooled over chains
+    quantiles: Vec<[P2Quantile; 3]>,
+    // capacity and seed of the per chain reservoirs, if enabled
+    reservoir: Option<(usize, u64)>,
+}
+
+impl OnlineMonitor {
+    /// Creates a monitor for the given parameter names with no chains yet.
+    pub fn new(names: Vec<String>) -> OnlineMonitor {
+        let sketches = QUANTILE_PROBS.map(|p| P2Quantile::new(p).unwrap());
+        OnlineMonitor {
+            quantiles: vec![sketches; names.len()],
+            names,
+            states: Vec::new(),
+            reservoir: None,
+        }
+    }
+
+    /// Creates a monitor that also keeps a random subsample of at most
+    /// `capacity` draws of every parameter in every chain, e.g. to draw
+    /// histograms or density estimates with bounded memory.
+    ///
+    /// # Arguments
+    /// * `names` - Parameter names in the order draws are pushed
+    /// * `capacity` - Maximum number of draws kept per parameter and chain
+    /// * `seed` - Seed for the reservoir sampling, for reproducible subsamples
+    pub fn with_reservoir(names: Vec<String>, capacity: usize, seed: u64) -> OnlineMonitor {
+        let mut monitor = OnlineMonitor::new(names);
+        monitor.reservoir = Some((capacity, seed));
+        monitor
+    }
+
+    /// Random subsample of the draws of a parameter in a chain, if the
+    /// monitor was created with `with_reservoir` and the chain exists.
+    pub fn reservoir(&self, chain: usize, parameter: usize) -> Option<&[f64]> {
+        self.states
+            .get(chain)?
+            .get(parameter)?
+            .reservoir
+            .as_ref()
+            .map(|r| r.sample())
+    }
+
+    /// Parameter names in the order draws are expected.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Number of chains seen so far.
+    pub fn num_chains(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Adds one draw of every parameter to a chain.  Draws with NaN or
+    /// infinite values are rejected with an error and leave the monitor
+    /// unchanged.
+    ///
+    /// # Arguments
+    /// * `chain` - Index of the chain, starting from zero
+    /// * `draw` - One value per parameter, in the same order as the names
+    pub fn push(&mut self, chain: usize, draw: &[f64]) -> Result<(), Error> {
+        if draw.len() != self.names.len() {
+            return Err(anyhow!(
+                "Draw has {} values but there are {} parameters",
+                draw.len(),
+                self.names.len()
+            ));
+        }
+        if let Some(idx) = draw.iter().position(|x| !x.is_finite()) {
+            return Err(anyhow!(
+                "Draw has non-finite value {} for {}",
+                draw[idx],
+                self.names[idx]
+            ));
+        }
+        while self.states.len() <= chain {
+            let c = self.states.len() as u64;
+            let states = (0..self.names.len() as u64)
+                .map(|p| ChainState {
+                    // give every parameter and chain its own stream
+                    reservoir: self
+                        .reservoir
+                        .map(|(capacity, seed)| Reservoir::new(capacity, seed ^ ((c << 32) | p))),
+                    ..ChainState::default()
+                })
+                .collect();
+            self.states.push(states);
+        }
+        for ((state, sketches), x) in self.states[chain]
+            .iter_mut()
+            .zip(self.quantiles.iter_mut())
+            .zip(draw)
+        {
+            state.push(*x);
+            for sketch in sketches.iter_mut() {
+                sketch.push(*x);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the complete state of the monitor, i.e. the running
+    /// statistics, batch means, quantile sketches and reservoirs of every
+    /// chain, so that a long analysis can be resumed with
+    /// [`from_checkpoint`](#method.from_checkpoint) after an interruption
+    /// without reading the earlier draws again.  The restored monitor
+    /// continues exactly where this one stopped.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut out = Encoder::new();
+        out.bytes(CHECKPOINT_MAGIC);
+        out.u64(CHECKPOINT_VERSION);
+        out.usize(self.names.len());
+        for name in self.names.iter() {
+            out.str(name);
+        }
+        out.bool(self.reservoir.is_some());
+        if let Some((capacity, seed)) = self.reservoir {
+            out.usize(capacity);
+            out.u64(seed);
+        }
+        for sketches in self.quantiles.iter() {
+            for sketch in sketches.iter() {
+                sketch.encode(&mut out);
+            }
+        }
+        out.usize(self.states.len());
+        for state in self.states.iter().flatten() {
+            state.encode(&mut out);
+        }
+        out.into_bytes()
+    }
+
+    /// Restores a monitor saved with [`checkpoint`](#method.checkpoint).
+    pub fn from_checkpoint(bytes: &[u8]) -> Result<OnlineMonitor, Error> {
+        let mut input = Decoder::new(bytes);
+        if input.bytes(CHECKPOINT_MAGIC.len()).ok() != Some(&CHECKPOINT_MAGIC[..]) {
+            return Err(anyhow!("Not an online monitor checkpoint"));
+        }
+        let version = input.u64()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(anyhow!(
+                "Unsupported checkpoint version {}, expected {}",
+                version,
+                CHECKPOINT_VERSION
+            ));
+        }
+        let num_names = input.len(8)?;
+        let names = (0..num_names)
+            .map(|_| input.string())
+            .collect::<Result<Vec<String>, Error>>()?;
+        let reservoir = match input.bool()? {
+            true => Some((input.usize()?, input.u64()?)),
+            false => None,
+        };
+        let mut quantiles = Vec::with_capacity(names.len());
+        for _ in 0..names.len() {
+            let sketches = [
+                P2Quantile::decode(&mut input)?,
+                P2Quantile::decode(&mut input)?,
+                P2Quantile::decode(&mut input)?,
+            ];
+            quantiles.push(sketches);
+        }
+        let num_chains = input.len(names.len().max(1))?;
+        let mut states = Vec::with_capacity(num_chains);
+        for _ in 0..num_chains {
+            let chain = (0..names.len())
+                .map(|_| ChainState::decode(&mut input))
+                .collect::<Result<Vec<ChainState>, Error>>()?;
+            states.push(chain);
+        }
+        input.finish()?;
+        Ok(OnlineMonitor {
+            names,
+            states,
+            quantiles,
+            reservoir,
+        })
+    }
+
+    /// Writes a [`checkpoint`](#method.checkpoint) to a file.  The data
+    /// goes to a temporary file next to it first, which then replaces the
+    /// file, so an interruption never leaves a partial checkpoint behind.
+    #[cfg(feature = "fs")]
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.checkpoint())
+            .with_context(|| format!("Failed to write {}", Path::new(&tmp).display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+
+    /// Restores a monitor from a checkpoint file written with
+    /// [`save_checkpoint`](#method.save_checkpoint).
+    #[cfg(feature = "fs")]
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<OnlineMonitor, Error> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        OnlineMonitor::from_checkpoint(&bytes)
+            .with_context(|| format!("Failed to restore {}", path.display()))
+    }
+
+    /// Computes the current status of every parameter.
+    pub fn snapshot(&self) -> Snapshot {
+        let parameters: Vec<ParameterStatus> = (0..self.names.len())
+            .map(|p| self.parameter_status(p))
+            .collect();
+        Snapshot {
+            num_draws: parameters.first().map(|s| s.num_draws).unwrap_or(0),
+            parameters,
+        }
+    }
+
+    fn parameter_status(&self, p: usize) -> ParameterStatus {
+        let chains: Vec<&ChainState> = self.states.iter().map(|c| &c[p]).collect();
+        let num_draws: usize = chains.iter().map(|c| c.stats.count()).sum();
+
+        // pool the chain statistics into overall mean and variance
+        let mut mean = 0.0;
+        for c in chains.iter() {
+            mean += c.stats.mean() * c.stats.count() as f64;
+        }
+        if num_draws > 0 {
+            mean /= num_draws as f64;
+        }
+        let mut ss = 0.0;
+        for c in chains.iter() {
+            let n = c.stats.count() as f64;
+            ss += c.stats.sample_variance() * (n - 1.0).max(0.0)
+                + n * (c.stats.mean() - mean).powi(2);
+        }
+        let sd = if num_draws > 1 {
+            (ss / (num_draws - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+
+        ParameterStatus {
+            name: self.names[p].clone(),
+            num_draws,
+            mean,
+            sd,
+            q5: self.quantiles[p][0].estimate(),
+            q50: self.quantiles[p][1].estimate(),
+            q95: self.quantiles[p][2].estimate(),
+            ess: online_ess(&chains),
+            rhat: online_rhat(&chains),
+        }
+    }
+}
+
+fn online_ess(chains: &[&ChainState]) -> Option<f64> {
+    if chains.is_empty() {
+        return None;
+    }
+    let mut ess = 0.0;
+    for c in chains.iter() {
+        let asymptotic = c.batches.asymptotic_variance()?;
+        if asymptotic > 0.0 {
+            ess += c.stats.count() as f64 * c.stats.sample_variance() / asymptotic;
+        }
+    }
+    Some(ess)
+}
+
+fn online_rhat(chains: &[&ChainState]) -> Option<f64> {
+    let stats: Vec<RunningStats> = chains.iter().map(|c| c.stats).collect();
+    rhat_from_stats(&stats)
+}
+
+/// Potential scale reduction factor from the running statistics of every
+/// chain, or `None` with fewer than two chains of at least two draws.
+pub(crate) fn rhat_from_stats(chains: &[RunningStats]) -> Option<f64> {
+    let m = chains.len();
+    let n = chains.iter().map(|c| c.count()).min()?;
+    if m < 2 || n < 2 {
+        return None;
+    }
+    let mut means = RunningStats::new();
+    let mut within = 0.0;
+    for c in chains.iter() {
+        means.push(c.mean());
+        within += c.sample_variance();
+    }
+    within /= m as f64;
+    if within <= 0.0 {
+        return None;
+    }
+    let n = n as f64;
+    let var_between = n * means.sample_variance();
+    Some(((var_between / within + n - 1.0) / n).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use crate::ess::compute_effective_sample_size;
+    #[cfg(feature = "fs")]
+    use crate::rhat::potential_scale_reduction_factor;
+    #[cfg(feature = "fs")]
+    use crate::utils::read_csv;
+    use crate::utils::{mean, sample_variance};
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_running_stats() {
+        let arr = vec![2.0, -1.0, 0.5, 4.0, 3.5];
+        let mut stats = RunningStats::new();
+        assert_abs_diff_eq!(stats.sample_variance(), 0.0);
+        for x in arr.iter() {
+            stats.push(*x);
+        }
+        assert_eq!(stats.count(), 5);
+        assert_abs_diff_eq!(stats.mean(), mean(&arr).unwrap(), epsilon = 1e-12);
+        assert_abs_diff_eq!(
+            stats.sample_variance(),
+            sample_variance(&arr).unwrap(),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn test_batch_means_bounded() {
+        let mut batches = BatchMeans::default();
+        for i in 0..10_000 {
+            batches.push(i as f64);
+        }
+        assert!(batches.means.len() >= MAX_BATCHES / 2);
+        assert!(batches.means.len() < MAX_BATCHES);
+        assert_eq!(batches.batch_size, 256);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_online_monitor_matches_batch_diagnostics() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let mut monitor = OnlineMonitor::new(vec!["d".to_string(), "mu.1".to_string()]);
+        for i in 0..1000 {
+            monitor.push(0, &[samples1[4][i], samples1[6][i]]).unwrap();
+            monitor.push(1, &[samples2[4][i], samples2[6][i]]).unwrap();
+        }
+        let snapshot = monitor.snapshot();
+        assert_eq!(monitor.num_chains(), 2);
+        assert_eq!(snapshot.num_draws, 2000);
+
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+        let status = &snapshot.parameters[0];
+        assert_eq!(status.name, "d");
+        assert_abs_diff_eq!(
+            status.rhat.unwrap(),
+            potential_scale_reduction_factor(&chains).unwrap(),
+            epsilon = 1e-10
+        );
+        let flat = crate::utils::flatten(&chains);
+        assert_abs_diff_eq!(status.mean, mean(&flat).unwrap(), epsilon = 1e-10);
+        assert_abs_diff_eq!(
+            status.sd,
+            sample_variance(&flat).unwrap().sqrt(),
+            epsilon = 1e-10
+        );
+        let q = crate::utils::quantiles(&flat, &[0.05, 0.95]).unwrap();
+        assert!((status.q5.unwrap() - q[0]).abs() < 0.01);
+        assert!((status.q95.unwrap() - q[1]).abs() < 0.01);
+        // batch means is only a rough approximation of the Geyer estimator
+        let ess = compute_effective_sample_size(&chains).unwrap();
+        assert!((status.ess.unwrap() / ess - 1.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_online_monitor_reservoir() {
+        let mut monitor = OnlineMonitor::with_reservoir(vec!["a".to_string()], 10, 3);
+        for i in 0..1000 {
+            monitor.push(i % 2, &[i as f64]).unwrap();
+        }
+        assert_eq!(monitor.reservoir(0, 0).unwrap().len(), 10);
+        assert!(monitor
+            .reservoir(1, 0)
+            .unwrap()
+            .iter()
+            .all(|x| x % 2.0 == 1.0));
+        assert!(monitor.reservoir(2, 0).is_none());
+        assert!(OnlineMonitor::new(vec!["a".to_string()])
+            .reservoir(0, 0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_online_monitor_checkpoint() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let draws: Vec<f64> = crate::utils::normal_draws(3000, 5);
+        let mut monitor = OnlineMonitor::with_reservoir(names, 20, 7);
+        for (i, x) in draws[..1000].iter().enumerate() {
+            monitor.push(i % 3, &[*x, x * x]).unwrap();
+        }
+        let mut resumed = OnlineMonitor::from_checkpoint(&monitor.checkpoint()).unwrap();
+        assert_eq!(resumed, monitor);
+        for (i, x) in draws[1000..].iter().enumerate() {
+            monitor.push(i % 3, &[*x, x * x]).unwrap();
+            resumed.push(i % 3, &[*x, x * x]).unwrap();
+        }
+        assert_eq!(resumed, monitor);
+        assert_eq!(resumed.snapshot(), monitor.snapshot());
+
+        let bytes = OnlineMonitor::new(vec!["a".to_string()]).checkpoint();
+        assert_eq!(
+            OnlineMonitor::from_checkpoint(&bytes).unwrap().num_chains(),
+            0
+        );
+        assert!(OnlineMonitor::from_checkpoint(&bytes[..bytes.len() - 1]).is_err());
+        assert!(OnlineMonitor::from_checkpoint(b"MCMCMON").is_err());
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        assert!(OnlineMonitor::from_checkpoint(&newer).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_online_monitor_checkpoint_file() {
+        let path = std::env::temp_dir().join(format!("mcmc-checkpoint-{}.bin", std::process::id()));
+        let mut monitor = OnlineMonitor::new(vec!["a".to_string()]);
+        monitor.push(1, &[0.5]).unwrap();
+        monitor.save_checkpoint(&path).unwrap();
+        monitor.push(0, &[1.5]).unwrap();
+        monitor.save_checkpoint(&path).unwrap();
+        assert_eq!(OnlineMonitor::load_checkpoint(&path).unwrap(), monitor);
+        std::fs::remove_file(&path).unwrap();
+        assert!(OnlineMonitor::load_checkpoint(&path).is_err());
+    }
+
+    #[test]
+    fn test_online_monitor_early_and_errors() {
+        let mut monitor = OnlineMonitor::new(vec!["a".to_string()]);
+        assert_eq!(monitor.snapshot().num_draws, 0);
+        assert!(monitor.push(0, &[1.0, 2.0]).is_err());
+        monitor.push(0, &[1.0]).unwrap();
+        let status = &monitor.snapshot().parameters[0];
+        assert!(status.rhat.is_none());
+        assert!(status.ess.is_none());
+        assert_eq!(status.q50, Some(1.0));
+        // chains can arrive out of order
+        monitor.push(2, &[1.0]).unwrap();
+        assert_eq!(monitor.num_chains(), 3);
+        // non-finite draws are rejected without touching any chain
+        let before = monitor.clone();
+        assert!(monitor.push(3, &[f64::NAN]).is_err());
+        assert!(monitor.push(0, &[f64::INFINITY]).is_err());
+        assert_eq!(monitor, before);
+    }
+}
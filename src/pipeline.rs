@@ -0,0 +1,373 @@
+use crate::diagnostics::pareto_diags;
+use crate::draws::Draws;
+use crate::ess::{compute_estimated_mcse, compute_split_effective_sample_size};
+use crate::rhat::{
+    multivariate_potential_scale_reduction_factor, split_potential_scale_reduction_factor,
+};
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+
+/// Result of running a [`Diagnostic`](trait.Diagnostic.html) on a set of
+/// draws.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticOutput {
+    /// One value for the draws as a whole, e.g. a multivariate R hat
+    Scalar(f64),
+    /// One value per parameter, in the column order of the draws
+    PerParameter(Vec<f64>),
+}
+
+/// A diagnostic that can be run by a [`Pipeline`](struct.Pipeline.html)
+/// alongside the built-in ones.  Implement it to have custom diagnostics
+/// appear in the same report.
+pub trait Diagnostic {
+    /// Name the results are reported under, which must be unique within a
+    /// pipeline.
+    fn name(&self) -> &str;
+
+    /// Computes the diagnostic.  Per parameter outputs must have one value
+    /// for each parameter of the draws.
+    fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error>;
+}
+
+/// Applies a diagnostic of the chains of one parameter to every parameter,
+/// using only the chains that sample the target distribution.  This is how
+/// the built-in per parameter diagnostics are computed, and custom ones can
+/// implement [`Diagnostic::compute`](trait.Diagnostic.html#tymethod.compute)
+/// with it too:
+///
+/// ```
+/// use mcmc::draws::Draws;
+/// use mcmc::pipeline::{per_parameter, Diagnostic, DiagnosticOutput, Pipeline};
+///
+/// /// Largest absolute draw of every parameter.
+/// struct MaxAbs;
+///
+/// impl Diagnostic for MaxAbs {
+///     fn name(&self) -> &str {
+///         "max_abs"
+///     }
+///
+///     fn compute(&self, draws: &Draws) -> anyhow::Result<DiagnosticOutput> {
+///         per_parameter(draws, |chains| {
+///             Ok(chains.iter().flatten().fold(0.0, |m: f64, x| m.max(x.abs())))
+///         })
+///     }
+/// }
+///
+/// let mut pipeline = Pipeline::with_builtins();
+/// pipeline.add(MaxAbs).unwrap();
+/// ```
+pub fn per_parameter<F>(draws: &Draws, f: F) -> Result<DiagnosticOutput, Error>
+where
+    F: Fn(&Array2) -> Result<f64, Error>,
+{
+    draws
+        .names()
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            f(&draws.target_parameter(idx)).with_context(|| format!("Failed for {}", name))
+        })
+        .collect::<Result<Vec<f64>, Error>>()
+        .map(DiagnosticOutput::PerParameter)
+}
+
+/// Split potential scale reduction factor of every parameter, reported as
+/// `rhat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SplitRhat;
+
+impl Diagnostic for SplitRhat {
+    fn name(&self) -> &str {
+        "rhat"
+    }
+
+    fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+        per_parameter(draws, split_potential_scale_reduction_factor)
+    }
+}
+
+/// Split effective sample size of every parameter, reported as `ess`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SplitEss;
+
+impl Diagnostic for SplitEss {
+    fn name(&self) -> &str {
+        "ess"
+    }
+
+    fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+        per_parameter(draws, compute_split_effective_sample_size)
+    }
+}
+
+/// Monte Carlo standard error of the mean of every parameter, reported as
+/// `mcse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Mcse;
+
+impl Diagnostic for Mcse {
+    fn name(&self) -> &str {
+        "mcse"
+    }
+
+    fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+        per_parameter(draws, compute_estimated_mcse)
+    }
+}
+
+/// Pareto shape estimate of the tails of every parameter, reported as
+/// `khat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParetoKhat;
+
+impl Diagnostic for ParetoKhat {
+    fn name(&self) -> &str {
+        "khat"
+    }
+
+    fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+        per_parameter(draws, |chains| Ok(pareto_diags(chains)?.khat))
+    }
+}
+
+/// Multivariate potential scale reduction factor of all parameters jointly,
+/// reported as `mpsrf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MultivariateRhat;
+
+impl Diagnostic for MultivariateRhat {
+    fn name(&self) -> &str {
+        "mpsrf"
+    }
+
+    fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+        let parameters: Vec<Array2> = (0..draws.num_parameters())
+            .map(|idx| draws.target_parameter(idx).into_owned())
+            .collect();
+        Ok(DiagnosticOutput::Scalar(
+            multivariate_potential_scale_reduction_factor(&parameters)?,
+        ))
+    }
+}
+
+/// A configured set of diagnostics that are run together on the same draws.
+#[derive(Default)]
+pub struct Pipeline {
+    diagnostics: Vec<Box<dyn Diagnostic>>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline without any diagnostics.
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Creates a pipeline with the per parameter built-in diagnostics:
+    /// split R hat, split ESS, MCSE and Pareto k hat.
+    pub fn with_builtins() -> Pipeline {
+        Pipeline {
+            diagnostics: vec![
+                Box::new(SplitRhat),
+                Box::new(SplitEss),
+                Box::new(Mcse),
+                Box::new(ParetoKhat),
+            ],
+        }
+    }
+
+    /// Adds a diagnostic, which is run after the ones already added.  Fails
+    /// if the pipeline already has a diagnostic with the same name.
+    pub fn add<D: Diagnostic + 'static>(&mut self, diagnostic: D) -> Result<(), Error> {
+        if self.names().any(|n| n == diagnostic.name()) {
+            return Err(anyhow!(
+                "Pipeline already has a diagnostic named {:?}",
+                diagnostic.name()
+            ));
+        }
+        self.diagnostics.push(Box::new(diagnostic));
+        Ok(())
+    }
+
+    /// Names of the diagnostics in the order they are run.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.diagnostics.iter().map(|d| d.name())
+    }
+
+    /// Runs every diagnostic in order on the draws.
+    pub fn run(&self, draws: &Draws) -> Result<PipelineReport, Error> {
+        let mut results = Vec::with_capacity(self.diagnostics.len());
+        for diagnostic in self.diagnostics.iter() {
+            let name = diagnostic.name();
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("diagnostic", name = %name).entered();
+            let output = diagnostic
+                .compute(draws)
+                .with_context(|| format!("Diagnostic {} failed", name))?;
+            if let DiagnosticOutput::PerParameter(ref values) = output {
+                if values.len() != draws.num_parameters() {
+                    return Err(anyhow!(
+                        "Diagnostic {} returned {} values for {} parameters",
+                        name,
+                        values.len(),
+                        draws.num_parameters()
+                    ));
+                }
+            }
+            results.push((name.to_string(), output));
+        }
+        Ok(PipelineReport {
+            parameters: draws.names().to_vec(),
+            results,
+        })
+    }
+}
+
+/// Results of running a [`Pipeline`](struct.Pipeline.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineReport {
+    parameters: Vec<String>,
+    results: Vec<(String, DiagnosticOutput)>,
+}
+
+impl PipelineReport {
+    /// Reassembles a report from its parts, e.g. one read back from JSON,
+    /// checking that the per parameter outputs have a value for every
+    /// parameter.
+    #[cfg(feature = "json")]
+    pub(crate) fn from_parts(
+        parameters: Vec<String>,
+        results: Vec<(String, DiagnosticOutput)>,
+    ) -> Result<PipelineReport, Error> {
+        for (name, output) in results.iter() {
+            if let DiagnosticOutput::PerParameter(values) = output {
+                if values.len() != parameters.len() {
+                    return Err(anyhow!(
+                        "Diagnostic {} has {} values for {} parameters",
+                        name,
+                        values.len(),
+                        parameters.len()
+                    ));
+                }
+            }
+        }
+        Ok(PipelineReport {
+            parameters,
+            results,
+        })
+    }
+
+    /// Parameter names the per parameter outputs refer to, in column order.
+    pub fn parameters(&self) -> &[String] {
+        &self.parameters
+    }
+
+    /// Name and output of every diagnostic in the order they were run.
+    pub fn results(&self) -> &[(String, DiagnosticOutput)] {
+        &self.results
+    }
+
+    /// Looks up the output of a diagnostic by its name.
+    pub fn get(&self, diagnostic: &str) -> Option<&DiagnosticOutput> {
+        self.results
+            .iter()
+            .find(|(name, _)| name == diagnostic)
+            .map(|(_, output)| output)
+    }
+
+    /// Looks up the value of a per parameter diagnostic for one parameter.
+    pub fn value(&self, diagnostic: &str, parameter: &str) -> Option<f64> {
+        match self.get(diagnostic)? {
+            DiagnosticOutput::PerParameter(values) => {
+                let idx = self.parameters.iter().position(|p| p == parameter)?;
+                Some(values[idx])
+            }
+            DiagnosticOutput::Scalar(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    /// Largest absolute draw of every parameter.
+    struct MaxAbs;
+
+    impl Diagnostic for MaxAbs {
+        fn name(&self) -> &str {
+            "max_abs"
+        }
+
+        fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+            per_parameter(draws, |chains| {
+                Ok(chains
+                    .iter()
+                    .flatten()
+                    .fold(0.0, |m: f64, x| m.max(x.abs())))
+            })
+        }
+    }
+
+    fn draws() -> Draws {
+        let chains = (0..4)
+            .map(|c| vec![normal_draws(300, 2 * c), normal_draws(300, 2 * c + 1)])
+            .collect();
+        Draws::from_chains(vec!["a".to_string(), "b".to_string()], chains).unwrap()
+    }
+
+    #[test]
+    fn test_pipeline_builtins_and_custom() {
+        let draws = draws();
+        let mut pipeline = Pipeline::with_builtins();
+        pipeline.add(MultivariateRhat).unwrap();
+        pipeline.add(MaxAbs).unwrap();
+        assert!(pipeline.add(SplitRhat).is_err());
+        assert_eq!(
+            pipeline.names().collect::<Vec<_>>(),
+            vec!["rhat", "ess", "mcse", "khat", "mpsrf", "max_abs"]
+        );
+
+        let report = pipeline.run(&draws).unwrap();
+        assert_eq!(report.results().len(), 6);
+        let b = draws.parameter(1);
+        assert_eq!(
+            report.value("rhat", "b"),
+            Some(split_potential_scale_reduction_factor(&b).unwrap())
+        );
+        assert_eq!(
+            report.value("ess", "b"),
+            Some(compute_split_effective_sample_size(&b).unwrap())
+        );
+        match report.get("mpsrf") {
+            Some(DiagnosticOutput::Scalar(r)) => assert!(*r < 1.05),
+            other => panic!("unexpected output {:?}", other),
+        }
+        assert_eq!(report.value("mpsrf", "a"), None);
+        assert!(report.value("max_abs", "a").unwrap() > 2.0);
+        assert_eq!(report.value("max_abs", "c"), None);
+        assert_eq!(report.get("missing"), None);
+    }
+
+    #[test]
+    fn test_pipeline_checks_output_length() {
+        struct Wrong;
+
+        impl Diagnostic for Wrong {
+            fn name(&self) -> &str {
+                "wrong"
+            }
+
+            fn compute(&self, _: &Draws) -> Result<DiagnosticOutput, Error> {
+                Ok(DiagnosticOutput::PerParameter(vec![1.0]))
+            }
+        }
+
+        let mut pipeline = Pipeline::new();
+        pipeline.add(Wrong).unwrap();
+        let err = pipeline.run(&draws()).unwrap_err();
+        assert!(format!("{}", err).contains("1 values for 2 parameters"));
+    }
+}
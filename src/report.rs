@@ -0,0 +1,880 @@
+use crate::diagnostics::{verdict_with_thresholds, Thresholds};
+use crate::draws::Draws;
+use crate::pipeline::{DiagnosticOutput, Pipeline};
+use crate::summary::{summary_report_lenient, ReportOptions, ReportOrder, SummaryReport};
+use crate::utils::csv_field;
+use anyhow::{Error, Result};
+use std::fmt::Write;
+#[cfg(feature = "json")]
+use {
+    crate::pipeline::PipelineReport,
+    crate::summary::{QuantileIntervals, Summary},
+    crate::Array1,
+    anyhow::{anyhow, Context},
+    serde_json::{json, Map, Value},
+    std::convert::TryFrom,
+};
+
+#[cfg(feature = "plot")]
+mod html;
+#[cfg(all(feature = "plot", feature = "fs"))]
+pub use html::to_html_file;
+#[cfg(feature = "plot")]
+pub use html::{to_html, to_html_with_pipeline, HtmlOptions};
+
+/// Version of the layout written by [`to_json`](fn.to_json.html) and
+/// [`to_csv`](fn.to_csv.html).  It goes up whenever a field is renamed,
+/// removed or changes meaning.  Fields may be added within a version, so
+/// readers ignore fields they don't know and treat new ones as optional.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Value of the `schema` field that marks the diagnostic reports of this
+/// crate.
+const SCHEMA_NAME: &str = "mcmc-report";
+
+/// One reason for a verdict other than converged, as stored in a
+/// [`Report`](struct.Report.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Check that failed, e.g. `high_rhat` or `divergences`
+    pub kind: String,
+    /// Parameter the warning is about, if any
+    pub parameter: Option<String>,
+    /// Chain the warning is about, counting from zero, if any
+    pub chain: Option<usize>,
+    /// Value that failed the check, if any
+    pub value: Option<f64>,
+    /// Description for people, as in the HTML report
+    pub message: String,
+}
+
+/// Diagnostic report of one set of draws: the posterior summaries, the
+/// convergence verdict and the configuration they were computed with.  It
+/// can be stored with [`to_json`](fn.to_json.html) and read back, also by
+/// later versions of this crate, with [`from_json`](fn.from_json.html) to
+/// compare runs over time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Version of the layout the report was written with, see
+    /// [`SCHEMA_VERSION`](constant.SCHEMA_VERSION.html)
+    pub schema_version: u32,
+    /// Version of this crate that computed the report
+    pub crate_version: String,
+    /// Order of the parameters, quantiles and interval mass of the
+    /// summaries
+    pub options: ReportOptions,
+    /// Limits the verdict was decided with
+    pub thresholds: Thresholds,
+    /// Number of chains that sample the target distribution
+    pub num_chains: usize,
+    /// Number of draws per chain
+    pub num_draws: usize,
+    /// Verdict in lower case: `converged`, `suspect` or `not converged`
+    pub verdict: String,
+    /// Reasons for the verdict, empty when converged
+    pub warnings: Vec<Warning>,
+    /// Posterior summary of every parameter
+    pub summary: SummaryReport,
+}
+
+/// Computes the diagnostic report of draws, with the summaries of
+/// [`summary_report`](../summary/fn.summary_report.html) and the verdict of
+/// [`verdict_with_thresholds`](../diagnostics/fn.verdict_with_thresholds.html),
+/// stamped with the schema and crate version.  Parameters that can't be
+/// summarized, e.g. the constant `stepsize__` of Stan output, are left out
+/// of the summaries with a `not_summarized` warning, as in the HTML report.
+///
+/// # Arguments
+/// * `draws` - Draws to report on; tempered chains are left out
+/// * `options` - Order of the parameters, quantiles and interval mass
+/// * `thresholds` - Limits for each convergence check
+pub fn diagnostic_report(
+    draws: &Draws,
+    options: &ReportOptions,
+    thresholds: &Thresholds,
+) -> Result<Report, Error> {
+    let verdict = verdict_with_thresholds(draws, thresholds)?;
+    let (summary, failed) = summary_report_lenient(draws, options)?;
+    let mut warnings: Vec<Warning> = verdict
+        .evidence()
+        .iter()
+        .map(|e| {
+            let (kind, parameter, chain, value) = e.columns();
+            Warning {
+                kind: kind.to_string(),
+                parameter: parameter.map(str::to_string),
+                chain,
+                value,
+                message: e.to_string(),
+            }
+        })
+        .collect();
+    warnings.extend(failed.into_iter().map(|(name, err)| Warning {
+        kind: "not_summarized".to_string(),
+        message: format!("{}: could not be summarized: {:#}", name, err),
+        parameter: Some(name),
+        chain: None,
+        value: None,
+    }));
+    Ok(Report {
+        schema_version: SCHEMA_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        options: options.clone(),
+        thresholds: *thresholds,
+        num_chains: draws.target_chains().len(),
+        num_draws: draws.num_draws(),
+        verdict: verdict.name().to_string(),
+        warnings,
+        summary,
+    })
+}
+
+/// Computes the diagnostic report of
+/// [`diagnostic_report`](fn.diagnostic_report.html) together with the
+/// results of a diagnostics pipeline, which are stored with the summaries,
+/// see [`summary_report_with_pipeline`](../summary/fn.summary_report_with_pipeline.html).
+///
+/// # Arguments
+/// * `draws` - Draws to report on; tempered chains are left out
+/// * `options` - Order of the parameters, quantiles and interval mass
+/// * `thresholds` - Limits for each convergence check
+/// * `pipeline` - Diagnostics to run on the draws
+pub fn diagnostic_report_with_pipeline(
+    draws: &Draws,
+    options: &ReportOptions,
+    thresholds: &Thresholds,
+    pipeline: &Pipeline,
+) -> Result<Report, Error> {
+    let mut report = diagnostic_report(draws, options, thresholds)?;
+    report.summary.set_diagnostics(pipeline.run(draws)?);
+    Ok(report)
+}
+
+/// Name of a parameter order in the serialized configuration.
+fn order_name(order: ReportOrder) -> &'static str {
+    match order {
+        ReportOrder::Input => "input",
+        ReportOrder::Sorted => "sorted",
+    }
+}
+
+/// Thresholds by their names in the serialized configuration.
+fn threshold_fields(thresholds: &mut Thresholds) -> [(&'static str, &mut f64); 7] {
+    [
+        ("rhat_max", &mut thresholds.rhat_max),
+        ("rhat_not_converged", &mut thresholds.rhat_not_converged),
+        ("ess_min", &mut thresholds.ess_min),
+        ("ess_relative_min", &mut thresholds.ess_relative_min),
+        ("ebfmi_min", &mut thresholds.ebfmi_min),
+        ("divergence_rate_max", &mut thresholds.divergence_rate_max),
+        ("mode_dip_min", &mut thresholds.mode_dip_min),
+    ]
+}
+
+/// Writes a report as pretty printed JSON of the form
+///
+/// ```text
+/// {
+///   "schema": "mcmc-report",
+///   "schema_version": 1,
+///   "crate_version": "0.1.3",
+///   "config": {
+///     "order": "input",
+///     "quantiles": [0.05, 0.5, 0.95],
+///     "hdi_mass": 0.89,
+///     "thresholds": { "rhat_max": 1.01, "ess_min": 400.0, ... }
+///   },
+///   "num_chains": 4,
+///   "num_draws": 1000,
+///   "verdict": "suspect",
+///   "warnings": [
+///     { "kind": "high_rhat", "parameter": "mu", "chain": null, "value": 1.02,
+///       "message": "mu: rank normalized split R hat is 1.020" }
+///   ],
+///   "parameters": [
+///     { "parameter": "mu", "mean": 0.1, "mcse": 0.01, "sd": 1.0, "q5": -1.5,
+///       "q50": 0.1, "q95": 1.7, "ess": 812.0, "rhat": 1.02,
+///       "quantiles": [-1.5, 0.1, 1.7], "hdi": [-1.4, 1.6],
+///       "diagnostics": { "khat": 0.12 } }
+///   ],
+///   "diagnostics": [
+///     { "name": "khat", "per_parameter": true },
+///     { "name": "mpsrf", "per_parameter": false, "value": 1.01 }
+///   ]
+/// }
+/// ```
+///
+/// The `diagnostics` are those of a pipeline in the order they were run,
+/// and are only written for reports computed with one, see
+/// [`diagnostic_report_with_pipeline`](fn.diagnostic_report_with_pipeline.html).
+/// Per parameter diagnostics have their values in the `diagnostics` of the
+/// parameters, the others in `value`.
+///
+/// `order` is `input` or `sorted`, and `quantiles` of every parameter
+/// follow the probabilities of the configuration.  Without an interval mass
+/// `hdi_mass` is null and the parameters have no `hdi`.  The parameters
+/// only have `rhat_sd`, `tail_ess`, `mcse_q5`, `mcse_q95` and
+/// `quantile_intervals`, an object of `[lower, upper]` pairs under `q5`,
+/// `q50` and `q95`, when they were computed.  JSON has no numbers for NaN
+/// and infinite values, so they are written as the strings `"NaN"`,
+/// `"inf"` and `"-inf"`.
+#[cfg(feature = "json")]
+pub fn to_json(report: &Report) -> String {
+    serde_json::to_string_pretty(&to_json_value(report)).unwrap()
+}
+
+/// JSON document of [`to_json`](fn.to_json.html), e.g. to embed in a
+/// response.
+#[cfg(feature = "json")]
+pub(crate) fn to_json_value(report: &Report) -> Value {
+    let mut thresholds = report.thresholds;
+    let thresholds: Map<String, Value> = threshold_fields(&mut thresholds)
+        .iter()
+        .map(|(name, value)| (name.to_string(), number_value(**value)))
+        .collect();
+    let warnings: Vec<Value> = report
+        .warnings
+        .iter()
+        .map(|w| {
+            json!({
+                "kind": w.kind,
+                "parameter": w.parameter,
+                "chain": w.chain,
+                "value": w.value.map(number_value),
+                "message": w.message,
+            })
+        })
+        .collect();
+    let summary = &report.summary;
+    let parameters: Vec<Value> = summary
+        .iter()
+        .map(|(name, s)| {
+            let mut parameter = json!({
+                "parameter": name,
+                "mean": number_value(s.mean),
+                "mcse": number_value(s.mcse),
+                "sd": number_value(s.sd),
+                "q5": number_value(s.q5),
+                "q50": number_value(s.q50),
+                "q95": number_value(s.q95),
+                "ess": number_value(s.ess),
+                "rhat": number_value(s.rhat),
+            });
+            let fields = parameter.as_object_mut().unwrap();
+            let optional = [
+                ("rhat_sd", s.rhat_sd),
+                ("tail_ess", s.tail_ess),
+                ("mcse_q5", s.mcse_q5),
+                ("mcse_q95", s.mcse_q95),
+            ];
+            for (key, value) in optional.iter() {
+                if let Some(value) = value {
+                    fields.insert(key.to_string(), number_value(*value));
+                }
+            }
+            if let Some(intervals) = s.quantile_intervals {
+                fields.insert(
+                    "quantile_intervals".to_string(),
+                    json!({
+                        "q5": pair_value(intervals.q5),
+                        "q50": pair_value(intervals.q50),
+                        "q95": pair_value(intervals.q95),
+                    }),
+                );
+            }
+            let quantiles = summary.quantiles(name).unwrap_or(&[]);
+            fields.insert("quantiles".to_string(), numbers_value(quantiles));
+            if let Some(hdi) = summary.hdi(name) {
+                fields.insert("hdi".to_string(), pair_value(hdi));
+            }
+            if let Some(diagnostics) = summary.diagnostics() {
+                let values: Map<String, Value> = diagnostics
+                    .results()
+                    .iter()
+                    .filter_map(|(diagnostic, _)| {
+                        let value = diagnostics.value(diagnostic, name)?;
+                        Some((diagnostic.clone(), number_value(value)))
+                    })
+                    .collect();
+                fields.insert("diagnostics".to_string(), Value::Object(values));
+            }
+            parameter
+        })
+        .collect();
+    let mut document = json!({
+        "schema": SCHEMA_NAME,
+        "schema_version": report.schema_version,
+        "crate_version": report.crate_version,
+        "config": {
+            "order": order_name(report.options.order),
+            "quantiles": numbers_value(&report.options.quantiles),
+            "hdi_mass": report.options.hdi_mass.map(number_value),
+            "thresholds": thresholds,
+        },
+        "num_chains": report.num_chains,
+        "num_draws": report.num_draws,
+        "verdict": report.verdict,
+        "warnings": warnings,
+        "parameters": parameters,
+    });
+    if let Some(diagnostics) = summary.diagnostics() {
+        let list: Vec<Value> = diagnostics
+            .results()
+            .iter()
+            .map(|(name, output)| match output {
+                DiagnosticOutput::Scalar(value) => json!({
+                    "name": name,
+                    "per_parameter": false,
+                    "value": number_value(*value),
+                }),
+                DiagnosticOutput::PerParameter(_) => json!({
+                    "name": name,
+                    "per_parameter": true,
+                }),
+            })
+            .collect();
+        document["diagnostics"] = Value::Array(list);
+    }
+    document
+}
+
+/// Number as JSON, with the strings of [`to_json`](fn.to_json.html) for
+/// NaN and infinite values.
+#[cfg(feature = "json")]
+fn number_value(x: f64) -> Value {
+    if x.is_nan() {
+        json!("NaN")
+    } else if x == f64::INFINITY {
+        json!("inf")
+    } else if x == f64::NEG_INFINITY {
+        json!("-inf")
+    } else {
+        json!(x)
+    }
+}
+
+#[cfg(feature = "json")]
+fn numbers_value(xs: &[f64]) -> Value {
+    Value::Array(xs.iter().map(|&x| number_value(x)).collect())
+}
+
+#[cfg(feature = "json")]
+fn pair_value((lower, upper): (f64, f64)) -> Value {
+    numbers_value(&[lower, upper])
+}
+
+/// Reads a report written by [`to_json`](fn.to_json.html) with this or an
+/// earlier schema version.  Reports of newer schema versions are rejected
+/// rather than misread.  Numbers may also be null, which is read as NaN.
+///
+/// # Arguments
+/// * `json` - Contents of the JSON file
+#[cfg(feature = "json")]
+pub fn from_json(json: &str) -> Result<Report, Error> {
+    let value: Value = serde_json::from_str(json).context("Failed to parse report JSON")?;
+    if value.get("schema").and_then(Value::as_str) != Some(SCHEMA_NAME) {
+        return Err(anyhow!(
+            "Not a diagnostic report, expected schema {:?}",
+            SCHEMA_NAME
+        ));
+    }
+    let schema_version = count(&value, "schema_version")?;
+    if schema_version == 0 || schema_version > SCHEMA_VERSION as usize {
+        return Err(anyhow!(
+            "Report schema version {} is not supported, this version of the crate reads up to {}",
+            schema_version,
+            SCHEMA_VERSION
+        ));
+    }
+
+    let config = field(&value, "config")?;
+    let order = match field(config, "order")?.as_str() {
+        Some("input") => ReportOrder::Input,
+        Some("sorted") => ReportOrder::Sorted,
+        _ => return Err(anyhow!("Invalid parameter order in report configuration")),
+    };
+    let probs = numbers(config, "quantiles")?;
+    let hdi_mass = match field(config, "hdi_mass")? {
+        Value::Null => None,
+        _ => Some(number(config, "hdi_mass")?),
+    };
+    let mut thresholds = Thresholds::default();
+    let limits = field(config, "thresholds")?;
+    for (name, slot) in threshold_fields(&mut thresholds).iter_mut() {
+        if limits.get(*name).is_some() {
+            **slot = number(limits, name)?;
+        }
+    }
+
+    let warnings = array(&value, "warnings")?
+        .iter()
+        .map(|w| {
+            Ok(Warning {
+                kind: string(w, "kind")?,
+                parameter: optional(w, "parameter", string)?,
+                chain: optional(w, "chain", count)?,
+                value: optional(w, "value", number)?,
+                message: string(w, "message")?,
+            })
+        })
+        .collect::<Result<Vec<Warning>, Error>>()
+        .context("Invalid warning in report")?;
+
+    let parameters = array(&value, "parameters")?;
+    let mut names = Vec::with_capacity(parameters.len());
+    let mut summaries = Vec::with_capacity(parameters.len());
+    let mut quantiles = Vec::with_capacity(parameters.len());
+    let mut hdis = Vec::with_capacity(parameters.len());
+    for p in parameters.iter() {
+        let name = string(p, "parameter")?;
+        let context = || format!("Invalid summary of {} in report", name);
+        summaries.push(read_summary(p).with_context(context)?);
+        quantiles.push(numbers(p, "quantiles").with_context(context)?);
+        if hdi_mass.is_some() {
+            hdis.push(pair(p, "hdi").with_context(context)?);
+        }
+        names.push(name);
+    }
+    let diagnostics = match value.get("diagnostics") {
+        None | Some(Value::Null) => None,
+        Some(_) => Some(read_diagnostics(&value, &names).context("Invalid diagnostics in report")?),
+    };
+    let mut summary =
+        SummaryReport::from_parts(names, summaries, probs.clone(), quantiles, hdi_mass, hdis)?;
+    if let Some(diagnostics) = diagnostics {
+        summary.set_diagnostics(diagnostics);
+    }
+    Ok(Report {
+        schema_version: schema_version as u32,
+        crate_version: string(&value, "crate_version")?,
+        options: ReportOptions {
+            order,
+            quantiles: probs,
+            hdi_mass,
+        },
+        thresholds,
+        num_chains: count(&value, "num_chains")?,
+        num_draws: count(&value, "num_draws")?,
+        verdict: string(&value, "verdict")?,
+        warnings,
+        summary,
+    })
+}
+
+/// Reads the results of the diagnostics pipeline of a JSON report with the
+/// parameters of `names`.
+#[cfg(feature = "json")]
+fn read_diagnostics(value: &Value, names: &[String]) -> Result<PipelineReport, Error> {
+    let parameters = array(value, "parameters")?;
+    let mut results = Vec::new();
+    for d in array(value, "diagnostics")?.iter() {
+        let name = string(d, "name")?;
+        let per_parameter = field(d, "per_parameter")?
+            .as_bool()
+            .ok_or_else(|| anyhow!("Field \"per_parameter\" in report is not a boolean"))?;
+        let output = if per_parameter {
+            let values = parameters
+                .iter()
+                .map(|p| number(field(p, "diagnostics")?, &name))
+                .collect::<Result<Array1, Error>>()
+                .with_context(|| format!("Invalid values of {}", name))?;
+            DiagnosticOutput::PerParameter(values)
+        } else {
+            DiagnosticOutput::Scalar(number(d, "value")?)
+        };
+        results.push((name, output));
+    }
+    PipelineReport::from_parts(names.to_vec(), results)
+}
+
+/// Reads the posterior summary of one parameter of a JSON report.
+#[cfg(feature = "json")]
+fn read_summary(value: &Value) -> Result<Summary, Error> {
+    let quantile_intervals = match value.get("quantile_intervals") {
+        Some(intervals) => Some(QuantileIntervals {
+            q5: pair(intervals, "q5")?,
+            q50: pair(intervals, "q50")?,
+            q95: pair(intervals, "q95")?,
+        }),
+        None => None,
+    };
+    Ok(Summary {
+        mean: number(value, "mean")?,
+        mcse: number(value, "mcse")?,
+        sd: number(value, "sd")?,
+        q5: number(value, "q5")?,
+        q50: number(value, "q50")?,
+        q95: number(value, "q95")?,
+        ess: number(value, "ess")?,
+        rhat: number(value, "rhat")?,
+        rhat_sd: optional(value, "rhat_sd", number)?,
+        quantile_intervals,
+        tail_ess: optional(value, "tail_ess", number)?,
+        mcse_q5: optional(value, "mcse_q5", number)?,
+        mcse_q95: optional(value, "mcse_q95", number)?,
+    })
+}
+
+/// Field of a JSON object that must be present.
+#[cfg(feature = "json")]
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value, Error> {
+    value
+        .get(key)
+        .ok_or_else(|| anyhow!("Missing field {:?} in report", key))
+}
+
+/// Field that may be absent or null.
+#[cfg(feature = "json")]
+fn optional<T>(
+    value: &Value,
+    key: &str,
+    read: fn(&Value, &str) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => read(value, key).map(Some),
+    }
+}
+
+#[cfg(feature = "json")]
+fn to_number(value: &Value, key: &str) -> Result<f64, Error> {
+    match value {
+        Value::Null => Ok(f64::NAN),
+        Value::String(text) => match text.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "inf" => Ok(f64::INFINITY),
+            "-inf" => Ok(f64::NEG_INFINITY),
+            _ => Err(anyhow!("Field {:?} in report is not a number", key)),
+        },
+        _ => value
+            .as_f64()
+            .ok_or_else(|| anyhow!("Field {:?} in report is not a number", key)),
+    }
+}
+
+#[cfg(feature = "json")]
+fn number(value: &Value, key: &str) -> Result<f64, Error> {
+    to_number(field(value, key)?, key)
+}
+
+#[cfg(feature = "json")]
+fn numbers(value: &Value, key: &str) -> Result<Array1, Error> {
+    array(value, key)?
+        .iter()
+        .map(|x| to_number(x, key))
+        .collect()
+}
+
+#[cfg(feature = "json")]
+fn pair(value: &Value, key: &str) -> Result<(f64, f64), Error> {
+    match numbers(value, key)?[..] {
+        [lower, upper] => Ok((lower, upper)),
+        _ => Err(anyhow!(
+            "Field {:?} in report is not a pair of numbers",
+            key
+        )),
+    }
+}
+
+#[cfg(feature = "json")]
+fn count(value: &Value, key: &str) -> Result<usize, Error> {
+    field(value, key)?
+        .as_u64()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| anyhow!("Field {:?} in report is not a count", key))
+}
+
+#[cfg(feature = "json")]
+fn string(value: &Value, key: &str) -> Result<String, Error> {
+    field(value, key)?
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Field {:?} in report is not a string", key))
+}
+
+#[cfg(feature = "json")]
+fn array<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>, Error> {
+    field(value, key)?
+        .as_array()
+        .ok_or_else(|| anyhow!("Field {:?} in report is not an array", key))
+}
+
+/// Writes the summaries of a report as CSV, one row per parameter, after
+/// `#` comment lines with the same schema and crate version, configuration,
+/// verdict and warnings as [`to_json`](fn.to_json.html), e.g.
+///
+/// ```text
+/// # schema: mcmc-report
+/// # schema_version: 1
+/// # crate_version: 0.1.3
+/// # order: input
+/// # quantiles: 0.05 0.5 0.95
+/// # hdi_mass: 0.89
+/// # rhat_max: 1.01
+/// # ...
+/// # verdict: suspect
+/// # warning: mu: rank normalized split R hat is 1.020
+/// parameter,mean,mcse,sd,q5,q50,q95,ess,rhat,rhat_sd,tail_ess,mcse_q5,mcse_q95,quantile_0.05,quantile_0.5,quantile_0.95,hdi_lower,hdi_upper
+/// mu,0.1,0.01,1,-1.5,0.1,1.7,812,1.02,NaN,NaN,NaN,NaN,-1.5,0.1,1.7,-1.4,1.6
+/// ```
+///
+/// Summaries that were not computed are written as NaN, and the HDI columns
+/// are left out without an interval mass.  The per parameter diagnostics of
+/// a pipeline follow as one column each, e.g. `diagnostic_khat`, and the
+/// others are comments like `# diagnostic mpsrf: 1.01`.  Parameter names with
+/// commas or quotes are quoted.
+pub fn to_csv(report: &Report) -> String {
+    let options = &report.options;
+    let mut csv = String::new();
+    let mut comment = |key: &str, value: String| writeln!(csv, "# {}: {}", key, value).unwrap();
+    comment("schema", SCHEMA_NAME.to_string());
+    comment("schema_version", report.schema_version.to_string());
+    comment("crate_version", report.crate_version.clone());
+    comment("order", order_name(options.order).to_string());
+    let probs: Vec<String> = options.quantiles.iter().map(|p| p.to_string()).collect();
+    comment("quantiles", probs.join(" "));
+    comment(
+        "hdi_mass",
+        options
+            .hdi_mass
+            .map_or_else(|| "none".to_string(), |mass| mass.to_string()),
+    );
+    let mut thresholds = report.thresholds;
+    for (name, value) in threshold_fields(&mut thresholds).iter() {
+        comment(name, value.to_string());
+    }
+    comment("num_chains", report.num_chains.to_string());
+    comment("num_draws", report.num_draws.to_string());
+    comment("verdict", report.verdict.clone());
+    for warning in report.warnings.iter() {
+        comment("warning", warning.message.clone());
+    }
+    let summary = &report.summary;
+    let mut columns = Vec::new();
+    for (name, output) in summary.diagnostics().map_or(&[][..], |d| d.results()) {
+        match output {
+            DiagnosticOutput::Scalar(value) => {
+                comment(&format!("diagnostic {}", name), value.to_string())
+            }
+            DiagnosticOutput::PerParameter(_) => columns.push(name.as_str()),
+        }
+    }
+
+    csv.push_str("parameter,mean,mcse,sd,q5,q50,q95,ess,rhat,rhat_sd,tail_ess,mcse_q5,mcse_q95");
+    for p in options.quantiles.iter() {
+        write!(csv, ",quantile_{}", p).unwrap();
+    }
+    if options.hdi_mass.is_some() {
+        csv.push_str(",hdi_lower,hdi_upper");
+    }
+    for name in columns.iter() {
+        write!(csv, ",{}", csv_field(&format!("diagnostic_{}", name))).unwrap();
+    }
+    csv.push('\n');
+    for (name, s) in summary.iter() {
+        let mut values = vec![
+            s.mean,
+            s.mcse,
+            s.sd,
+            s.q5,
+            s.q50,
+            s.q95,
+            s.ess,
+            s.rhat,
+            s.rhat_sd.unwrap_or(f64::NAN),
+            s.tail_ess.unwrap_or(f64::NAN),
+            s.mcse_q5.unwrap_or(f64::NAN),
+            s.mcse_q95.unwrap_or(f64::NAN),
+        ];
+        values.extend_from_slice(summary.quantiles(name).unwrap_or(&[]));
+        if let Some((lower, upper)) = summary.hdi(name) {
+            values.extend_from_slice(&[lower, upper]);
+        }
+        if let Some(diagnostics) = summary.diagnostics() {
+            values.extend(
+                columns
+                    .iter()
+                    .map(|d| diagnostics.value(d, name).unwrap_or(f64::NAN)),
+            );
+        }
+        csv.push_str(&csv_field(name));
+        for value in values.iter() {
+            write!(csv, ",{}", value).unwrap();
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    fn draws() -> Draws {
+        // as in the HTML report, without the constant parameter and with a
+        // comma
+        let names = vec!["a".to_string(), "b,1".to_string()];
+        let chains = (0..2)
+            .map(|c| {
+                vec![
+                    normal_draws(500, 3 * c),
+                    normal_draws(500, 3 * c + 1)
+                        .iter()
+                        .map(|x| x + 3.0 * c as f64)
+                        .collect(),
+                ]
+            })
+            .collect();
+        Draws::from_chains(names, chains).unwrap()
+    }
+
+    fn options() -> ReportOptions {
+        ReportOptions {
+            quantiles: vec![0.1, 0.9],
+            hdi_mass: Some(0.89),
+            ..ReportOptions::default()
+        }
+    }
+
+    fn report() -> Report {
+        diagnostic_report(&draws(), &options(), &Thresholds::default()).unwrap()
+    }
+
+    #[test]
+    fn test_diagnostic_report() {
+        let report = report();
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!((report.num_chains, report.num_draws), (2, 500));
+        assert_eq!(report.verdict, "not converged");
+        let warning = &report.warnings[0];
+        assert_eq!(warning.kind, "high_rhat");
+        assert_eq!(warning.parameter.as_deref(), Some("b,1"));
+        assert_eq!(report.summary.probs(), &[0.1, 0.9]);
+        assert!(report.summary.hdi("a").is_some());
+
+        // a constant parameter is left out with a warning
+        let names = vec!["a".to_string(), "c".to_string()];
+        let chains = (0..2)
+            .map(|c| vec![normal_draws(500, c), vec![1.0; 500]])
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let report =
+            diagnostic_report(&draws, &ReportOptions::default(), &Thresholds::default()).unwrap();
+        assert_eq!(report.summary.names(), &["a"]);
+        let warning = report.warnings.last().unwrap();
+        assert_eq!(warning.kind, "not_summarized");
+        assert_eq!(warning.parameter.as_deref(), Some("c"));
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_json_round_trip() {
+        let report = report();
+        let json = to_json(&report);
+        assert!(json.contains("\"schema\": \"mcmc-report\""));
+        assert_eq!(from_json(&json).unwrap(), report);
+
+        // null numbers, fields of later crate versions and thresholds
+        // missing from earlier ones
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let mut edited = value.clone();
+        edited["parameters"][0]["mean"] = Value::Null;
+        edited["parameters"][0]["added_later"] = json!(1);
+        edited["config"]["thresholds"]
+            .as_object_mut()
+            .unwrap()
+            .remove("mode_dip_min");
+        let read = from_json(&edited.to_string()).unwrap();
+        assert!(read.summary.get("a").unwrap().mean.is_nan());
+        assert_eq!(read.thresholds, Thresholds::default());
+
+        // computed but undefined or infinite values stay apart from the
+        // ones that weren't computed
+        let mut undefined = value.clone();
+        undefined["parameters"][0]["rhat_sd"] = json!("NaN");
+        undefined["parameters"][0]["tail_ess"] = json!("inf");
+        undefined["config"]["thresholds"]["rhat_max"] = json!("inf");
+        let read = from_json(&to_json(&from_json(&undefined.to_string()).unwrap())).unwrap();
+        let a = read.summary.get("a").unwrap();
+        assert!(a.rhat_sd.unwrap().is_nan());
+        assert_eq!(a.tail_ess, Some(f64::INFINITY));
+        assert_eq!(a.mcse_q5, None);
+        assert_eq!(read.thresholds.rhat_max, f64::INFINITY);
+        undefined["parameters"][0]["mean"] = json!("many");
+        assert!(from_json(&undefined.to_string()).is_err());
+
+        let mut newer = value.clone();
+        newer["schema_version"] = json!(SCHEMA_VERSION + 1);
+        let err = from_json(&newer.to_string()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("schema version 2 is not supported"));
+        let mut no_interval = value.clone();
+        no_interval["parameters"][1]
+            .as_object_mut()
+            .unwrap()
+            .remove("hdi");
+        assert!(from_json(&no_interval.to_string()).is_err());
+        assert!(from_json("{\"schema\": \"other\"}").is_err());
+        assert!(from_json("[").is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let csv = to_csv(&report());
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "# schema: mcmc-report");
+        assert_eq!(lines[1], format!("# schema_version: {}", SCHEMA_VERSION));
+        assert!(lines.contains(&"# quantiles: 0.1 0.9"));
+        assert!(lines.contains(&"# hdi_mass: 0.89"));
+        assert!(lines.contains(&"# verdict: not converged"));
+        let header = lines.iter().position(|l| !l.starts_with('#')).unwrap();
+        assert!(lines[header].ends_with(",quantile_0.1,quantile_0.9,hdi_lower,hdi_upper"));
+        assert_eq!(lines.len(), header + 3);
+        assert!(lines[header + 1].starts_with("a,"));
+        assert!(lines[header + 2].starts_with("\"b,1\","));
+        assert_eq!(lines[header + 1].split(',').count(), 17);
+    }
+
+    #[test]
+    fn test_report_with_pipeline() {
+        let draws = draws();
+        let mut pipeline = Pipeline::with_builtins();
+        pipeline.add(crate::pipeline::MultivariateRhat).unwrap();
+        let report =
+            diagnostic_report_with_pipeline(&draws, &options(), &Thresholds::default(), &pipeline)
+                .unwrap();
+        assert_eq!(report.summary.names(), self::report().summary.names());
+        assert!(self::report().summary.diagnostics().is_none());
+        let diagnostics = report.summary.diagnostics().unwrap();
+        assert_eq!(
+            diagnostics.value("rhat", "b,1"),
+            Some(report.summary.get("b,1").unwrap().rhat)
+        );
+
+        let csv = to_csv(&report);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines.iter().any(|l| l.starts_with("# diagnostic mpsrf: ")));
+        let header = lines.iter().position(|l| !l.starts_with('#')).unwrap();
+        assert!(lines[header].ends_with(
+            ",hdi_upper,diagnostic_rhat,diagnostic_ess,diagnostic_mcse,diagnostic_khat"
+        ));
+        assert_eq!(lines[header + 1].split(',').count(), 21);
+
+        #[cfg(feature = "json")]
+        {
+            let json = to_json(&report);
+            assert_eq!(from_json(&json).unwrap(), report);
+            assert!(json.contains("\"name\": \"mpsrf\""));
+            assert!(!to_json(&self::report()).contains("\"diagnostics\""));
+            let mut missing: Value = serde_json::from_str(&json).unwrap();
+            missing["parameters"][1]["diagnostics"]
+                .as_object_mut()
+                .unwrap()
+                .remove("khat");
+            assert!(from_json(&missing.to_string()).is_err());
+        }
+    }
+}
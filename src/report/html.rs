@@ -0,0 +1,573 @@
+use crate::diagnostics::hmc::{tree_depth_histogram, TreeDepthHistogram};
+use crate::diagnostics::{verdict_with_thresholds, Thresholds};
+use crate::draws::Draws;
+use crate::pipeline::{DiagnosticOutput, Pipeline};
+use crate::stats::{acf_at, hdi};
+use crate::summary::{check_intervals, summarize, Summary, DEFAULT_QUANTILES};
+use crate::utils::{flatten, quantile_sorted, ranks};
+use crate::{Array1, Array2};
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use anyhow::{Error, Result};
+use std::fmt::Write;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// Colors of the chains in the plots, reused when there are more chains.
+const PALETTE: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b",
+];
+/// Size of every plot in pixels.
+const WIDTH: f64 = 360.0;
+const HEIGHT: f64 = 160.0;
+/// Longest trace drawn per chain before thinning it for the plot.
+const MAX_TRACE_POINTS: usize = 1000;
+/// Number of bins of the rank histograms.
+const RANK_BINS: usize = 20;
+/// Largest lag of the autocorrelation plots.
+const MAX_LAG: usize = 40;
+
+/// Options of the HTML report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlOptions {
+    /// Title of the page
+    pub title: String,
+    /// Number of parameters, those with the largest R hat, that get trace,
+    /// rank and autocorrelation plots
+    pub num_plots: usize,
+    /// Limits beyond which the draws get a warning
+    pub thresholds: Thresholds,
+    /// Probabilities of the quantiles in the summary table, each in [0, 1]
+    pub quantiles: Vec<f64>,
+    /// Probability mass of the highest density interval in the summary
+    /// table, in (0, 1), or `None` for no interval
+    pub hdi_mass: Option<f64>,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> HtmlOptions {
+        HtmlOptions {
+            title: "MCMC diagnostics".to_string(),
+            num_plots: 5,
+            thresholds: Thresholds::default(),
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+            hdi_mass: None,
+        }
+    }
+}
+
+/// Renders a self-contained HTML page with the posterior summary of every
+/// parameter, the warnings of
+/// [`verdict_with_thresholds`](../diagnostics/fn.verdict_with_thresholds.html), and
+/// inline SVG trace, rank histogram and autocorrelation plots of the worst
+/// parameters.  The page has no external scripts or stylesheets, so it can be
+/// shared as a single file.
+///
+/// # Arguments
+/// * `draws` - Draws to report on; tempered chains are left out
+/// * `options` - Title, number of plots, warning thresholds, quantiles and
+///   interval mass
+pub fn to_html(draws: &Draws, options: &HtmlOptions) -> Result<String, Error> {
+    to_html_with_pipeline(draws, options, &Pipeline::new())
+}
+
+/// Renders the HTML page of [`to_html`](fn.to_html.html) with the results
+/// of a diagnostics pipeline: per parameter diagnostics as more columns of
+/// the summary table, and the others in a list below it.
+///
+/// # Arguments
+/// * `draws` - Draws to report on; tempered chains are left out
+/// * `options` - Title, number of plots, warning thresholds, quantiles and
+///   interval mass
+/// * `pipeline` - Diagnostics to run on the draws
+pub fn to_html_with_pipeline(
+    draws: &Draws,
+    options: &HtmlOptions,
+    pipeline: &Pipeline,
+) -> Result<String, Error> {
+    check_intervals(&options.quantiles, options.hdi_mass)?;
+    let mut warnings = match verdict_with_thresholds(draws, &options.thresholds) {
+        Ok(verdict) => verdict.evidence().iter().map(|e| e.to_string()).collect(),
+        Err(err) => vec![format!("Convergence could not be checked: {:#}", err)],
+    };
+    let diagnostics = match pipeline.run(draws) {
+        Ok(report) => report.results().to_vec(),
+        Err(err) => {
+            warnings.push(format!("Diagnostics could not be computed: {:#}", err));
+            Vec::new()
+        }
+    };
+    let mut rows = Vec::new();
+    let mut intervals = Vec::new();
+    for (idx, name) in draws.names().iter().enumerate() {
+        let chains = draws.target_parameter(idx);
+        match summarize(&chains).and_then(|summary| Ok((summary, intervals_of(&chains, options)?)))
+        {
+            Ok((summary, interval)) => {
+                rows.push((idx, summary));
+                intervals.push(interval);
+            }
+            Err(err) => warnings.push(format!("{}: could not be summarized: {:#}", name, err)),
+        }
+    }
+
+    let mut html = String::new();
+    let title = escape(&options.title);
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>",
+        title
+    )?;
+    html.push_str(
+        "<style>body{font-family:sans-serif;margin:2em}\
+         table{border-collapse:collapse}td,th{padding:2px 8px;text-align:right}\
+         td:first-child,th:first-child{text-align:left}tr:nth-child(even){background:#f4f4f4}\
+         .warn{color:#b00}svg{border:1px solid #ddd;margin:4px}</style>\n</head>\n<body>\n",
+    );
+    writeln!(html, "<h1>{}</h1>", title)?;
+    writeln!(
+        html,
+        "<p>{} parameters, {} chains, {} draws per chain</p>",
+        draws.num_parameters(),
+        draws.target_chains().len(),
+        draws.num_draws()
+    )?;
+
+    html.push_str("<h2>Warnings</h2>\n");
+    if warnings.is_empty() {
+        html.push_str("<p>None</p>\n");
+    } else {
+        html.push_str("<ul class=\"warn\">\n");
+        for warning in warnings.iter() {
+            writeln!(html, "<li>{}</li>", escape(warning))?;
+        }
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str(
+        "<h2>Summary</h2>\n<table>\n<tr><th>parameter</th><th>mean</th><th>mcse</th><th>sd</th>",
+    );
+    for &p in options.quantiles.iter() {
+        write!(html, "<th>{}</th>", percent(p))?;
+    }
+    if let Some(mass) = options.hdi_mass {
+        write!(html, "<th>{} HDI</th>", percent(mass))?;
+    }
+    html.push_str("<th>ess</th><th>R hat</th>");
+    for (name, output) in diagnostics.iter() {
+        if let DiagnosticOutput::PerParameter(_) = output {
+            write!(html, "<th>{}</th>", escape(name))?;
+        }
+    }
+    html.push_str("</tr>\n");
+    for ((idx, s), (quantiles, hdi)) in rows.iter().zip(intervals.iter()) {
+        write!(
+            html,
+            "<tr><td>{}</td><td>{:.4}</td><td>{:.4}</td><td>{:.4}</td>",
+            escape(&draws.names()[*idx]),
+            s.mean,
+            s.mcse,
+            s.sd
+        )?;
+        for q in quantiles.iter() {
+            write!(html, "<td>{:.4}</td>", q)?;
+        }
+        if let Some((lower, upper)) = hdi {
+            write!(html, "<td>[{:.4}, {:.4}]</td>", lower, upper)?;
+        }
+        write!(html, "<td>{:.0}</td><td>{:.3}</td>", s.ess, s.rhat)?;
+        for (_, output) in diagnostics.iter() {
+            if let DiagnosticOutput::PerParameter(values) = output {
+                write!(html, "<td>{:.4}</td>", values[*idx])?;
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    let scalars: Vec<String> = diagnostics
+        .iter()
+        .filter_map(|(name, output)| match output {
+            DiagnosticOutput::Scalar(value) => Some(format!("{}: {:.4}", escape(name), value)),
+            DiagnosticOutput::PerParameter(_) => None,
+        })
+        .collect();
+    if !scalars.is_empty() {
+        html.push_str("<h2>Diagnostics</h2>\n<ul>\n");
+        for scalar in scalars.iter() {
+            writeln!(html, "<li>{}</li>", scalar)?;
+        }
+        html.push_str("</ul>\n");
+    }
+
+    if let Ok(histogram) = tree_depth_histogram(draws) {
+        html.push_str("<h2>Sampler</h2>\n");
+        html.push_str(&tree_depth_svg(&histogram));
+        let steps: Vec<String> = histogram
+            .mean_leapfrog_steps
+            .iter()
+            .enumerate()
+            .map(|(c, n)| format!("chain {}: {:.1}", escape(&draws.target_chain_name(c)), n))
+            .collect();
+        writeln!(
+            html,
+            "<p>Mean leapfrog steps per draw: {}</p>",
+            steps.join(", ")
+        )?;
+    }
+
+    let mut worst: Vec<&(usize, Summary)> = rows.iter().collect();
+    // NaN R hat sorts first, as the most suspicious
+    worst.sort_by(|a, b| {
+        b.1.rhat
+            .partial_cmp(&a.1.rhat)
+            .unwrap_or_else(|| b.1.rhat.is_nan().cmp(&a.1.rhat.is_nan()))
+    });
+    if options.num_plots > 0 && !worst.is_empty() {
+        html.push_str("<h2>Parameters with the largest R hat</h2>\n");
+    }
+    for (idx, _) in worst.into_iter().take(options.num_plots) {
+        let chains = draws.target_parameter(*idx);
+        writeln!(html, "<h3>{}</h3>", escape(&draws.names()[*idx]))?;
+        html.push_str(&trace_svg(&chains));
+        html.push_str(&rank_svg(&chains));
+        html.push_str(&acf_svg(&chains));
+        html.push('\n');
+    }
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Quantiles and highest density interval of a parameter for the summary
+/// table.
+fn intervals_of(
+    chains: &Array2,
+    options: &HtmlOptions,
+) -> Result<(Array1, Option<(f64, f64)>), Error> {
+    let mut sorted = flatten(chains);
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let quantiles = options
+        .quantiles
+        .iter()
+        .map(|&p| quantile_sorted(&sorted, p))
+        .collect::<Result<Array1, Error>>()?;
+    let interval = match options.hdi_mass {
+        Some(mass) => Some(hdi(&sorted, mass)?),
+        None => None,
+    };
+    Ok((quantiles, interval))
+}
+
+/// Formats a probability as a percentage without trailing zeros, e.g. 5%
+/// or 2.5%.
+fn percent(p: f64) -> String {
+    let formatted = format!("{:.2}", 100.0 * p);
+    format!("{}%", formatted.trim_end_matches('0').trim_end_matches('.'))
+}
+
+/// Writes the report of [`to_html`](fn.to_html.html) to a file.
+///
+/// # Arguments
+/// * `draws` - Draws to report on
+/// * `path` - File to create or overwrite
+/// * `options` - Title, number of plots and warning thresholds
+#[cfg(feature = "fs")]
+pub fn to_html_file<P: AsRef<Path>>(
+    draws: &Draws,
+    path: P,
+    options: &HtmlOptions,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let html = to_html(draws, options)?;
+    std::fs::write(path, html).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Escapes text for use in HTML content.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Opens an SVG element with a title line.
+fn svg_start(title: &str) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\"><text x=\"4\" y=\"12\" font-size=\"11\">{t}</text>",
+        w = WIDTH,
+        h = HEIGHT,
+        t = title
+    )
+}
+
+/// Maps a value in `lo..hi` to a y coordinate, leaving room for the title.
+fn to_y(value: f64, lo: f64, hi: f64) -> f64 {
+    let range = if hi > lo { hi - lo } else { 1.0 };
+    HEIGHT - 4.0 - (value - lo) / range * (HEIGHT - 20.0)
+}
+
+/// Draws a polyline through the points.
+fn polyline(points: &[(f64, f64)], color: &str) -> String {
+    let coords: Vec<String> = points
+        .iter()
+        .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+        .collect();
+    format!(
+        "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1\" points=\"{}\"/>",
+        color,
+        coords.join(" ")
+    )
+}
+
+/// Trace plot of every chain, thinned to a bounded number of points.
+fn trace_svg(chains: &Array2) -> String {
+    let flat = flatten(chains);
+    let lo = flat.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = flat.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let mut svg = svg_start("trace");
+    for (c, chain) in chains.iter().enumerate() {
+        let step = chain.len().div_ceil(MAX_TRACE_POINTS).max(1);
+        let scale = WIDTH / chain.len().max(2) as f64;
+        let points: Vec<(f64, f64)> = chain
+            .iter()
+            .enumerate()
+            .step_by(step)
+            .map(|(i, &x)| (i as f64 * scale, to_y(x, lo, hi)))
+            .collect();
+        svg.push_str(&polyline(&points, PALETTE[c % PALETTE.len()]));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Histogram of the ranks of each chain's draws among all draws, drawn as
+/// one step line per chain.  Lines far from the dashed uniform level mean the
+/// chains have not mixed.
+fn rank_svg(chains: &Array2) -> String {
+    let all_ranks = ranks(&flatten(chains));
+    let total = all_ranks.len() as f64;
+    let mut counts = vec![vec![0usize; RANK_BINS]; chains.len()];
+    let mut offset = 0;
+    for (c, chain) in chains.iter().enumerate() {
+        for rank in all_ranks[offset..offset + chain.len()].iter() {
+            let bin = (((rank - 1.0) / total) * RANK_BINS as f64) as usize;
+            counts[c][bin.min(RANK_BINS - 1)] += 1;
+        }
+        offset += chain.len();
+    }
+    let max = counts.iter().flatten().cloned().max().unwrap_or(1) as f64;
+    let bin_width = WIDTH / RANK_BINS as f64;
+    let mut svg = svg_start("rank histogram");
+    for (c, chain_counts) in counts.iter().enumerate() {
+        let mut points = Vec::with_capacity(2 * RANK_BINS);
+        for (b, &count) in chain_counts.iter().enumerate() {
+            let y = to_y(count as f64, 0.0, max);
+            points.push((b as f64 * bin_width, y));
+            points.push(((b + 1) as f64 * bin_width, y));
+        }
+        svg.push_str(&polyline(&points, PALETTE[c % PALETTE.len()]));
+    }
+    if let Some(chain) = chains.first() {
+        let expected = to_y(chain.len() as f64 / RANK_BINS as f64, 0.0, max);
+        write!(
+            svg,
+            "<line x1=\"0\" x2=\"{}\" y1=\"{:.1}\" y2=\"{:.1}\" stroke=\"#888\" \
+             stroke-dasharray=\"4 3\"/>",
+            WIDTH, expected, expected
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Autocorrelation of each chain up to a fixed lag.  Chains without any
+/// variation are left out.
+fn acf_svg(chains: &Array2) -> String {
+    let mut svg = svg_start("autocorrelation");
+    let zero = to_y(0.0, -1.0, 1.0);
+    write!(
+        svg,
+        "<line x1=\"0\" x2=\"{}\" y1=\"{:.1}\" y2=\"{:.1}\" stroke=\"#888\"/>",
+        WIDTH, zero, zero
+    )
+    .unwrap();
+    for (c, chain) in chains.iter().enumerate() {
+        let lags: Vec<usize> = (0..=MAX_LAG.min(chain.len().saturating_sub(1))).collect();
+        if let Ok(acf) = acf_at(chain, &lags) {
+            let scale = WIDTH / MAX_LAG as f64;
+            let points: Vec<(f64, f64)> = acf
+                .iter()
+                .enumerate()
+                .map(|(lag, &r)| (lag as f64 * scale, to_y(r, -1.0, 1.0)))
+                .collect();
+            svg.push_str(&polyline(&points, PALETTE[c % PALETTE.len()]));
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Bar chart of the tree depths, with the bars of the chains side by side.
+fn tree_depth_svg(histogram: &TreeDepthHistogram) -> String {
+    let max = histogram
+        .counts
+        .iter()
+        .flatten()
+        .cloned()
+        .max()
+        .unwrap_or(1) as f64;
+    let group_width = WIDTH / histogram.depths.len().max(1) as f64;
+    let bar_width = group_width * 0.8 / histogram.counts.len().max(1) as f64;
+    let mut svg = svg_start("tree depth");
+    for (c, chain_counts) in histogram.counts.iter().enumerate() {
+        for (depth, &count) in chain_counts.iter().enumerate() {
+            let y = to_y(count as f64, 0.0, max);
+            write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+                depth as f64 * group_width + 0.1 * group_width + c as f64 * bar_width,
+                y,
+                bar_width,
+                HEIGHT - 4.0 - y,
+                PALETTE[c % PALETTE.len()]
+            )
+            .unwrap();
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    fn draws() -> Draws {
+        // b<1> is shifted in the second chain, so it has a large R hat
+        let names = vec!["a".to_string(), "b<1>".to_string(), "c".to_string()];
+        let chains = (0..2)
+            .map(|c| {
+                vec![
+                    normal_draws(500, 3 * c),
+                    normal_draws(500, 3 * c + 1)
+                        .iter()
+                        .map(|x| x + 3.0 * c as f64)
+                        .collect(),
+                    vec![1.0; 500],
+                ]
+            })
+            .collect();
+        Draws::from_chains(names, chains).unwrap()
+    }
+
+    #[test]
+    fn test_to_html() {
+        let options = HtmlOptions {
+            num_plots: 1,
+            ..HtmlOptions::default()
+        };
+        let html = to_html(&draws(), &options).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.ends_with("</html>\n"));
+        // summary rows for a and b<1>, while the constant c only gets a warning
+        assert_eq!(html.matches("<tr><td>").count(), 2);
+        assert!(html.contains("<li>b&lt;1&gt;: rank normalized split R hat"));
+        assert!(html.contains("<li>c: could not be summarized"));
+        assert!(!html.contains("<li>a:"));
+        // plots only for b<1>, which has the largest R hat
+        assert_eq!(html.matches("<svg").count(), 3);
+        assert!(html.contains("<h3>b&lt;1&gt;</h3>"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn test_to_html_intervals() {
+        let html = to_html(&draws(), &HtmlOptions::default()).unwrap();
+        assert!(html.contains("<th>sd</th><th>5%</th><th>50%</th><th>95%</th><th>ess</th>"));
+
+        let options = HtmlOptions {
+            quantiles: vec![0.025, 0.975],
+            hdi_mass: Some(0.89),
+            ..HtmlOptions::default()
+        };
+        let html = to_html(&draws(), &options).unwrap();
+        assert!(html.contains("<th>sd</th><th>2.5%</th><th>97.5%</th><th>89% HDI</th><th>ess</th>"));
+        assert_eq!(html.matches("<td>[").count(), 2);
+
+        let options = HtmlOptions {
+            quantiles: vec![-0.1],
+            ..HtmlOptions::default()
+        };
+        assert!(to_html(&draws(), &options).is_err());
+    }
+
+    #[test]
+    fn test_to_html_with_pipeline() {
+        struct Largest;
+
+        impl crate::pipeline::Diagnostic for Largest {
+            fn name(&self) -> &str {
+                "largest"
+            }
+
+            fn compute(&self, draws: &Draws) -> Result<DiagnosticOutput, Error> {
+                crate::pipeline::per_parameter(draws, |chains| {
+                    Ok(chains.iter().flatten().fold(f64::MIN, |m, &x| m.max(x)))
+                })
+            }
+        }
+
+        let mut pipeline = Pipeline::new();
+        pipeline.add(Largest).unwrap();
+        let html = to_html_with_pipeline(&draws(), &HtmlOptions::default(), &pipeline).unwrap();
+        assert!(html.contains("<th>R hat</th><th>largest</th></tr>"));
+        assert_eq!(html.matches("<tr><td>").count(), 2);
+        assert!(!html.contains("<h2>Diagnostics</h2>"));
+        assert_eq!(
+            to_html(&draws(), &HtmlOptions::default()).unwrap(),
+            to_html_with_pipeline(&draws(), &HtmlOptions::default(), &Pipeline::new()).unwrap()
+        );
+
+        // the multivariate R hat of the constant c fails, which is a warning
+        pipeline.add(crate::pipeline::MultivariateRhat).unwrap();
+        let html = to_html_with_pipeline(&draws(), &HtmlOptions::default(), &pipeline).unwrap();
+        assert!(html.contains("<li>Diagnostics could not be computed: Diagnostic mpsrf failed"));
+        assert!(!html.contains("<th>largest</th>"));
+
+        let full = draws();
+        let (a, b) = (full.parameter(0), full.parameter(1));
+        let chains = (0..2).map(|c| vec![a[c].clone(), b[c].clone()]).collect();
+        let draws = Draws::from_chains(full.names()[..2].to_vec(), chains).unwrap();
+        let html = to_html_with_pipeline(&draws, &HtmlOptions::default(), &pipeline).unwrap();
+        assert!(html.contains("<h2>Diagnostics</h2>\n<ul>\n<li>mpsrf: "));
+    }
+
+    #[test]
+    fn test_to_html_tree_depth() {
+        let mut draws = draws();
+        draws
+            .add_parameter("treedepth__", vec![vec![2.0; 500], vec![3.0; 500]])
+            .unwrap();
+        let html = to_html(&draws, &HtmlOptions::default()).unwrap();
+        assert!(html.contains("<h2>Sampler</h2>"));
+        assert_eq!(html.matches("<rect").count(), 8);
+        assert!(html.contains("Mean leapfrog steps per draw: chain 1: 3.0, chain 2: 7.0"));
+        assert!(!to_html(&self::draws(), &HtmlOptions::default())
+            .unwrap()
+            .contains("<h2>Sampler</h2>"));
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_to_html_file() {
+        let path = std::env::temp_dir().join(format!("mcmc-report-{}.html", std::process::id()));
+        to_html_file(&draws(), &path, &HtmlOptions::default()).unwrap();
+        let html = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(html, to_html(&draws(), &HtmlOptions::default()).unwrap());
+        // two parameters could be summarized, so both get plots
+        assert_eq!(html.matches("<svg").count(), 6);
+    }
+}
@@ -1,6 +1,8 @@
-use crate::utils::{mean, sample_variance, split_chains};
+use crate::draws::Draws;
+use crate::ess::rank_normalize;
+use crate::utils::{mean, quantile_sorted, sample_variance, split_slices};
 use crate::{Array1, Array2};
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 
 /// Computes the potential scale reduction (Rhat) for the specified
 /// parameter across all kept samples.  Chains are trimmed from the
@@ -14,8 +16,322 @@ use anyhow::{Error, Result};
 ///
 /// # Arguments
 /// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
-///              the same parameter
+///   the same parameter
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_chains = chains.len()))
+)]
 pub fn potential_scale_reduction_factor(chains: &Array2) -> Result<f64, Error> {
+    let chains: Vec<&[f64]> = chains.iter().map(|c| c.as_slice()).collect();
+    potential_scale_reduction(&chains)
+}
+
+/// Shared implementation of the plain and split potential scale reduction
+/// factor on borrowed chains.
+fn potential_scale_reduction(chains: &[&[f64]]) -> Result<f64, Error> {
+    let v = variance_components(chains)?;
+    let n = v.num_draws as f64;
+    Ok(((v.between / v.within + n - 1.0) / n).sqrt())
+}
+
+/// Split potential scale reduction factor over one window of iterations,
+/// see [`local_potential_scale_reduction_factor`](fn.local_potential_scale_reduction_factor.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalRhat {
+    /// Index of the first draw in the window
+    pub start: usize,
+    /// Index one past the last draw in the window
+    pub end: usize,
+    /// Split R hat of the draws in the window
+    pub rhat: f64,
+}
+
+/// Computes the split potential scale reduction factor separately over
+/// consecutive windows of iterations, e.g. quarters of the chains with
+/// `window = num_draws / 4`.  A full-chain R hat close to one can hide chains
+/// that only agree on average, such as one that drifts early and
+/// recovers; the per-window series shows where they disagree.
+///
+/// Chains are trimmed to the length of the shortest chain and a last window
+/// shorter than `window` is left out.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `window` - Number of draws per window, at least 4 so each half of a
+///   split window has a variance
+pub fn local_potential_scale_reduction_factor(
+    chains: &Array2,
+    window: usize,
+) -> Result<Vec<LocalRhat>, Error> {
+    if window < 4 {
+        return Err(anyhow!("Window must have at least 4 draws, got {}", window));
+    }
+    let num_draws = chains.iter().map(|c| c.len()).min().unwrap_or(0);
+    if num_draws < window {
+        return Err(anyhow!(
+            "Chains have {} draws, fewer than one window of {}",
+            num_draws,
+            window
+        ));
+    }
+    (0..num_draws / window)
+        .map(|w| {
+            let (start, end) = (w * window, (w + 1) * window);
+            let mut split = Vec::with_capacity(2 * chains.len());
+            for chain in chains.iter() {
+                let (first, second) = chain[start..end].split_at(window / 2);
+                split.push(first);
+                // drop the middle draw of odd windows like split_slices
+                split.push(&second[window % 2..]);
+            }
+            Ok(LocalRhat {
+                start,
+                end,
+                rhat: potential_scale_reduction(&split)?,
+            })
+        })
+        .collect()
+}
+
+/// Computes the multivariate potential scale reduction factor of Brooks and
+/// Gelman (1998) for several parameters jointly, the value of
+/// `gelman.diag(...)$mpsrf` in the R package coda.  It bounds the
+/// univariate R hat of every linear combination of the parameters, so it
+/// catches chains that agree on each margin but not on the joint
+/// distribution.  The cost is cubic in the number of parameters, so it is
+/// meant for blocks of related parameters rather than a whole model, see
+/// [`multivariate_potential_scale_reduction_factor_subset`](fn.multivariate_potential_scale_reduction_factor_subset.html).
+///
+/// With within-chain covariance `W` and between-chain covariance `B / N` of
+/// the chain means, the result is
+/// `sqrt((N - 1) / N + (M + 1) / M * lambda)` where `lambda` is the largest
+/// eigenvalue of `W^-1 B / N`.  Chains are trimmed to the length of the
+/// shortest chain.
+///
+/// # Arguments
+/// * `parameters` - One vector of chains per parameter, all with the same
+///   number of chains
+pub fn multivariate_potential_scale_reduction_factor(parameters: &[Array2]) -> Result<f64, Error> {
+    let p = parameters.len();
+    if p == 0 {
+        return Err(anyhow!("Need at least one parameter"));
+    }
+    let m = parameters[0].len();
+    if m < 2 || parameters.iter().any(|chains| chains.len() != m) {
+        return Err(anyhow!(
+            "Every parameter needs the same number of chains, at least 2"
+        ));
+    }
+    let n = parameters
+        .iter()
+        .flat_map(|chains| chains.iter().map(|c| c.len()))
+        .min()
+        .unwrap();
+    if n < 2 {
+        return Err(anyhow!("Need at least 2 draws per chain"));
+    }
+
+    // chain_means[chain][parameter]
+    let mut chain_means = vec![vec![0.0; p]; m];
+    for (i, chains) in parameters.iter().enumerate() {
+        for (c, chain) in chains.iter().enumerate() {
+            chain_means[c][i] = mean(&chain[..n])?;
+        }
+    }
+    let mut within = vec![vec![0.0; p]; p];
+    for (c, means) in chain_means.iter().enumerate() {
+        for i in 0..p {
+            let xi = &parameters[i][c][..n];
+            for j in 0..=i {
+                let xj = &parameters[j][c][..n];
+                let cov: f64 = xi
+                    .iter()
+                    .zip(xj)
+                    .map(|(a, b)| (a - means[i]) * (b - means[j]))
+                    .sum();
+                within[i][j] += cov / (n - 1) as f64 / m as f64;
+            }
+        }
+    }
+    let grand: Array1 = (0..p)
+        .map(|i| chain_means.iter().map(|means| means[i]).sum::<f64>() / m as f64)
+        .collect();
+    let mut between = vec![vec![0.0; p]; p];
+    for means in chain_means.iter() {
+        for i in 0..p {
+            for j in 0..=i {
+                between[i][j] += (means[i] - grand[i]) * (means[j] - grand[j]) / (m - 1) as f64;
+            }
+        }
+    }
+    for i in 0..p {
+        for j in 0..i {
+            within[j][i] = within[i][j];
+            between[j][i] = between[i][j];
+        }
+    }
+
+    // lambda is the largest eigenvalue of the symmetric L^-1 B L^-T, where
+    // W = L L^T
+    let l = cholesky(&within).ok_or_else(|| {
+        anyhow!("Within-chain covariance is singular, e.g. a parameter is constant")
+    })?;
+    let half: Array2 = between.iter().map(|row| forward_solve(&l, row)).collect();
+    let columns: Array2 = (0..p)
+        .map(|j| {
+            let column: Array1 = half.iter().map(|row| row[j]).collect();
+            forward_solve(&l, &column)
+        })
+        .collect();
+    let lambda = largest_eigenvalue(columns);
+    let n = n as f64;
+    let m = m as f64;
+    Ok(((n - 1.0) / n + (m + 1.0) / m * lambda).sqrt())
+}
+
+/// Computes the multivariate potential scale reduction factor over the
+/// parameters whose names match any of the patterns, see
+/// [`Draws::select`](../draws/struct.Draws.html#method.select) for the
+/// pattern syntax and
+/// [`multivariate_potential_scale_reduction_factor`](fn.multivariate_potential_scale_reduction_factor.html)
+/// for the estimate.
+///
+/// # Arguments
+/// * `draws` - Draws of all the parameters
+/// * `patterns` - Names or wildcard patterns such as `theta[*]`
+pub fn multivariate_potential_scale_reduction_factor_subset(
+    draws: &Draws,
+    patterns: &[&str],
+) -> Result<f64, Error> {
+    let selected = draws.select(patterns);
+    if selected.is_empty() {
+        return Err(anyhow!("No parameters match {:?}", patterns));
+    }
+    let parameters: Vec<Array2> = selected
+        .into_iter()
+        .map(|idx| draws.target_parameter(idx).into_owned())
+        .collect();
+    multivariate_potential_scale_reduction_factor(&parameters)
+}
+
+/// Lower triangular Cholesky factor of a symmetric matrix, or `None` if it
+/// is not positive definite.
+pub(crate) fn cholesky(a: &Array2) -> Option<Array2> {
+    let p = a.len();
+    let mut l = vec![vec![0.0; p]; p];
+    for i in 0..p {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            if i == j {
+                let d = a[i][i] - sum;
+                if d <= 0.0 || !d.is_finite() {
+                    return None;
+                }
+                l[i][i] = d.sqrt();
+            } else {
+                l[i][j] = (a[i][j] - sum) / l[j][j];
+            }
+        }
+    }
+    Some(l)
+}
+
+/// Solves `L x = b` for lower triangular `L`.
+pub(crate) fn forward_solve(l: &Array2, b: &[f64]) -> Array1 {
+    let mut x = vec![0.0; b.len()];
+    for i in 0..b.len() {
+        let sum: f64 = (0..i).map(|k| l[i][k] * x[k]).sum();
+        x[i] = (b[i] - sum) / l[i][i];
+    }
+    x
+}
+
+/// Largest eigenvalue of a symmetric matrix with the cyclic Jacobi method.
+fn largest_eigenvalue(mut a: Array2) -> f64 {
+    let p = a.len();
+    for _ in 0..100 {
+        let off: f64 = (0..p)
+            .flat_map(|i| (0..p).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        let scale: f64 = (0..p).map(|i| a[i][i] * a[i][i]).sum();
+        if off <= 1e-24 * scale.max(f64::MIN_POSITIVE) {
+            break;
+        }
+        for i in 0..p {
+            for j in (i + 1)..p {
+                if a[i][j] == 0.0 {
+                    continue;
+                }
+                let theta = (a[j][j] - a[i][i]) / (2.0 * a[i][j]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (aki, akj) = (row[i], row[j]);
+                    row[i] = c * aki - s * akj;
+                    row[j] = s * aki + c * akj;
+                }
+                let (top, bottom) = a.split_at_mut(j);
+                for (aik, ajk) in top[i].iter_mut().zip(bottom[0].iter_mut()) {
+                    let (x, y) = (*aik, *ajk);
+                    *aik = c * x - s * y;
+                    *ajk = s * x + c * y;
+                }
+            }
+        }
+    }
+    (0..p).map(|i| a[i][i]).fold(f64::NEG_INFINITY, f64::max)
+}
+
+/// Building blocks of the potential scale reduction factor for one
+/// parameter, for implementing variants of R hat on top of the same
+/// estimates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VarianceComponents {
+    /// Number of draws per chain, N
+    pub num_draws: usize,
+    /// Number of chains, M
+    pub num_chains: usize,
+    /// Within-chain variance W, the mean of the chain variances
+    pub within: f64,
+    /// Between-chain variance B, N times the variance of the chain means
+    pub between: f64,
+    /// Marginal posterior variance estimate var+ = (N - 1) / N W + B / N,
+    /// so that R hat is the square root of var+ / W
+    pub var_plus: f64,
+}
+
+/// Computes the within-chain and between-chain variances and the marginal
+/// posterior variance estimate used by
+/// [`potential_scale_reduction_factor`](fn.potential_scale_reduction_factor.html).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn variance_decomposition(chains: &Array2) -> Result<VarianceComponents, Error> {
+    let chains: Vec<&[f64]> = chains.iter().map(|c| c.as_slice()).collect();
+    variance_components(&chains)
+}
+
+/// Computes the variance components of the split chains, as used by
+/// [`split_potential_scale_reduction_factor`](fn.split_potential_scale_reduction_factor.html).
+/// The number of chains of the result counts each half separately.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn split_variance_decomposition(chains: &Array2) -> Result<VarianceComponents, Error> {
+    let split = split_slices(chains)?;
+    variance_components(&split)
+}
+
+fn variance_components(chains: &[&[f64]]) -> Result<VarianceComponents, Error> {
+    if chains.is_empty() {
+        return Err(anyhow!("Need at least one chain"));
+    }
     let m = chains.len();
     let n = chains.iter().map(|c| c.len()).min().unwrap();
     let mut split_chain_mean: Array1 = Vec::new();
@@ -28,12 +344,17 @@ pub fn potential_scale_reduction_factor(chains: &Array2) -> Result<f64, Error> {
         split_chain_var.push(chain_var);
     }
 
+    let num_draws = n;
     let n = n as f64;
     let var_between = n * sample_variance(&split_chain_mean)?;
     let var_within = mean(&split_chain_var)?;
-    let result = ((var_between / var_within + n - 1.0) / n).sqrt();
-
-    Ok(result)
+    Ok(VarianceComponents {
+        num_draws,
+        num_chains: m,
+        within: var_within,
+        between: var_between,
+        var_plus: (n - 1.0) / n * var_within + var_between / n,
+    })
 }
 
 /// Computes the split potential scale reduction (Rhat) for the
@@ -52,22 +373,170 @@ pub fn potential_scale_reduction_factor(chains: &Array2) -> Result<f64, Error> {
 ///
 /// # Arguments
 /// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
-///              the same parameter
+///   the same parameter
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_chains = chains.len()))
+)]
 pub fn split_potential_scale_reduction_factor(chains: &Array2) -> Result<f64, Error> {
-    let num_draws = chains.iter().map(|c| c.len()).min().unwrap();
-    // trim chains to the length of the shortest chain
-    let mut trimmed = Vec::new();
+    let split = split_slices(chains)?;
+    potential_scale_reduction(&split)
+}
+
+/// Computes the split potential scale reduction (Rhat) for the scale of the
+/// specified parameter, i.e. the split Rhat of the squared deviations of each
+/// chain from its own mean.  Chains that agree on location but not on spread
+/// have a plain Rhat close to one but a large scale Rhat.
+///
+/// Chains are trimmed from the back to match the length of the shortest
+/// chain.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn split_sd_potential_scale_reduction_factor(chains: &Array2) -> Result<f64, Error> {
+    let num_draws = chains.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut squared: Array2 = Vec::with_capacity(chains.len());
     for chain in chains.iter() {
-        trimmed.push(chain[..num_draws].to_vec());
+        let chain = &chain[..num_draws];
+        let chain_mean = mean(chain)?;
+        squared.push(chain.iter().map(|x| (x - chain_mean).powi(2)).collect());
+    }
+    split_potential_scale_reduction_factor(&squared)
+}
+
+/// Computes the rank normalized split potential scale reduction factor of
+/// the specified parameter, the larger of the bulk R hat, i.e. the split
+/// R hat of the normal scores of the ranks of the draws over all chains,
+/// and the tail R hat, the same of the draws folded around their median.
+/// Unlike the classic split R hat it is well defined for heavy tailed
+/// distributions and catches chains that differ in scale rather than in
+/// location.  When the folded draws are all the same, as for a parameter
+/// taking two values symmetric about its median, the bulk R hat is returned.
+///
+/// See Vehtari et al. (2021)
+/// ["Rank-normalization, folding, and localization"](https://doi.org/10.1214/20-BA1221),
+/// as implemented by `rhat` in the R package posterior.
+///
+/// Chains are trimmed from the back to match the length of the shortest
+/// chain.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn rank_normalized_split_potential_scale_reduction_factor(
+    chains: &Array2,
+) -> Result<f64, Error> {
+    let split = split_slices(chains)?;
+    let half = split[0].len();
+    if half == 0 {
+        return Err(anyhow!("Need at least 2 draws per chain to compute R hat"));
+    }
+    // the median is of all draws, including the middle one the split
+    // leaves out when the number of draws is odd
+    let num_draws = chains.iter().map(|c| c.len()).min().unwrap();
+    let mut sorted: Array1 = chains
+        .iter()
+        .flat_map(|c| &c[..num_draws])
+        .cloned()
+        .collect();
+    if sorted.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to compute R hat"));
+    }
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = quantile_sorted(&sorted, 0.5)?;
+    let pooled = split.concat();
+    let (z, _) = rank_normalize(&pooled)?;
+    let bulk = potential_scale_reduction(&z.chunks(half).collect::<Vec<_>>())?;
+    let folded: Array1 = pooled.iter().map(|x| (x - median).abs()).collect();
+    let (z, _) = rank_normalize(&folded)?;
+    let tail = potential_scale_reduction(&z.chunks(half).collect::<Vec<_>>())?;
+    // f64::max ignores the NaN of constant folded draws
+    Ok(bulk.max(tail))
+}
+
+/// Split R hat with one chain left out, see
+/// [`leave_one_chain_out_rhat`](fn.leave_one_chain_out_rhat.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainInfluence {
+    /// Index of the chain left out
+    pub chain: usize,
+    /// Split R hat of the other chains
+    pub rhat: f64,
+    /// Change of split R hat from leaving the chain out, negative when the
+    /// other chains agree better without it
+    pub change: f64,
+}
+
+/// Split R hat of all chains and the influence of every chain on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaveOneChainOut {
+    /// Split R hat of all chains
+    pub rhat: f64,
+    /// Split R hat without each chain, in the original order
+    pub chains: Vec<ChainInfluence>,
+}
+
+impl LeaveOneChainOut {
+    /// The chain whose removal lowers R hat the most, the likely culprit of
+    /// an elevated R hat.  Chains whose change is NaN, e.g. because the
+    /// remaining chains are constant, are not considered.
+    pub fn most_influential(&self) -> Option<ChainInfluence> {
+        self.chains
+            .iter()
+            .copied()
+            .filter(|c| !c.change.is_nan())
+            .min_by(|a, b| a.change.total_cmp(&b.change))
     }
-    let split = split_chains(trimmed)?;
-    potential_scale_reduction_factor(&split)
+}
+
+/// Recomputes the split potential scale reduction factor with each chain
+/// left out in turn.  When R hat is elevated because a single chain is stuck
+/// somewhere else, leaving that chain out brings R hat back close to one
+/// while leaving out any other chain barely changes it, which identifies the
+/// culprit directly; when all R hats stay high, the chains disagree more
+/// broadly.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter, at least 2 of them
+pub fn leave_one_chain_out_rhat(chains: &Array2) -> Result<LeaveOneChainOut, Error> {
+    if chains.len() < 2 {
+        return Err(anyhow!(
+            "Need at least 2 chains to leave one out, got {}",
+            chains.len()
+        ));
+    }
+    let rhat = split_potential_scale_reduction_factor(chains)?;
+    let influence = (0..chains.len())
+        .map(|left_out| {
+            let rest: Array2 = chains
+                .iter()
+                .enumerate()
+                .filter(|&(idx, _)| idx != left_out)
+                .map(|(_, chain)| chain.clone())
+                .collect();
+            let without = split_potential_scale_reduction_factor(&rest)?;
+            Ok(ChainInfluence {
+                chain: left_out,
+                rhat: without,
+                change: without - rhat,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(LeaveOneChainOut {
+        rhat,
+        chains: influence,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "fs")]
     use crate::utils::read_csv;
+    use crate::utils::split_chains;
+    #[cfg(feature = "fs")]
     use std::path::PathBuf;
 
     #[test]
@@ -105,6 +574,7 @@ mod tests {
         assert_eq!(split[3], vec![8.0, 8.5]);
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_stan_blocker_unit_test_potential_scale_reduction_factor() {
         // Based on the unit test in Stan 2.2.4 but using slightly more precision:
@@ -128,6 +598,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_stan_blocker_unit_test_split_potential_scale_reduction_factor() {
         // Based on the unit test in Stan 2.2.4 but using slightly more precision:
@@ -151,4 +622,181 @@ mod tests {
             assert_abs_diff_eq!(actual, expected, epsilon = 1e-6);
         }
     }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_rank_normalized_split_potential_scale_reduction_factor() {
+        // reference values from rhat() of the R package posterior
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let expected_rhats = [
+            (0, 1.0168275760376753),
+            (4, 1.007802723886028),
+            (5, 1.010901967801023),
+            (6, 1.0073145096613214),
+            (7, 1.0033275277185683),
+            (8, 1.0040088452248528),
+        ];
+        for &(i, expected) in expected_rhats.iter() {
+            let chains = vec![samples1[i].clone(), samples2[i].clone()];
+            let actual = rank_normalized_split_potential_scale_reduction_factor(&chains).unwrap();
+            assert_abs_diff_eq!(actual, expected, epsilon = 1e-8);
+        }
+
+        // the shorter chain trims the other one, leaving an odd number of
+        // draws
+        let chains = vec![samples1[4][..999].to_vec(), samples2[4].clone()];
+        let actual = rank_normalized_split_potential_scale_reduction_factor(&chains).unwrap();
+        assert_abs_diff_eq!(actual, 1.0081640077112115, epsilon = 1e-8);
+
+        assert!(rank_normalized_split_potential_scale_reduction_factor(&vec![vec![1.0]]).is_err());
+        let chains = vec![vec![1.0, f64::NAN, 2.0], vec![0.0, 1.0, 2.0]];
+        assert!(rank_normalized_split_potential_scale_reduction_factor(&chains).is_err());
+    }
+
+    #[test]
+    fn test_variance_decomposition() {
+        let chains = vec![vec![1.0, 2.0, 3.0], vec![3.0, 4.0, 5.0]];
+        let v = variance_decomposition(&chains).unwrap();
+        assert_eq!((v.num_draws, v.num_chains), (3, 2));
+        assert_abs_diff_eq!(v.within, 1.0);
+        assert_abs_diff_eq!(v.between, 6.0);
+        assert_abs_diff_eq!(v.var_plus, 2.0 / 3.0 + 2.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(
+            (v.var_plus / v.within).sqrt(),
+            potential_scale_reduction_factor(&chains).unwrap(),
+            epsilon = 1e-12
+        );
+
+        let split = split_variance_decomposition(&chains).unwrap();
+        assert_eq!((split.num_draws, split.num_chains), (1, 4));
+        assert!(variance_decomposition(&vec![]).is_err());
+    }
+
+    #[test]
+    fn test_leave_one_chain_out_rhat() {
+        let mut chains: Array2 = (0..4).map(|s| crate::utils::normal_draws(500, s)).collect();
+        chains[2] = chains[2].iter().map(|x| x + 3.0).collect();
+        let loco = leave_one_chain_out_rhat(&chains).unwrap();
+        assert!(loco.rhat > 1.2);
+        assert_eq!(loco.chains.len(), 4);
+        let culprit = loco.most_influential().unwrap();
+        assert_eq!(culprit.chain, 2);
+        assert!(culprit.rhat < 1.02);
+        assert_abs_diff_eq!(culprit.change, culprit.rhat - loco.rhat);
+        assert!(loco.chains[0].rhat > 1.2);
+
+        // constant chains give NaN R hats, which are skipped
+        let constant = vec![vec![1.0; 100]; 3];
+        let loco = leave_one_chain_out_rhat(&constant).unwrap();
+        assert!(loco.chains.iter().all(|c| c.change.is_nan()));
+        assert_eq!(loco.most_influential(), None);
+
+        assert!(leave_one_chain_out_rhat(&vec![crate::utils::normal_draws(10, 1)]).is_err());
+    }
+
+    #[test]
+    fn test_local_potential_scale_reduction_factor() {
+        let a = crate::utils::normal_draws(1000, 1);
+        let mut b = crate::utils::normal_draws(1000, 2);
+        // the second chain starts far away and converges after 250 draws
+        for x in b.iter_mut().take(250) {
+            *x += 5.0;
+        }
+        let chains = vec![a, b];
+        let local = local_potential_scale_reduction_factor(&chains, 250).unwrap();
+        assert_eq!(local.len(), 4);
+        assert_eq!((local[1].start, local[1].end), (250, 500));
+        assert!(local[0].rhat > 1.5);
+        assert!(local[1..].iter().all(|w| w.rhat < 1.05));
+
+        // a single window over everything is the split R hat
+        let whole = local_potential_scale_reduction_factor(&chains, 1000).unwrap();
+        assert_abs_diff_eq!(
+            whole[0].rhat,
+            split_potential_scale_reduction_factor(&chains).unwrap()
+        );
+        assert_eq!(
+            local_potential_scale_reduction_factor(&chains, 300)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(local_potential_scale_reduction_factor(&chains, 3).is_err());
+        assert!(local_potential_scale_reduction_factor(&chains, 1001).is_err());
+    }
+
+    #[test]
+    fn test_multivariate_potential_scale_reduction_factor() {
+        let normal = |seed| crate::utils::normal_draws(1000, seed);
+        // with one parameter it reduces to coda's univariate point estimate
+        let chains = vec![normal(1), normal(2), normal(3)];
+        let v = variance_decomposition(&chains).unwrap();
+        let expected = (999.0 / 1000.0 + 4.0 / 3.0 * v.between / 1000.0 / v.within).sqrt();
+        let actual = multivariate_potential_scale_reduction_factor(&[chains]).unwrap();
+        assert_abs_diff_eq!(actual, expected, epsilon = 1e-12);
+
+        // a and b are each fine marginally, but chain 2 has them
+        // anticorrelated where chain 1 has them correlated
+        let (x, y, z) = (normal(4), normal(5), normal(6));
+        let a = vec![x.clone(), y.clone()];
+        let b: Array2 = vec![
+            x.iter().zip(&z).map(|(x, z)| x + 0.1 * z).collect(),
+            y.iter().zip(&z).map(|(y, z)| -y + 0.1 * z).collect(),
+        ];
+        let b_shifted: Array2 = b
+            .iter()
+            .enumerate()
+            .map(|(c, chain)| chain.iter().map(|v| v + c as f64 * 0.2).collect())
+            .collect();
+        let joint = vec![a.clone(), b_shifted.clone()];
+        let mpsrf = multivariate_potential_scale_reduction_factor(&joint).unwrap();
+        for chains in joint.iter() {
+            let v = variance_decomposition(chains).unwrap();
+            let univariate = (999.0 / 1000.0 + 1.5 * v.between / 1000.0 / v.within).sqrt();
+            assert!(mpsrf > univariate);
+        }
+
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let draws = Draws::from_chains(
+            names,
+            (0..2)
+                .map(|c| vec![a[c].clone(), b_shifted[c].clone(), vec![1.0; 1000]])
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(
+            multivariate_potential_scale_reduction_factor_subset(&draws, &["a", "b"]).unwrap(),
+            mpsrf
+        );
+        // c is constant, so the within-chain covariance is singular
+        assert!(multivariate_potential_scale_reduction_factor_subset(&draws, &["*"]).is_err());
+        assert!(multivariate_potential_scale_reduction_factor_subset(&draws, &["d"]).is_err());
+        assert!(multivariate_potential_scale_reduction_factor(&[vec![normal(1)]]).is_err());
+    }
+
+    #[test]
+    fn test_largest_eigenvalue() {
+        let a = vec![
+            vec![2.0, 1.0, 0.0],
+            vec![1.0, 2.0, 1.0],
+            vec![0.0, 1.0, 2.0],
+        ];
+        assert_abs_diff_eq!(largest_eigenvalue(a), 2.0 + 2f64.sqrt(), epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_split_sd_potential_scale_reduction_factor() {
+        let a = crate::utils::normal_draws(1000, 1);
+        let b = crate::utils::normal_draws(1000, 2);
+        let same = vec![a.clone(), b.clone()];
+        assert!(split_sd_potential_scale_reduction_factor(&same).unwrap() < 1.01);
+
+        // same location, three times the spread
+        let wide = vec![a, b.iter().map(|x| 3.0 * x).collect()];
+        assert!(split_potential_scale_reduction_factor(&wide).unwrap() < 1.01);
+        assert!(split_sd_potential_scale_reduction_factor(&wide).unwrap() > 1.1);
+        assert!(split_sd_potential_scale_reduction_factor(&vec![vec![]]).is_err());
+    }
 }
@@ -0,0 +1,190 @@
+use crate::draws::Draws;
+use crate::rhat::split_potential_scale_reduction_factor;
+use crate::summary::{summarize, Summary};
+use crate::utils::{concat_chains, mean, sample_variance};
+use crate::Array2;
+use anyhow::{anyhow, Context, Error, Result};
+
+/// Several independent runs of the same model, e.g. with different seeds,
+/// each with its own chains.  Keeping the runs apart allows checking that
+/// each one converged and that they agree with each other.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MultiRun {
+    runs: Vec<Draws>,
+}
+
+/// How much the posterior summary of one parameter varies between runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunVariability {
+    /// Summary of the parameter in each run
+    pub summaries: Vec<Summary>,
+    /// Standard deviation of the posterior means across runs
+    pub mean_sd: f64,
+    /// Average Monte Carlo standard error of the mean within a run.  If the
+    /// runs sample the same distribution, `mean_sd` should be about this
+    /// large; a much larger `mean_sd` means the result depends on the seed.
+    pub mean_mcse: f64,
+    /// Standard deviation of the posterior standard deviations across runs
+    pub sd_sd: f64,
+    /// Standard deviation of the 5% quantiles across runs
+    pub q5_sd: f64,
+    /// Standard deviation of the medians across runs
+    pub q50_sd: f64,
+    /// Standard deviation of the 95% quantiles across runs
+    pub q95_sd: f64,
+}
+
+impl MultiRun {
+    /// Creates a container without any runs.
+    pub fn new() -> MultiRun {
+        MultiRun::default()
+    }
+
+    /// Adds a run, which must have the same parameters in the same order as
+    /// the runs already added.
+    pub fn push_run(&mut self, run: Draws) -> Result<(), Error> {
+        if let Some(first) = self.runs.first() {
+            if first.names() != run.names() {
+                return Err(anyhow!(
+                    "Parameters of run {} do not match the first run",
+                    self.runs.len() + 1
+                ));
+            }
+        }
+        self.runs.push(run);
+        Ok(())
+    }
+
+    /// All runs, in the order they were added.
+    pub fn runs(&self) -> &[Draws] {
+        &self.runs
+    }
+
+    /// Number of runs.
+    pub fn num_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Chains of the parameter in every run.
+    fn parameter(&self, name: &str) -> Result<Vec<Array2>, Error> {
+        if self.runs.is_empty() {
+            return Err(anyhow!("No runs added"));
+        }
+        let idx = self.runs[0]
+            .index_of(name)
+            .ok_or_else(|| anyhow!("No parameter named {:?}", name))?;
+        Ok(self
+            .runs
+            .iter()
+            .map(|run| run.target_parameter(idx).into_owned())
+            .collect())
+    }
+
+    /// Computes the split R hat of the parameter over the chains of each run
+    /// separately, to check that every run converged on its own.
+    pub fn within_run_rhat(&self, name: &str) -> Result<Vec<f64>, Error> {
+        self.parameter(name)?
+            .iter()
+            .enumerate()
+            .map(|(idx, chains)| {
+                split_potential_scale_reduction_factor(chains)
+                    .with_context(|| format!("Failed to compute R hat of run {}", idx + 1))
+            })
+            .collect()
+    }
+
+    /// Computes the split R hat of the parameter treating each run, with its
+    /// chains pooled, as a single chain.  Runs that each converged but to
+    /// different answers, e.g. different modes, have a large across-run R
+    /// hat.  Needs at least two runs.
+    pub fn across_run_rhat(&self, name: &str) -> Result<f64, Error> {
+        if self.runs.len() < 2 {
+            return Err(anyhow!("Need at least 2 runs to compare them"));
+        }
+        let pooled: Array2 = self
+            .parameter(name)?
+            .iter()
+            .map(|chains| concat_chains(chains).remove(0))
+            .collect();
+        split_potential_scale_reduction_factor(&pooled)
+    }
+
+    /// Summarizes the parameter in each run and how much the summaries vary
+    /// from run to run.  Needs at least two runs.
+    pub fn variability(&self, name: &str) -> Result<RunVariability, Error> {
+        if self.runs.len() < 2 {
+            return Err(anyhow!("Need at least 2 runs to compare them"));
+        }
+        let summaries = self
+            .parameter(name)?
+            .iter()
+            .enumerate()
+            .map(|(idx, chains)| {
+                summarize(chains).with_context(|| format!("Failed to summarize run {}", idx + 1))
+            })
+            .collect::<Result<Vec<Summary>, Error>>()?;
+        let spread = |f: fn(&Summary) -> f64| -> Result<f64, Error> {
+            let values: Vec<f64> = summaries.iter().map(f).collect();
+            Ok(sample_variance(&values)?.sqrt())
+        };
+        Ok(RunVariability {
+            mean_sd: spread(|s| s.mean)?,
+            mean_mcse: mean(&summaries.iter().map(|s| s.mcse).collect::<Vec<f64>>())?,
+            sd_sd: spread(|s| s.sd)?,
+            q5_sd: spread(|s| s.q5)?,
+            q50_sd: spread(|s| s.q50)?,
+            q95_sd: spread(|s| s.q95)?,
+            summaries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    fn run(seed: u64, shift: f64) -> Draws {
+        let chains = (0..2)
+            .map(|c| {
+                vec![normal_draws(500, 10 * seed + c)
+                    .iter()
+                    .map(|x| x + shift)
+                    .collect()]
+            })
+            .collect();
+        Draws::from_chains(vec!["theta".to_string()], chains).unwrap()
+    }
+
+    #[test]
+    fn test_agreeing_runs() {
+        let mut runs = MultiRun::new();
+        for seed in 1..=4 {
+            runs.push_run(run(seed, 0.0)).unwrap();
+        }
+        assert_eq!(runs.num_runs(), 4);
+        let within = runs.within_run_rhat("theta").unwrap();
+        assert_eq!(within.len(), 4);
+        assert!(within.iter().all(|r| *r < 1.02));
+        assert!(runs.across_run_rhat("theta").unwrap() < 1.02);
+        let variability = runs.variability("theta").unwrap();
+        assert_eq!(variability.summaries.len(), 4);
+        assert!(variability.mean_sd < 3.0 * variability.mean_mcse);
+    }
+
+    #[test]
+    fn test_disagreeing_runs() {
+        let mut runs = MultiRun::new();
+        runs.push_run(run(1, 0.0)).unwrap();
+        runs.push_run(run(2, 2.0)).unwrap();
+        // each run is fine on its own
+        assert!(runs.within_run_rhat("theta").unwrap()[1] < 1.02);
+        assert!(runs.across_run_rhat("theta").unwrap() > 1.2);
+        let variability = runs.variability("theta").unwrap();
+        assert!(variability.mean_sd > 10.0 * variability.mean_mcse);
+
+        assert!(runs.across_run_rhat("phi").is_err());
+        assert!(runs.push_run(Draws::new(vec!["phi".to_string()])).is_err());
+        assert!(MultiRun::new().within_run_rhat("theta").is_err());
+    }
+}
@@ -0,0 +1,521 @@
+//! Diagnostics as a service for teams whose samplers are not written in
+//! Rust.  Clients post a finished run and read back its summaries,
+//! convergence diagnostics and warnings as JSON:
+//!
+//! * `POST /draws` with a `text/csv`, `application/x-ndjson` (one JSON
+//!   object per draw, see [`Format::Json`](../io/stream/enum.Format.html)) or,
+//!   with the `arrow` feature, `application/vnd.apache.arrow.stream` body
+//!   stores the draws and answers `201` with their `id`.  Draws are assigned
+//!   to chains by the `chain` column as in
+//!   [`stream::read_draws`](../io/stream/fn.read_draws.html).
+//! * `GET /draws/{id}/summary` returns the
+//!   [diagnostic report](../report/fn.diagnostic_report.html) in the
+//!   schema-versioned layout of [`report::to_json`](../report/fn.to_json.html),
+//!   with the posterior summary of every parameter and the
+//!   [`verdict`](../diagnostics/fn.verdict_with_thresholds.html) with its
+//!   evidence, so it can be read back with
+//!   [`report::from_json`](../report/fn.from_json.html).
+//! * `GET /draws/{id}/warnings` returns only the verdict and its evidence,
+//!   as the `verdict` and `warnings` of the report.
+//! * `GET /draws/{id}/diagnostics` returns split R hat and the bulk and
+//!   tail effective sample sizes of every parameter.
+//! * `DELETE /draws/{id}` forgets the draws.
+//!
+//! Undefined values such as the R hat of a constant parameter are `null`
+//! in the diagnostics.  Errors are answered with a plain text message.
+//! Runs are kept in memory only.
+use crate::diagnostics::{verdict_with_thresholds, Thresholds};
+use crate::draws::Draws;
+use crate::ess::compute_bulk_tail_ess;
+use crate::http::{read_request, reject, write_response, ConnectionLimit};
+pub use crate::http::{Request, Response};
+use crate::io::stream::{self, Format, StreamOptions};
+use crate::report::{diagnostic_report, to_json_value};
+use crate::rhat::split_potential_scale_reduction_factor;
+use crate::summary::ReportOptions;
+use anyhow::{Context, Error, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Settings of the diagnostics service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceOptions {
+    /// Largest accepted request body in bytes
+    pub max_body: usize,
+    /// Number of runs kept in memory; posting more forgets the oldest
+    pub max_runs: usize,
+    /// Number of connections answered at once by
+    /// [`serve`](fn.serve.html); more are answered `503` straight away
+    pub max_connections: usize,
+    /// Time a client has to send its whole request before it is answered
+    /// `408`, so slow clients can't hold connections indefinitely
+    pub request_timeout: Duration,
+    /// Name of the column (or JSON key) identifying the chain of each draw
+    pub chain_column: String,
+    /// Limits beyond which the draws get a warning
+    pub thresholds: Thresholds,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> ServiceOptions {
+        ServiceOptions {
+            max_body: 256 << 20,
+            max_runs: 64,
+            max_connections: 16,
+            request_timeout: Duration::from_secs(60),
+            chain_column: "chain".to_string(),
+            thresholds: Thresholds::default(),
+        }
+    }
+}
+
+/// Runs posted so far, by id.
+#[derive(Debug, Default)]
+struct Runs {
+    next_id: u64,
+    draws: BTreeMap<u64, Arc<Draws>>,
+}
+
+/// State of the diagnostics service, shared by the connection threads of
+/// [`serve`](fn.serve.html).  [`handle`](#method.handle) answers a single
+/// request without any networking, e.g. to embed the service in another
+/// HTTP server.
+#[derive(Debug, Default)]
+pub struct Service {
+    options: ServiceOptions,
+    runs: Mutex<Runs>,
+}
+
+/// JSON response.
+fn json_response(status: u16, value: &Value) -> Response {
+    Response {
+        status,
+        content_type: "application/json".to_string(),
+        body: value.to_string().into_bytes(),
+    }
+}
+
+/// Split R hat and bulk and tail ESS of every parameter.
+fn diagnostics(draws: &Draws) -> Value {
+    let parameters: Vec<Value> = draws
+        .names()
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let chains = draws.target_parameter(idx);
+            let rhat = split_potential_scale_reduction_factor(&chains).ok();
+            let ess = compute_bulk_tail_ess(&chains).ok();
+            json!({
+                "parameter": name,
+                "rhat": rhat,
+                "ess_bulk": ess.map(|e| e.bulk),
+                "ess_tail": ess.map(|e| e.tail),
+            })
+        })
+        .collect();
+    json!({
+        "num_chains": draws.target_chains().len(),
+        "num_draws": draws.num_draws(),
+        "parameters": parameters,
+    })
+}
+
+impl Service {
+    /// Creates a service without any runs.
+    pub fn new(options: ServiceOptions) -> Service {
+        Service {
+            options,
+            runs: Mutex::new(Runs::default()),
+        }
+    }
+
+    /// Settings of the service.
+    pub fn options(&self) -> &ServiceOptions {
+        &self.options
+    }
+
+    /// Answers a single request, see the [module documentation](index.html)
+    /// for the routes.
+    pub fn handle(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["draws"]) => self.post_draws(request),
+            (method, ["draws", id, rest @ ..]) if rest.len() <= 1 => {
+                let id = match id.parse::<u64>() {
+                    Ok(id) => id,
+                    Err(_) => return Response::text(404, "Not found\n"),
+                };
+                match (method, rest) {
+                    ("DELETE", []) => match self.runs.lock().unwrap().draws.remove(&id) {
+                        Some(_) => json_response(200, &json!({ "id": id })),
+                        None => Response::text(404, "No draws with this id\n"),
+                    },
+                    ("GET", [view]) => {
+                        let draws = match self.runs.lock().unwrap().draws.get(&id) {
+                            Some(draws) => draws.clone(),
+                            None => return Response::text(404, "No draws with this id\n"),
+                        };
+                        match *view {
+                            "summary" => self.report(&draws),
+                            "warnings" => self.warnings(&draws),
+                            "diagnostics" => json_response(200, &diagnostics(&draws)),
+                            _ => Response::text(404, "Not found\n"),
+                        }
+                    }
+                    _ => Response::text(405, "Method not allowed\n"),
+                }
+            }
+            ("POST", _) | ("GET", _) | ("DELETE", _) => Response::text(404, "Not found\n"),
+            _ => Response::text(405, "Method not allowed\n"),
+        }
+    }
+
+    /// Parses and stores posted draws.
+    fn post_draws(&self, request: &Request) -> Response {
+        let options = |format| StreamOptions {
+            format,
+            chain_column: self.options.chain_column.clone(),
+            ..StreamOptions::default()
+        };
+        let draws = match request.content_type.as_deref() {
+            Some("text/csv") | Some("text/plain") | None => {
+                stream::read_draws(&request.body[..], &options(Format::Csv))
+            }
+            Some("application/json") | Some("application/x-ndjson") => {
+                stream::read_draws(&request.body[..], &options(Format::Json))
+            }
+            #[cfg(feature = "arrow")]
+            Some("application/vnd.apache.arrow.stream") => {
+                arrow_ipc::reader::StreamReader::try_new(&request.body[..], None)
+                    .context("Invalid Arrow IPC stream")
+                    .and_then(|reader| {
+                        crate::io::arrow::draws_from_batches(reader, &self.options.chain_column)
+                    })
+            }
+            Some(other) => {
+                return Response::text(415, &format!("Unsupported content type {}\n", other))
+            }
+        };
+        let draws = match draws {
+            Ok(draws) => draws,
+            Err(err) => return Response::text(422, &format!("{:#}\n", err)),
+        };
+        let (num_parameters, num_chains, num_draws) = (
+            draws.num_parameters(),
+            draws.num_chains(),
+            draws.num_draws(),
+        );
+        let mut runs = self.runs.lock().unwrap();
+        let id = runs.next_id;
+        runs.next_id += 1;
+        runs.draws.insert(id, Arc::new(draws));
+        while runs.draws.len() > self.options.max_runs.max(1) {
+            let oldest = *runs.draws.keys().next().unwrap();
+            runs.draws.remove(&oldest);
+        }
+        json_response(
+            201,
+            &json!({
+                "id": id,
+                "num_parameters": num_parameters,
+                "num_chains": num_chains,
+                "num_draws": num_draws,
+            }),
+        )
+    }
+
+    /// Diagnostic report of the draws.
+    fn report(&self, draws: &Draws) -> Response {
+        match diagnostic_report(draws, &ReportOptions::default(), &self.options.thresholds) {
+            Ok(report) => json_response(200, &to_json_value(&report)),
+            Err(err) => Response::text(422, &format!("{:#}\n", err)),
+        }
+    }
+
+    /// Verdict of the draws with its evidence.
+    fn warnings(&self, draws: &Draws) -> Response {
+        let verdict = match verdict_with_thresholds(draws, &self.options.thresholds) {
+            Ok(verdict) => verdict,
+            Err(err) => return Response::text(422, &format!("{:#}\n", err)),
+        };
+        let warnings: Vec<Value> = verdict
+            .evidence()
+            .iter()
+            .map(|e| {
+                let (kind, parameter, chain, value) = e.columns();
+                json!({
+                    "kind": kind,
+                    "parameter": parameter,
+                    "chain": chain,
+                    "value": value,
+                    "message": e.to_string(),
+                })
+            })
+            .collect();
+        json_response(
+            200,
+            &json!({ "verdict": verdict.name(), "warnings": warnings }),
+        )
+    }
+}
+
+/// Longest wait for the next bytes of a request, which bounds how far the
+/// request timeout can be overrun.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answers a single connection.
+fn respond(mut stream: TcpStream, service: &Service) -> Result<(), Error> {
+    let timeout = service.options.request_timeout;
+    stream.set_read_timeout(Some(
+        timeout.min(READ_TIMEOUT).max(Duration::from_millis(1)),
+    ))?;
+    let deadline = Instant::now() + timeout;
+    let response = match read_request(&mut stream, service.options.max_body, deadline) {
+        Ok(request) => service.handle(&request),
+        Err(response) => response,
+    };
+    write_response(&mut stream, &response)?;
+    Ok(())
+}
+
+/// Serves the diagnostics service on a bound listener, answering every
+/// connection on its own thread, up to
+/// [`max_connections`](struct.ServiceOptions.html#structfield.max_connections)
+/// at once.  Only returns if accepting connections fails.
+///
+/// # Arguments
+/// * `listener` - Listener bound to the address to serve on
+/// * `service` - Service answering the requests
+pub fn serve(listener: TcpListener, service: Arc<Service>) -> Result<(), Error> {
+    let limit = ConnectionLimit::new(service.options.max_connections.max(1));
+    loop {
+        let (stream, _) = listener.accept().context("Failed to accept connection")?;
+        // a client that goes away mid-request is not an error worth
+        // stopping for
+        let slot = match limit.acquire() {
+            Some(slot) => slot,
+            None => {
+                reject(stream);
+                continue;
+            }
+        };
+        let service = service.clone();
+        std::thread::spawn(move || {
+            let _ = respond(stream, &service);
+            drop(slot);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+    use std::io::{Read, Write};
+
+    fn csv() -> String {
+        let mut csv = "chain,mu,sigma\n".to_string();
+        let (a, b) = (normal_draws(400, 1), normal_draws(400, 2));
+        for i in 0..400 {
+            csv.push_str(&format!("1,{},1.0\n2,{},1.0\n", a[i], b[i] + 5.0));
+        }
+        csv
+    }
+
+    fn request(method: &str, path: &str, content_type: Option<&str>, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            content_type: content_type.map(String::from),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_service() {
+        let service = Service::new(ServiceOptions::default());
+        let posted = service.handle(&request("POST", "/draws", Some("text/csv"), &csv()));
+        assert_eq!(posted.status, 201);
+        let posted = body(&posted);
+        assert_eq!(posted["num_chains"], 2);
+        assert_eq!(posted["num_draws"], 400);
+        let id = posted["id"].as_u64().unwrap();
+
+        let get = |view: &str| {
+            service.handle(&request(
+                "GET",
+                &format!("/draws/{}/{}", id, view),
+                None,
+                "",
+            ))
+        };
+        let summary = body(&get("summary"));
+        assert_eq!(summary["schema"], "mcmc-report");
+        assert!((summary["parameters"][0]["mean"].as_f64().unwrap() - 2.5).abs() < 0.2);
+        // the constant sigma can't be summarized
+        assert_eq!(summary["parameters"].as_array().unwrap().len(), 1);
+        assert!(summary["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|w| w["kind"] == "not_summarized" && w["parameter"] == "sigma"));
+        let report =
+            crate::report::from_json(std::str::from_utf8(&get("summary").body).unwrap()).unwrap();
+        assert_eq!(report.num_draws, 400);
+
+        let diagnostics = body(&get("diagnostics"));
+        let mu = &diagnostics["parameters"][0];
+        assert_eq!(mu["parameter"], "mu");
+        assert!(mu["rhat"].as_f64().unwrap() > 1.5);
+        assert!(mu["ess_bulk"].as_f64().unwrap() > 0.0);
+        assert!(diagnostics["parameters"][1]["rhat"].is_null());
+
+        let warnings = body(&get("warnings"));
+        assert_eq!(warnings["verdict"], "not converged");
+        let high_rhat = warnings["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|w| w["kind"] == "high_rhat")
+            .unwrap();
+        assert_eq!(high_rhat["parameter"], "mu");
+        // only the verdict, without the summaries
+        assert!(warnings.get("parameters").is_none());
+        assert!(warnings["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|w| w["kind"] != "not_summarized"));
+
+        let path = format!("/draws/{}", id);
+        assert_eq!(
+            service.handle(&request("DELETE", &path, None, "")).status,
+            200
+        );
+        assert_eq!(get("summary").status, 404);
+    }
+
+    #[test]
+    fn test_service_errors() {
+        let service = Service::new(ServiceOptions {
+            max_runs: 1,
+            ..ServiceOptions::default()
+        });
+        let post =
+            |content_type, body| service.handle(&request("POST", "/draws", content_type, body));
+        assert_eq!(post(Some("application/xml"), "<draws/>").status, 415);
+        assert_eq!(post(Some("text/csv"), "a,b\n1.0,x\n").status, 422);
+        let json = "{\"chain\": 1, \"a\": 0.5}\n{\"chain\": 2, \"a\": 1.5}\n";
+        assert_eq!(body(&post(Some("application/x-ndjson"), json))["id"], 0);
+        assert_eq!(body(&post(Some("application/json"), json))["id"], 1);
+        // only the most recent run is kept
+        assert_eq!(
+            service
+                .handle(&request("GET", "/draws/0/summary", None, ""))
+                .status,
+            404
+        );
+        assert_eq!(
+            service
+                .handle(&request("GET", "/draws/1/other", None, ""))
+                .status,
+            404
+        );
+        assert_eq!(
+            service.handle(&request("PUT", "/draws/1", None, "")).status,
+            405
+        );
+        assert_eq!(service.handle(&request("GET", "/", None, "")).status, 404);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_service_arrow() {
+        use arrow_array::{Float64Array, Int64Array, RecordBatch};
+        use arrow_schema::{DataType, Field, Schema};
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("chain", DataType::Int64, false),
+            Field::new("theta", DataType::Float64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 1, 2])),
+                Arc::new(Float64Array::from(vec![0.1, 0.2, 0.3, 0.4])),
+            ],
+        )
+        .unwrap();
+        let mut ipc = Vec::new();
+        {
+            let mut writer = arrow_ipc::writer::StreamWriter::try_new(&mut ipc, &schema).unwrap();
+            writer.write(&batch).unwrap();
+            writer.finish().unwrap();
+        }
+        let service = Service::new(ServiceOptions::default());
+        let response = service.handle(&Request {
+            method: "POST".to_string(),
+            path: "/draws".to_string(),
+            content_type: Some("application/vnd.apache.arrow.stream".to_string()),
+            body: ipc,
+        });
+        assert_eq!(response.status, 201);
+        assert_eq!(body(&response)["num_chains"], 2);
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Arc::new(Service::new(ServiceOptions::default()));
+        std::thread::spawn(move || serve(listener, service));
+
+        let send = |raw: String| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let csv = csv();
+        let response = send(format!(
+            "POST /draws HTTP/1.1\r\nContent-Type: text/csv\r\nContent-Length: {}\r\n\r\n{}",
+            csv.len(),
+            csv
+        ));
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.contains("\"id\":0"));
+        let response = send("GET /draws/0/warnings HTTP/1.1\r\n\r\n".to_string());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\"verdict\":\"not converged\""));
+    }
+
+    #[test]
+    fn test_serve_connection_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Arc::new(Service::new(ServiceOptions {
+            max_connections: 1,
+            ..ServiceOptions::default()
+        }));
+        std::thread::spawn(move || serve(listener, service));
+
+        // a client that never finishes its request holds the only slot
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.write_all(b"GET /draws/0/summary HTTP/1.1\r\n")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        // the answer doesn't wait for the request
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+}
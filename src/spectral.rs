@@ -0,0 +1,368 @@
+use crate::utils::{autocovariance, mean, sample_variance};
+use crate::Array1;
+use anyhow::{anyhow, Error, Result};
+
+/// Spectral density estimate evaluated at the positive Fourier frequencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    /// Frequencies in cycles per draw, in increasing order and excluding zero
+    pub frequencies: Array1,
+    /// Spectral density estimate at each frequency
+    pub density: Array1,
+}
+
+/// Autoregressive model fit by the Yule-Walker equations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArFit {
+    /// Autoregressive coefficients, starting at lag one
+    pub coefficients: Array1,
+    /// Innovations variance of the fitted model
+    pub var_pred: f64,
+}
+
+/// Computes the raw periodogram of a single chain after removing the mean.
+/// The series is zero padded to the next power of two before the discrete
+/// Fourier transform, as is done by R's `spec.pgram` with `fast = TRUE`, so
+/// the frequencies are `k / N` for the padded length `N`.
+///
+/// Power concentrated at low frequencies indicates strong positive
+/// autocorrelation, i.e. poor mixing.
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(n = chain.len()))
+)]
+pub fn periodogram(chain: &[f64]) -> Result<Spectrum, Error> {
+    let n = chain.len();
+    if n < 2 {
+        return Err(anyhow!(
+            "Must have at least 2 samples to compute periodogram"
+        ));
+    }
+    if chain.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to compute periodogram"));
+    }
+    let full = full_periodogram(chain)?;
+    let padded = full.len();
+    let frequencies = (1..=padded / 2).map(|k| k as f64 / padded as f64).collect();
+    let density = full[1..=padded / 2].to_vec();
+    Ok(Spectrum {
+        frequencies,
+        density,
+    })
+}
+
+/// Computes a smoothed estimate of the spectral density of a single chain by
+/// applying a modified Daniell kernel with half width `m` to the periodogram.
+/// The kernel puts weight `1 / 2m` on the `2m - 1` central ordinates and
+/// `1 / 4m` on the two outermost, and wraps around circularly like R's
+/// `kernapply`.
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+/// * `m` - Half width of the smoothing kernel; `0` returns the raw periodogram
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(n = chain.len()))
+)]
+pub fn smoothed_spectral_density(chain: &[f64], m: usize) -> Result<Spectrum, Error> {
+    let raw = periodogram(chain)?;
+    if m == 0 {
+        return Ok(raw);
+    }
+    let full = full_periodogram(chain)?;
+    let padded = full.len();
+    let weight = |j: usize| {
+        if j == m {
+            1.0 / (4 * m) as f64
+        } else {
+            1.0 / (2 * m) as f64
+        }
+    };
+    let mut density = Vec::new();
+    for k in 1..=padded / 2 {
+        let mut total = full[k] * weight(0);
+        for j in 1..=m {
+            total += weight(j) * full[(k + j) % padded];
+            total += weight(j) * full[(k + padded - (j % padded)) % padded];
+        }
+        density.push(total);
+    }
+    Ok(Spectrum {
+        frequencies: raw.frequencies,
+        density,
+    })
+}
+
+/// Fits an autoregressive model to a single chain with the Yule-Walker
+/// equations, solved by Levinson-Durbin recursion on the biased sample
+/// autocovariances.  The order is chosen by AIC up to
+/// `min(n - 2, 10 * log10(n))`, matching the defaults of R's `ar` except
+/// that the order leaves at least one degree of freedom for the prediction
+/// variance.
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(n = chain.len()))
+)]
+pub fn ar_yule_walker(chain: &[f64]) -> Result<ArFit, Error> {
+    let n = chain.len();
+    if n < 2 {
+        return Err(anyhow!("Must have at least 2 samples to fit AR model"));
+    }
+    if chain.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to fit AR model"));
+    }
+    let max_order = ((10.0 * (n as f64).log10()) as usize).min(n - 2);
+    let acov = autocovariance(chain, max_order)?;
+    if acov[0] <= 0.0 {
+        return Err(anyhow!("No AR fit when elements are all constant"));
+    }
+
+    // Levinson-Durbin recursion, keeping the coefficients for each order so
+    // the best one by AIC can be returned at the end
+    let mut all_coefficients: Vec<Array1> = vec![Vec::new()];
+    let mut variances = vec![acov[0]];
+    let mut phi: Array1 = Vec::new();
+    let mut var = acov[0];
+    for k in 1..=max_order {
+        let mut numerator = acov[k];
+        for (j, p) in phi.iter().enumerate() {
+            numerator -= p * acov[k - 1 - j];
+        }
+        let reflection = numerator / var;
+        let mut next = vec![0.0; k];
+        for j in 0..(k - 1) {
+            next[j] = phi[j] - reflection * phi[k - 2 - j];
+        }
+        next[k - 1] = reflection;
+        var *= 1.0 - reflection * reflection;
+        phi = next;
+        all_coefficients.push(phi.clone());
+        variances.push(var);
+    }
+
+    let aic = |k: usize| n as f64 * variances[k].ln() + 2.0 * k as f64;
+    let order = (0..=max_order)
+        .min_by(|&a, &b| aic(a).total_cmp(&aic(b)))
+        .unwrap();
+    let var_pred = variances[order] * n as f64 / (n as f64 - (order as f64 + 1.0));
+    Ok(ArFit {
+        coefficients: all_coefficients[order].clone(),
+        var_pred,
+    })
+}
+
+/// Estimates the spectral density at frequency zero of a single chain by
+/// fitting an autoregressive model, as in `spectrum0.ar` from the R package
+/// [coda](https://cran.r-project.org/package=coda).  The result divided by
+/// the chain length is the asymptotic variance of the chain mean.
+///
+/// Returns zero when the chain is constant after removing a linear trend.
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(n = chain.len()))
+)]
+pub fn spectrum0(chain: &[f64]) -> Result<f64, Error> {
+    if chain.len() < 2 {
+        return Err(anyhow!("Must have at least 2 samples to compute spectrum0"));
+    }
+    if chain.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("All values must be finite to compute spectrum0"));
+    }
+    if sample_variance(&detrend(chain)?)?.sqrt() < 1e-10 {
+        return Ok(0.0);
+    }
+    let fit = ar_yule_walker(chain)?;
+    let total: f64 = fit.coefficients.iter().sum();
+    Ok(fit.var_pred / (1.0 - total).powi(2))
+}
+
+/// Residuals from a least squares fit of a linear trend in the draw index.
+fn detrend(chain: &[f64]) -> Result<Array1, Error> {
+    let z: Array1 = (1..=chain.len()).map(|i| i as f64).collect();
+    let z_mean = mean(&z)?;
+    let x_mean = mean(chain)?;
+    let mut sxz = 0.0;
+    let mut szz = 0.0;
+    for (x, z) in chain.iter().zip(&z) {
+        sxz += (x - x_mean) * (z - z_mean);
+        szz += (z - z_mean) * (z - z_mean);
+    }
+    let slope = sxz / szz;
+    Ok(chain
+        .iter()
+        .zip(&z)
+        .map(|(x, z)| x - x_mean - slope * (z - z_mean))
+        .collect())
+}
+
+/// Periodogram at all `N` Fourier frequencies of the zero padded, demeaned
+/// chain, with the ordinate at frequency zero replaced by its neighbours.
+fn full_periodogram(chain: &[f64]) -> Result<Array1, Error> {
+    let n = chain.len();
+    let chain_mean = mean(chain)?;
+    let padded = n.next_power_of_two();
+    let mut re: Array1 = chain.iter().map(|x| x - chain_mean).collect();
+    re.resize(padded, 0.0);
+    let mut im = vec![0.0; padded];
+    fft(&mut re, &mut im);
+    let mut full: Array1 = re
+        .iter()
+        .zip(&im)
+        .map(|(r, i)| (r * r + i * i) / n as f64)
+        .collect();
+    full[0] = 0.5 * (full[1] + full[padded - 1]);
+    Ok(full)
+}
+
+/// In-place iterative radix-2 fast Fourier transform.  The length of both
+/// slices must be the same power of two.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "trace", skip_all, fields(n = re.len()))
+)]
+pub(crate) fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+        let (w_im, w_re) = angle.sin_cos();
+        for start in (0..n).step_by(len) {
+            let mut cur_re = 1.0;
+            let mut cur_im = 0.0;
+            for k in 0..len / 2 {
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * cur_re - im[b] * cur_im;
+                let t_im = re[b] * cur_im + im[b] * cur_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+                let next_re = cur_re * w_re - cur_im * w_im;
+                cur_im = cur_re * w_im + cur_im * w_re;
+                cur_re = next_re;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+
+    #[test]
+    fn test_fft_matches_naive_dft() {
+        let x = vec![1.0, -2.0, 0.5, 3.0, 0.0, 1.5, -1.0, 2.0];
+        let mut re = x.clone();
+        let mut im = vec![0.0; x.len()];
+        fft(&mut re, &mut im);
+        let n = x.len();
+        for k in 0..n {
+            let mut expected_re = 0.0;
+            let mut expected_im = 0.0;
+            for (t, v) in x.iter().enumerate() {
+                let angle = -2.0 * std::f64::consts::PI * (k * t) as f64 / n as f64;
+                expected_re += v * angle.cos();
+                expected_im += v * angle.sin();
+            }
+            assert_abs_diff_eq!(re[k], expected_re, epsilon = 1e-10);
+            assert_abs_diff_eq!(im[k], expected_im, epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_periodogram_peak_at_sinusoid_frequency() {
+        let chain: Array1 = (0..64)
+            .map(|t| (2.0 * std::f64::consts::PI * 8.0 * t as f64 / 64.0).cos())
+            .collect();
+        let spec = periodogram(&chain).unwrap();
+        assert_eq!(spec.frequencies.len(), 32);
+        let peak = spec
+            .density
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap()
+            .0;
+        assert_abs_diff_eq!(spec.frequencies[peak], 0.125);
+        // all power of a pure cosine ends up at a single frequency: n/4
+        assert_abs_diff_eq!(spec.density[peak], 16.0, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_smoothed_spectral_density() {
+        let chain: Array1 = (0..64)
+            .map(|t| (2.0 * std::f64::consts::PI * 8.0 * t as f64 / 64.0).cos())
+            .collect();
+        let raw = periodogram(&chain).unwrap();
+        assert_eq!(smoothed_spectral_density(&chain, 0).unwrap(), raw);
+        let smooth = smoothed_spectral_density(&chain, 2).unwrap();
+        // peak is at index 7, and the kernel weights are 1/4, 1/4, 1/4, 1/8, 1/8
+        assert_abs_diff_eq!(smooth.density[7], 4.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(smooth.density[6], 4.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(smooth.density[5], 2.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(smooth.density[4], 0.0, epsilon = 1e-10);
+        let total_raw: f64 = raw.density.iter().sum();
+        let total_smooth: f64 = smooth.density.iter().sum();
+        assert_abs_diff_eq!(total_raw, total_smooth, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_ar_yule_walker_recovers_ar1() {
+        let innovations = normal_draws(4000, 7);
+        let mut prev = 0.0;
+        let chain: Array1 = innovations
+            .iter()
+            .map(|e| {
+                prev = 0.8 * prev + e;
+                prev
+            })
+            .collect();
+        let fit = ar_yule_walker(&chain).unwrap();
+        assert_abs_diff_eq!(fit.coefficients[0], 0.8, epsilon = 0.05);
+
+        let innovations_var = sample_variance(&innovations).unwrap();
+        let spec = spectrum0(&chain).unwrap();
+        let expected = innovations_var / (1.0 - 0.8_f64).powi(2);
+        assert!((spec / expected - 1.0).abs() < 0.25);
+    }
+
+    #[test]
+    fn test_spectrum0_edge_cases() {
+        assert!(spectrum0(&[1.0]).is_err());
+        assert!(spectrum0(&[1.0, f64::NAN, 2.0]).is_err());
+        assert_abs_diff_eq!(spectrum0(&[1.0, 2.0, 3.0, 4.0, 5.0]).unwrap(), 0.0);
+        assert!(periodogram(&[1.0]).is_err());
+        assert!(ar_yule_walker(&[1.0, f64::NAN, 2.0]).is_err());
+        assert!(ar_yule_walker(&[1.0, f64::INFINITY, 2.0]).is_err());
+        let fit = ar_yule_walker(&[1.0, 2.0]).unwrap();
+        assert!(fit.coefficients.is_empty());
+        assert!(fit.var_pred.is_finite());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::utils::mean;
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Error, Result};
+
+/// Result of a single stationarity hypothesis test applied to one chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TestResult {
+    /// Value of the test statistic
+    pub statistic: f64,
+    /// Approximate p-value, interpolated from tabulated critical values and
+    /// therefore clipped to the range covered by the table
+    pub p_value: f64,
+    /// Lag truncation parameter used by the test
+    pub lag: usize,
+}
+
+/// KPSS and augmented Dickey-Fuller results for a single chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StationarityReport {
+    /// KPSS test, where the null hypothesis is level stationarity
+    pub kpss: TestResult,
+    /// Augmented Dickey-Fuller test, where the null hypothesis is a unit root
+    pub adf: TestResult,
+}
+
+/// Computes the KPSS test for the null hypothesis that a chain is level
+/// stationary.  Large values of the statistic (small p-values) are evidence
+/// against stationarity.
+///
+/// The long run variance is estimated with a Bartlett window using the
+/// "short" lag truncation `trunc(4 * (n / 100)^(1/4))`, and p-values are
+/// interpolated between 0.01 and 0.1 from the table in Kwiatkowski et al (1992).
+///
+/// Based on the reference implementation of `kpss.test` in the R package
+/// [tseries](https://cran.r-project.org/package=tseries).
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+pub fn kpss_test(chain: &[f64]) -> Result<TestResult, Error> {
+    let n = chain.len();
+    if n < 3 {
+        return Err(anyhow!("Must have at least 3 samples to compute KPSS test"));
+    }
+    check_finite(chain)?;
+    let chain_mean = mean(chain)?;
+    let residuals: Array1 = chain.iter().map(|x| x - chain_mean).collect();
+
+    let mut partial_sum = 0.0;
+    let mut eta = 0.0;
+    for e in residuals.iter() {
+        partial_sum += e;
+        eta += partial_sum * partial_sum;
+    }
+    eta /= (n * n) as f64;
+
+    let lag = (4.0 * (n as f64 / 100.0).powf(0.25)) as usize;
+    let s2 = long_run_variance(&residuals, lag);
+    if s2 <= 0.0 {
+        return Err(anyhow!("No KPSS statistic when elements are all constant"));
+    }
+    let statistic = eta / s2;
+
+    let table = [0.347, 0.463, 0.574, 0.739];
+    let table_p = [0.1, 0.05, 0.025, 0.01];
+    let p_value = interpolate(&table, &table_p, statistic);
+    Ok(TestResult {
+        statistic,
+        p_value,
+        lag,
+    })
+}
+
+/// Computes the augmented Dickey-Fuller test for the null hypothesis that a
+/// chain has a unit root, against the alternative of (trend) stationarity.
+/// Strongly negative values of the statistic (small p-values) are evidence
+/// for stationarity.
+///
+/// The test regression includes a constant, a linear trend and
+/// `trunc((n - 1)^(1/3))` lagged differences, and p-values are interpolated
+/// between 0.01 and 0.99 from the table in Banerjee et al (1993).
+///
+/// Based on the reference implementation of `adf.test` in the R package
+/// [tseries](https://cran.r-project.org/package=tseries).
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+pub fn adf_test(chain: &[f64]) -> Result<TestResult, Error> {
+    let lag = ((chain.len().max(1) - 1) as f64).cbrt() as usize;
+    check_finite(chain)?;
+    // number of regressors: lagged level, constant, trend and lagged differences
+    let num_regressors = 3 + lag;
+    let diffs: Array1 = chain.windows(2).map(|w| w[1] - w[0]).collect();
+    let k = lag + 1;
+    if diffs.len() < k || diffs.len() + 1 - k <= num_regressors {
+        return Err(anyhow!(
+            "Not enough samples to compute augmented Dickey-Fuller test"
+        ));
+    }
+
+    let mut response = Vec::new();
+    let mut design = Vec::new();
+    for t in (k - 1)..diffs.len() {
+        response.push(diffs[t]);
+        let mut row = vec![chain[t], 1.0, (t + 1) as f64];
+        for i in 1..k {
+            row.push(diffs[t - i]);
+        }
+        design.push(row);
+    }
+    let (coefficients, std_errors) = least_squares(&design, &response)?;
+    let statistic = coefficients[0] / std_errors[0];
+
+    let table_n = [25.0, 50.0, 100.0, 250.0, 500.0, 100_000.0];
+    let table = [
+        [4.38, 4.15, 4.04, 3.99, 3.98, 3.96],
+        [3.95, 3.80, 3.73, 3.69, 3.68, 3.66],
+        [3.60, 3.50, 3.45, 3.43, 3.42, 3.41],
+        [3.24, 3.18, 3.15, 3.13, 3.13, 3.12],
+        [1.14, 1.19, 1.22, 1.23, 1.24, 1.25],
+        [0.80, 0.87, 0.90, 0.92, 0.93, 0.94],
+        [0.50, 0.58, 0.62, 0.64, 0.65, 0.66],
+        [0.15, 0.24, 0.28, 0.31, 0.32, 0.33],
+    ];
+    let table_p = [0.01, 0.025, 0.05, 0.10, 0.90, 0.95, 0.975, 0.99];
+    // critical values for this sample size, one per tabulated probability
+    let critical: Array1 = table
+        .iter()
+        .map(|row| {
+            let negated: Array1 = row.iter().map(|v| -v).collect();
+            interpolate(&table_n, &negated, diffs.len() as f64)
+        })
+        .collect();
+    let p_value = interpolate(&critical, &table_p, statistic);
+    Ok(TestResult {
+        statistic,
+        p_value,
+        lag,
+    })
+}
+
+/// Runs the KPSS and augmented Dickey-Fuller tests separately on each chain,
+/// returning one report per chain in the original order.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_chains = chains.len()))
+)]
+pub fn stationarity_tests(chains: &Array2) -> Result<Vec<StationarityReport>, Error> {
+    let mut reports = Vec::new();
+    for chain in chains.iter() {
+        reports.push(StationarityReport {
+            kpss: kpss_test(chain)?,
+            adf: adf_test(chain)?,
+        });
+    }
+    Ok(reports)
+}
+
+fn check_finite(chain: &[f64]) -> Result<(), Error> {
+    if chain.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!(
+            "All values must be finite to compute stationarity tests"
+        ));
+    }
+    Ok(())
+}
+
+/// Newey-West estimate of the long run variance using Bartlett weights.
+fn long_run_variance(residuals: &[f64], lag: usize) -> f64 {
+    let n = residuals.len();
+    let mut s2 = residuals.iter().map(|e| e * e).sum::<f64>() / n as f64;
+    let mut correction = 0.0;
+    for i in 1..=lag.min(n - 1) {
+        let acov: f64 = (i..n).map(|j| residuals[j] * residuals[j - i]).sum();
+        correction += acov * (1.0 - i as f64 / (lag as f64 + 1.0));
+    }
+    s2 += 2.0 * correction / n as f64;
+    s2
+}
+
+/// Linear interpolation of `y` at `x0`, where `x` is monotonic in either
+/// direction.  Values outside the range of `x` are clamped to the end points.
+fn interpolate(x: &[f64], y: &[f64], x0: f64) -> f64 {
+    let (x, y): (Array1, Array1) = if x[0] > x[x.len() - 1] {
+        (
+            x.iter().rev().cloned().collect(),
+            y.iter().rev().cloned().collect(),
+        )
+    } else {
+        (x.to_vec(), y.to_vec())
+    };
+    if x0 <= x[0] {
+        return y[0];
+    }
+    for i in 1..x.len() {
+        if x0 <= x[i] {
+            let w = (x0 - x[i - 1]) / (x[i] - x[i - 1]);
+            return y[i - 1] + w * (y[i] - y[i - 1]);
+        }
+    }
+    y[y.len() - 1]
+}
+
+/// Ordinary least squares fit returning the coefficients and their standard
+/// errors, solving the normal equations by Gauss-Jordan elimination.
+fn least_squares(design: &Array2, response: &[f64]) -> Result<(Array1, Array1), Error> {
+    let n = design.len();
+    let p = design[0].len();
+    // augmented matrix [X'X | I] so we get the inverse of X'X along the way
+    let mut a = vec![vec![0.0; 2 * p]; p];
+    let mut xty = vec![0.0; p];
+    for (row, y) in design.iter().zip(response) {
+        for i in 0..p {
+            xty[i] += row[i] * y;
+            for j in 0..p {
+                a[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    for (i, a_i) in a.iter_mut().enumerate() {
+        a_i[p + i] = 1.0;
+    }
+    for col in 0..p {
+        let pivot = (col..p)
+            .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+            .unwrap();
+        if a[pivot][col].abs() < 1e-12 {
+            return Err(anyhow!("Regression design matrix is singular"));
+        }
+        a.swap(col, pivot);
+        let scale = a[col][col];
+        for v in a[col].iter_mut() {
+            *v /= scale;
+        }
+        let pivot_row = a[col].clone();
+        for (row, a_row) in a.iter_mut().enumerate() {
+            if row != col {
+                let factor = a_row[col];
+                for (v, pv) in a_row.iter_mut().zip(&pivot_row) {
+                    *v -= factor * pv;
+                }
+            }
+        }
+    }
+    let coefficients: Array1 = (0..p)
+        .map(|i| (0..p).map(|j| a[i][p + j] * xty[j]).sum())
+        .collect();
+    let rss: f64 = design
+        .iter()
+        .zip(response)
+        .map(|(row, y)| {
+            let fitted: f64 = row.iter().zip(&coefficients).map(|(x, b)| x * b).sum();
+            (y - fitted).powi(2)
+        })
+        .sum();
+    let sigma2 = rss / (n - p) as f64;
+    let std_errors = (0..p).map(|i| (sigma2 * a[i][p + i]).sqrt()).collect();
+    Ok((coefficients, std_errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use crate::utils::read_csv;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    #[cfg(feature = "fs")]
+    fn random_walk(chain: &[f64]) -> Array1 {
+        let mut total = 0.0;
+        chain
+            .iter()
+            .map(|x| {
+                total += x;
+                total
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_kpss_stationary_and_random_walk() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+
+        let stationary = kpss_test(&samples1[6]).unwrap();
+        assert_eq!(stationary.lag, 7);
+        assert!(stationary.p_value > 0.05);
+
+        let walk = kpss_test(&random_walk(&samples1[6])).unwrap();
+        assert!(walk.statistic > stationary.statistic);
+        assert_abs_diff_eq!(walk.p_value, 0.01);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_adf_stationary_and_random_walk() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+
+        let stationary = adf_test(&samples1[6]).unwrap();
+        assert_eq!(stationary.lag, 9);
+        assert_abs_diff_eq!(stationary.p_value, 0.01);
+
+        let walk = adf_test(&random_walk(&samples1[6])).unwrap();
+        assert!(walk.statistic > stationary.statistic);
+        assert!(walk.p_value > 0.05);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_stationarity_tests_per_chain() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[5].clone(), samples2[5].clone()];
+        let reports = stationarity_tests(&chains).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].kpss, kpss_test(&samples2[5]).unwrap());
+    }
+
+    #[test]
+    fn test_stationarity_bad_input() {
+        assert!(kpss_test(&[1.0, 2.0]).is_err());
+        assert!(kpss_test(&[1.0, 1.0, 1.0, 1.0]).is_err());
+        assert!(adf_test(&[1.0, 2.0, 3.0]).is_err());
+        assert!(adf_test(&[1.0, f64::NAN, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]).is_err());
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let x = [0.347, 0.463, 0.574, 0.739];
+        let y = [0.1, 0.05, 0.025, 0.01];
+        assert_abs_diff_eq!(interpolate(&x, &y, 0.1), 0.1);
+        assert_abs_diff_eq!(interpolate(&x, &y, 1.0), 0.01);
+        assert_abs_diff_eq!(interpolate(&x, &y, 0.405), 0.075, epsilon = 1e-12);
+        let reversed = [0.739, 0.574, 0.463, 0.347];
+        let rev_y = [0.01, 0.025, 0.05, 0.1];
+        assert_abs_diff_eq!(
+            interpolate(&reversed, &rev_y, 0.405),
+            0.075,
+            epsilon = 1e-12
+        );
+    }
+}
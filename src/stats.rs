@@ -0,0 +1,1227 @@
+use crate::checkpoint::{Decoder, Encoder};
+use crate::draws::Draws;
+use crate::ess::{compute_estimated_mcse, compute_split_effective_sample_size};
+use crate::spectral::ar_yule_walker;
+use crate::utils::{dot, flatten, mean, quantile_sorted, quantiles, sample_variance, Rng};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+use std::convert::TryFrom;
+
+/// Computes the sample autocorrelation of a single chain at the requested
+/// lags only, using the same biased autocovariance estimator as Stan and R's
+/// `acf`.  Each lag costs one pass over the chain, so asking for a handful of
+/// lags is much cheaper than computing the full autocorrelation function.
+///
+/// # Arguments
+/// * `chain` - Slice of samples for a single parameter from a single chain
+/// * `lags` - Lags to compute, in any order; each must be less than the chain length
+pub fn acf_at(chain: &[f64], lags: &[usize]) -> Result<Array1, Error> {
+    let n = chain.len();
+    if let Some(lag) = lags.iter().find(|&&lag| lag >= n) {
+        return Err(anyhow!(
+            "Lag {} is out of range for a chain of {} samples",
+            lag,
+            n
+        ));
+    }
+    let center = mean(chain)?;
+    let centered: Array1 = chain.iter().map(|x| x - center).collect();
+    let variance = dot(&centered, &centered);
+    if variance <= 0.0 {
+        return Err(anyhow!("No autocorrelation when elements are all constant"));
+    }
+    Ok(lags
+        .iter()
+        .map(|&lag| dot(&centered[..n - lag], &centered[lag..]) / variance)
+        .collect())
+}
+
+/// Descriptive statistics of a single chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainSummary {
+    /// Name of the chain, its [label](../draws/struct.ChainLabel.html) id or
+    /// its position counting from one
+    pub chain_name: String,
+    /// Number of draws in the chain
+    pub num_draws: usize,
+    /// Mean of the chain
+    pub mean: f64,
+    /// Standard deviation of the chain
+    pub sd: f64,
+    /// Smallest draw
+    pub min: f64,
+    /// Largest draw
+    pub max: f64,
+    /// 5% quantile
+    pub q5: f64,
+    /// Median
+    pub q50: f64,
+    /// 95% quantile
+    pub q95: f64,
+    /// Lag one autocorrelation, the coefficient of an AR(1) fit; `NaN` for a
+    /// constant chain or one with fewer than two draws
+    pub ar1: f64,
+    /// Mixing time implied by `ar1`, see
+    /// [`ChainMixing::ar1_mixing_time`](struct.ChainMixing.html#structfield.ar1_mixing_time)
+    pub ar1_mixing_time: f64,
+}
+
+/// Computes descriptive statistics for each chain separately, making it easy
+/// to spot the chain that disagrees with the others when R hat is high.  The
+/// AR(1) coefficient and mixing time of each chain point out the one that
+/// mixes more slowly than the others; see
+/// [`per_chain_mixing`](fn.per_chain_mixing.html) for higher order fits.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn per_chain_summary(chains: &Array2) -> Result<Vec<ChainSummary>, Error> {
+    per_chain_summary_named(chains, position_names(chains.len()))
+}
+
+/// Computes the [per chain summary](fn.per_chain_summary.html) of a
+/// parameter of the draws, over the chains at inverse temperature one,
+/// with the names of the chains, e.g. the chain ids of the sampler.
+///
+/// # Arguments
+/// * `draws` - Draws containing the parameter
+/// * `name` - Name of the parameter
+pub fn per_chain_summary_from_draws(draws: &Draws, name: &str) -> Result<Vec<ChainSummary>, Error> {
+    let (chains, chain_names) = named_target_chains(draws, name)?;
+    per_chain_summary_named(&chains, chain_names)
+}
+
+/// Names of chains known only by their position.
+fn position_names(num_chains: usize) -> Vec<String> {
+    (1..=num_chains).map(|c| c.to_string()).collect()
+}
+
+/// Chains of a parameter at inverse temperature one with their names.
+pub(crate) fn named_target_chains(
+    draws: &Draws,
+    name: &str,
+) -> Result<(Array2, Vec<String>), Error> {
+    let idx = draws
+        .index_of(name)
+        .ok_or_else(|| anyhow!("No parameter named {}", name))?;
+    let chain_names = (0..draws.target_chains().len())
+        .map(|c| draws.target_chain_name(c))
+        .collect();
+    Ok((draws.target_parameter(idx).into_owned(), chain_names))
+}
+
+fn per_chain_summary_named(
+    chains: &Array2,
+    chain_names: Vec<String>,
+) -> Result<Vec<ChainSummary>, Error> {
+    chains
+        .iter()
+        .zip(chain_names)
+        .map(|(chain, chain_name)| {
+            let q = quantiles(chain, &[0.0, 0.05, 0.5, 0.95, 1.0])
+                .with_context(|| format!("Failed to summarize chain {}", chain_name))?;
+            let ar1 = if chain.len() < 2 {
+                f64::NAN
+            } else {
+                acf_at(chain, &[1]).map_or(f64::NAN, |acf| acf[0])
+            };
+            Ok(ChainSummary {
+                chain_name,
+                num_draws: chain.len(),
+                mean: mean(chain)?,
+                sd: sample_variance(chain)?.sqrt(),
+                min: q[0],
+                max: q[4],
+                q5: q[1],
+                q50: q[2],
+                q95: q[3],
+                ar1,
+                ar1_mixing_time: mixing_time(ar1),
+            })
+        })
+        .collect()
+}
+
+/// Autoregressive summary of how quickly a single chain mixes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainMixing {
+    /// Name of the chain, as in
+    /// [`ChainSummary`](struct.ChainSummary.html#structfield.chain_name)
+    pub chain_name: String,
+    /// Coefficient of an AR(1) fit, the lag one autocorrelation
+    pub ar1: f64,
+    /// Number of draws for the AR(1) autocorrelation to decay by a factor
+    /// of `e`, `-1 / ln(ar1)`; zero for `ar1 <= 0` and infinite for
+    /// `ar1 >= 1`
+    pub ar1_mixing_time: f64,
+    /// Coefficients of the AR(p) model chosen by AIC, starting at lag one,
+    /// see [`ar_yule_walker`](../spectral/fn.ar_yule_walker.html)
+    pub ar_coefficients: Array1,
+    /// Integrated autocorrelation time implied by the AR(p) fit, the chain
+    /// length over its effective sample size; `(1 + ar1) / (1 - ar1)` for an
+    /// AR(1) model
+    pub integrated_time: f64,
+}
+
+/// Fits autoregressive models to each chain separately and reports the
+/// mixing times they imply, a cheap per chain complement to
+/// [`per_chain_summary`](fn.per_chain_summary.html): a chain whose AR(1)
+/// coefficient is much closer to one than those of the others mixes more
+/// slowly, e.g. because it is stuck in a narrow region.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn per_chain_mixing(chains: &Array2) -> Result<Vec<ChainMixing>, Error> {
+    per_chain_mixing_named(chains, position_names(chains.len()))
+}
+
+/// Computes the [per chain mixing](fn.per_chain_mixing.html) of a
+/// parameter of the draws, over the chains at inverse temperature one,
+/// with the names of the chains.
+///
+/// # Arguments
+/// * `draws` - Draws containing the parameter
+/// * `name` - Name of the parameter
+pub fn per_chain_mixing_from_draws(draws: &Draws, name: &str) -> Result<Vec<ChainMixing>, Error> {
+    let (chains, chain_names) = named_target_chains(draws, name)?;
+    per_chain_mixing_named(&chains, chain_names)
+}
+
+fn per_chain_mixing_named(
+    chains: &Array2,
+    chain_names: Vec<String>,
+) -> Result<Vec<ChainMixing>, Error> {
+    chains
+        .iter()
+        .zip(chain_names)
+        .map(|(chain, chain_name)| {
+            let context = || format!("Failed to fit chain {}", chain_name);
+            if chain.iter().any(|x| !x.is_finite()) {
+                return Err(anyhow!("Chain {} has NaN or infinite draws", chain_name));
+            }
+            let ar1 = acf_at(chain, &[1]).with_context(context)?[0];
+            let fit = ar_yule_walker(chain).with_context(context)?;
+            let total: f64 = fit.coefficients.iter().sum();
+            let variance = sample_variance(chain)?;
+            Ok(ChainMixing {
+                chain_name,
+                ar1,
+                ar1_mixing_time: mixing_time(ar1),
+                integrated_time: fit.var_pred / ((1.0 - total).powi(2) * variance),
+                ar_coefficients: fit.coefficients,
+            })
+        })
+        .collect()
+}
+
+/// Number of draws for an AR(1) autocorrelation to decay by a factor of `e`.
+fn mixing_time(ar1: f64) -> f64 {
+    if ar1 <= 0.0 {
+        0.0
+    } else if ar1 >= 1.0 {
+        f64::INFINITY
+    } else {
+        -1.0 / ar1.ln()
+    }
+}
+
+/// Cross-correlation of two parameters, see
+/// [`cross_correlation`](fn.cross_correlation.html).  Lag `k` pairs draw
+/// `t` of the first parameter with draw `t + k` of the second, so the
+/// correlations are stored for lags `-max_lag..=max_lag` at positions
+/// `0..=2 * max_lag`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossCorrelation {
+    /// Largest absolute lag computed
+    pub max_lag: usize,
+    /// Cross-correlation of each chain
+    pub per_chain: Vec<Array1>,
+    /// Cross-correlation pooled over the chains, from the sums of the
+    /// within chain cross-covariances and variances
+    pub pooled: Array1,
+}
+
+impl CrossCorrelation {
+    /// Pooled cross-correlation at lag `k`, if `|k| <= max_lag`.
+    pub fn at(&self, lag: i64) -> Option<f64> {
+        let pos = lag.checked_add(self.max_lag as i64)?;
+        usize::try_from(pos)
+            .ok()
+            .and_then(|p| self.pooled.get(p))
+            .copied()
+    }
+}
+
+/// Computes the cross-correlation of two parameters within each chain and
+/// pooled over chains, at lag zero and up to `max_lag` draws in either
+/// direction, with the same biased estimator as [`acf_at`](fn.acf_at.html)
+/// and R's `ccf`.  Strongly correlated pairs such as the intercept and slope
+/// of a regression on uncentered predictors mix slowly together: a sampler
+/// that can only move along the ridge shows large cross-correlations at
+/// long lags, which the autocorrelations of each parameter on its own don't
+/// attribute to the pair.
+///
+/// # Arguments
+/// * `a` - Draws of the first parameter, one vector per chain
+/// * `b` - Draws of the second parameter with the same shape as `a`
+/// * `max_lag` - Largest lag, less than the length of every chain
+pub fn cross_correlation(
+    a: &Array2,
+    b: &Array2,
+    max_lag: usize,
+) -> Result<CrossCorrelation, Error> {
+    if a.is_empty() || a.len() != b.len() || a.iter().zip(b).any(|(x, y)| x.len() != y.len()) {
+        return Err(anyhow!(
+            "Both parameters must have the same number of chains and draws"
+        ));
+    }
+    if let Some((idx, x)) = a.iter().enumerate().find(|(_, x)| max_lag >= x.len()) {
+        return Err(anyhow!(
+            "Lag {} is out of range for chain {} of {} samples",
+            max_lag,
+            idx + 1,
+            x.len()
+        ));
+    }
+    let width = 2 * max_lag + 1;
+    let mut per_chain = Vec::with_capacity(a.len());
+    let mut pooled_cov = vec![0.0; width];
+    let (mut pooled_aa, mut pooled_bb) = (0.0, 0.0);
+    for (idx, (x, y)) in a.iter().zip(b).enumerate() {
+        let n = x.len();
+        let center = |chain: &[f64]| -> Result<Array1, Error> {
+            let m = mean(chain)?;
+            Ok(chain.iter().map(|v| v - m).collect())
+        };
+        let (x, y) = (center(x)?, center(y)?);
+        let (aa, bb) = (dot(&x, &x), dot(&y, &y));
+        if aa <= 0.0 || bb <= 0.0 {
+            return Err(anyhow!(
+                "No cross-correlation when chain {} is constant",
+                idx + 1
+            ));
+        }
+        let cov: Array1 = (0..width)
+            .map(|pos| {
+                if pos >= max_lag {
+                    let lag = pos - max_lag;
+                    dot(&x[..n - lag], &y[lag..])
+                } else {
+                    let lag = max_lag - pos;
+                    dot(&x[lag..], &y[..n - lag])
+                }
+            })
+            .collect();
+        for (total, c) in pooled_cov.iter_mut().zip(cov.iter()) {
+            *total += c;
+        }
+        pooled_aa += aa;
+        pooled_bb += bb;
+        let scale = (aa * bb).sqrt();
+        per_chain.push(cov.iter().map(|c| c / scale).collect());
+    }
+    let scale = (pooled_aa * pooled_bb).sqrt();
+    Ok(CrossCorrelation {
+        max_lag,
+        per_chain,
+        pooled: pooled_cov.iter().map(|c| c / scale).collect(),
+    })
+}
+
+/// Streaming estimate of a single quantile with the P-square algorithm of
+/// Jain and Chlamtac (1985), which keeps five markers whose heights are
+/// adjusted with piecewise parabolic interpolation as values arrive.  Memory
+/// use is constant, so it suits monitoring long runs where storing every
+/// draw is not an option.  The estimate is exact for up to five values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct P2Quantile {
+    prob: f64,
+    count: usize,
+    // marker heights, actual positions, desired positions and the increments
+    // of the desired positions, with positions starting from one
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    /// Creates an estimator of the `prob` quantile, which must be in (0, 1).
+    pub fn new(prob: f64) -> Result<P2Quantile, Error> {
+        if !(prob > 0.0 && prob < 1.0) {
+            return Err(anyhow!(
+                "Quantile probability must be in (0, 1), got {}",
+                prob
+            ));
+        }
+        Ok(P2Quantile {
+            prob,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [
+                1.0,
+                1.0 + 2.0 * prob,
+                1.0 + 4.0 * prob,
+                3.0 + 2.0 * prob,
+                5.0,
+            ],
+            increments: [0.0, prob / 2.0, prob, (1.0 + prob) / 2.0, 1.0],
+        })
+    }
+
+    /// Adds a single value.  NaN and infinite values are ignored, since
+    /// they have no place among the markers.
+    pub fn push(&mut self, x: f64) {
+        if !x.is_finite() {
+            return;
+        }
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(|a, b| a.total_cmp(b));
+            }
+            return;
+        }
+        self.count += 1;
+        let q = &mut self.heights;
+        let k = if x < q[0] {
+            q[0] = x;
+            0
+        } else if x >= q[4] {
+            q[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < q[i + 1]).unwrap()
+        };
+        for n in self.positions[k + 1..].iter_mut() {
+            *n += 1.0;
+        }
+        for (d, inc) in self.desired.iter_mut().zip(self.increments.iter()) {
+            *d += inc;
+        }
+        for i in 1..4 {
+            let n = &mut self.positions;
+            let d = self.desired[i] - n[i];
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = d.signum();
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+                q[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    /// Probability of the estimated quantile.
+    pub fn prob(&self) -> f64 {
+        self.prob
+    }
+
+    /// Number of finite values seen so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Current estimate of the quantile, or `None` before the first value.
+    /// Up to five values it is the exact quantile of the values seen, since
+    /// the middle marker only tracks the quantile once the markers move.
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=5 => {
+                let mut sorted = self.heights[..self.count].to_vec();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                quantile_sorted(&sorted, self.prob).ok()
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+
+    /// Appends the state of the estimator to a checkpoint.
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.f64(self.prob);
+        out.usize(self.count);
+        for markers in [
+            &self.heights,
+            &self.positions,
+            &self.desired,
+            &self.increments,
+        ] {
+            for &x in markers.iter() {
+                out.f64(x);
+            }
+        }
+    }
+
+    /// Restores an estimator saved with [`encode`](#method.encode).
+    pub(crate) fn decode(input: &mut Decoder) -> Result<P2Quantile, Error> {
+        let mut sketch = P2Quantile::new(input.f64()?)?;
+        sketch.count = input.usize()?;
+        for markers in [
+            &mut sketch.heights,
+            &mut sketch.positions,
+            &mut sketch.desired,
+            &mut sketch.increments,
+        ] {
+            for x in markers.iter_mut() {
+                *x = input.f64()?;
+            }
+        }
+        Ok(sketch)
+    }
+}
+
+/// Fixed size uniform random subsample of a stream of values of unknown
+/// length, maintained with reservoir sampling (Vitter's algorithm R).  After
+/// any number of pushes every value seen so far is in the sample with the
+/// same probability, so histograms and density estimates of the sample are
+/// representative of the whole stream while memory stays bounded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reservoir {
+    capacity: usize,
+    count: usize,
+    sample: Array1,
+    rng: Rng,
+}
+
+impl Reservoir {
+    /// Creates an empty reservoir holding at most `capacity` values.  The
+    /// same seed always selects the same subsample of the same stream.
+    pub fn new(capacity: usize, seed: u64) -> Reservoir {
+        Reservoir {
+            capacity,
+            count: 0,
+            sample: Vec::with_capacity(capacity),
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Offers a single value to the reservoir.
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        if self.sample.len() < self.capacity {
+            self.sample.push(x);
+        } else {
+            let j = self.rng.below(self.count);
+            if j < self.capacity {
+                self.sample[j] = x;
+            }
+        }
+    }
+
+    /// Number of values seen so far, including those not kept.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Current subsample, in no particular order.
+    pub fn sample(&self) -> &[f64] {
+        &self.sample
+    }
+
+    /// Appends the state of the reservoir, including its random number
+    /// generator, to a checkpoint.
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.usize(self.capacity);
+        out.usize(self.count);
+        out.f64s(&self.sample);
+        out.u64(self.rng.state());
+    }
+
+    /// Restores a reservoir saved with [`encode`](#method.encode).
+    pub(crate) fn decode(input: &mut Decoder) -> Result<Reservoir, Error> {
+        let capacity = input.usize()?;
+        let count = input.usize()?;
+        let sample = input.f64s()?;
+        if sample.len() != capacity.min(count) {
+            return Err(anyhow!(
+                "Reservoir of capacity {} holds {} of {} values",
+                capacity,
+                sample.len(),
+                count
+            ));
+        }
+        let rng = Rng::from_state(input.u64()?)
+            .ok_or_else(|| anyhow!("Invalid random number generator state"))?;
+        Ok(Reservoir {
+            capacity,
+            count,
+            sample,
+            rng,
+        })
+    }
+}
+
+/// How two parameters are compared by [`contrast`](fn.contrast.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContrastKind {
+    /// First parameter minus the second
+    #[default]
+    Difference,
+    /// First parameter divided by the second
+    Ratio,
+}
+
+/// Options of [`contrast_with_options`](fn.contrast_with_options.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContrastOptions {
+    /// Difference or ratio of the two parameters
+    pub kind: ContrastKind,
+    /// Probability mass of the highest density interval
+    pub hdi_prob: f64,
+}
+
+impl Default for ContrastOptions {
+    fn default() -> ContrastOptions {
+        ContrastOptions {
+            kind: ContrastKind::Difference,
+            hdi_prob: 0.9,
+        }
+    }
+}
+
+/// Posterior summary of the difference or ratio of two parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contrast {
+    /// Posterior mean of the contrast
+    pub mean: f64,
+    /// Monte Carlo standard error of the mean
+    pub mcse: f64,
+    /// Posterior standard deviation of the contrast
+    pub sd: f64,
+    /// Lower end of the highest density interval
+    pub hdi_lower: f64,
+    /// Upper end of the highest density interval
+    pub hdi_upper: f64,
+    /// Posterior probability that the contrast is above its null value,
+    /// zero for a difference and one for a ratio
+    pub prob_greater: f64,
+}
+
+/// Summarizes the posterior of the difference between two parameters, see
+/// [`contrast_with_options`](fn.contrast_with_options.html).
+///
+/// # Arguments
+/// * `draws` - Draws holding both parameters
+/// * `first` - Name of the first parameter, e.g. `beta[1]`
+/// * `second` - Name of the parameter subtracted from the first
+pub fn contrast(draws: &Draws, first: &str, second: &str) -> Result<Contrast, Error> {
+    contrast_with_options(draws, first, second, &ContrastOptions::default())
+}
+
+/// Summarizes the posterior of the difference or ratio of two parameters.
+/// The contrast is computed draw by draw within each chain, so that the
+/// correlation between the parameters is kept and the MCSE accounts for the
+/// autocorrelation of the contrast itself.  Comparing separately computed
+/// summaries, or pairing draws after flattening chains of different
+/// lengths, gets both wrong.  Tempered chains are left out.
+///
+/// # Arguments
+/// * `draws` - Draws holding both parameters
+/// * `first` - Name of the first parameter
+/// * `second` - Name of the second parameter
+/// * `options` - Difference or ratio, and the mass of the interval
+pub fn contrast_with_options(
+    draws: &Draws,
+    first: &str,
+    second: &str,
+    options: &ContrastOptions,
+) -> Result<Contrast, Error> {
+    let lookup = |name: &str| {
+        draws
+            .index_of(name)
+            .ok_or_else(|| anyhow!("No parameter named {:?}", name))
+    };
+    let a = draws.target_parameter(lookup(first)?);
+    let b = draws.target_parameter(lookup(second)?);
+    let (combine, null): (fn(f64, f64) -> f64, f64) = match options.kind {
+        ContrastKind::Difference => (|x, y| x - y, 0.0),
+        ContrastKind::Ratio => (|x, y| x / y, 1.0),
+    };
+    let chains: Array2 = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| {
+            x.iter()
+                .zip(y.iter())
+                .map(|(&x, &y)| combine(x, y))
+                .collect()
+        })
+        .collect();
+    let mut sorted = flatten(&chains);
+    if sorted.iter().any(|x| x.is_nan()) {
+        return Err(anyhow!("Contrast of {} and {} is undefined", first, second));
+    }
+    sorted.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    let (hdi_lower, hdi_upper) = hdi(&sorted, options.hdi_prob)?;
+    Ok(Contrast {
+        mean: mean(&sorted)?,
+        mcse: compute_estimated_mcse(&chains)
+            .with_context(|| format!("Failed to compute MCSE of {} and {}", first, second))?,
+        sd: sample_variance(&sorted)?.sqrt(),
+        hdi_lower,
+        hdi_upper,
+        prob_greater: sorted.iter().filter(|&&x| x > null).count() as f64 / sorted.len() as f64,
+    })
+}
+
+/// Shortest interval between two draws that contains at least the given
+/// fraction of the sorted draws, as computed by ArviZ.  The draws must be
+/// finite, since the width of an interval between two equal infinities is
+/// undefined.
+pub(crate) fn hdi(sorted: &[f64], prob: f64) -> Result<(f64, f64), Error> {
+    if !(prob > 0.0 && prob < 1.0) {
+        return Err(anyhow!(
+            "Interval probability must be in (0, 1), got {}",
+            prob
+        ));
+    }
+    if sorted.is_empty() {
+        return Err(anyhow!("Can't compute interval of empty array"));
+    }
+    if sorted.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("Can't compute interval of non-finite draws"));
+    }
+    let n = sorted.len();
+    // number of draws spanned, beyond the first, by each candidate interval
+    let width = ((prob * n as f64).floor() as usize).min(n - 1);
+    let start = (0..n - width)
+        .min_by(|&i, &j| {
+            (sorted[i + width] - sorted[i])
+                .partial_cmp(&(sorted[j + width] - sorted[j]))
+                .unwrap()
+        })
+        .unwrap();
+    Ok((sorted[start], sorted[start + width]))
+}
+
+/// Estimate of the posterior probability of an event, see
+/// [`event_probability`](fn.event_probability.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventProbability {
+    /// Fraction of draws in which the event happened
+    pub prob: f64,
+    /// Monte Carlo standard error of the probability, from the effective
+    /// sample size of the indicator series
+    pub mcse: f64,
+    /// Binomial standard error `sqrt(p (1 - p) / n)` that treats the draws
+    /// as independent, which is too small for autocorrelated chains
+    pub naive_mcse: f64,
+    /// Split effective sample size of the indicator series, `NaN` when the
+    /// event happened in all draws or in none
+    pub ess: f64,
+}
+
+/// Estimates the posterior probability of an event, e.g. `|x| x > 0.0` for
+/// `Pr(theta > 0)`, with a Monte Carlo standard error that accounts for the
+/// autocorrelation of the chains.  The indicator of the event has its own
+/// autocorrelation, often stronger than that of the parameter for events in
+/// the tails, so the standard error uses the effective sample size of the
+/// indicator series rather than the number of draws.  When the event happened
+/// in all draws or in none the standard error is zero, which only means the
+/// probability is too extreme to estimate from this many draws.
+///
+/// # Arguments
+/// * `chains` - Draws of the parameter, one vector per chain
+/// * `event` - Whether a draw is in the event
+pub fn event_probability<F>(chains: &Array2, event: F) -> Result<EventProbability, Error>
+where
+    F: Fn(f64) -> bool,
+{
+    let indicators: Array2 = chains
+        .iter()
+        .map(|c| c.iter().map(|&x| event(x) as u8 as f64).collect())
+        .collect();
+    let flat = flatten(&indicators);
+    let prob = mean(&flat)?;
+    let variance = prob * (1.0 - prob);
+    let naive_mcse = (variance / flat.len() as f64).sqrt();
+    if variance == 0.0 {
+        return Ok(EventProbability {
+            prob,
+            mcse: 0.0,
+            naive_mcse,
+            ess: f64::NAN,
+        });
+    }
+    let ess = compute_split_effective_sample_size(&indicators)?;
+    Ok(EventProbability {
+        prob,
+        mcse: (variance / ess).sqrt(),
+        naive_mcse,
+        ess,
+    })
+}
+
+/// Number of grid points of the kernel density estimate of
+/// [`hdr`](fn.hdr.html).
+const HDR_GRID: usize = 512;
+
+/// Gaussian kernel density estimate of sorted finite draws on a grid of
+/// `HDR_GRID` points from the smallest to the largest draw, with
+/// Silverman's rule of thumb bandwidth.  Returns the first grid point, the
+/// grid step and the unnormalized density at every grid point.
+pub(crate) fn kde_grid(sorted: &[f64]) -> Result<(f64, f64, Array1), Error> {
+    let n = sorted.len() as f64;
+    let sd = sample_variance(sorted)?.sqrt();
+    let iqr = quantile_sorted(sorted, 0.75)? - quantile_sorted(sorted, 0.25)?;
+    let spread = if iqr > 0.0 { sd.min(iqr / 1.34) } else { sd };
+    if spread <= 0.0 {
+        return Err(anyhow!("Can't compute density of constant draws"));
+    }
+    let bandwidth = 0.9 * spread * n.powf(-0.2);
+
+    // linear binning of the draws onto the grid, then the density at every
+    // grid point from the kernels of the bins within four bandwidths
+    let (lo, hi) = (sorted[0], sorted[sorted.len() - 1]);
+    let step = (hi - lo) / (HDR_GRID - 1) as f64;
+    let mut weights = vec![0.0; HDR_GRID];
+    for x in sorted.iter() {
+        let pos = (x - lo) / step;
+        let i = (pos.floor() as usize).min(HDR_GRID - 2);
+        let frac = pos - i as f64;
+        weights[i] += 1.0 - frac;
+        weights[i + 1] += frac;
+    }
+    let reach = ((4.0 * bandwidth / step).ceil() as usize).min(HDR_GRID);
+    let kernel: Array1 = (0..=reach)
+        .map(|k| (-0.5 * (k as f64 * step / bandwidth).powi(2)).exp())
+        .collect();
+    let density: Array1 = (0..HDR_GRID)
+        .map(|i| {
+            let from = i.saturating_sub(reach);
+            let to = (i + reach).min(HDR_GRID - 1);
+            (from..=to)
+                .map(|j| weights[j] * kernel[i.abs_diff(j)])
+                .sum()
+        })
+        .collect();
+    Ok((lo, step, density))
+}
+
+/// Highest density region of a parameter, see [`hdr`](fn.hdr.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hdr {
+    /// Disjoint intervals making up the region, in increasing order
+    pub intervals: Vec<(f64, f64)>,
+    /// Whether the region has more than one interval, i.e. the density has
+    /// several well separated modes
+    pub multimodal: bool,
+}
+
+/// Computes the highest density region of a parameter, the smallest set
+/// holding the given posterior probability, which unlike the highest density
+/// interval can consist of several intervals.  For a bimodal posterior a
+/// single interval covers the low density gap between the modes, which is
+/// misleading; here each mode gets its own interval and the region is
+/// flagged as multimodal.
+///
+/// The density is a Gaussian kernel density estimate on a grid with
+/// Silverman's rule of thumb bandwidth, and the region is every grid point
+/// whose density is above the level at which the grid points hold `prob` of
+/// the total mass, as with `multimodal=True` in ArviZ.  Tempered chains should
+/// be left out by the caller.
+///
+/// # Arguments
+/// * `chains` - Draws of the parameter, one vector per chain
+/// * `prob` - Probability mass of the region, e.g. 0.9
+pub fn hdr(chains: &Array2, prob: f64) -> Result<Hdr, Error> {
+    if !(prob > 0.0 && prob < 1.0) {
+        return Err(anyhow!(
+            "Region probability must be in (0, 1), got {}",
+            prob
+        ));
+    }
+    let mut sorted = flatten(chains);
+    if sorted.len() < 2 {
+        return Err(anyhow!("Need at least 2 draws to compute a density"));
+    }
+    if sorted.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("Can't compute density of non-finite draws"));
+    }
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (lo, step, density) = kde_grid(&sorted)?;
+    let hi = sorted[sorted.len() - 1];
+
+    let mut order: Vec<usize> = (0..HDR_GRID).collect();
+    order.sort_by(|&a, &b| density[b].partial_cmp(&density[a]).unwrap());
+    let total: f64 = density.iter().sum();
+    let mut mass = 0.0;
+    let mut level = 0.0;
+    for &i in order.iter() {
+        mass += density[i];
+        level = density[i];
+        if mass >= prob * total {
+            break;
+        }
+    }
+    let mut intervals = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, &d) in density.iter().enumerate() {
+        match (d >= level, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                intervals.push((lo + s as f64 * step, lo + (i - 1) as f64 * step));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        intervals.push((lo + s as f64 * step, hi));
+    }
+    Ok(Hdr {
+        multimodal: intervals.len() > 1,
+        intervals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use crate::utils::read_csv;
+    #[cfg(feature = "fs")]
+    use arima::acf;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_acf_at_matches_full_acf() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let chain = &samples[4];
+        let full = acf::acf(chain, Some(50), false).unwrap();
+        let lags = [50, 1, 0, 5, 10];
+        let actual = acf_at(chain, &lags).unwrap();
+        for (lag, rho) in lags.iter().zip(actual.iter()) {
+            assert_abs_diff_eq!(*rho, full[*lag], epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_event_probability() {
+        // strongly autocorrelated AR(1) chains with coefficient 0.9
+        let chains: Array2 = (0..4)
+            .map(|c| {
+                let mut x = 0.0;
+                crate::utils::normal_draws(2000, 20 + c)
+                    .iter()
+                    .map(|e| {
+                        x = 0.9 * x + e;
+                        x
+                    })
+                    .collect()
+            })
+            .collect();
+        let estimate = event_probability(&chains, |x| x > 0.0).unwrap();
+        assert_abs_diff_eq!(estimate.prob, 0.5, epsilon = 0.1);
+        assert_abs_diff_eq!(
+            estimate.naive_mcse,
+            (estimate.prob * (1.0 - estimate.prob) / 8000.0).sqrt()
+        );
+        // the binomial formula is several times too small here
+        assert!(estimate.mcse > 2.5 * estimate.naive_mcse);
+        assert!(estimate.ess < 2000.0);
+
+        let independent: Array2 = (0..4)
+            .map(|c| crate::utils::normal_draws(2000, 30 + c))
+            .collect();
+        let estimate = event_probability(&independent, |x| x > 0.0).unwrap();
+        assert_abs_diff_eq!(estimate.mcse / estimate.naive_mcse, 1.0, epsilon = 0.15);
+
+        let never = event_probability(&independent, |x| x > 100.0).unwrap();
+        assert_eq!((never.prob, never.mcse), (0.0, 0.0));
+        assert!(never.ess.is_nan());
+        assert!(event_probability(&vec![vec![]], |x| x > 0.0).is_err());
+    }
+
+    #[test]
+    fn test_hdr() {
+        let normal = crate::utils::normal_draws(4000, 3);
+        let unimodal = hdr(&vec![normal.clone()], 0.9).unwrap();
+        assert!(!unimodal.multimodal);
+        assert_eq!(unimodal.intervals.len(), 1);
+        let (lower, upper) = unimodal.intervals[0];
+        assert_abs_diff_eq!(lower, -1.645, epsilon = 0.15);
+        assert_abs_diff_eq!(upper, 1.645, epsilon = 0.15);
+
+        // two well separated modes get an interval each
+        let chains = vec![
+            normal.iter().map(|x| x - 4.0).collect(),
+            crate::utils::normal_draws(4000, 4)
+                .iter()
+                .map(|x| x + 4.0)
+                .collect(),
+        ];
+        let bimodal = hdr(&chains, 0.9).unwrap();
+        assert!(bimodal.multimodal);
+        assert_eq!(bimodal.intervals.len(), 2);
+        let (a, b) = (bimodal.intervals[0], bimodal.intervals[1]);
+        assert!(a.0 < -5.0 && a.1 > -3.0 && a.1 < 0.0);
+        assert!(b.0 > 0.0 && b.0 < 3.0 && b.1 > 5.0);
+        // a single interval would cover the empty gap between the modes
+        let mut sorted = flatten(&chains);
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let (lower, upper) = hdi(&sorted, 0.9).unwrap();
+        assert!(lower < -5.0 && upper > 5.0);
+
+        assert!(hdr(&vec![vec![1.0; 10]], 0.9).is_err());
+        assert!(hdr(&chains, 1.0).is_err());
+        assert!(hdr(&vec![vec![1.0, f64::NAN]], 0.5).is_err());
+    }
+
+    #[test]
+    fn test_p2_quantile() {
+        let draws = crate::utils::normal_draws(20_000, 5);
+        let exact = crate::utils::quantiles(&draws, &[0.05, 0.5, 0.95]).unwrap();
+        for (prob, expected) in [0.05, 0.5, 0.95].iter().zip(exact.iter()) {
+            let mut sketch = P2Quantile::new(*prob).unwrap();
+            for x in draws.iter() {
+                sketch.push(*x);
+            }
+            assert_eq!(sketch.count(), 20_000);
+            assert_abs_diff_eq!(sketch.estimate().unwrap(), expected, epsilon = 0.03);
+        }
+    }
+
+    #[test]
+    fn test_p2_quantile_few_values() {
+        let mut sketch = P2Quantile::new(0.5).unwrap();
+        assert!(sketch.estimate().is_none());
+        for x in [3.0, 1.0, 2.0, 10.0] {
+            sketch.push(x);
+        }
+        assert_abs_diff_eq!(sketch.estimate().unwrap(), 2.5);
+        // the fifth value still gives the exact quantile rather than the
+        // middle marker
+        let mut tail = P2Quantile::new(0.95).unwrap();
+        for x in [3.0, 1.0, 2.0, 10.0, 4.0] {
+            tail.push(x);
+        }
+        assert_abs_diff_eq!(tail.estimate().unwrap(), 8.8, epsilon = 1e-12);
+        for x in [f64::NAN, 5.0, f64::NEG_INFINITY, 0.0, 7.0] {
+            sketch.push(x);
+        }
+        assert_eq!(sketch.count(), 7);
+        assert_abs_diff_eq!(sketch.estimate().unwrap(), 3.0);
+        assert!(P2Quantile::new(1.0).is_err());
+    }
+
+    #[test]
+    fn test_reservoir() {
+        let mut reservoir = Reservoir::new(100, 1);
+        for i in 0..50 {
+            reservoir.push(i as f64);
+        }
+        // everything is kept until the reservoir is full
+        assert_eq!(reservoir.sample().len(), 50);
+        for i in 50..100_000 {
+            reservoir.push(i as f64);
+        }
+        assert_eq!(reservoir.count(), 100_000);
+        assert_eq!(reservoir.sample().len(), 100);
+        // the subsample is spread over the whole stream
+        let m = mean(reservoir.sample()).unwrap();
+        assert!((m / 50_000.0 - 1.0).abs() < 0.2);
+
+        let mut again = Reservoir::new(100, 1);
+        for i in 0..100_000 {
+            again.push(i as f64);
+        }
+        assert_eq!(again, reservoir);
+    }
+
+    #[test]
+    fn test_cross_correlation() {
+        // the second parameter follows the first two draws later
+        let chains: Vec<(Array1, Array1)> = (0..2)
+            .map(|c| {
+                let x = crate::utils::normal_draws(2000, 30 + c);
+                let noise = crate::utils::normal_draws(2000, 40 + c);
+                let y = (0..x.len())
+                    .map(|t| if t >= 2 { x[t - 2] } else { 0.0 } + 0.5 * noise[t])
+                    .collect();
+                (x, y)
+            })
+            .collect();
+        let a: Array2 = chains.iter().map(|c| c.0.clone()).collect();
+        let b: Array2 = chains.iter().map(|c| c.1.clone()).collect();
+        let result = cross_correlation(&a, &b, 5).unwrap();
+        assert_eq!(result.per_chain.len(), 2);
+        assert_eq!(result.pooled.len(), 11);
+        // corr(x, x + 0.5 e) = 1 / sqrt(1.25)
+        assert_abs_diff_eq!(result.at(2).unwrap(), 0.894, epsilon = 0.03);
+        assert_abs_diff_eq!(result.per_chain[1][7], 0.894, epsilon = 0.03);
+        assert!(result.at(0).unwrap().abs() < 0.1);
+        assert!(result.at(-2).unwrap().abs() < 0.1);
+        assert_eq!(result.at(6), None);
+        assert_eq!(result.at(-6), None);
+
+        // lag zero is the correlation, and swapping the parameters mirrors
+        // the lags
+        let single = cross_correlation(&a[..1].to_vec(), &a[..1].to_vec(), 3).unwrap();
+        assert_abs_diff_eq!(single.at(0).unwrap(), 1.0, epsilon = 1e-12);
+        assert_eq!(single.per_chain[0], single.pooled);
+        let acf = acf_at(&a[0], &[1, 2, 3]).unwrap();
+        assert_abs_diff_eq!(single.at(-3).unwrap(), acf[2], epsilon = 1e-12);
+        assert_abs_diff_eq!(single.at(1).unwrap(), acf[0], epsilon = 1e-12);
+        let swapped = cross_correlation(&b, &a, 5).unwrap();
+        assert_abs_diff_eq!(
+            swapped.at(-2).unwrap(),
+            result.at(2).unwrap(),
+            epsilon = 1e-12
+        );
+
+        assert!(cross_correlation(&a, &b[..1].to_vec(), 5).is_err());
+        assert!(cross_correlation(&a, &b, 2000).is_err());
+        assert!(cross_correlation(&a, &b, usize::MAX).is_err());
+        assert!(cross_correlation(&a, &b, usize::MAX / 2).is_err());
+        assert!(cross_correlation(&vec![vec![1.0; 10]], &vec![a[0][..10].to_vec()], 1).is_err());
+    }
+
+    #[test]
+    fn test_per_chain_summary() {
+        let chains = vec![(1..=21).map(f64::from).collect(), vec![-1.0, 1.0]];
+        let summaries = per_chain_summary(&chains).unwrap();
+        assert_eq!(summaries.len(), 2);
+        let first = &summaries[0];
+        assert_eq!(first.chain_name, "1");
+        assert_eq!(first.num_draws, 21);
+        assert_abs_diff_eq!(first.mean, 11.0);
+        assert_abs_diff_eq!(first.sd, (38.5f64).sqrt(), epsilon = 1e-12);
+        assert_eq!((first.min, first.max), (1.0, 21.0));
+        assert_abs_diff_eq!(first.q5, 2.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(first.q50, 11.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(first.q95, 20.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(summaries[1].mean, 0.0);
+        assert_abs_diff_eq!(first.ar1, acf_at(&chains[0], &[1]).unwrap()[0]);
+        assert_abs_diff_eq!(first.ar1_mixing_time, -1.0 / first.ar1.ln());
+        let constant = per_chain_summary(&vec![vec![2.0; 5]]).unwrap();
+        assert!(constant[0].ar1.is_nan() && constant[0].ar1_mixing_time.is_nan());
+        assert!(per_chain_summary(&vec![vec![1.0], vec![]]).is_err());
+
+        // the draws based variant names the chains by their labels and
+        // leaves out tempered chains
+        let names = vec!["x".to_string()];
+        let mut draws = Draws::from_chains(
+            names,
+            vec![
+                vec![chains[0].clone()],
+                vec![chains[0].clone()],
+                vec![chains[1].clone()],
+            ],
+        )
+        .unwrap();
+        draws
+            .set_chain_label(0, crate::draws::ChainLabel::new("worker-7"))
+            .unwrap();
+        draws.set_inverse_temperature(1, 0.5).unwrap();
+        let named = per_chain_summary_from_draws(&draws, "x").unwrap();
+        assert_eq!(named.len(), 2);
+        assert_eq!(named[0].chain_name, "worker-7");
+        assert_eq!(named[1].chain_name, "3");
+        assert_eq!(named[0].mean, first.mean);
+        let mixing = per_chain_mixing_from_draws(&draws, "x").unwrap();
+        assert_eq!(mixing[0].chain_name, "worker-7");
+        assert_eq!(mixing[1].chain_name, "3");
+        assert!(per_chain_summary_from_draws(&draws, "y").is_err());
+    }
+
+    #[test]
+    fn test_per_chain_mixing() {
+        // AR(1) chains with coefficients 0.9 and 0, whose integrated
+        // autocorrelation times are 19 and 1
+        let noise = crate::utils::normal_draws(20000, 3);
+        let mut ar = vec![0.0; noise.len()];
+        for i in 1..noise.len() {
+            ar[i] = 0.9 * ar[i - 1] + noise[i];
+        }
+        let chains = vec![ar, crate::utils::normal_draws(20000, 4)];
+        let mixing = per_chain_mixing(&chains).unwrap();
+        assert_abs_diff_eq!(mixing[0].ar1, 0.9, epsilon = 0.01);
+        assert_abs_diff_eq!(mixing[0].ar1_mixing_time, -1.0 / 0.9f64.ln(), epsilon = 1.0);
+        assert_abs_diff_eq!(mixing[0].ar_coefficients[0], 0.9, epsilon = 0.02);
+        assert_abs_diff_eq!(mixing[0].integrated_time, 19.0, epsilon = 2.0);
+        assert_abs_diff_eq!(mixing[1].integrated_time, 1.0, epsilon = 0.1);
+        assert!(mixing[1].ar1_mixing_time < 0.5);
+
+        assert!(per_chain_mixing(&vec![vec![1.0; 10]]).is_err());
+        let mut nan = crate::utils::normal_draws(100, 5);
+        nan[10] = f64::NAN;
+        assert!(per_chain_mixing(&vec![nan]).is_err());
+    }
+
+    #[test]
+    fn test_contrast() {
+        // b shares most of its variation with a, so the paired difference
+        // is much more precise than the two marginals suggest
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chains: Vec<Array2> = (0..2)
+            .map(|c| {
+                let common = crate::utils::normal_draws(1000, 2 * c);
+                let noise = crate::utils::normal_draws(1000, 2 * c + 1);
+                let a: Array1 = common.iter().map(|x| 10.0 + x).collect();
+                let b = common
+                    .iter()
+                    .zip(noise.iter())
+                    .map(|(x, e)| 9.0 + x + 0.1 * e)
+                    .collect();
+                vec![a, b]
+            })
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+
+        let diff = contrast(&draws, "a", "b").unwrap();
+        assert_abs_diff_eq!(diff.mean, 1.0, epsilon = 0.02);
+        assert!(diff.sd < 0.12);
+        assert!(diff.mcse < 0.01);
+        assert!(diff.hdi_lower < 1.0 && 1.0 < diff.hdi_upper);
+        assert!(diff.hdi_upper - diff.hdi_lower < 0.4);
+        assert_abs_diff_eq!(diff.prob_greater, 1.0);
+
+        let reversed = contrast(&draws, "b", "a").unwrap();
+        assert_abs_diff_eq!(reversed.mean, -diff.mean, epsilon = 1e-12);
+        assert_abs_diff_eq!(reversed.prob_greater, 0.0);
+
+        let options = ContrastOptions {
+            kind: ContrastKind::Ratio,
+            ..ContrastOptions::default()
+        };
+        let ratio = contrast_with_options(&draws, "a", "b", &options).unwrap();
+        assert_abs_diff_eq!(ratio.mean, 10.0 / 9.0, epsilon = 0.01);
+        assert_abs_diff_eq!(ratio.prob_greater, 1.0);
+
+        assert!(contrast(&draws, "a", "c").is_err());
+    }
+
+    #[test]
+    fn test_hdi() {
+        // the shortest interval holding 80% of the draws avoids the outlier
+        let sorted = vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 100.0];
+        assert_eq!(hdi(&sorted, 0.8).unwrap(), (0.0, 8.0));
+        assert_eq!(hdi(&sorted, 0.3).unwrap(), (0.0, 3.0));
+        assert!(hdi(&sorted, 1.0).is_err());
+        assert!(hdi(&[], 0.5).is_err());
+        let inf = f64::INFINITY;
+        assert!(hdi(&[0.0, inf, inf, inf], 0.3).is_err());
+    }
+
+    #[test]
+    fn test_acf_at_errors() {
+        assert!(acf_at(&[1.0, 2.0, 3.0], &[3]).is_err());
+        assert!(acf_at(&[1.0, 1.0, 1.0], &[1]).is_err());
+        assert!(acf_at(&[], &[]).is_err());
+    }
+}
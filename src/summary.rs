@@ -0,0 +1,1665 @@
+use crate::diagnostics::Thresholds;
+use crate::draws::Draws;
+use crate::ess::{
+    compute_bulk_tail_ess, compute_effective_sample_size, compute_estimated_mcse,
+    compute_mcse_quantile, compute_weighted_effective_sample_size, normalize_log_weights,
+};
+use crate::pipeline::{Pipeline, PipelineReport};
+use crate::rhat::{
+    split_potential_scale_reduction_factor, split_sd_potential_scale_reduction_factor,
+};
+use crate::stats::hdi;
+use crate::utils::{
+    flatten, mean, natural_key, quantile_sorted, quantiles, sample_variance, NanPolicy, Rng,
+};
+use crate::{Array1, Array2};
+use anyhow::{anyhow, Context, Error, Result};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Posterior summary of a single parameter, with the same columns that
+/// CmdStan's `stansummary` reports.  More optional columns may be added, so
+/// summaries can only be built by this crate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Summary {
+    /// Posterior mean over all chains
+    pub mean: f64,
+    /// Monte Carlo standard error of the mean
+    pub mcse: f64,
+    /// Posterior standard deviation over all chains
+    pub sd: f64,
+    /// 5% posterior quantile
+    pub q5: f64,
+    /// Posterior median
+    pub q50: f64,
+    /// 95% posterior quantile
+    pub q95: f64,
+    /// Effective sample size
+    pub ess: f64,
+    /// Split potential scale reduction factor
+    pub rhat: f64,
+    /// Split potential scale reduction factor of the scale, only computed
+    /// when requested in the summary options
+    pub rhat_sd: Option<f64>,
+    /// Bootstrap confidence intervals of the quantiles, only computed when
+    /// requested in the summary options
+    pub quantile_intervals: Option<QuantileIntervals>,
+    /// Tail effective sample size, only computed when requested in the
+    /// summary options
+    pub tail_ess: Option<f64>,
+    /// Monte Carlo standard error of the 5% quantile, only computed when
+    /// requested in the summary options
+    pub mcse_q5: Option<f64>,
+    /// Monte Carlo standard error of the 95% quantile, only computed when
+    /// requested in the summary options
+    pub mcse_q95: Option<f64>,
+}
+
+/// Block bootstrap confidence intervals of the reported quantiles, each as
+/// `(lower, upper)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantileIntervals {
+    /// Interval of the 5% quantile
+    pub q5: (f64, f64),
+    /// Interval of the median
+    pub q50: (f64, f64),
+    /// Interval of the 95% quantile
+    pub q95: (f64, f64),
+}
+
+/// Settings of the block bootstrap of the quantiles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BootstrapOptions {
+    /// Number of bootstrap replicates
+    pub num_replicates: usize,
+    /// Length of the resampled blocks, or zero to pick it from the
+    /// autocorrelation of the draws
+    pub block_length: usize,
+    /// Confidence level of the intervals
+    pub level: f64,
+    /// Seed of the resampling
+    pub seed: u64,
+}
+
+impl Default for BootstrapOptions {
+    fn default() -> BootstrapOptions {
+        BootstrapOptions {
+            num_replicates: 200,
+            block_length: 0,
+            level: 0.9,
+            seed: 0,
+        }
+    }
+}
+
+/// Optional columns of a posterior summary, and which parameters of a
+/// container get them.
+///
+/// The optional columns are expensive next to the others, which adds up on
+/// containers with thousands of generated quantities such as
+/// `log_lik[...]`.  `skip` leaves them out for parameters matching a
+/// pattern, and `only_if_suspicious` computes them only for parameters
+/// whose R hat or ESS already look bad.  Both only apply to summaries of
+/// whole containers, e.g. [`summarize_draws_with_options`](fn.summarize_draws_with_options.html).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SummaryOptions {
+    /// Also compute the scale R hat, see
+    /// [`split_sd_potential_scale_reduction_factor`](../rhat/fn.split_sd_potential_scale_reduction_factor.html)
+    pub rhat_sd: bool,
+    /// Also compute block bootstrap confidence intervals of the quantiles,
+    /// see [`bootstrap_quantiles`](fn.bootstrap_quantiles.html)
+    pub bootstrap: Option<BootstrapOptions>,
+    /// Also compute the tail ESS, see
+    /// [`compute_bulk_tail_ess`](../ess/fn.compute_bulk_tail_ess.html)
+    pub tail_ess: bool,
+    /// Also compute the MCSE of the 5% and 95% quantiles, see
+    /// [`compute_mcse_quantile`](../ess/fn.compute_mcse_quantile.html)
+    pub mcse_quantiles: bool,
+    /// Parameters that never get the optional columns, as patterns of
+    /// [`Draws::select`](../draws/struct.Draws.html#method.select), e.g.
+    /// `log_lik[*]`
+    pub skip: Vec<String>,
+    /// Compute the optional columns only for parameters whose R hat is above
+    /// `rhat_max` or whose ESS is below `ess_min`; the other limits are not
+    /// used
+    pub only_if_suspicious: Option<Thresholds>,
+    /// What to do with NaN draws: fail, summarize the other draws of each
+    /// chain, or report a summary of NaN values for the parameter
+    pub nan_policy: NanPolicy,
+}
+
+/// Computes the posterior summary of the specified parameter across all
+/// chains.  As in `stansummary`, the effective sample size is the (non-split)
+/// Geyer estimate and R hat is the split potential scale reduction factor.
+/// Draws with NaN or infinite values can't be summarized and give an error;
+/// the NaN policy of [`summarize_with_options`](fn.summarize_with_options.html)
+/// can skip or keep the NaN ones instead.
+///
+/// See the CmdStan guide section
+/// ["stansummary"](https://mc-stan.org/docs/2_24/cmdstan-guide/stansummary.html).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+pub fn summarize(chains: &Array2) -> Result<Summary, Error> {
+    summarize_with_options(chains, &SummaryOptions::default())
+}
+
+/// Computes the posterior summary of the specified parameter across all
+/// chains like [`summarize`](fn.summarize.html), including the optional
+/// columns selected in `options`.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `options` - Optional columns to compute
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(num_chains = chains.len())))]
+pub fn summarize_with_options(chains: &Array2, options: &SummaryOptions) -> Result<Summary, Error> {
+    summarize_chains(chains, options, |_| true)
+}
+
+/// Summary of the chains after applying the NaN policy of the options,
+/// with the optional columns if `optional` accepts the basic summary.
+fn summarize_chains<F: Fn(&Summary) -> bool>(
+    chains: &Array2,
+    options: &SummaryOptions,
+    optional: F,
+) -> Result<Summary, Error> {
+    let has_nan = chains.iter().flatten().any(|x| x.is_nan());
+    let filtered: Array2;
+    let chains = match options.nan_policy {
+        NanPolicy::Skip if has_nan => {
+            filtered = chains
+                .iter()
+                .map(|chain| chain.iter().copied().filter(|x| !x.is_nan()).collect())
+                .collect();
+            &filtered
+        }
+        NanPolicy::Keep if has_nan => return Ok(nan_summary()),
+        _ => chains,
+    };
+    let mut summary = basic_summary(chains)?;
+    if optional(&summary) {
+        add_optional_columns(&mut summary, chains, options)?;
+    }
+    Ok(summary)
+}
+
+/// Summary of a parameter with NaN draws kept by the NaN policy.
+fn nan_summary() -> Summary {
+    Summary {
+        mean: f64::NAN,
+        mcse: f64::NAN,
+        sd: f64::NAN,
+        q5: f64::NAN,
+        q50: f64::NAN,
+        q95: f64::NAN,
+        ess: f64::NAN,
+        rhat: f64::NAN,
+        rhat_sd: None,
+        quantile_intervals: None,
+        tail_ess: None,
+        mcse_q5: None,
+        mcse_q95: None,
+    }
+}
+
+/// Summary without any of the optional columns.
+fn basic_summary(chains: &Array2) -> Result<Summary, Error> {
+    let flattened = flatten(chains);
+    if flattened.iter().any(|x| !x.is_finite()) {
+        return Err(anyhow!("Draws must be finite"));
+    }
+    let q = quantiles(&flattened, &[0.05, 0.5, 0.95])?;
+    Ok(Summary {
+        mean: mean(&flattened)?,
+        mcse: compute_estimated_mcse(chains)?,
+        sd: sample_variance(&flattened)?.sqrt(),
+        q5: q[0],
+        q50: q[1],
+        q95: q[2],
+        ess: compute_effective_sample_size(chains)?,
+        rhat: split_potential_scale_reduction_factor(chains)?,
+        rhat_sd: None,
+        quantile_intervals: None,
+        tail_ess: None,
+        mcse_q5: None,
+        mcse_q95: None,
+    })
+}
+
+/// Fills in the optional columns selected in `options`.
+fn add_optional_columns(
+    summary: &mut Summary,
+    chains: &Array2,
+    options: &SummaryOptions,
+) -> Result<(), Error> {
+    if options.rhat_sd {
+        summary.rhat_sd = Some(split_sd_potential_scale_reduction_factor(chains)?);
+    }
+    if let Some(ref bootstrap) = options.bootstrap {
+        summary.quantile_intervals = Some(bootstrap_quantiles(chains, bootstrap)?);
+    }
+    if options.tail_ess {
+        summary.tail_ess = Some(compute_bulk_tail_ess(chains)?.tail);
+    }
+    if options.mcse_quantiles {
+        summary.mcse_q5 = Some(compute_mcse_quantile(chains, 0.05)?);
+        summary.mcse_q95 = Some(compute_mcse_quantile(chains, 0.95)?);
+    }
+    Ok(())
+}
+
+/// Computes confidence intervals of the 5%, 50% and 95% posterior quantiles
+/// with a moving block bootstrap within each chain.  The endpoints of
+/// credible intervals from short or autocorrelated chains are often much
+/// noisier than the mean, and these intervals show how far they could move
+/// on another run.  Resampling blocks of consecutive draws rather than
+/// single draws keeps the autocorrelation; the automatic block length is
+/// twice the number of draws per effective draw.
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `options` - Number of replicates, block length, level and seed
+pub fn bootstrap_quantiles(
+    chains: &Array2,
+    options: &BootstrapOptions,
+) -> Result<QuantileIntervals, Error> {
+    if !(options.level > 0.0 && options.level < 1.0) {
+        return Err(anyhow!(
+            "Confidence level must be in (0, 1), got {}",
+            options.level
+        ));
+    }
+    if options.num_replicates < 2 {
+        return Err(anyhow!("Need at least 2 bootstrap replicates"));
+    }
+    let shortest = chains.iter().map(|c| c.len()).min().unwrap_or(0);
+    if shortest == 0 {
+        return Err(anyhow!("Can't bootstrap empty chains"));
+    }
+    let block_length = if options.block_length > 0 {
+        options.block_length
+    } else {
+        let num_draws = flatten(chains).len() as f64;
+        let ess = compute_effective_sample_size(chains)?;
+        (2.0 * num_draws / ess).ceil() as usize
+    }
+    .clamp(1, shortest);
+
+    let probs = [0.05, 0.5, 0.95];
+    let mut rng = Rng::new(options.seed);
+    let mut replicates = vec![Vec::with_capacity(options.num_replicates); probs.len()];
+    let mut resampled = Vec::with_capacity(flatten(chains).len());
+    for _ in 0..options.num_replicates {
+        resampled.clear();
+        for chain in chains.iter() {
+            let end = resampled.len() + chain.len();
+            while resampled.len() < end {
+                let start = rng.below(chain.len() - block_length + 1);
+                let take = block_length.min(end - resampled.len());
+                resampled.extend_from_slice(&chain[start..start + take]);
+            }
+        }
+        for (values, q) in replicates.iter_mut().zip(quantiles(&resampled, &probs)?) {
+            values.push(q);
+        }
+    }
+    let tail = (1.0 - options.level) / 2.0;
+    let interval = |values: &[f64]| -> Result<(f64, f64), Error> {
+        let ends = quantiles(values, &[tail, 1.0 - tail])?;
+        Ok((ends[0], ends[1]))
+    };
+    Ok(QuantileIntervals {
+        q5: interval(&replicates[0])?,
+        q50: interval(&replicates[1])?,
+        q95: interval(&replicates[2])?,
+    })
+}
+
+/// Computes the posterior summary of every parameter in the draws, in column
+/// order, including the optional columns selected in `options`.  Only the
+/// chains that sample the target distribution are used when some are
+/// tempered.  With the `tracing` feature each parameter gets its own span
+/// named after it, so slow parameters stand out in a profile.
+pub fn summarize_draws_with_options(
+    draws: &Draws,
+    options: &SummaryOptions,
+) -> Result<Vec<Summary>, Error> {
+    let patterns: Vec<&str> = options.skip.iter().map(|p| p.as_str()).collect();
+    let skipped = draws.select(&patterns);
+    (0..draws.num_parameters())
+        .map(|idx| summarize_parameter(draws, idx, options, skipped.contains(&idx)))
+        .collect()
+}
+
+/// Summarizes one column of the draws for
+/// [`summarize_draws_with_options`](fn.summarize_draws_with_options.html) and its parallel variant,
+/// leaving out the optional columns if the parameter is `skipped` or looks
+/// fine when only suspicious parameters get them.
+fn summarize_parameter(
+    draws: &Draws,
+    idx: usize,
+    options: &SummaryOptions,
+    skipped: bool,
+) -> Result<Summary, Error> {
+    let name = &draws.names()[idx];
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("parameter", name = %name).entered();
+    let chains = draws.target_parameter(idx);
+    let result = summarize_chains(&chains, options, |summary| {
+        let suspicious = match options.only_if_suspicious {
+            Some(ref limits) => summary.rhat > limits.rhat_max || summary.ess < limits.ess_min,
+            None => true,
+        };
+        !skipped && suspicious
+    });
+    result.with_context(|| format!("Failed to summarize {}", name))
+}
+
+/// How [`summarize_draws_parallel`](fn.summarize_draws_parallel.html)
+/// spreads the parameters over threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelOptions {
+    /// Number of worker threads, or zero to use the available parallelism
+    pub num_threads: usize,
+    /// Give every parameter to a fixed thread and, if several parameters
+    /// fail, always report the first one in column order.  Without it
+    /// threads take the next parameter whenever they are free, which
+    /// balances uneven workloads better, and stop at the first failure any
+    /// of them sees, so the reported error can change from run to run.
+    pub deterministic: bool,
+}
+
+impl Default for ParallelOptions {
+    fn default() -> ParallelOptions {
+        ParallelOptions {
+            num_threads: 0,
+            deterministic: true,
+        }
+    }
+}
+
+/// Computes the same summaries as
+/// [`summarize_draws_with_options`](fn.summarize_draws_with_options.html)
+/// on several threads.  Each parameter is summarized by a single thread with
+/// the sequential code, so the summaries are in column order and
+/// bit-identical to the sequential ones whatever the number of threads.
+///
+/// # Arguments
+/// * `draws` - Draws to summarize
+/// * `options` - Optional columns to compute
+/// * `parallel` - Number of threads and scheduling
+pub fn summarize_draws_parallel(
+    draws: &Draws,
+    options: &SummaryOptions,
+    parallel: &ParallelOptions,
+) -> Result<Vec<Summary>, Error> {
+    let num_parameters = draws.num_parameters();
+    let num_threads = match parallel.num_threads {
+        // targets without threads, like wasm, report an error here
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(num_parameters);
+    if num_threads <= 1 {
+        return summarize_draws_with_options(draws, options);
+    }
+    let patterns: Vec<&str> = options.skip.iter().map(|p| p.as_str()).collect();
+    let skipped = draws.select(&patterns);
+    let mut results: Vec<Option<Result<Summary, Error>>> = Vec::new();
+    results.resize_with(num_parameters, || None);
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let chunk = num_parameters.div_ceil(num_threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_threads)
+            .map(|worker| {
+                let (next, failed, skipped) = (&next, &failed, &skipped);
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    if parallel.deterministic {
+                        let end = ((worker + 1) * chunk).min(num_parameters);
+                        for idx in worker * chunk..end {
+                            let result =
+                                summarize_parameter(draws, idx, options, skipped.contains(&idx));
+                            let stop = result.is_err();
+                            done.push((idx, result));
+                            if stop {
+                                break;
+                            }
+                        }
+                    } else {
+                        while !failed.load(Ordering::Relaxed) {
+                            let idx = next.fetch_add(1, Ordering::Relaxed);
+                            if idx >= num_parameters {
+                                break;
+                            }
+                            let result =
+                                summarize_parameter(draws, idx, options, skipped.contains(&idx));
+                            if result.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            done.push((idx, result));
+                        }
+                    }
+                    done
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (idx, result) in worker.join().expect("summary thread panicked") {
+                results[idx] = Some(result);
+            }
+        }
+    });
+    // after a failure some parameters may not have been summarized
+    if let Some(pos) = results.iter().position(|r| matches!(r, Some(Err(_)))) {
+        if let Some(Err(err)) = results.swap_remove(pos) {
+            return Err(err);
+        }
+    }
+    Ok(results.into_iter().map(|r| r.unwrap().unwrap()).collect())
+}
+
+/// Order of the parameters in a [`SummaryReport`](struct.SummaryReport.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportOrder {
+    /// Column order of the draws, i.e. the order of the CSV header
+    #[default]
+    Input,
+    /// Sorted by name, with indices compared as numbers so that `beta[2]`
+    /// comes before `beta[10]`
+    Sorted,
+}
+
+/// Probabilities of the quantiles reported by default, those of
+/// `stansummary`.
+pub const DEFAULT_QUANTILES: [f64; 3] = [0.05, 0.5, 0.95];
+
+/// Options of [`summary_report`](fn.summary_report.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportOptions {
+    /// Order in which the report lists the parameters
+    pub order: ReportOrder,
+    /// Probabilities of the quantiles the report lists for every
+    /// parameter, each in [0, 1], e.g. `[0.025, 0.5, 0.975]`
+    pub quantiles: Vec<f64>,
+    /// Probability mass of the highest density interval the report lists
+    /// for every parameter, in (0, 1), e.g. 0.89, or `None` for no interval
+    pub hdi_mass: Option<f64>,
+}
+
+impl Default for ReportOptions {
+    fn default() -> ReportOptions {
+        ReportOptions {
+            order: ReportOrder::default(),
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+            hdi_mass: None,
+        }
+    }
+}
+
+/// Posterior summaries of many parameters that can be looked up by name,
+/// with the quantiles and highest density intervals chosen in the
+/// [`ReportOptions`](struct.ReportOptions.html), and the results of a
+/// diagnostics [`Pipeline`](../pipeline/struct.Pipeline.html) if it was
+/// computed with one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryReport {
+    names: Vec<String>,
+    summaries: Vec<Summary>,
+    probs: Vec<f64>,
+    quantiles: Vec<Array1>,
+    hdi_mass: Option<f64>,
+    hdis: Vec<(f64, f64)>,
+    diagnostics: Option<PipelineReport>,
+}
+
+impl SummaryReport {
+    /// Reassembles a report from its parts, e.g. one read back from JSON,
+    /// checking that there are quantiles and an interval for every
+    /// parameter.
+    #[cfg(feature = "json")]
+    pub(crate) fn from_parts(
+        names: Vec<String>,
+        summaries: Vec<Summary>,
+        probs: Vec<f64>,
+        quantiles: Vec<Array1>,
+        hdi_mass: Option<f64>,
+        hdis: Vec<(f64, f64)>,
+    ) -> Result<SummaryReport, Error> {
+        let num_hdis = if hdi_mass.is_some() { names.len() } else { 0 };
+        if summaries.len() != names.len()
+            || quantiles.len() != names.len()
+            || quantiles.iter().any(|q| q.len() != probs.len())
+            || hdis.len() != num_hdis
+        {
+            return Err(anyhow!(
+                "Report parts don't match {} parameters",
+                names.len()
+            ));
+        }
+        Ok(SummaryReport {
+            names,
+            summaries,
+            probs,
+            quantiles,
+            hdi_mass,
+            hdis,
+            diagnostics: None,
+        })
+    }
+
+    /// Adds the results of a diagnostics pipeline run on the same draws.
+    pub(crate) fn set_diagnostics(&mut self, diagnostics: PipelineReport) {
+        self.diagnostics = Some(diagnostics);
+    }
+
+    /// Position of a parameter in report order.
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Looks up the summary of a parameter by its name as written in the
+    /// draws, e.g. `beta.2` for CmdStan output.
+    pub fn get(&self, name: &str) -> Option<&Summary> {
+        self.position(name).map(|idx| &self.summaries[idx])
+    }
+
+    /// Probabilities of the quantiles in the report.
+    pub fn probs(&self) -> &[f64] {
+        &self.probs
+    }
+
+    /// Looks up the quantiles of a parameter, one for each of
+    /// [`probs`](#method.probs).
+    pub fn quantiles(&self, name: &str) -> Option<&[f64]> {
+        self.position(name).map(|idx| &self.quantiles[idx][..])
+    }
+
+    /// Probability mass of the highest density intervals in the report, if
+    /// any.
+    pub fn hdi_mass(&self) -> Option<f64> {
+        self.hdi_mass
+    }
+
+    /// Looks up the highest density interval of a parameter, if the report
+    /// has intervals.
+    pub fn hdi(&self, name: &str) -> Option<(f64, f64)> {
+        self.hdi_mass?;
+        self.position(name).map(|idx| self.hdis[idx])
+    }
+
+    /// Results of the diagnostics pipeline, if the report was computed with
+    /// [`summary_report_with_pipeline`](fn.summary_report_with_pipeline.html).
+    pub fn diagnostics(&self) -> Option<&PipelineReport> {
+        self.diagnostics.as_ref()
+    }
+
+    /// Parameter names in report order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Summaries in report order.
+    pub fn summaries(&self) -> &[Summary] {
+        &self.summaries
+    }
+
+    /// Iterates over the parameter names and their summaries in report order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Summary)> {
+        self.names
+            .iter()
+            .map(|n| n.as_str())
+            .zip(self.summaries.iter())
+    }
+
+    /// Number of parameters in the report.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether the report has no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The summaries as columns, in report order.
+    pub fn to_columns(&self) -> SummaryColumns {
+        SummaryColumns::from_summaries(self.names.clone(), &self.summaries).unwrap()
+    }
+}
+
+/// Checks the quantile probabilities and interval mass asked of a report.
+pub(crate) fn check_intervals(quantiles: &[f64], hdi_mass: Option<f64>) -> Result<(), Error> {
+    if let Some(p) = quantiles.iter().find(|p| !(**p >= 0.0 && **p <= 1.0)) {
+        return Err(anyhow!(
+            "Quantile probabilities must be in [0, 1], got {}",
+            p
+        ));
+    }
+    match hdi_mass {
+        Some(mass) if !(mass > 0.0 && mass < 1.0) => {
+            Err(anyhow!("Interval mass must be in (0, 1), got {}", mass))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Positions of the parameters in the order the options ask for.
+fn report_order(draws: &Draws, options: &ReportOptions) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..draws.num_parameters()).collect();
+    if options.order == ReportOrder::Sorted {
+        order.sort_by_cached_key(|&idx| natural_key(&draws.names()[idx]));
+    }
+    order
+}
+
+/// Computes the posterior summary of every parameter like
+/// [`summarize_draws_with_options`](fn.summarize_draws_with_options.html), and returns it as a report
+/// that can be looked up by parameter name, together with the quantiles and
+/// the highest density interval chosen in the options, e.g. an 89% HDI.
+/// They are computed from the same chains as the summaries; the quantiles
+/// are interpolated as by R's default type 7.
+///
+/// # Arguments
+/// * `draws` - Draws to summarize
+/// * `options` - Order of the parameters, quantiles and interval mass
+pub fn summary_report(draws: &Draws, options: &ReportOptions) -> Result<SummaryReport, Error> {
+    check_intervals(&options.quantiles, options.hdi_mass)?;
+    let summaries = summarize_draws_with_options(draws, &SummaryOptions::default())?;
+    let rows = report_order(draws, options)
+        .into_iter()
+        .map(|idx| Ok((idx, summaries[idx], report_intervals(draws, idx, options)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(assemble_report(draws, options, rows))
+}
+
+/// Computes the report of [`summary_report`](fn.summary_report.html)
+/// together with the results of a diagnostics pipeline, so that custom
+/// diagnostics are reported next to the summaries, e.g. as columns of the
+/// CSV and HTML reports.
+///
+/// # Arguments
+/// * `draws` - Draws to summarize
+/// * `options` - Order of the parameters, quantiles and interval mass
+/// * `pipeline` - Diagnostics to run on the draws
+pub fn summary_report_with_pipeline(
+    draws: &Draws,
+    options: &ReportOptions,
+    pipeline: &Pipeline,
+) -> Result<SummaryReport, Error> {
+    let mut report = summary_report(draws, options)?;
+    report.set_diagnostics(pipeline.run(draws)?);
+    Ok(report)
+}
+
+/// Computes the report of [`summary_report`](fn.summary_report.html) of the
+/// parameters that can be summarized, and returns the names of the others
+/// with the reason.
+pub(crate) fn summary_report_lenient(
+    draws: &Draws,
+    options: &ReportOptions,
+) -> Result<(SummaryReport, Vec<(String, Error)>), Error> {
+    check_intervals(&options.quantiles, options.hdi_mass)?;
+    let mut rows = Vec::new();
+    let mut failed = Vec::new();
+    for idx in report_order(draws, options) {
+        let summary = summarize(&draws.target_parameter(idx));
+        match summary.and_then(|s| Ok((s, report_intervals(draws, idx, options)?))) {
+            Ok((summary, intervals)) => rows.push((idx, summary, intervals)),
+            Err(err) => failed.push((draws.names()[idx].clone(), err)),
+        }
+    }
+    Ok((assemble_report(draws, options, rows), failed))
+}
+
+/// Quantiles and highest density interval of one parameter of a report.
+type Intervals = (Array1, Option<(f64, f64)>);
+
+/// Quantiles and highest density interval of a parameter for a report.
+fn report_intervals(
+    draws: &Draws,
+    idx: usize,
+    options: &ReportOptions,
+) -> Result<Intervals, Error> {
+    let mut sorted = flatten(&draws.target_parameter(idx));
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let quantiles = options
+        .quantiles
+        .iter()
+        .map(|&p| quantile_sorted(&sorted, p))
+        .collect::<Result<Array1, Error>>()?;
+    let interval = match options.hdi_mass {
+        Some(mass) => Some(hdi(&sorted, mass)?),
+        None => None,
+    };
+    Ok((quantiles, interval))
+}
+
+/// Report of the parameters of the rows, in their order.
+fn assemble_report(
+    draws: &Draws,
+    options: &ReportOptions,
+    rows: Vec<(usize, Summary, Intervals)>,
+) -> SummaryReport {
+    let mut report = SummaryReport {
+        names: Vec::with_capacity(rows.len()),
+        summaries: Vec::with_capacity(rows.len()),
+        probs: options.quantiles.clone(),
+        quantiles: Vec::with_capacity(rows.len()),
+        hdi_mass: options.hdi_mass,
+        hdis: Vec::new(),
+        diagnostics: None,
+    };
+    for (idx, summary, (quantiles, interval)) in rows {
+        report.names.push(draws.names()[idx].clone());
+        report.summaries.push(summary);
+        report.quantiles.push(quantiles);
+        report.hdis.extend(interval);
+    }
+    report
+}
+
+/// Posterior summaries of many parameters as one vector per column rather
+/// than one struct per parameter, e.g. to move each column into an Arrow
+/// array or a polars series without a copy, see
+/// [`summarize_draws_columnar`](fn.summarize_draws_columnar.html).  All
+/// columns have one value per parameter, in the order of `names`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SummaryColumns {
+    /// Parameter names
+    pub names: Vec<String>,
+    /// Posterior means
+    pub mean: Array1,
+    /// Monte Carlo standard errors of the means
+    pub mcse: Array1,
+    /// Posterior standard deviations
+    pub sd: Array1,
+    /// 5% posterior quantiles
+    pub q5: Array1,
+    /// Posterior medians
+    pub q50: Array1,
+    /// 95% posterior quantiles
+    pub q95: Array1,
+    /// Effective sample sizes
+    pub ess: Array1,
+    /// Split potential scale reduction factors
+    pub rhat: Array1,
+    /// Scale R hats if any parameter has one, with NaN for the others
+    pub rhat_sd: Option<Array1>,
+    /// Tail effective sample sizes if any parameter has one, with NaN for
+    /// the others
+    pub tail_ess: Option<Array1>,
+    /// Monte Carlo standard errors of the 5% quantiles if any parameter has
+    /// one, with NaN for the others
+    pub mcse_q5: Option<Array1>,
+    /// Monte Carlo standard errors of the 95% quantiles if any parameter has
+    /// one, with NaN for the others
+    pub mcse_q95: Option<Array1>,
+}
+
+impl SummaryColumns {
+    /// Transposes summaries of the named parameters into columns.  The
+    /// bootstrap intervals of the quantiles are left out.
+    ///
+    /// # Arguments
+    /// * `names` - Parameter names
+    /// * `summaries` - Summaries in the order of `names`
+    pub fn from_summaries(
+        names: Vec<String>,
+        summaries: &[Summary],
+    ) -> Result<SummaryColumns, Error> {
+        if names.len() != summaries.len() {
+            return Err(anyhow!(
+                "Got {} names for {} summaries",
+                names.len(),
+                summaries.len()
+            ));
+        }
+        let column = |f: fn(&Summary) -> f64| summaries.iter().map(f).collect();
+        let optional = |f: fn(&Summary) -> Option<f64>| {
+            if summaries.iter().any(|s| f(s).is_some()) {
+                Some(summaries.iter().map(|s| f(s).unwrap_or(f64::NAN)).collect())
+            } else {
+                None
+            }
+        };
+        Ok(SummaryColumns {
+            names,
+            mean: column(|s| s.mean),
+            mcse: column(|s| s.mcse),
+            sd: column(|s| s.sd),
+            q5: column(|s| s.q5),
+            q50: column(|s| s.q50),
+            q95: column(|s| s.q95),
+            ess: column(|s| s.ess),
+            rhat: column(|s| s.rhat),
+            rhat_sd: optional(|s| s.rhat_sd),
+            tail_ess: optional(|s| s.tail_ess),
+            mcse_q5: optional(|s| s.mcse_q5),
+            mcse_q95: optional(|s| s.mcse_q95),
+        })
+    }
+
+    /// Number of parameters.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether there are no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// Computes the posterior summary of every parameter in the draws like
+/// [`summarize_draws_with_options`](fn.summarize_draws_with_options.html),
+/// and returns it as columns in column order of the draws.
+pub fn summarize_draws_columnar(
+    draws: &Draws,
+    options: &SummaryOptions,
+) -> Result<SummaryColumns, Error> {
+    let summaries = summarize_draws_with_options(draws, options)?;
+    SummaryColumns::from_summaries(draws.names().to_vec(), &summaries)
+}
+
+/// Names tried for a column of per-draw log-weights, as written by SMC and
+/// annealed importance samplers.
+pub const LOG_WEIGHT_COLUMNS: &[&str] = &["log_weight", "log_weights", "logw", "lw__"];
+
+/// Computes the posterior summary of the specified parameter from weighted
+/// draws, e.g. the particles of a sequential Monte Carlo sampler or the
+/// output of annealed importance sampling.  The log-weights are normalized
+/// with [`normalize_log_weights`](../ess/fn.normalize_log_weights.html),
+/// and the mean, standard deviation and quantiles are those of the weighted
+/// draws; the quantiles are taken from the weighted empirical distribution
+/// without interpolation.  The ESS is
+/// [`compute_weighted_effective_sample_size`](../ess/fn.compute_weighted_effective_sample_size.html),
+/// which accounts for both the spread of the weights and the
+/// autocorrelation of the chains, and the MCSE follows from it.  R hat
+/// ignores the weights.  With equal weights the mean, sd, ESS and MCSE
+/// match [`summarize`](fn.summarize.html).
+///
+/// # Arguments
+/// * `chains` - Reference to a vector of chains, each of which is a vector of samples for
+///   the same parameter
+/// * `log_weights` - Unnormalized log-weights with the same shape as `chains`
+pub fn summarize_weighted(chains: &Array2, log_weights: &Array2) -> Result<Summary, Error> {
+    if chains.len() != log_weights.len()
+        || chains
+            .iter()
+            .zip(log_weights)
+            .any(|(c, w)| c.len() != w.len())
+    {
+        return Err(anyhow!(
+            "Log-weights must have the same shape as the chains"
+        ));
+    }
+    let weights = normalize_log_weights(log_weights)?;
+    let pairs = weighted_pairs(chains, &weights)?;
+    let weighted_mean: f64 = pairs.iter().map(|(x, w)| w * x).sum();
+    let spread: f64 = pairs
+        .iter()
+        .map(|(x, w)| w * (x - weighted_mean).powi(2))
+        .sum();
+    // the reliability weights analogue of Bessel's correction, which is
+    // n / (n - 1) for equal weights
+    let squares: f64 = pairs.iter().map(|(_, w)| w * w).sum();
+    let sd = if squares < 1.0 {
+        (spread / (1.0 - squares)).sqrt()
+    } else {
+        0.0
+    };
+    let quantile = |p: f64| weighted_quantile(&pairs, p);
+    let ess = compute_weighted_effective_sample_size(chains, &weights)?;
+    Ok(Summary {
+        mean: weighted_mean,
+        mcse: sd / ess.sqrt(),
+        sd,
+        q5: quantile(0.05),
+        q50: quantile(0.5),
+        q95: quantile(0.95),
+        ess,
+        rhat: split_potential_scale_reduction_factor(chains)?,
+        rhat_sd: None,
+        quantile_intervals: None,
+        tail_ess: None,
+        mcse_q5: None,
+        mcse_q95: None,
+    })
+}
+
+/// Draws paired with their normalized weights, sorted by value.
+fn weighted_pairs(chains: &Array2, weights: &Array2) -> Result<Vec<(f64, f64)>, Error> {
+    let mut pairs: Vec<(f64, f64)> = flatten(chains).into_iter().zip(flatten(weights)).collect();
+    if pairs.iter().any(|(x, _)| !x.is_finite()) {
+        return Err(anyhow!("Draws must be finite"));
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(pairs)
+}
+
+/// Slack of the comparisons of cumulative weights, which are off by
+/// rounding, so that e.g. a tenth of ten equal weights reaches 0.1.
+const WEIGHT_TOLERANCE: f64 = 1e-12;
+
+/// Quantile of the weighted empirical distribution, the smallest draw at
+/// which the cumulative weight reaches `p`.
+fn weighted_quantile(pairs: &[(f64, f64)], p: f64) -> f64 {
+    let mut cumulative = 0.0;
+    for &(x, w) in pairs.iter() {
+        cumulative += w;
+        if cumulative >= p - WEIGHT_TOLERANCE {
+            return x;
+        }
+    }
+    pairs[pairs.len() - 1].0
+}
+
+/// Shortest interval between two draws holding at least `mass` of the
+/// weight, found with two pointers over the sorted draws.
+fn weighted_hdi(pairs: &[(f64, f64)], mass: f64) -> (f64, f64) {
+    let mut best = (pairs[0].0, pairs[pairs.len() - 1].0);
+    let mut inside = 0.0;
+    let mut end = 0;
+    for start in 0..pairs.len() {
+        while end < pairs.len() && inside < mass - WEIGHT_TOLERANCE {
+            inside += pairs[end].1;
+            end += 1;
+        }
+        if inside < mass - WEIGHT_TOLERANCE {
+            break;
+        }
+        if pairs[end - 1].0 - pairs[start].0 < best.1 - best.0 {
+            best = (pairs[start].0, pairs[end - 1].0);
+        }
+        inside -= pairs[start].1;
+    }
+    best
+}
+
+/// Computes the weighted posterior summary of every parameter of draws
+/// with a column of log-weights, the first of
+/// [`LOG_WEIGHT_COLUMNS`](constant.LOG_WEIGHT_COLUMNS.html) found, see
+/// [`summarize_weighted`](fn.summarize_weighted.html).  The report lists
+/// all other parameters, and uses only the chains that sample the target
+/// distribution when some are tempered.  Its quantiles and highest density
+/// intervals are those of the weighted draws.
+///
+/// # Arguments
+/// * `draws` - Draws with a log-weight column
+/// * `options` - Order of the parameters, quantiles and interval mass
+pub fn weighted_summary_report(
+    draws: &Draws,
+    options: &ReportOptions,
+) -> Result<SummaryReport, Error> {
+    let column = LOG_WEIGHT_COLUMNS
+        .iter()
+        .find_map(|c| draws.index_of(c))
+        .ok_or_else(|| anyhow!("No log-weight column found"))?;
+    check_intervals(&options.quantiles, options.hdi_mass)?;
+    let log_weights = draws.target_parameter(column);
+    let weights = normalize_log_weights(&log_weights)?;
+    let order: Vec<usize> = report_order(draws, options)
+        .into_iter()
+        .filter(|&idx| idx != column)
+        .collect();
+    let mut summaries = Vec::with_capacity(order.len());
+    let mut quantiles = Vec::with_capacity(order.len());
+    let mut hdis = Vec::with_capacity(order.len());
+    for &idx in order.iter() {
+        let chains = draws.target_parameter(idx);
+        let context = || format!("Failed to summarize {}", draws.names()[idx]);
+        summaries.push(summarize_weighted(&chains, &log_weights).with_context(context)?);
+        let pairs = weighted_pairs(&chains, &weights).with_context(context)?;
+        quantiles.push(
+            options
+                .quantiles
+                .iter()
+                .map(|&p| weighted_quantile(&pairs, p))
+                .collect(),
+        );
+        if let Some(mass) = options.hdi_mass {
+            hdis.push(weighted_hdi(&pairs, mass));
+        }
+    }
+    Ok(SummaryReport {
+        names: order
+            .iter()
+            .map(|&idx| draws.names()[idx].clone())
+            .collect(),
+        summaries,
+        probs: options.quantiles.clone(),
+        quantiles,
+        hdi_mass: options.hdi_mass,
+        hdis,
+        diagnostics: None,
+    })
+}
+
+/// Convergence summary of a block of indexed parameters such as all of
+/// `theta[1]`, `theta[2]`, ...
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupSummary {
+    /// Name of the block, i.e. the parameter names without their indices
+    pub name: String,
+    /// Number of parameters in the block
+    pub size: usize,
+    /// Smallest effective sample size in the block
+    pub min_ess: f64,
+    /// Full name of the parameter with the smallest effective sample size
+    pub min_ess_parameter: String,
+    /// Largest split potential scale reduction factor in the block
+    pub max_rhat: f64,
+    /// Full name of the parameter with the largest R hat
+    pub max_rhat_parameter: String,
+}
+
+/// Computes one convergence summary per block of indexed parameters, so that
+/// a hierarchical model with thousands of elements produces one line per
+/// block with its worst ESS and R hat and where they occur.  Parameters
+/// without an index form a block of their own.  Blocks are ordered by their
+/// first parameter.  A NaN ESS or R hat counts as the worst in its block.
+/// Tempered chains are left out as in
+/// [`summarize_draws_with_options`](fn.summarize_draws_with_options.html).
+pub fn summarize_groups(draws: &Draws) -> Result<Vec<GroupSummary>, Error> {
+    let mut groups: Vec<GroupSummary> = Vec::new();
+    let mut group_ids: HashMap<String, usize> = HashMap::new();
+    for (idx, name) in draws.names().iter().enumerate() {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("parameter", name = %name).entered();
+        let chains = draws.target_parameter(idx);
+        let ess = compute_effective_sample_size(&chains)
+            .with_context(|| format!("Failed to summarize {}", name))?;
+        let rhat = split_potential_scale_reduction_factor(&chains)
+            .with_context(|| format!("Failed to summarize {}", name))?;
+        let block = block_name(name);
+        match group_ids.get(block) {
+            Some(&group) => groups[group].add(name, ess, rhat),
+            None => {
+                group_ids.insert(block.to_string(), groups.len());
+                groups.push(GroupSummary {
+                    name: block.to_string(),
+                    size: 1,
+                    min_ess: ess,
+                    min_ess_parameter: name.clone(),
+                    max_rhat: rhat,
+                    max_rhat_parameter: name.clone(),
+                });
+            }
+        }
+    }
+    Ok(groups)
+}
+
+impl GroupSummary {
+    /// Counts another parameter of the block.  A NaN ESS or R hat is worse
+    /// than any other, and the first one found is kept.
+    fn add(&mut self, name: &str, ess: f64, rhat: f64) {
+        self.size += 1;
+        if !self.min_ess.is_nan() && (ess.is_nan() || ess < self.min_ess) {
+            self.min_ess = ess;
+            self.min_ess_parameter = name.to_string();
+        }
+        if !self.max_rhat.is_nan() && (rhat.is_nan() || rhat > self.max_rhat) {
+            self.max_rhat = rhat;
+            self.max_rhat_parameter = name.to_string();
+        }
+    }
+}
+
+/// Name of the block an indexed parameter belongs to, for both `theta[1,2]`
+/// and the `theta.1.2` that CmdStan writes in its CSV headers.
+fn block_name(name: &str) -> &str {
+    if let Some(pos) = name.find('[') {
+        return &name[..pos];
+    }
+    let bytes = name.as_bytes();
+    match (1..bytes.len()).find(|&i| bytes[i - 1] == b'.' && bytes[i].is_ascii_digit()) {
+        Some(i) => &name[..i - 1],
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::normal_draws;
+    #[cfg(feature = "fs")]
+    use crate::utils::read_csv;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_summarize_blocker() {
+        // Based on running stansummary from CmdStan on the blocker example
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+        let summary = summarize(&chains).unwrap();
+
+        assert_abs_diff_eq!(summary.mcse, 2.661215900e-03, epsilon = 1e-8);
+        assert_abs_diff_eq!(summary.ess, 467.36757686, epsilon = 1e-8);
+        assert_abs_diff_eq!(summary.rhat, 1.00718209, epsilon = 1e-6);
+        assert_abs_diff_eq!(
+            summary.mcse,
+            summary.sd / summary.ess.sqrt(),
+            epsilon = 1e-12
+        );
+        assert!(summary.q5 < summary.q50 && summary.q50 < summary.q95);
+    }
+
+    #[test]
+    fn test_summarize_with_rhat_sd() {
+        let chains = vec![
+            crate::utils::normal_draws(500, 1),
+            crate::utils::normal_draws(500, 2),
+        ];
+        assert!(summarize(&chains).unwrap().rhat_sd.is_none());
+        let options = SummaryOptions {
+            rhat_sd: true,
+            ..SummaryOptions::default()
+        };
+        let summary = summarize_with_options(&chains, &options).unwrap();
+        assert_abs_diff_eq!(
+            summary.rhat_sd.unwrap(),
+            split_sd_potential_scale_reduction_factor(&chains).unwrap()
+        );
+        assert_eq!(summary.rhat, summarize(&chains).unwrap().rhat);
+    }
+
+    #[test]
+    fn test_bootstrap_quantiles() {
+        // AR(1) chains with coefficient 0.9
+        let chains: Array2 = (0..2)
+            .map(|c| {
+                let mut x = 0.0;
+                normal_draws(1000, 40 + c)
+                    .iter()
+                    .map(|e| {
+                        x = 0.9 * x + e;
+                        x
+                    })
+                    .collect()
+            })
+            .collect();
+        let options = SummaryOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..SummaryOptions::default()
+        };
+        let summary = summarize_with_options(&chains, &options).unwrap();
+        let intervals = summary.quantile_intervals.unwrap();
+        for (q, (lower, upper)) in [
+            (summary.q5, intervals.q5),
+            (summary.q50, intervals.q50),
+            (summary.q95, intervals.q95),
+        ]
+        .iter()
+        {
+            assert!(
+                lower < q && q < upper,
+                "{} not in ({}, {})",
+                q,
+                lower,
+                upper
+            );
+        }
+        assert!(summarize(&chains).unwrap().quantile_intervals.is_none());
+
+        // resampling single draws ignores the autocorrelation, so the
+        // intervals come out too narrow
+        let single = BootstrapOptions {
+            block_length: 1,
+            ..BootstrapOptions::default()
+        };
+        let naive = bootstrap_quantiles(&chains, &single).unwrap();
+        let width = |(lower, upper): (f64, f64)| upper - lower;
+        assert!(width(intervals.q50) > 2.0 * width(naive.q50));
+        assert_eq!(naive, bootstrap_quantiles(&chains, &single).unwrap());
+
+        let bad = BootstrapOptions {
+            level: 1.0,
+            ..BootstrapOptions::default()
+        };
+        assert!(bootstrap_quantiles(&chains, &bad).is_err());
+        assert!(bootstrap_quantiles(&vec![vec![]], &BootstrapOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_summarize_too_few_draws() {
+        let chains = vec![vec![1.0, 2.0, 3.0]];
+        assert!(summarize(&chains).is_err());
+    }
+
+    #[test]
+    fn test_summarize_non_finite() {
+        let mut x = normal_draws(100, 1);
+        x[10] = f64::NAN;
+        assert!(summarize(&vec![x.clone()]).is_err());
+        x[10] = f64::INFINITY;
+        assert!(summarize(&vec![x.clone()]).is_err());
+        assert!(crate::utils::quantiles(&[1.0, f64::NAN, 0.0], &[0.0]).is_ok());
+
+        let policy = |nan_policy| SummaryOptions {
+            nan_policy,
+            ..SummaryOptions::default()
+        };
+        let y = normal_draws(100, 2);
+        let mut with_nan = vec![x.clone(), y.clone()];
+        with_nan[0][10] = f64::NAN;
+        with_nan[1][20] = f64::NAN;
+        assert!(summarize_with_options(&with_nan, &policy(NanPolicy::Error)).is_err());
+        let skipped = summarize_with_options(&with_nan, &policy(NanPolicy::Skip)).unwrap();
+        let mut without = vec![x, y];
+        without[0].remove(10);
+        without[1].remove(20);
+        assert_eq!(skipped, summarize(&without).unwrap());
+        let kept = summarize_with_options(&with_nan, &policy(NanPolicy::Keep)).unwrap();
+        assert!(kept.mean.is_nan() && kept.rhat.is_nan());
+
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chains = with_nan
+            .iter()
+            .map(|c| vec![c.clone(), normal_draws(100, 3)]);
+        let draws = Draws::from_chains(names, chains.collect()).unwrap();
+        assert!(summarize_draws_with_options(&draws, &SummaryOptions::default()).is_err());
+        let summaries = summarize_draws_with_options(&draws, &policy(NanPolicy::Keep)).unwrap();
+        assert!(summaries[0].mean.is_nan());
+        assert_eq!(summaries[1], summarize(&draws.parameter(1)).unwrap());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_summarize_draws_with_options() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let draws = crate::io::stan::read_files(&[
+            d.join("test/stan/blocker.1.csv"),
+            d.join("test/stan/blocker.2.csv"),
+        ])
+        .unwrap();
+        let summaries = summarize_draws_with_options(&draws, &SummaryOptions::default()).unwrap();
+        assert_eq!(summaries.len(), draws.num_parameters());
+        let idx = draws.index_of("d").unwrap();
+        assert_eq!(summaries[idx], summarize(&draws.parameter(idx)).unwrap());
+
+        // the step size is constant within a chain, so only keep d
+        let chains = draws.parameter(idx);
+        let mut tempered = Draws::from_chains(
+            vec!["d".to_string()],
+            chains.iter().map(|c| vec![c.clone()]).collect(),
+        )
+        .unwrap();
+        tempered.set_inverse_temperature(1, 0.5).unwrap();
+        assert_eq!(
+            summarize_draws_with_options(&tempered, &SummaryOptions::default()).unwrap()[0],
+            summarize(&chains[..1].to_vec()).unwrap()
+        );
+
+        let short = Draws::from_chains(vec!["a".to_string()], vec![vec![vec![1.0]]]).unwrap();
+        let err = summarize_draws_with_options(&short, &SummaryOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to summarize a"));
+    }
+
+    #[test]
+    fn test_summarize_draws_budget() {
+        let names = ["mu", "log_lik[1]", "log_lik[2]", "stuck"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let chains = (0..4)
+            .map(|c| {
+                (0..4)
+                    .map(|p| {
+                        let shift = if p == 3 { c as f64 } else { 0.0 };
+                        normal_draws(500, 10 * c + p)
+                            .iter()
+                            .map(|x| x + shift)
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let mut options = SummaryOptions {
+            tail_ess: true,
+            mcse_quantiles: true,
+            skip: vec!["log_lik[*]".to_string()],
+            ..SummaryOptions::default()
+        };
+        let summaries = summarize_draws_with_options(&draws, &options).unwrap();
+        let mu = draws.parameter(0);
+        assert_eq!(summaries[0], summarize_with_options(&mu, &options).unwrap());
+        assert_abs_diff_eq!(
+            summaries[0].tail_ess.unwrap(),
+            compute_bulk_tail_ess(&mu).unwrap().tail
+        );
+        assert_abs_diff_eq!(
+            summaries[0].mcse_q95.unwrap(),
+            compute_mcse_quantile(&mu, 0.95).unwrap()
+        );
+        assert_eq!(summaries[1], summarize(&draws.parameter(1)).unwrap());
+        assert!(summaries[2].mcse_q5.is_none());
+        assert!(summaries[3].tail_ess.is_some());
+
+        // only the stuck parameter looks suspicious
+        options.only_if_suspicious = Some(Thresholds::default());
+        let summaries = summarize_draws_with_options(&draws, &options).unwrap();
+        assert!(summaries[0].tail_ess.is_none());
+        assert_eq!(summaries[0].rhat, summarize(&mu).unwrap().rhat);
+        assert!(summaries[3].tail_ess.is_some() && summaries[3].mcse_q5.is_some());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_summarize_draws_parallel() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let draws = crate::io::stan::read_files(&[
+            d.join("test/stan/blocker.1.csv"),
+            d.join("test/stan/blocker.2.csv"),
+        ])
+        .unwrap();
+        // a few columns are enough and keep the test fast
+        let columns: Vec<usize> = (7..14).collect();
+        let names = columns.iter().map(|&i| draws.names()[i].clone()).collect();
+        let chains = (0..draws.num_chains())
+            .map(|c| {
+                columns
+                    .iter()
+                    .map(|&i| draws.parameter(i)[c].clone())
+                    .collect()
+            })
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let summary_options = SummaryOptions {
+            rhat_sd: true,
+            bootstrap: Some(BootstrapOptions {
+                num_replicates: 20,
+                ..BootstrapOptions::default()
+            }),
+            tail_ess: true,
+            mcse_quantiles: true,
+            skip: vec![draws.names()[0].clone()],
+            ..SummaryOptions::default()
+        };
+        let sequential = summarize_draws_with_options(&draws, &summary_options).unwrap();
+        assert!(sequential[0].rhat_sd.is_none() && sequential[0].tail_ess.is_none());
+        assert!(sequential[1].rhat_sd.is_some());
+        assert!(sequential[1].quantile_intervals.is_some());
+        assert!(sequential[1].tail_ess.is_some() && sequential[1].mcse_q95.is_some());
+        for &num_threads in [0, 1, 2, 3, 100].iter() {
+            for &deterministic in [true, false].iter() {
+                let options = ParallelOptions {
+                    num_threads,
+                    deterministic,
+                };
+                assert_eq!(
+                    summarize_draws_parallel(&draws, &summary_options, &options).unwrap(),
+                    sequential
+                );
+            }
+        }
+
+        let names: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+        let chains = vec![vec![vec![1.0], vec![2.0], vec![3.0], vec![4.0]]];
+        let short = Draws::from_chains(names, chains).unwrap();
+        let options = ParallelOptions {
+            num_threads: 2,
+            deterministic: true,
+        };
+        let err =
+            summarize_draws_parallel(&short, &SummaryOptions::default(), &options).unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to summarize a"));
+        let options = ParallelOptions {
+            deterministic: false,
+            ..options
+        };
+        assert!(summarize_draws_parallel(&short, &SummaryOptions::default(), &options).is_err());
+    }
+
+    #[test]
+    fn test_summary_report() {
+        let names = ["beta[10]", "alpha", "beta[2]"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let chains = (0..2)
+            .map(|c| {
+                (0..3)
+                    .map(|p| crate::utils::normal_draws(200, 10 * c + p))
+                    .collect()
+            })
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let summaries = summarize_draws_with_options(&draws, &SummaryOptions::default()).unwrap();
+
+        let report = summary_report(&draws, &ReportOptions::default()).unwrap();
+        assert_eq!(report.len(), 3);
+        assert_eq!(report.names(), draws.names());
+        assert_eq!(report.get("beta[2]"), Some(&summaries[2]));
+        assert_eq!(report.get("beta[3]"), None);
+
+        let options = ReportOptions {
+            order: ReportOrder::Sorted,
+            ..ReportOptions::default()
+        };
+        let sorted = summary_report(&draws, &options).unwrap();
+        let order: Vec<&str> = sorted.iter().map(|(name, _)| name).collect();
+        assert_eq!(order, vec!["alpha", "beta[2]", "beta[10]"]);
+        assert_eq!(sorted.summaries()[2], summaries[0]);
+        assert_eq!(sorted.get("beta[2]"), report.get("beta[2]"));
+        assert_eq!(report.probs(), &DEFAULT_QUANTILES);
+        assert_eq!(report.hdi("alpha"), None);
+        assert_eq!(report.diagnostics(), None);
+
+        let pipeline = Pipeline::with_builtins();
+        let with_pipeline = summary_report_with_pipeline(&draws, &options, &pipeline).unwrap();
+        assert_eq!(with_pipeline.summaries(), sorted.summaries());
+        let diagnostics = with_pipeline.diagnostics().unwrap();
+        assert_eq!(diagnostics, &pipeline.run(&draws).unwrap());
+        assert_eq!(
+            diagnostics.value("rhat", "beta[2]"),
+            Some(summaries[2].rhat)
+        );
+    }
+
+    #[test]
+    fn test_summary_report_intervals() {
+        // 0, 1, ..., 100 and its square
+        let x: Array1 = (0..=100).map(f64::from).collect();
+        let y = x.iter().map(|v| v * v).collect();
+        let names = vec!["x".to_string(), "y".to_string()];
+        let draws = Draws::from_chains(names, vec![vec![x, y]]).unwrap();
+        let options = ReportOptions {
+            quantiles: vec![0.025, 0.5, 0.975],
+            hdi_mass: Some(0.89),
+            ..ReportOptions::default()
+        };
+        let report = summary_report(&draws, &options).unwrap();
+        assert_eq!(report.probs(), &[0.025, 0.5, 0.975]);
+        assert_eq!(report.hdi_mass(), Some(0.89));
+        assert_eq!(report.quantiles("x"), Some(&[2.5, 50.0, 97.5][..]));
+        let (lower, upper) = report.hdi("x").unwrap();
+        assert!(upper - lower >= 89.0 && upper - lower <= 91.0);
+        // the density of the square is highest near zero
+        assert_eq!(report.hdi("y").unwrap().0, 0.0);
+        assert_eq!(report.quantiles("z"), None);
+
+        for (quantiles, hdi_mass) in [(vec![1.5], None), (vec![], Some(1.0))].iter() {
+            let options = ReportOptions {
+                quantiles: quantiles.clone(),
+                hdi_mass: *hdi_mass,
+                ..ReportOptions::default()
+            };
+            assert!(summary_report(&draws, &options).is_err());
+            assert!(weighted_summary_report(&draws, &options).is_err());
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_summarize_groups() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let draws = crate::io::stan::read_files(&[
+            d.join("test/stan/blocker.1.csv"),
+            d.join("test/stan/blocker.2.csv"),
+        ])
+        .unwrap();
+        let summaries = summarize_draws_with_options(&draws, &SummaryOptions::default()).unwrap();
+        let groups = summarize_groups(&draws).unwrap();
+        let mu = groups.iter().find(|g| g.name == "mu").unwrap();
+        let indices: Vec<usize> = (0..draws.num_parameters())
+            .filter(|&i| draws.names()[i].starts_with("mu."))
+            .collect();
+        assert_eq!(mu.size, indices.len());
+        let worst = indices
+            .iter()
+            .max_by(|&&a, &&b| summaries[a].rhat.partial_cmp(&summaries[b].rhat).unwrap())
+            .unwrap();
+        assert_eq!(mu.max_rhat, summaries[*worst].rhat);
+        assert_eq!(mu.max_rhat_parameter, draws.names()[*worst]);
+        let d_group = groups.iter().find(|g| g.name == "d").unwrap();
+        assert_eq!(d_group.size, 1);
+        assert_eq!(d_group.min_ess, summaries[draws.index_of("d").unwrap()].ess);
+
+        assert_eq!(block_name("theta[1,2]"), "theta");
+        assert_eq!(block_name("theta.1.2"), "theta");
+        assert_eq!(block_name("sigma.y"), "sigma.y");
+    }
+
+    #[test]
+    fn test_group_summary_nan_is_worst() {
+        let mut group = GroupSummary {
+            name: "theta".to_string(),
+            size: 1,
+            min_ess: 400.0,
+            min_ess_parameter: "theta[1]".to_string(),
+            max_rhat: 1.01,
+            max_rhat_parameter: "theta[1]".to_string(),
+        };
+        group.add("theta[2]", f64::NAN, f64::NAN);
+        group.add("theta[3]", 10.0, 1.5);
+        assert_eq!(group.size, 3);
+        assert!(group.min_ess.is_nan());
+        assert_eq!(group.min_ess_parameter, "theta[2]");
+        assert!(group.max_rhat.is_nan());
+        assert_eq!(group.max_rhat_parameter, "theta[2]");
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_summarize_weighted() {
+        let d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let samples1 = read_csv(&d.join("test/stan/blocker.1.csv"), 41, 1000);
+        let samples2 = read_csv(&d.join("test/stan/blocker.2.csv"), 41, 1000);
+        let chains = vec![samples1[4].clone(), samples2[4].clone()];
+        let plain = summarize(&chains).unwrap();
+        let equal = summarize_weighted(&chains, &vec![vec![700.0; 1000]; 2]).unwrap();
+        assert_abs_diff_eq!(equal.mean, plain.mean, epsilon = 1e-10);
+        assert_abs_diff_eq!(equal.sd, plain.sd, epsilon = 1e-10);
+        assert_abs_diff_eq!(equal.ess, plain.ess, epsilon = 1e-6);
+        assert_abs_diff_eq!(equal.mcse, plain.mcse, epsilon = 1e-10);
+        assert_eq!(equal.rhat, plain.rhat);
+        assert_abs_diff_eq!(equal.q50, plain.q50, epsilon = 1e-3);
+
+        // importance weights exp(x) tilt N(0, 1) draws to N(1, 1)
+        let x = vec![normal_draws(5000, 3)];
+        let tilted = summarize_weighted(&x, &x).unwrap();
+        assert_abs_diff_eq!(tilted.mean, 1.0, epsilon = 0.05);
+        assert_abs_diff_eq!(tilted.sd, 1.0, epsilon = 0.05);
+        assert_abs_diff_eq!(tilted.q50, 1.0, epsilon = 0.05);
+        assert!(tilted.ess < 0.5 * 5000.0);
+
+        assert!(summarize_weighted(&chains, &vec![vec![0.0; 1000]]).is_err());
+        assert!(summarize_weighted(&x, &vec![vec![f64::NAN; 5000]]).is_err());
+    }
+
+    #[test]
+    fn test_summarize_weighted_non_finite_draws() {
+        let weights = vec![vec![0.0; 100]];
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY].iter() {
+            let mut x = normal_draws(100, 4);
+            x[50] = *bad;
+            let err = summarize_weighted(&vec![x], &weights).unwrap_err();
+            assert_eq!(err.to_string(), "Draws must be finite");
+        }
+    }
+
+    #[test]
+    fn test_weighted_summary_report() {
+        let x = normal_draws(2000, 5);
+        let names = vec!["b".to_string(), "log_weight".to_string(), "a".to_string()];
+        let y = x.iter().map(|v| 2.0 * v).collect();
+        let draws = Draws::from_chains(names, vec![vec![x.clone(), x.clone(), y]]).unwrap();
+        let options = ReportOptions {
+            order: ReportOrder::Sorted,
+            ..ReportOptions::default()
+        };
+        let report = weighted_summary_report(&draws, &options).unwrap();
+        assert_eq!(report.names(), &["a", "b"]);
+        assert_eq!(
+            report.get("b"),
+            Some(&summarize_weighted(&vec![x.clone()], &vec![x]).unwrap())
+        );
+        assert!(report.get("a").unwrap().mean > 1.5);
+
+        // equal weights give the quantiles of the draws themselves
+        let x: Array1 = (1..=10).map(f64::from).collect();
+        let names = vec!["x".to_string(), "lw__".to_string()];
+        let draws = Draws::from_chains(names, vec![vec![x, vec![0.0; 10]]]).unwrap();
+        let options = ReportOptions {
+            quantiles: vec![0.1, 0.5],
+            hdi_mass: Some(0.5),
+            ..ReportOptions::default()
+        };
+        let report = weighted_summary_report(&draws, &options).unwrap();
+        assert_eq!(report.quantiles("x"), Some(&[1.0, 5.0][..]));
+        assert_eq!(report.hdi("x"), Some((1.0, 5.0)));
+        let skewed = [(0.0, 0.1), (1.0, 0.1), (2.0, 0.6), (3.0, 0.2)];
+        assert_eq!(weighted_hdi(&skewed, 0.6), (2.0, 2.0));
+        assert_eq!(weighted_hdi(&skewed, 0.75), (2.0, 3.0));
+
+        let unweighted = Draws::from_chains(vec!["b".to_string()], vec![vec![vec![1.0, 2.0]]]);
+        assert!(weighted_summary_report(&unweighted.unwrap(), &options).is_err());
+    }
+
+    #[test]
+    fn test_summarize_draws_columnar() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chains = (0..2)
+            .map(|c| vec![normal_draws(300, c), normal_draws(300, c + 10)])
+            .collect();
+        let draws = Draws::from_chains(names, chains).unwrap();
+        let summaries = summarize_draws_with_options(&draws, &SummaryOptions::default()).unwrap();
+        let columns = summarize_draws_columnar(&draws, &SummaryOptions::default()).unwrap();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns.names, draws.names());
+        assert_eq!(columns.mean[1], summaries[1].mean);
+        assert_eq!(columns.rhat[0], summaries[0].rhat);
+        assert_eq!(columns.q95[1], summaries[1].q95);
+        assert!(columns.tail_ess.is_none());
+
+        let options = SummaryOptions {
+            tail_ess: true,
+            skip: vec!["b".to_string()],
+            ..SummaryOptions::default()
+        };
+        let columns = summarize_draws_columnar(&draws, &options).unwrap();
+        let tail_ess = columns.tail_ess.unwrap();
+        assert!(tail_ess[0] > 0.0);
+        assert!(tail_ess[1].is_nan());
+        assert!(columns.mcse_q5.is_none());
+
+        let report = summary_report(&draws, &ReportOptions::default()).unwrap();
+        assert_eq!(
+            report.to_columns().ess,
+            vec![summaries[0].ess, summaries[1].ess]
+        );
+        assert!(SummaryColumns::from_summaries(vec![], &summaries).is_err());
+    }
+}
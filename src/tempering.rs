@@ -0,0 +1,206 @@
+use crate::draws::Draws;
+use anyhow::{anyhow, Error, Result};
+
+/// One proposed swap between the replicas at two adjacent temperatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapProposal {
+    /// Index of the colder of the two temperature levels, so that the swap is
+    /// between levels `pair` and `pair + 1` with level zero the coldest
+    pub pair: usize,
+    /// Whether the swap was accepted
+    pub accepted: bool,
+}
+
+/// Swap statistics of one pair of adjacent temperature levels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapRate {
+    /// Number of proposed swaps
+    pub proposed: usize,
+    /// Number of accepted swaps
+    pub accepted: usize,
+    /// Fraction of proposed swaps that were accepted, `NaN` if none were
+    /// proposed
+    pub rate: f64,
+}
+
+/// Computes the swap acceptance rate of every pair of adjacent temperature
+/// levels.  Rates that differ a lot between pairs mean the temperature
+/// ladder is poorly spaced, and a pair with a rate near zero splits the
+/// ladder in two, so that information from the hot chains never reaches the
+/// target.
+///
+/// # Arguments
+/// * `proposals` - Every proposed swap, in any order
+/// * `num_levels` - Number of temperature levels, i.e. replicas
+pub fn swap_acceptance_rates(
+    proposals: &[SwapProposal],
+    num_levels: usize,
+) -> Result<Vec<SwapRate>, Error> {
+    if num_levels < 2 {
+        return Err(anyhow!("Need at least 2 temperature levels"));
+    }
+    let mut rates = vec![
+        SwapRate {
+            proposed: 0,
+            accepted: 0,
+            rate: f64::NAN,
+        };
+        num_levels - 1
+    ];
+    for proposal in proposals.iter() {
+        let rate = rates.get_mut(proposal.pair).ok_or_else(|| {
+            anyhow!(
+                "Swap between levels {} and {} is out of range for {} levels",
+                proposal.pair,
+                proposal.pair + 1,
+                num_levels
+            )
+        })?;
+        rate.proposed += 1;
+        if proposal.accepted {
+            rate.accepted += 1;
+        }
+    }
+    for rate in rates.iter_mut().filter(|r| r.proposed > 0) {
+        rate.rate = rate.accepted as f64 / rate.proposed as f64;
+    }
+    Ok(rates)
+}
+
+/// Counts the round trips of each replica, where a round trip is a journey
+/// from the coldest level to the hottest and back to the coldest.  The round
+/// trip rate is the standard measure of how well a tempering schedule moves
+/// replicas across the ladder; a replica that never completes one has not
+/// carried any information from the hot end to the target.
+///
+/// # Arguments
+/// * `levels` - Temperature level of each replica at each iteration,
+///   `levels[replica][iteration]`, with level zero the coldest
+/// * `num_levels` - Number of temperature levels
+pub fn round_trips(levels: &[Vec<usize>], num_levels: usize) -> Result<Vec<usize>, Error> {
+    if num_levels < 2 {
+        return Err(anyhow!("Need at least 2 temperature levels"));
+    }
+    let hottest = num_levels - 1;
+    levels
+        .iter()
+        .map(|replica| {
+            let mut trips = 0;
+            // trips start at the coldest level, so this is None until the
+            // replica first gets there and then says whether it is heading
+            // for the hot end
+            let mut heading_hot: Option<bool> = None;
+            for &level in replica.iter() {
+                if level > hottest {
+                    return Err(anyhow!(
+                        "Level {} is out of range for {} levels",
+                        level,
+                        num_levels
+                    ));
+                }
+                if level == 0 {
+                    if heading_hot == Some(false) {
+                        trips += 1;
+                    }
+                    heading_hot = Some(true);
+                } else if level == hottest && heading_hot == Some(true) {
+                    heading_hot = Some(false);
+                }
+            }
+            Ok(trips)
+        })
+        .collect()
+}
+
+/// Counts round trips like [`round_trips`](fn.round_trips.html) for draws in
+/// which each chain is a replica and a column records the inverse
+/// temperature of every draw.  Levels are numbered from coldest (inverse
+/// temperature one) to hottest, and the inverse temperatures must be
+/// finite.
+///
+/// # Arguments
+/// * `draws` - Draws with one chain per replica
+/// * `column` - Name of the column with the inverse temperature of each draw
+pub fn round_trips_from_draws(draws: &Draws, column: &str) -> Result<Vec<usize>, Error> {
+    let idx = draws
+        .index_of(column)
+        .ok_or_else(|| anyhow!("No parameter named {:?}", column))?;
+    let betas = draws.parameter(idx);
+    if let Some(beta) = betas.iter().flatten().find(|b| !b.is_finite()) {
+        return Err(anyhow!(
+            "Inverse temperatures in {:?} must be finite, got {}",
+            column,
+            beta
+        ));
+    }
+    let mut ladder: Vec<f64> = Vec::new();
+    for &beta in betas.iter().flatten() {
+        if !ladder.contains(&beta) {
+            ladder.push(beta);
+        }
+    }
+    ladder.sort_by(|a, b| b.total_cmp(a));
+    let levels: Vec<Vec<usize>> = betas
+        .iter()
+        .map(|chain| {
+            chain
+                .iter()
+                .map(|beta| ladder.iter().position(|b| b == beta).unwrap())
+                .collect()
+        })
+        .collect();
+    round_trips(&levels, ladder.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_acceptance_rates() {
+        let proposals: Vec<SwapProposal> = [(0, true), (0, false), (1, true), (0, true)]
+            .iter()
+            .map(|&(pair, accepted)| SwapProposal { pair, accepted })
+            .collect();
+        let rates = swap_acceptance_rates(&proposals, 4).unwrap();
+        assert_eq!(rates.len(), 3);
+        assert_eq!((rates[0].proposed, rates[0].accepted), (3, 2));
+        assert_abs_diff_eq!(rates[0].rate, 2.0 / 3.0);
+        assert_abs_diff_eq!(rates[1].rate, 1.0);
+        assert!(rates[2].rate.is_nan());
+        assert!(swap_acceptance_rates(&proposals, 2).is_err());
+        assert!(swap_acceptance_rates(&[], 1).is_err());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let levels = vec![
+            // starts mid ladder, one full trip, then half of another
+            vec![1, 0, 1, 2, 1, 0, 1, 2],
+            // reaches the hot end first, which doesn't count as a trip
+            vec![2, 1, 0, 1, 2, 2, 1, 0, 0, 1, 2, 1, 0],
+            vec![0, 0, 0],
+        ];
+        assert_eq!(round_trips(&levels, 3).unwrap(), vec![1, 2, 0]);
+        assert!(round_trips(&[vec![3]], 3).is_err());
+    }
+
+    #[test]
+    fn test_round_trips_from_draws() {
+        let names = vec!["a".to_string(), "beta".to_string()];
+        let chains = vec![
+            vec![vec![0.0; 5], vec![1.0, 0.5, 0.25, 0.5, 1.0]],
+            vec![vec![0.0; 5], vec![0.25, 0.25, 1.0, 1.0, 0.25]],
+            vec![vec![0.0; 5], vec![0.5, 1.0, 0.5, 0.25, 0.5]],
+        ];
+        let draws = Draws::from_chains(names, chains).unwrap();
+        assert_eq!(
+            round_trips_from_draws(&draws, "beta").unwrap(),
+            vec![1, 0, 0]
+        );
+        assert!(round_trips_from_draws(&draws, "b").is_err());
+        let names = vec!["beta".to_string()];
+        let nan = Draws::from_chains(names, vec![vec![vec![1.0, f64::NAN]]]).unwrap();
+        assert!(round_trips_from_draws(&nan, "beta").is_err());
+    }
+}
@@ -1,33 +1,110 @@
 use crate::{Array1, Array2};
-use anyhow::{anyhow, Error, Result};
-use average::Mean;
-use average::Variance;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
-};
+use anyhow::{anyhow, Context, Error, Result};
+use std::io::BufRead;
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+/// Number of independent accumulators used by the chunked reductions below.
+/// Splitting the dependency chain this way lets the compiler emit packed SIMD
+/// adds and multiplies on stable Rust without any explicit intrinsics.
+const LANES: usize = 8;
+
+/// Compute the sum of an array using `LANES` independent accumulators.
+pub(crate) fn sum(arr: &[f64]) -> f64 {
+    let mut acc = [0.0; LANES];
+    let chunks = arr.chunks_exact(LANES);
+    let remainder = chunks.remainder();
+    for chunk in chunks {
+        for (a, x) in acc.iter_mut().zip(chunk) {
+            *a += x;
+        }
+    }
+    acc.iter().sum::<f64>() + remainder.iter().sum::<f64>()
+}
+
+/// Compute the dot product of two arrays of equal length using `LANES`
+/// independent accumulators.
+pub(crate) fn dot(a: &[f64], b: &[f64]) -> f64 {
+    debug_assert_eq!(a.len(), b.len());
+    let mut acc = [0.0; LANES];
+    let chunks_a = a.chunks_exact(LANES);
+    let chunks_b = b.chunks_exact(LANES);
+    let remainder: f64 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (chunk_a, chunk_b) in chunks_a.zip(chunks_b) {
+        for ((acc, x), y) in acc.iter_mut().zip(chunk_a).zip(chunk_b) {
+            *acc += x * y;
+        }
+    }
+    acc.iter().sum::<f64>() + remainder
+}
+
+/// Compute the sum of squared deviations of an array from `center`.
+fn sum_squared_deviations(arr: &[f64], center: f64) -> f64 {
+    let mut acc = [0.0; LANES];
+    let chunks = arr.chunks_exact(LANES);
+    let remainder: f64 = chunks
+        .remainder()
+        .iter()
+        .map(|x| (x - center) * (x - center))
+        .sum();
+    for chunk in chunks {
+        for (a, x) in acc.iter_mut().zip(chunk) {
+            let d = x - center;
+            *a += d * d;
+        }
+    }
+    acc.iter().sum::<f64>() + remainder
+}
 
 /// Compute the arithmetic mean of an array.
-pub(in crate) fn mean(arr: &[f64]) -> Result<f64, Error> {
+pub(crate) fn mean(arr: &[f64]) -> Result<f64, Error> {
     if arr.is_empty() {
         return Err(anyhow!("Can't take mean of empty array"));
     }
-    let mean = arr.iter().collect::<Mean>();
-    Ok(mean.mean())
+    Ok(sum(arr) / arr.len() as f64)
+}
+
+/// Compute `log(sum(exp(arr)))` without overflow, `-inf` for an empty array.
+pub(crate) fn log_sum_exp(arr: &[f64]) -> f64 {
+    let max = arr.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if !max.is_finite() {
+        return max;
+    }
+    max + arr.iter().map(|x| (x - max).exp()).sum::<f64>().ln()
 }
 
 /// Compute the sample variance of an array using Bessel's correction.
-pub(in crate) fn sample_variance(arr: &[f64]) -> Result<f64, Error> {
+/// Arrays with a single element have a variance of zero.
+pub(crate) fn sample_variance(arr: &[f64]) -> Result<f64, Error> {
     if arr.is_empty() {
         return Err(anyhow!("Can't take variance of empty array"));
     }
-    let var: Variance = arr.iter().collect();
-    Ok(var.sample_variance())
+    if arr.len() == 1 {
+        return Ok(0.0);
+    }
+    let center = mean(arr)?;
+    Ok(sum_squared_deviations(arr, center) / (arr.len() - 1) as f64)
+}
+
+/// Compute the biased (divided by `n`) sample autocovariances of a chain for
+/// lags `0..=max_lag`, the same estimator used by Stan.  `max_lag` is capped
+/// at `n - 1`.
+pub(crate) fn autocovariance(chain: &[f64], max_lag: usize) -> Result<Array1, Error> {
+    let n = chain.len();
+    let center = mean(chain)?;
+    let centered: Array1 = chain.iter().map(|x| x - center).collect();
+    Ok((0..=max_lag.min(n - 1))
+        .map(|lag| dot(&centered[..n - lag], &centered[lag..]) / n as f64)
+        .collect())
 }
 
 /// Clone a 2D array into one long 1D array.
-pub(in crate) fn flatten(chains: &Array2) -> Array1 {
+pub(crate) fn flatten(chains: &Array2) -> Array1 {
     let mut flattened = Vec::new();
     for chain in chains {
         flattened.extend(chain);
@@ -35,6 +112,327 @@ pub(in crate) fn flatten(chains: &Array2) -> Array1 {
     flattened
 }
 
+/// Quotes a CSV field that contains a comma, quote or line break.
+pub(crate) fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Compute the quantile of an already sorted array for probability `prob`,
+/// linearly interpolating between order statistics (type 7 in R, which is
+/// also what Stan and NumPy use by default).
+pub(crate) fn quantile_sorted(sorted: &[f64], prob: f64) -> Result<f64, Error> {
+    if sorted.is_empty() {
+        return Err(anyhow!("Can't take quantile of empty array"));
+    }
+    if !(0.0..=1.0).contains(&prob) {
+        return Err(anyhow!(
+            "Quantile probability must be in [0, 1], got {}",
+            prob
+        ));
+    }
+    let h = (sorted.len() - 1) as f64 * prob;
+    let lo = h.floor() as usize;
+    let hi = h.ceil() as usize;
+    Ok(sorted[lo] + (h - lo as f64) * (sorted[hi] - sorted[lo]))
+}
+
+/// Compute several quantiles of an array, sorting a copy of it only once.
+pub(crate) fn quantiles(arr: &[f64], probs: &[f64]) -> Result<Array1, Error> {
+    let mut sorted = arr.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    probs.iter().map(|p| quantile_sorted(&sorted, *p)).collect()
+}
+
+/// Compute the ranks of an array starting from one, giving tied values the
+/// average of the ranks they span.
+pub(crate) fn ranks(arr: &[f64]) -> Array1 {
+    sorted_ranks(arr).0
+}
+
+/// Compute the ranks of an array like [`ranks`], together with a sorted copy
+/// of the array, for callers that also need quantiles.
+pub(crate) fn sorted_ranks(arr: &[f64]) -> (Array1, Array1) {
+    let mut order: Vec<usize> = (0..arr.len()).collect();
+    order.sort_by(|&a, &b| arr[a].total_cmp(&arr[b]));
+    let mut result = vec![0.0; arr.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && arr[order[end]] == arr[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &idx in order[start..end].iter() {
+            result[idx] = rank;
+        }
+        start = end;
+    }
+    let sorted = order.iter().map(|&idx| arr[idx]).collect();
+    (result, sorted)
+}
+
+/// Quantile function of the standard normal distribution, using algorithm
+/// AS 241 of Wichura (1988), which is accurate to about 1e-16 and is what R's
+/// `qnorm` uses.
+pub(crate) fn normal_quantile(p: f64) -> f64 {
+    fn polynomial(coefficients: &[f64], x: f64) -> f64 {
+        coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c)
+    }
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let q = p - 0.5;
+    if q.abs() <= 0.425 {
+        let r = 0.180625 - q * q;
+        let num = polynomial(
+            &[
+                3.387_132_872_796_366_6,
+                133.141_667_891_784_38,
+                1_971.590_950_306_551_4,
+                13_731.693_765_509_46,
+                45_921.953_931_549_87,
+                67_265.770_927_008_7,
+                33_430.575_583_588_13,
+                2_509.080_928_730_122_7,
+            ],
+            r,
+        );
+        let den = polynomial(
+            &[
+                1.0,
+                42.313_330_701_600_91,
+                687.187_007_492_057_9,
+                5_394.196_021_424_751,
+                21_213.794_301_586_597,
+                39_307.895_800_092_71,
+                28_729.085_735_721_943,
+                5_226.495_278_852_854,
+            ],
+            r,
+        );
+        return q * num / den;
+    }
+    let r = if q < 0.0 { p } else { 1.0 - p };
+    let r = (-r.ln()).sqrt();
+    let value = if r <= 5.0 {
+        let r = r - 1.6;
+        polynomial(
+            &[
+                1.423_437_110_749_683_5,
+                4.630_337_846_156_546,
+                5.769_497_221_460_691,
+                3.647_848_324_763_204_5,
+                1.270_458_252_452_368_4,
+                0.241_780_725_177_450_6,
+                0.022_723_844_989_269_184,
+                7.745_450_142_783_414e-4,
+            ],
+            r,
+        ) / polynomial(
+            &[
+                1.0,
+                2.053_191_626_637_759,
+                1.676_384_830_183_803_8,
+                0.689_767_334_985_1,
+                0.148_103_976_427_480_07,
+                0.015_198_666_563_616_457,
+                5.475_938_084_995_345e-4,
+                1.050_750_071_644_416_8e-9,
+            ],
+            r,
+        )
+    } else {
+        let r = r - 5.0;
+        polynomial(
+            &[
+                6.657_904_643_501_103,
+                5.463_784_911_164_114,
+                1.784_826_539_917_291_3,
+                0.296_560_571_828_504_9,
+                0.026_532_189_526_576_124,
+                0.001_242_660_947_388_078_4,
+                2.711_555_568_743_487_6e-5,
+                2.010_334_399_292_288_1e-7,
+            ],
+            r,
+        ) / polynomial(
+            &[
+                1.0,
+                0.599_832_206_555_888,
+                0.136_929_880_922_735_8,
+                0.014_875_361_290_850_615,
+                7.868_691_311_456_133e-4,
+                1.846_318_317_510_054_8e-5,
+                1.421_511_758_316_446e-7,
+                2.044_263_103_389_939_7e-15,
+            ],
+            r,
+        )
+    };
+    if q < 0.0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Natural logarithm of the gamma function for positive arguments, using the
+/// Lanczos approximation (g = 7, n = 9).
+pub(crate) fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // reflection formula
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let mut a = COEFFICIENTS[0];
+    let t = x + 7.5;
+    for (i, c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        a += c / (x + i as f64);
+    }
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// Upper regularized incomplete gamma function Q(a, x), from the series
+/// expansion for `x < a + 1` and a continued fraction otherwise.
+pub(crate) fn regularized_gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let log_prefactor = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut total = term;
+        let mut n = a;
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            total += term;
+            if term.abs() < total.abs() * 1e-15 {
+                break;
+            }
+        }
+        1.0 - total * log_prefactor.exp()
+    } else {
+        // modified Lentz's method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        h * log_prefactor.exp()
+    }
+}
+
+/// Regularized incomplete beta function I_x(a, b), from the continued
+/// fraction, using the symmetry relation where it converges faster.
+pub(crate) fn regularized_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    if x > (a + 1.0) / (a + b + 2.0) {
+        return 1.0 - regularized_beta(1.0 - x, b, a);
+    }
+    let log_prefactor =
+        ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln();
+    // modified Lentz's method
+    let tiny = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < tiny {
+        d = tiny;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..1000 {
+        let m = m as f64;
+        for &an in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ]
+        .iter()
+        {
+            d = 1.0 + an * d;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = 1.0 + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    log_prefactor.exp() * h / a
+}
+
+/// Quantile function of the beta distribution, found by bisection on the
+/// regularized incomplete beta function.
+pub(crate) fn beta_quantile(p: f64, a: f64, b: f64) -> f64 {
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..200 {
+        let mid = 0.5 * (lo + hi);
+        if regularized_beta(mid, a, b) < p {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+        if hi - lo < 1e-15 {
+            break;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+/// Survival function (upper tail probability) of the chi-square
+/// distribution with `df` degrees of freedom.
+pub(crate) fn chi_square_sf(statistic: f64, df: f64) -> f64 {
+    regularized_gamma_q(df / 2.0, statistic / 2.0)
+}
+
 /// Splits each chain into two chains of equal length.  When the
 /// number of total draws N is odd, the (N+1)/2th draw is ignored.
 ///
@@ -55,10 +453,62 @@ pub fn split_chains(chains: Array2) -> Result<Array2, Error> {
     } else {
         ((num_draws - 1) / 2, 1)
     };
-    let mut split_draws = Vec::new();
-    for chain in chains {
-        split_draws.push(chain[..half].to_vec());
-        split_draws.push(chain[(half + offset)..].to_vec());
+    let mut split_draws = Vec::with_capacity(2 * chains.len());
+    for mut chain in chains {
+        // reuse the chain's buffer for the first half so only the second
+        // half is copied
+        let second = chain.split_off(half + offset);
+        chain.truncate(half);
+        split_draws.push(chain);
+        split_draws.push(second);
+    }
+    Ok(split_draws)
+}
+
+/// Pools all chains into a single chain by concatenating them in order, e.g.
+/// to estimate posterior quantities from several independent runs once their
+/// diagnostics have been checked separately.  Returns a one chain array so
+/// the result can still be passed to the functions in this crate.
+pub fn concat_chains(chains: &Array2) -> Array2 {
+    vec![flatten(chains)]
+}
+
+/// Splits a name into text and numbers so that names sort the way a person
+/// would order them, e.g. `beta[2]` before `beta[10]`.
+pub(crate) fn natural_key(name: &str) -> Vec<(String, u64)> {
+    let mut key = Vec::new();
+    let mut rest = name;
+    while !rest.is_empty() {
+        let text_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (text, tail) = rest.split_at(text_len);
+        let digits_len = tail
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (digits, tail) = tail.split_at(digits_len);
+        key.push((text.to_string(), digits.parse().unwrap_or(u64::MAX)));
+        rest = tail;
+    }
+    key
+}
+
+/// Trims chains to the length of the shortest chain and splits each one in
+/// half like `split_chains`, but borrows the halves instead of copying them.
+/// When the number of draws is odd the middle draw is ignored.
+pub(crate) fn split_slices(chains: &[Array1]) -> Result<Vec<&[f64]>, Error> {
+    if chains.is_empty() {
+        return Err(anyhow!("Can't split empty array of chains"));
+    }
+    let num_draws = chains.iter().map(|c| c.len()).min().unwrap();
+    if num_draws < 1 {
+        return Err(anyhow!("No samples to split"));
+    }
+    let half = num_draws / 2;
+    let mut split_draws = Vec::with_capacity(2 * chains.len());
+    for chain in chains.iter() {
+        split_draws.push(&chain[..half]);
+        split_draws.push(&chain[(num_draws - half)..num_draws]);
     }
     Ok(split_draws)
 }
@@ -69,27 +519,214 @@ pub fn split_chains(chains: Array2) -> Result<Array2, Error> {
 ///
 /// # Arguments
 /// * `skip_rows` - Number of rows to skip before numeric values. For example,
-///                 if there is a header row you can pass in the value `1`.
+///   if there is a header row you can pass in the value `1`.
 /// * `n_rows` - Number of rows to read in. Use if you only want a certain
-///              subset of rows or if there are improper rows after the numeric
-///              rows (e.g. in Stan sample files there are commented rows at the end).
+///   subset of rows or if there are improper rows after the numeric
+///   rows (e.g. in Stan sample files there are commented rows at the end).
+#[cfg(feature = "fs")]
 pub fn read_csv(path: &PathBuf, skip_rows: usize, n_rows: usize) -> Array2 {
+    let f = File::open(path).unwrap();
+    read_csv_from_reader(BufReader::new(f), skip_rows, n_rows)
+}
+
+/// Same as [`read_csv`](fn.read_csv.html) but reads from an in-memory buffer
+/// instead of a file, so it is available without the `fs` feature.  The
+/// bytes usually come from a user, e.g. an upload to a browser dashboard, so
+/// a cell that is not a number or a row with a different number of cells
+/// than the first one is an error rather than a panic.
+pub fn read_csv_from_bytes(bytes: &[u8], skip_rows: usize, n_rows: usize) -> Result<Array2, Error> {
+    let options = CsvOptions {
+        skip_rows,
+        n_rows,
+        ..CsvOptions::default()
+    };
+    try_read_csv_from_reader(bytes, &options)
+}
+
+#[cfg(feature = "fs")]
+fn read_csv_from_reader<R: BufRead>(reader: R, skip_rows: usize, n_rows: usize) -> Array2 {
     let mut result: Array2 = Vec::new();
-    let f = File::open(&path).unwrap();
-    let f = BufReader::new(f);
-    for line in f.lines().skip(skip_rows).take(n_rows) {
-        if let Ok(line) = line {
-            for (idx, value) in line.split(',').enumerate() {
-                if idx >= result.len() {
-                    result.push(Vec::new())
-                }
-                result[idx].push(value.parse::<f64>().unwrap());
+    for line in reader.lines().skip(skip_rows).take(n_rows).flatten() {
+        for (idx, value) in line.split(',').enumerate() {
+            if idx >= result.len() {
+                result.push(Vec::new())
             }
+            result[idx].push(value.parse::<f64>().unwrap());
         }
     }
     result
 }
 
+/// What to do with missing values, i.e. NaN, in the input: the
+/// `nan_policy` of [`CsvOptions`](struct.CsvOptions.html) and of
+/// [`SummaryOptions`](../summary/struct.SummaryOptions.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NanPolicy {
+    /// Fail with an error saying where the value is
+    #[default]
+    Error,
+    /// Leave out the draws with a missing value
+    Skip,
+    /// Keep the missing values, which makes whatever is computed from them
+    /// NaN
+    Keep,
+}
+
+/// Options for the validating CSV readers
+/// [`try_read_csv`](fn.try_read_csv.html) and
+/// [`try_read_csv_from_bytes`](fn.try_read_csv_from_bytes.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvOptions {
+    /// Number of rows to skip before numeric values, e.g. `1` for a header
+    pub skip_rows: usize,
+    /// Maximum number of rows to read after the skipped ones
+    pub n_rows: usize,
+    /// What to do with cells that are `NaN` or not numbers, such as `NA` or
+    /// an empty cell: fail, leave out their rows, or read them as `NaN`.
+    /// Most diagnostics return an error or `NaN` for chains containing
+    /// `NaN`, so keeping them is mainly useful for inspecting or cleaning
+    /// such files, or with the same policy in the summaries.
+    pub nan_policy: NanPolicy,
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            skip_rows: 0,
+            n_rows: usize::MAX,
+            nan_policy: NanPolicy::Error,
+        }
+    }
+}
+
+/// Reads a CSV file of numbers like [`read_csv`](fn.read_csv.html), but
+/// returns an error with the line and column of the problem instead of
+/// panicking when the file can't be read, a cell is not a number and the
+/// NaN policy is to fail, or a row has a different number of cells than the
+/// first one.
+#[cfg(feature = "fs")]
+pub fn try_read_csv(path: &PathBuf, options: &CsvOptions) -> Result<Array2, Error> {
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    try_read_csv_from_reader(BufReader::new(f), options)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Same as [`try_read_csv`](fn.try_read_csv.html) but reads from an
+/// in-memory buffer instead of a file.
+pub fn try_read_csv_from_bytes(bytes: &[u8], options: &CsvOptions) -> Result<Array2, Error> {
+    try_read_csv_from_reader(bytes, options)
+}
+
+fn try_read_csv_from_reader<R: BufRead>(reader: R, options: &CsvOptions) -> Result<Array2, Error> {
+    let mut result: Array2 = Vec::new();
+    let lines = reader
+        .lines()
+        .enumerate()
+        .skip(options.skip_rows)
+        .take(options.n_rows);
+    for (line_idx, line) in lines {
+        let line = line.with_context(|| format!("Failed to read line {}", line_idx + 1))?;
+        let mut row = Vec::with_capacity(result.len());
+        for (col_idx, value) in line.split(',').enumerate() {
+            let x = value.trim().parse::<f64>().unwrap_or(f64::NAN);
+            if x.is_nan() && options.nan_policy == NanPolicy::Error {
+                return Err(anyhow!(
+                    "Invalid value {:?} at line {}, column {}",
+                    value,
+                    line_idx + 1,
+                    col_idx + 1
+                ));
+            }
+            row.push(x);
+        }
+        if result.is_empty() {
+            result = vec![Vec::new(); row.len()];
+        } else if row.len() != result.len() {
+            return Err(anyhow!(
+                "Expected {} values at line {} but found {}",
+                result.len(),
+                line_idx + 1,
+                row.len()
+            ));
+        }
+        if options.nan_policy == NanPolicy::Skip && row.iter().any(|x| x.is_nan()) {
+            continue;
+        }
+        for (column, x) in result.iter_mut().zip(row) {
+            column.push(x);
+        }
+    }
+    Ok(result)
+}
+
+/// Small seeded xorshift64* pseudo random number generator, so randomized
+/// algorithms are reproducible without an external dependency.  Not suitable
+/// for cryptographic use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        // scramble the seed so that nearby seeds give unrelated streams, and
+        // avoid the all zero state
+        Rng {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    /// Internal state, to restore the generator with
+    /// [`from_state`](#method.from_state).
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Generator continuing from a saved state, which is never zero.
+    pub(crate) fn from_state(state: u64) -> Option<Rng> {
+        if state == 0 {
+            return None;
+        }
+        Some(Rng { state })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform draw from [0, 1).
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform draw from `0..n`, which must not be empty.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_f64() * n as f64) as usize
+    }
+}
+
+/// Deterministic standard normal draws for use in unit tests, generated with
+/// a xorshift generator and the Box-Muller transform.
+#[cfg(test)]
+pub(crate) fn normal_draws(n: usize, seed: u64) -> Array1 {
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut uniform = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        ((state >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    };
+    (0..n)
+        .map(|_| {
+            let (u1, u2) = (uniform(), uniform());
+            (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,6 +759,110 @@ mod tests {
         assert!(mean(&empty).is_err());
     }
 
+    #[test]
+    fn test_chunked_reductions() {
+        // lengths on either side of a multiple of the number of lanes
+        for n in [0, 1, 7, 8, 9, 31, 100] {
+            let a: Array1 = (0..n).map(|i| (i as f64 * 0.37).sin()).collect();
+            let b: Array1 = (0..n).map(|i| (i as f64 * 0.11).cos()).collect();
+            let naive_sum: f64 = a.iter().sum();
+            let naive_dot: f64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+            assert_abs_diff_eq!(sum(&a), naive_sum, epsilon = 1e-12);
+            assert_abs_diff_eq!(dot(&a, &b), naive_dot, epsilon = 1e-12);
+        }
+        assert_abs_diff_eq!(sample_variance(&[3.0]).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_autocovariance_matches_arima() {
+        let arr = normal_draws(200, 3);
+        let expected = arima::acf::acf(&arr, None, true).unwrap();
+        let actual = autocovariance(&arr, arr.len()).unwrap();
+        assert_eq!(actual.len(), arr.len());
+        for (a, e) in actual.iter().zip(&expected) {
+            assert_abs_diff_eq!(a, e, epsilon = 1e-12);
+        }
+        assert_eq!(autocovariance(&arr, 5).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_quantiles() {
+        // Values computed with numpy.quantile
+        let arr = vec![3.0, 1.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0];
+        let q = quantiles(&arr, &[0.0, 0.05, 0.5, 0.95, 1.0]).unwrap();
+        assert_abs_diff_eq!(q[0], 1.0);
+        assert_abs_diff_eq!(q[1], 1.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(q[2], 3.5, epsilon = 1e-12);
+        assert_abs_diff_eq!(q[3], 7.949999999999999, epsilon = 1e-12);
+        assert_abs_diff_eq!(q[4], 9.0);
+        assert!(quantiles(&arr, &[1.5]).is_err());
+        assert!(quantiles(&[], &[0.5]).is_err());
+    }
+
+    #[test]
+    fn test_ranks_with_ties() {
+        let r = ranks(&[3.0, 1.0, 2.0, 1.0, 5.0]);
+        assert_eq!(r, vec![4.0, 1.5, 3.0, 1.5, 5.0]);
+        assert!(ranks(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_chi_square_sf() {
+        // critical values from standard chi-square tables
+        assert_abs_diff_eq!(chi_square_sf(3.841459, 1.0), 0.05, epsilon = 1e-6);
+        assert_abs_diff_eq!(chi_square_sf(18.307038, 10.0), 0.05, epsilon = 1e-6);
+        assert_abs_diff_eq!(chi_square_sf(2.087912, 9.0), 0.99, epsilon = 1e-6);
+        assert_abs_diff_eq!(chi_square_sf(0.0, 3.0), 1.0);
+        assert_abs_diff_eq!(ln_gamma(5.0), 24f64.ln(), epsilon = 1e-12);
+        assert_abs_diff_eq!(
+            ln_gamma(0.5),
+            std::f64::consts::PI.sqrt().ln(),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn test_normal_quantile() {
+        // values from R's qnorm
+        assert_abs_diff_eq!(normal_quantile(0.5), 0.0);
+        assert_abs_diff_eq!(normal_quantile(0.975), 1.959963984540054, epsilon = 1e-14);
+        assert_abs_diff_eq!(normal_quantile(0.05), -1.6448536269514729, epsilon = 1e-14);
+        assert_abs_diff_eq!(normal_quantile(1e-10), -6.361340902404056, epsilon = 1e-12);
+        assert_eq!(normal_quantile(1.0), f64::INFINITY);
+        assert_abs_diff_eq!(normal_quantile(1e-300), -37.0471, epsilon = 1e-4);
+        let (r, sorted) = sorted_ranks(&[3.0, 1.0, 2.0]);
+        assert_eq!((r, sorted), (vec![3.0, 1.0, 2.0], vec![1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn test_beta_quantile() {
+        // closed forms: Beta(2, 3) has CDF 6x^2 - 8x^3 + 3x^4 and Beta(a, 1)
+        // has quantile p^(1 / a)
+        let x: f64 = 0.3;
+        let cdf = 6.0 * x.powi(2) - 8.0 * x.powi(3) + 3.0 * x.powi(4);
+        assert_abs_diff_eq!(regularized_beta(x, 2.0, 3.0), cdf, epsilon = 1e-12);
+        assert_abs_diff_eq!(
+            regularized_beta(0.8, 3.0, 2.0),
+            1.0 - regularized_beta(0.2, 2.0, 3.0),
+            epsilon = 1e-12
+        );
+        assert_abs_diff_eq!(
+            beta_quantile(0.3, 4.0, 1.0),
+            0.3f64.powf(0.25),
+            epsilon = 1e-12
+        );
+        assert_abs_diff_eq!(beta_quantile(0.7, 1.0, 1.0), 0.7, epsilon = 1e-12);
+        assert_abs_diff_eq!(beta_quantile(0.5, 500.0, 500.0), 0.5, epsilon = 1e-12);
+        assert_abs_diff_eq!(regularized_beta(0.0, 2.0, 2.0), 0.0);
+        assert_abs_diff_eq!(regularized_beta(1.0, 2.0, 2.0), 1.0);
+    }
+
+    #[test]
+    fn test_concat_chains() {
+        let chains = vec![vec![1.0, 2.0], vec![3.0], vec![]];
+        assert_eq!(concat_chains(&chains), vec![vec![1.0, 2.0, 3.0]]);
+    }
+
     #[test]
     fn test_split_empty_chains() {
         // Make sure the we Err on empty or minimum 0 length chains
@@ -150,6 +891,22 @@ mod tests {
         assert_eq!(split[3], vec![7.0, 8.0]);
     }
 
+    #[test]
+    fn test_split_slices_trims_and_borrows() {
+        let chains = vec![
+            vec![1.0, 2.0, 3.0, 4.0, 4.5, 9.0],
+            vec![5.0, 6.0, 7.0, 8.0, 8.5],
+        ];
+        let split = split_slices(&chains).unwrap();
+        assert_eq!(
+            split,
+            vec![&[1.0, 2.0][..], &[4.0, 4.5], &[5.0, 6.0], &[8.0, 8.5]]
+        );
+        assert_eq!(split[0].as_ptr(), chains[0].as_ptr());
+        assert!(split_slices(&[]).is_err());
+        assert!(split_slices(&[vec![1.0], vec![]]).is_err());
+    }
+
     #[test]
     fn test_split_odd_chains() {
         // Make sure the middle value gets dropped per the Stan reference implementation
@@ -172,4 +929,70 @@ mod tests {
         let flattened = flatten(&chains);
         assert_eq!(flattened, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
     }
+
+    #[test]
+    fn test_read_csv_from_bytes() {
+        let bytes = b"a,b\n1.0,2.0\n3.0,4.0\n# comment";
+        let result = read_csv_from_bytes(bytes, 1, 2).unwrap();
+        assert_eq!(result, vec![vec![1.0, 3.0], vec![2.0, 4.0]]);
+        assert!(read_csv_from_bytes(b"a,b\n1.0,2.0\n3.0,x\n", 1, 2).is_err());
+        assert!(read_csv_from_bytes(b"a,b\n1.0,2.0\n3.0\n", 1, 2).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_try_read_csv_from_bytes() {
+        let options = CsvOptions {
+            skip_rows: 1,
+            ..CsvOptions::default()
+        };
+        let bytes = b"a,b\n1.0,2.0\n3.0,4.0\n";
+        let result = try_read_csv_from_bytes(bytes, &options).unwrap();
+        assert_eq!(result, read_csv_from_bytes(bytes, 1, 2).unwrap());
+
+        let err = try_read_csv_from_bytes(b"a,b\n1.0,2.0\n3.0,NA\n", &options).unwrap_err();
+        assert!(format!("{:#}", err).contains("line 3, column 2"));
+        assert!(try_read_csv_from_bytes(b"a,b\n1.0,2.0\n3.0\n", &options).is_err());
+        assert!(try_read_csv_from_bytes(b"a,b\n1.0\n3.0,2.0\n", &options).is_err());
+
+        assert!(try_read_csv_from_bytes(b"a,b\n1.0,2.0\n3.0,NaN\n", &options).is_err());
+
+        let keep = CsvOptions {
+            nan_policy: NanPolicy::Keep,
+            ..options
+        };
+        let bytes = b"a,b\n1.0,2.0\n3.0,NA\n,4.0\n5.0,6.0\n";
+        let result = try_read_csv_from_bytes(bytes, &keep).unwrap();
+        assert!(result[1][1].is_nan());
+        assert!(result[0][2].is_nan());
+        let skip = CsvOptions {
+            nan_policy: NanPolicy::Skip,
+            ..options
+        };
+        let result = try_read_csv_from_bytes(bytes, &skip).unwrap();
+        assert_eq!(result, vec![vec![1.0, 5.0], vec![2.0, 6.0]]);
+        // a row with too few cells is an error whatever the policy
+        assert!(try_read_csv_from_bytes(b"a,b\n1.0,2.0\nNA\n", &skip).is_err());
+        assert!(try_read_csv(&PathBuf::from("test/missing.csv"), &options).is_err());
+    }
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(42);
+        let draws: Array1 = (0..10_000).map(|_| rng.next_f64()).collect();
+        assert!(draws.iter().all(|x| (0.0..1.0).contains(x)));
+        assert_abs_diff_eq!(mean(&draws).unwrap(), 0.5, epsilon = 0.01);
+        assert_eq!(Rng::new(42).next_u64(), Rng::new(42).next_u64());
+        assert_ne!(Rng::new(42).next_u64(), Rng::new(43).next_u64());
+        assert!((0..1000).all(|_| rng.below(3) < 3));
+    }
+
+    #[test]
+    fn test_normal_draws() {
+        let draws = normal_draws(10000, 1);
+        assert_eq!(draws, normal_draws(10000, 1));
+        assert_ne!(draws, normal_draws(10000, 2));
+        assert_abs_diff_eq!(mean(&draws).unwrap(), 0.0, epsilon = 0.05);
+        assert_abs_diff_eq!(sample_variance(&draws).unwrap(), 1.0, epsilon = 0.05);
+    }
 }